@@ -0,0 +1,544 @@
+//! broker 的命令行管理工具，通过节点的 grpc 管理接口操作正在运行的 broker
+//!
+//! ```bash
+//! geckoctl --addr http://127.0.0.1:1891 clients list
+//! geckoctl --json retained list --filter 'iot/#'
+//! ```
+
+use std::{
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use gecko_mqtt_proto::{
+    admin::{
+        gecko_admin_client::GeckoAdminClient, AddBanRequest, ClientInfo, DeleteBanRequest,
+        DeleteRetainedRequest, GetMetricsRequest, GetSessionRequest, JoinNodeRequest,
+        KickClientRequest, LeaveNodeRequest, ListBansRequest, ListClientsRequest, ListNodesRequest,
+        ListRetainedRequest, PublishRequest, SubscribeRequest, UnsubscribeRequest,
+    },
+    Ban, BanKind,
+};
+use serde_json::Value;
+use tonic::transport::Channel;
+
+use self::output::Output;
+
+mod output;
+
+#[derive(Debug, Parser)]
+#[clap(name = "geckoctl", about = "gecko-mqtt broker 管理工具")]
+struct Args {
+    /// broker 的 grpc 地址，即配置中的 peer_addr
+    #[clap(long, default_value = "http://127.0.0.1:1888")]
+    addr: String,
+    /// 以 json 格式输出
+    #[clap(long)]
+    json: bool,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// 客户端
+    #[clap(subcommand)]
+    Clients(Clients),
+    /// 客户端的订阅
+    #[clap(subcommand)]
+    Subscriptions(Subscriptions),
+    /// 保留消息
+    #[clap(subcommand)]
+    Retained(Retained),
+    /// 发布一条消息
+    Publish {
+        topic: String,
+        payload: String,
+        #[clap(long, default_value_t = 0)]
+        qos: u32,
+        #[clap(long)]
+        retain: bool,
+    },
+    /// 集群节点
+    #[clap(subcommand)]
+    Cluster(Cluster),
+    /// 节点的统计
+    Metrics,
+    /// 禁止连接的客户端
+    #[clap(subcommand)]
+    Bans(Bans),
+}
+
+#[derive(Debug, Subcommand)]
+enum Clients {
+    /// 列出客户端
+    List {
+        /// 只列出 client id 包含此字符串的客户端
+        #[clap(long, default_value = "")]
+        search: String,
+        /// 只列出在线的客户端
+        #[clap(long)]
+        connected: bool,
+        #[clap(long, default_value_t = 0)]
+        limit: u32,
+    },
+    /// 查看客户端的会话
+    Show { client_id: String },
+    /// 断开客户端连接
+    Kick { client_id: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum Subscriptions {
+    /// 列出客户端的订阅
+    List { client_id: String },
+    /// 代替客户端订阅
+    Add {
+        client_id: String,
+        #[clap(required = true)]
+        filters: Vec<String>,
+    },
+    /// 代替客户端取消订阅
+    Del {
+        client_id: String,
+        #[clap(required = true)]
+        filters: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum Retained {
+    /// 列出保留消息
+    List {
+        #[clap(long, default_value = "")]
+        filter: String,
+        #[clap(long, default_value_t = 0)]
+        limit: u32,
+    },
+    /// 删除保留消息
+    Del { topic: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum Cluster {
+    /// 列出集群中的节点
+    Status,
+    /// 添加对等节点，需要在每个节点上分别执行
+    Join { node_id: u64, addr: String },
+    /// 移除对等节点
+    Leave { node_id: u64 },
+}
+
+#[derive(Debug, Subcommand)]
+enum Bans {
+    /// 列出禁止规则
+    List,
+    /// 禁止客户端连接，并断开已连接的客户端
+    Add {
+        #[clap(value_enum)]
+        kind: Kind,
+        /// client id、用户名，或者 IP/CIDR
+        value: String,
+        /// 禁止的时长（秒），不指定时永久禁止
+        #[clap(long)]
+        duration: Option<u64>,
+        #[clap(long, default_value = "")]
+        reason: String,
+    },
+    /// 解除禁止
+    Del {
+        #[clap(value_enum)]
+        kind: Kind,
+        value: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Kind {
+    ClientId,
+    Username,
+    Addr,
+}
+
+impl From<Kind> for BanKind {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::ClientId => BanKind::BanClientId,
+            Kind::Username => BanKind::BanUsername,
+            Kind::Addr => BanKind::BanAddr,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let result = match GeckoAdminClient::connect(args.addr.clone()).await {
+        Ok(client) => run(client, args.command).await,
+        Err(e) => Err(format!("connect to {} error: {}", args.addr, e)),
+    };
+    match result {
+        Ok(output) => output.print(args.json),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+async fn run(mut client: GeckoAdminClient<Channel>, command: Command) -> Result<Output, String> {
+    let output = match command {
+        Command::Clients(Clients::List {
+            search,
+            connected,
+            limit,
+        }) => {
+            let clients = client
+                .list_clients(ListClientsRequest {
+                    search,
+                    connected_only: connected,
+                    limit,
+                })
+                .await
+                .map_err(error)?
+                .into_inner()
+                .clients;
+            Output::Table {
+                headers: vec![
+                    "client_id",
+                    "connected",
+                    "addr",
+                    "protocol",
+                    "subscriptions",
+                    "inflight",
+                    "queued",
+                ],
+                rows: clients
+                    .into_iter()
+                    .map(|c| {
+                        vec![
+                            c.client_id.into(),
+                            c.connected.into(),
+                            optional(c.addr),
+                            protocol(c.protocol),
+                            c.subscriptions.into(),
+                            c.inflight.into(),
+                            c.queued.into(),
+                        ]
+                    })
+                    .collect(),
+            }
+        }
+        Command::Clients(Clients::Show { client_id }) => {
+            let response = client
+                .get_session(GetSessionRequest {
+                    client_id: client_id.clone(),
+                })
+                .await
+                .map_err(error)?
+                .into_inner();
+            match response.client {
+                Some(info) if response.found => {
+                    let mut fields = client_fields(info);
+                    fields.push(("filters", response.filters.into()));
+                    Output::Record(fields)
+                }
+                _ => return Err(format!("client {} not found", client_id)),
+            }
+        }
+        Command::Clients(Clients::Kick { client_id }) => {
+            let found = client
+                .kick_client(KickClientRequest {
+                    client_id: client_id.clone(),
+                })
+                .await
+                .map_err(error)?
+                .into_inner()
+                .found;
+            if !found {
+                return Err(format!("client {} not connected", client_id));
+            }
+            Output::Done(format!("client {} kicked", client_id))
+        }
+        Command::Subscriptions(Subscriptions::List { client_id }) => {
+            let response = client
+                .get_session(GetSessionRequest {
+                    client_id: client_id.clone(),
+                })
+                .await
+                .map_err(error)?
+                .into_inner();
+            if !response.found {
+                return Err(format!("client {} not found", client_id));
+            }
+            Output::Table {
+                headers: vec!["filter"],
+                rows: response
+                    .filters
+                    .into_iter()
+                    .map(|filter| vec![filter.into()])
+                    .collect(),
+            }
+        }
+        Command::Subscriptions(Subscriptions::Add { client_id, filters }) => {
+            let found = client
+                .subscribe(SubscribeRequest {
+                    client_id: client_id.clone(),
+                    filters,
+                })
+                .await
+                .map_err(error)?
+                .into_inner()
+                .found;
+            if !found {
+                return Err(format!("client {} not found", client_id));
+            }
+            Output::Done(format!("client {} subscribed", client_id))
+        }
+        Command::Subscriptions(Subscriptions::Del { client_id, filters }) => {
+            let found = client
+                .unsubscribe(UnsubscribeRequest {
+                    client_id: client_id.clone(),
+                    filters,
+                })
+                .await
+                .map_err(error)?
+                .into_inner()
+                .found;
+            if !found {
+                return Err(format!("client {} not found", client_id));
+            }
+            Output::Done(format!("client {} unsubscribed", client_id))
+        }
+        Command::Retained(Retained::List { filter, limit }) => {
+            let messages = client
+                .list_retained(ListRetainedRequest { filter, limit })
+                .await
+                .map_err(error)?
+                .into_inner()
+                .messages;
+            Output::Table {
+                headers: vec!["topic", "qos", "payload"],
+                rows: messages
+                    .into_iter()
+                    .map(|m| {
+                        vec![
+                            m.topic.into(),
+                            m.qos.into(),
+                            String::from_utf8_lossy(&m.payload).into(),
+                        ]
+                    })
+                    .collect(),
+            }
+        }
+        Command::Retained(Retained::Del { topic }) => {
+            let found = client
+                .delete_retained(DeleteRetainedRequest {
+                    topic: topic.clone(),
+                })
+                .await
+                .map_err(error)?
+                .into_inner()
+                .found;
+            if !found {
+                return Err(format!("retained message on {} not found", topic));
+            }
+            Output::Done(format!("retained message on {} deleted", topic))
+        }
+        Command::Publish {
+            topic,
+            payload,
+            qos,
+            retain,
+        } => {
+            client
+                .publish(PublishRequest {
+                    topic: topic.clone(),
+                    payload: payload.into_bytes(),
+                    qos,
+                    retain,
+                })
+                .await
+                .map_err(error)?;
+            Output::Done(format!("published to {}", topic))
+        }
+        Command::Cluster(Cluster::Status) => {
+            let nodes = client
+                .list_nodes(ListNodesRequest {})
+                .await
+                .map_err(error)?
+                .into_inner()
+                .nodes;
+            Output::Table {
+                headers: vec!["node_id", "addr", "status", "local"],
+                rows: nodes
+                    .into_iter()
+                    .map(|n| {
+                        vec![
+                            n.node_id.into(),
+                            optional(n.addr),
+                            n.status.into(),
+                            n.local.into(),
+                        ]
+                    })
+                    .collect(),
+            }
+        }
+        Command::Cluster(Cluster::Join { node_id, addr }) => {
+            client
+                .join_node(JoinNodeRequest { node_id, addr })
+                .await
+                .map_err(error)?;
+            Output::Done(format!("node {} joined", node_id))
+        }
+        Command::Cluster(Cluster::Leave { node_id }) => {
+            let found = client
+                .leave_node(LeaveNodeRequest { node_id })
+                .await
+                .map_err(error)?
+                .into_inner()
+                .found;
+            if !found {
+                return Err(format!("node {} not found", node_id));
+            }
+            Output::Done(format!("node {} left", node_id))
+        }
+        Command::Metrics => {
+            let m = client
+                .get_metrics(GetMetricsRequest {})
+                .await
+                .map_err(error)?
+                .into_inner();
+            Output::Record(vec![
+                ("sessions_connected", m.sessions_connected.into()),
+                ("sessions_disconnected", m.sessions_disconnected.into()),
+                ("subscriptions", m.subscriptions.into()),
+                ("retained", m.retained.into()),
+                ("inflight", m.inflight.into()),
+                ("queued", m.queued.into()),
+                ("messages_received", m.messages_received.into()),
+                ("messages_sent", m.messages_sent.into()),
+                ("messages_dropped", m.messages_dropped.into()),
+                ("bytes_received", m.bytes_received.into()),
+                ("bytes_sent", m.bytes_sent.into()),
+            ])
+        }
+        Command::Bans(Bans::List) => {
+            let bans = client
+                .list_bans(ListBansRequest {})
+                .await
+                .map_err(error)?
+                .into_inner()
+                .bans;
+            Output::Table {
+                headers: vec!["kind", "value", "until", "reason", "created_at"],
+                rows: bans
+                    .into_iter()
+                    .map(|b| {
+                        vec![
+                            kind(b.kind()),
+                            b.value.into(),
+                            timestamp(b.until),
+                            optional(b.reason),
+                            timestamp(b.created_at),
+                        ]
+                    })
+                    .collect(),
+            }
+        }
+        Command::Bans(Bans::Add {
+            kind,
+            value,
+            duration,
+            reason,
+        }) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            client
+                .add_ban(AddBanRequest {
+                    ban: Some(Ban {
+                        kind: BanKind::from(kind) as i32,
+                        value: value.clone(),
+                        until: duration.map_or(0, |duration| now + duration),
+                        reason,
+                        created_at: now,
+                        ..Default::default()
+                    }),
+                })
+                .await
+                .map_err(error)?;
+            Output::Done(format!("{} banned", value))
+        }
+        Command::Bans(Bans::Del { kind, value }) => {
+            let found = client
+                .delete_ban(DeleteBanRequest {
+                    kind: BanKind::from(kind) as i32,
+                    value: value.clone(),
+                })
+                .await
+                .map_err(error)?
+                .into_inner()
+                .found;
+            if !found {
+                return Err(format!("ban on {} not found", value));
+            }
+            Output::Done(format!("ban on {} deleted", value))
+        }
+    };
+    Ok(output)
+}
+
+fn client_fields(c: ClientInfo) -> Vec<(&'static str, Value)> {
+    vec![
+        ("client_id", c.client_id.into()),
+        ("connected", c.connected.into()),
+        ("addr", optional(c.addr)),
+        ("username", optional(c.username)),
+        ("protocol", protocol(c.protocol)),
+        ("keep_alive", c.keep_alive.into()),
+        ("clean_session", c.clean_session.into()),
+        ("connected_at", timestamp(c.connected_at)),
+        ("subscriptions", c.subscriptions.into()),
+        ("inflight", c.inflight.into()),
+        ("queued", c.queued.into()),
+    ]
+}
+
+/// 离线会话没有连接信息
+fn protocol(level: u32) -> Value {
+    match level {
+        3 => "3.1".into(),
+        4 => "3.1.1".into(),
+        5 => "5.0".into(),
+        _ => Value::Null,
+    }
+}
+
+fn kind(kind: BanKind) -> Value {
+    match kind {
+        BanKind::BanClientId => "client-id".into(),
+        BanKind::BanUsername => "username".into(),
+        BanKind::BanAddr => "addr".into(),
+    }
+}
+
+/// 为 0 时表示没有
+fn timestamp(value: u64) -> Value {
+    match value {
+        0 => Value::Null,
+        value => value.into(),
+    }
+}
+
+fn optional(value: String) -> Value {
+    match value.is_empty() {
+        true => Value::Null,
+        false => value.into(),
+    }
+}
+
+fn error(status: tonic::Status) -> String {
+    format!("{:?}: {}", status.code(), status.message())
+}
@@ -0,0 +1,112 @@
+//! 命令的输出，表格或 json
+
+use serde_json::{Map, Value};
+
+pub enum Output {
+    /// 多行记录，json 输出为对象数组
+    Table {
+        headers: Vec<&'static str>,
+        rows: Vec<Vec<Value>>,
+    },
+    /// 单条记录，表格输出为两列
+    Record(Vec<(&'static str, Value)>),
+    /// 操作结果
+    Done(String),
+}
+
+impl Output {
+    pub fn print(&self, json: bool) {
+        if json {
+            println!("{}", self.to_json());
+        } else {
+            print!("{}", self.to_table());
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            Output::Table { headers, rows } => rows
+                .iter()
+                .map(|row| object(headers.iter().copied().zip(row.iter().cloned())))
+                .collect(),
+            Output::Record(fields) => object(fields.iter().cloned()),
+            Output::Done(message) => object([("message", Value::from(message.as_str()))]),
+        }
+    }
+
+    fn to_table(&self) -> String {
+        match self {
+            Output::Table { headers, rows } => {
+                let headers = headers.iter().map(|h| h.to_uppercase()).collect();
+                let rows = rows
+                    .iter()
+                    .map(|row| row.iter().map(cell).collect())
+                    .collect();
+                align(headers, rows)
+            }
+            Output::Record(fields) => align(
+                Vec::new(),
+                fields
+                    .iter()
+                    .map(|(name, value)| vec![name.to_string(), cell(value)])
+                    .collect(),
+            ),
+            Output::Done(message) => format!("{}\n", message),
+        }
+    }
+}
+
+fn object<'a>(fields: impl IntoIterator<Item = (&'a str, Value)>) -> Value {
+    Value::Object(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect::<Map<_, _>>(),
+    )
+}
+
+/// 字符串不带引号，数组用逗号连接
+fn cell(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".into(),
+        Value::Array(values) => values.iter().map(cell).collect::<Vec<_>>().join(","),
+        value => value.to_string(),
+    }
+}
+
+/// 按每列最长的内容左对齐，列之间空两格
+fn align(headers: Vec<String>, rows: Vec<Vec<String>>) -> String {
+    let lines = (!headers.is_empty())
+        .then_some(&headers)
+        .into_iter()
+        .chain(rows.iter())
+        .collect::<Vec<_>>();
+    let columns = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+    let widths = (0..columns)
+        .map(|i| {
+            lines
+                .iter()
+                .filter_map(|line| line.get(i))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let mut out = String::new();
+    for line in lines {
+        let mut text = String::new();
+        for (i, cell) in line.iter().enumerate() {
+            if i + 1 == line.len() {
+                text.push_str(cell);
+            } else {
+                let padding = widths[i] - cell.chars().count() + 2;
+                text.push_str(cell);
+                text.push_str(&" ".repeat(padding));
+            }
+        }
+        out.push_str(&text);
+        out.push('\n');
+    }
+    out
+}
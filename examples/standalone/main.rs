@@ -4,7 +4,7 @@ use async_trait::async_trait;
 use clap::Parser;
 use flexi_logger::{colored_opt_format, Logger};
 use gecko_mqtt::config::Config;
-use gecko_mqtt::{broker, Hook, Login};
+use gecko_mqtt::{broker, AuthResult, Hook, Login};
 use log::info;
 
 #[derive(Debug, serde::Deserialize, clap::Parser)]
@@ -32,13 +32,18 @@ async fn main() {
     let config_file = config_file_env.unwrap_or_else(|| args.config_file.unwrap());
 
     // 获取配置
-    let cfg = Config::from_path(&config_file).await;
+    let cfg = Config::from_path(&config_file).await.unwrap();
 
-    // 启动 broker
-    broker::Broker::new(cfg)
-        .start_with_hook(Arc::new(CustomHook))
-        .await
-        .unwrap()
+    // 启动 broker，ctrl-c 时优雅关闭
+    let broker = broker::Broker::new(cfg);
+    let shutdown = broker.shutdown_handle();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            info!("received ctrl-c, shutting down");
+            shutdown.trigger();
+        }
+    });
+    broker.start_with_hook(Arc::new(CustomHook)).await.unwrap()
 }
 
 struct CustomHook;
@@ -46,9 +51,9 @@ struct CustomHook;
 #[async_trait]
 impl Hook for CustomHook {
     /// 客户端认证
-    async fn authenticate(&self, _login: Login) -> bool {
+    async fn authenticate(&self, _login: Login) -> AuthResult {
         info!("login authenticate");
-        true
+        AuthResult::Allow
     }
     /// 客户端上线
     async fn connected(&self, client_id: &str) {
@@ -1,6 +1,10 @@
 fn main() {
     tonic_build::configure()
         .out_dir("src/")
-        .compile(&["proto/peer.proto"], &["proto/"])
+        .compile(&["proto/peer.proto", "proto/admin.proto"], &["proto/"])
+        .unwrap();
+    tonic_build::configure()
+        .out_dir("src/etcd/")
+        .compile(&["proto/etcd/rpc.proto"], &["proto/etcd/"])
         .unwrap();
 }
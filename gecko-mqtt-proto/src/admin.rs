@@ -0,0 +1,1369 @@
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ClientInfo {
+    #[prost(string, tag="1")]
+    pub client_id: ::prost::alloc::string::String,
+    #[prost(bool, tag="2")]
+    pub connected: bool,
+    /// 客户端地址，自定义连接为 incoming，进程内客户端为 local
+    #[prost(string, tag="3")]
+    pub addr: ::prost::alloc::string::String,
+    /// 协议级别，3 = 3.1, 4 = 3.1.1, 5 = 5.0
+    #[prost(uint32, tag="4")]
+    pub protocol: u32,
+    #[prost(uint32, tag="5")]
+    pub keep_alive: u32,
+    #[prost(bool, tag="6")]
+    pub clean_session: bool,
+    /// 最近一次连接的时间戳（毫秒）
+    #[prost(uint64, tag="7")]
+    pub connected_at: u64,
+    #[prost(uint32, tag="8")]
+    pub subscriptions: u32,
+    /// 已发送给客户端，等待确认的消息数
+    #[prost(uint32, tag="9")]
+    pub inflight: u32,
+    /// 会话和连接队列中等待发送的消息数
+    #[prost(uint32, tag="10")]
+    pub queued: u32,
+    #[prost(string, tag="11")]
+    pub username: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListClientsRequest {
+    /// 只返回 client id 包含此字符串的客户端，为空时不过滤
+    #[prost(string, tag="1")]
+    pub search: ::prost::alloc::string::String,
+    /// 只返回在线的客户端
+    #[prost(bool, tag="2")]
+    pub connected_only: bool,
+    /// 最多返回的数量，0 表示不限制
+    #[prost(uint32, tag="3")]
+    pub limit: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListClientsResponse {
+    #[prost(message, repeated, tag="1")]
+    pub clients: ::prost::alloc::vec::Vec<ClientInfo>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetSessionRequest {
+    #[prost(string, tag="1")]
+    pub client_id: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetSessionResponse {
+    #[prost(bool, tag="1")]
+    pub found: bool,
+    #[prost(message, optional, tag="2")]
+    pub client: ::core::option::Option<ClientInfo>,
+    #[prost(string, repeated, tag="3")]
+    pub filters: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KickClientRequest {
+    #[prost(string, tag="1")]
+    pub client_id: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KickClientResponse {
+    /// 客户端在线，已断开
+    #[prost(bool, tag="1")]
+    pub found: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeRequest {
+    #[prost(string, tag="1")]
+    pub client_id: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag="2")]
+    pub filters: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeResponse {
+    #[prost(bool, tag="1")]
+    pub found: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UnsubscribeRequest {
+    #[prost(string, tag="1")]
+    pub client_id: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag="2")]
+    pub filters: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UnsubscribeResponse {
+    #[prost(bool, tag="1")]
+    pub found: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RetainedMessage {
+    #[prost(string, tag="1")]
+    pub topic: ::prost::alloc::string::String,
+    #[prost(bytes="vec", tag="2")]
+    pub payload: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint32, tag="3")]
+    pub qos: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListRetainedRequest {
+    /// 为空时返回所有的保留消息
+    #[prost(string, tag="1")]
+    pub filter: ::prost::alloc::string::String,
+    /// 最多返回的数量，0 表示不限制
+    #[prost(uint32, tag="2")]
+    pub limit: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListRetainedResponse {
+    #[prost(message, repeated, tag="1")]
+    pub messages: ::prost::alloc::vec::Vec<RetainedMessage>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeleteRetainedRequest {
+    #[prost(string, tag="1")]
+    pub topic: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeleteRetainedResponse {
+    #[prost(bool, tag="1")]
+    pub found: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PublishRequest {
+    #[prost(string, tag="1")]
+    pub topic: ::prost::alloc::string::String,
+    #[prost(bytes="vec", tag="2")]
+    pub payload: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint32, tag="3")]
+    pub qos: u32,
+    #[prost(bool, tag="4")]
+    pub retain: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PublishResponse {
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListNodesRequest {
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NodeInfo {
+    #[prost(uint64, tag="1")]
+    pub node_id: u64,
+    /// 对等节点的 grpc 地址，本节点为空
+    #[prost(string, tag="2")]
+    pub addr: ::prost::alloc::string::String,
+    /// up, suspect, down, unknown
+    #[prost(string, tag="3")]
+    pub status: ::prost::alloc::string::String,
+    /// 是否为处理此请求的节点
+    #[prost(bool, tag="4")]
+    pub local: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListNodesResponse {
+    #[prost(message, repeated, tag="1")]
+    pub nodes: ::prost::alloc::vec::Vec<NodeInfo>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JoinNodeRequest {
+    #[prost(uint64, tag="1")]
+    pub node_id: u64,
+    /// 对等节点的 grpc 地址，如 <http://127.0.0.1:1891>
+    #[prost(string, tag="2")]
+    pub addr: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct JoinNodeResponse {
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LeaveNodeRequest {
+    #[prost(uint64, tag="1")]
+    pub node_id: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LeaveNodeResponse {
+    /// 是对等节点，已移除
+    #[prost(bool, tag="1")]
+    pub found: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetMetricsRequest {
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetMetricsResponse {
+    #[prost(uint64, tag="1")]
+    pub sessions_connected: u64,
+    #[prost(uint64, tag="2")]
+    pub sessions_disconnected: u64,
+    #[prost(uint64, tag="3")]
+    pub subscriptions: u64,
+    #[prost(uint64, tag="4")]
+    pub retained: u64,
+    #[prost(uint64, tag="5")]
+    pub inflight: u64,
+    #[prost(uint64, tag="6")]
+    pub queued: u64,
+    /// 以下为启动以来的累计值
+    #[prost(uint64, tag="7")]
+    pub messages_received: u64,
+    #[prost(uint64, tag="8")]
+    pub messages_sent: u64,
+    #[prost(uint64, tag="9")]
+    pub messages_dropped: u64,
+    #[prost(uint64, tag="10")]
+    pub bytes_received: u64,
+    #[prost(uint64, tag="11")]
+    pub bytes_sent: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListBansRequest {
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListBansResponse {
+    #[prost(message, repeated, tag="1")]
+    pub bans: ::prost::alloc::vec::Vec<super::peer::Ban>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AddBanRequest {
+    /// created_at 为 0 时使用当前时间
+    #[prost(message, optional, tag="1")]
+    pub ban: ::core::option::Option<super::peer::Ban>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AddBanResponse {
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeleteBanRequest {
+    #[prost(enumeration="super::peer::BanKind", tag="1")]
+    pub kind: i32,
+    #[prost(string, tag="2")]
+    pub value: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeleteBanResponse {
+    #[prost(bool, tag="1")]
+    pub found: bool,
+}
+/// Generated client implementations.
+pub mod gecko_admin_client {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    use tonic::codegen::http::Uri;
+    /// 节点的管理接口，只操作本节点上的会话，请求由 router 处理
+    #[derive(Debug, Clone)]
+    pub struct GeckoAdminClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl GeckoAdminClient<tonic::transport::Channel> {
+        /// Attempt to create a new client by connecting to a given endpoint.
+        pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+        where
+            D: std::convert::TryInto<tonic::transport::Endpoint>,
+            D::Error: Into<StdError>,
+        {
+            let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+            Ok(Self::new(conn))
+        }
+    }
+    impl<T> GeckoAdminClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_origin(inner: T, origin: Uri) -> Self {
+            let inner = tonic::client::Grpc::with_origin(inner, origin);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> GeckoAdminClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T::ResponseBody: Default,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+            >>::Error: Into<StdError> + Send + Sync,
+        {
+            GeckoAdminClient::new(InterceptedService::new(inner, interceptor))
+        }
+        /// Compress requests with the given encoding.
+        ///
+        /// This requires the server to support it otherwise it might respond with an
+        /// error.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.send_compressed(encoding);
+            self
+        }
+        /// Enable decompressing responses.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.accept_compressed(encoding);
+            self
+        }
+        /// 列出本节点上的客户端
+        pub async fn list_clients(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListClientsRequest>,
+        ) -> Result<tonic::Response<super::ListClientsResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/ListClients",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 查看会话的订阅和消息数
+        pub async fn get_session(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetSessionRequest>,
+        ) -> Result<tonic::Response<super::GetSessionResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/GetSession",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 断开客户端连接，会话按过期时间清理
+        pub async fn kick_client(
+            &mut self,
+            request: impl tonic::IntoRequest<super::KickClientRequest>,
+        ) -> Result<tonic::Response<super::KickClientResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/KickClient",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 代替客户端订阅
+        pub async fn subscribe(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SubscribeRequest>,
+        ) -> Result<tonic::Response<super::SubscribeResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/Subscribe",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 代替客户端取消订阅
+        pub async fn unsubscribe(
+            &mut self,
+            request: impl tonic::IntoRequest<super::UnsubscribeRequest>,
+        ) -> Result<tonic::Response<super::UnsubscribeResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/Unsubscribe",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 列出保留消息
+        pub async fn list_retained(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListRetainedRequest>,
+        ) -> Result<tonic::Response<super::ListRetainedResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/ListRetained",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 删除保留消息，同步给对等节点
+        pub async fn delete_retained(
+            &mut self,
+            request: impl tonic::IntoRequest<super::DeleteRetainedRequest>,
+        ) -> Result<tonic::Response<super::DeleteRetainedResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/DeleteRetained",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 发布一条消息，和客户端发布的消息一样投递到整个集群
+        pub async fn publish(
+            &mut self,
+            request: impl tonic::IntoRequest<super::PublishRequest>,
+        ) -> Result<tonic::Response<super::PublishResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/admin.GeckoAdmin/Publish");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 列出集群中的节点
+        pub async fn list_nodes(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListNodesRequest>,
+        ) -> Result<tonic::Response<super::ListNodesResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/ListNodes",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 添加对等节点，只用于静态配置的集群，配置了 etcd 时由集群管理器发现节点
+        pub async fn join_node(
+            &mut self,
+            request: impl tonic::IntoRequest<super::JoinNodeRequest>,
+        ) -> Result<tonic::Response<super::JoinNodeResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/JoinNode",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 移除对等节点，删除它的路由和会话
+        pub async fn leave_node(
+            &mut self,
+            request: impl tonic::IntoRequest<super::LeaveNodeRequest>,
+        ) -> Result<tonic::Response<super::LeaveNodeResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/LeaveNode",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 本节点的统计
+        pub async fn get_metrics(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetMetricsRequest>,
+        ) -> Result<tonic::Response<super::GetMetricsResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/GetMetrics",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 列出禁止连接的客户端，不包含已过期的
+        pub async fn list_bans(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListBansRequest>,
+        ) -> Result<tonic::Response<super::ListBansResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/ListBans",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 禁止客户端连接，断开已连接的客户端，同步给对等节点
+        pub async fn add_ban(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AddBanRequest>,
+        ) -> Result<tonic::Response<super::AddBanResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/admin.GeckoAdmin/AddBan");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 解除禁止，同步给对等节点
+        pub async fn delete_ban(
+            &mut self,
+            request: impl tonic::IntoRequest<super::DeleteBanRequest>,
+        ) -> Result<tonic::Response<super::DeleteBanResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/admin.GeckoAdmin/DeleteBan",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+    }
+}
+/// Generated server implementations.
+pub mod gecko_admin_server {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    ///Generated trait containing gRPC methods that should be implemented for use with GeckoAdminServer.
+    #[async_trait]
+    pub trait GeckoAdmin: Send + Sync + 'static {
+        /// 列出本节点上的客户端
+        async fn list_clients(
+            &self,
+            request: tonic::Request<super::ListClientsRequest>,
+        ) -> Result<tonic::Response<super::ListClientsResponse>, tonic::Status>;
+        /// 查看会话的订阅和消息数
+        async fn get_session(
+            &self,
+            request: tonic::Request<super::GetSessionRequest>,
+        ) -> Result<tonic::Response<super::GetSessionResponse>, tonic::Status>;
+        /// 断开客户端连接，会话按过期时间清理
+        async fn kick_client(
+            &self,
+            request: tonic::Request<super::KickClientRequest>,
+        ) -> Result<tonic::Response<super::KickClientResponse>, tonic::Status>;
+        /// 代替客户端订阅
+        async fn subscribe(
+            &self,
+            request: tonic::Request<super::SubscribeRequest>,
+        ) -> Result<tonic::Response<super::SubscribeResponse>, tonic::Status>;
+        /// 代替客户端取消订阅
+        async fn unsubscribe(
+            &self,
+            request: tonic::Request<super::UnsubscribeRequest>,
+        ) -> Result<tonic::Response<super::UnsubscribeResponse>, tonic::Status>;
+        /// 列出保留消息
+        async fn list_retained(
+            &self,
+            request: tonic::Request<super::ListRetainedRequest>,
+        ) -> Result<tonic::Response<super::ListRetainedResponse>, tonic::Status>;
+        /// 删除保留消息，同步给对等节点
+        async fn delete_retained(
+            &self,
+            request: tonic::Request<super::DeleteRetainedRequest>,
+        ) -> Result<tonic::Response<super::DeleteRetainedResponse>, tonic::Status>;
+        /// 发布一条消息，和客户端发布的消息一样投递到整个集群
+        async fn publish(
+            &self,
+            request: tonic::Request<super::PublishRequest>,
+        ) -> Result<tonic::Response<super::PublishResponse>, tonic::Status>;
+        /// 列出集群中的节点
+        async fn list_nodes(
+            &self,
+            request: tonic::Request<super::ListNodesRequest>,
+        ) -> Result<tonic::Response<super::ListNodesResponse>, tonic::Status>;
+        /// 添加对等节点，只用于静态配置的集群，配置了 etcd 时由集群管理器发现节点
+        async fn join_node(
+            &self,
+            request: tonic::Request<super::JoinNodeRequest>,
+        ) -> Result<tonic::Response<super::JoinNodeResponse>, tonic::Status>;
+        /// 移除对等节点，删除它的路由和会话
+        async fn leave_node(
+            &self,
+            request: tonic::Request<super::LeaveNodeRequest>,
+        ) -> Result<tonic::Response<super::LeaveNodeResponse>, tonic::Status>;
+        /// 本节点的统计
+        async fn get_metrics(
+            &self,
+            request: tonic::Request<super::GetMetricsRequest>,
+        ) -> Result<tonic::Response<super::GetMetricsResponse>, tonic::Status>;
+        /// 列出禁止连接的客户端，不包含已过期的
+        async fn list_bans(
+            &self,
+            request: tonic::Request<super::ListBansRequest>,
+        ) -> Result<tonic::Response<super::ListBansResponse>, tonic::Status>;
+        /// 禁止客户端连接，断开已连接的客户端，同步给对等节点
+        async fn add_ban(
+            &self,
+            request: tonic::Request<super::AddBanRequest>,
+        ) -> Result<tonic::Response<super::AddBanResponse>, tonic::Status>;
+        /// 解除禁止，同步给对等节点
+        async fn delete_ban(
+            &self,
+            request: tonic::Request<super::DeleteBanRequest>,
+        ) -> Result<tonic::Response<super::DeleteBanResponse>, tonic::Status>;
+    }
+    /// 节点的管理接口，只操作本节点上的会话，请求由 router 处理
+    #[derive(Debug)]
+    pub struct GeckoAdminServer<T: GeckoAdmin> {
+        inner: _Inner<T>,
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+    }
+    struct _Inner<T>(Arc<T>);
+    impl<T: GeckoAdmin> GeckoAdminServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            let inner = _Inner(inner);
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+            }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.enable(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.enable(encoding);
+            self
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for GeckoAdminServer<T>
+    where
+        T: GeckoAdmin,
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            let inner = self.inner.clone();
+            match req.uri().path() {
+                "/admin.GeckoAdmin/ListClients" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListClientsSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::ListClientsRequest>
+                    for ListClientsSvc<T> {
+                        type Response = super::ListClientsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListClientsRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).list_clients(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListClientsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/GetSession" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetSessionSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::GetSessionRequest>
+                    for GetSessionSvc<T> {
+                        type Response = super::GetSessionResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetSessionRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_session(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetSessionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/KickClient" => {
+                    #[allow(non_camel_case_types)]
+                    struct KickClientSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::KickClientRequest>
+                    for KickClientSvc<T> {
+                        type Response = super::KickClientResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::KickClientRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).kick_client(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = KickClientSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/Subscribe" => {
+                    #[allow(non_camel_case_types)]
+                    struct SubscribeSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::SubscribeRequest>
+                    for SubscribeSvc<T> {
+                        type Response = super::SubscribeResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::SubscribeRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).subscribe(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = SubscribeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/Unsubscribe" => {
+                    #[allow(non_camel_case_types)]
+                    struct UnsubscribeSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::UnsubscribeRequest>
+                    for UnsubscribeSvc<T> {
+                        type Response = super::UnsubscribeResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::UnsubscribeRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).unsubscribe(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = UnsubscribeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/ListRetained" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListRetainedSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::ListRetainedRequest>
+                    for ListRetainedSvc<T> {
+                        type Response = super::ListRetainedResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListRetainedRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).list_retained(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListRetainedSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/DeleteRetained" => {
+                    #[allow(non_camel_case_types)]
+                    struct DeleteRetainedSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::DeleteRetainedRequest>
+                    for DeleteRetainedSvc<T> {
+                        type Response = super::DeleteRetainedResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::DeleteRetainedRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).delete_retained(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = DeleteRetainedSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/Publish" => {
+                    #[allow(non_camel_case_types)]
+                    struct PublishSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::PublishRequest>
+                    for PublishSvc<T> {
+                        type Response = super::PublishResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PublishRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).publish(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = PublishSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/ListNodes" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListNodesSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::ListNodesRequest>
+                    for ListNodesSvc<T> {
+                        type Response = super::ListNodesResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListNodesRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).list_nodes(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListNodesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/JoinNode" => {
+                    #[allow(non_camel_case_types)]
+                    struct JoinNodeSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::JoinNodeRequest>
+                    for JoinNodeSvc<T> {
+                        type Response = super::JoinNodeResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::JoinNodeRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).join_node(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = JoinNodeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/LeaveNode" => {
+                    #[allow(non_camel_case_types)]
+                    struct LeaveNodeSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::LeaveNodeRequest>
+                    for LeaveNodeSvc<T> {
+                        type Response = super::LeaveNodeResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::LeaveNodeRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).leave_node(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = LeaveNodeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/GetMetrics" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetMetricsSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::GetMetricsRequest>
+                    for GetMetricsSvc<T> {
+                        type Response = super::GetMetricsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetMetricsRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_metrics(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetMetricsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/ListBans" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListBansSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::ListBansRequest>
+                    for ListBansSvc<T> {
+                        type Response = super::ListBansResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListBansRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).list_bans(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListBansSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/AddBan" => {
+                    #[allow(non_camel_case_types)]
+                    struct AddBanSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<T: GeckoAdmin> tonic::server::UnaryService<super::AddBanRequest>
+                    for AddBanSvc<T> {
+                        type Response = super::AddBanResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AddBanRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).add_ban(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = AddBanSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/admin.GeckoAdmin/DeleteBan" => {
+                    #[allow(non_camel_case_types)]
+                    struct DeleteBanSvc<T: GeckoAdmin>(pub Arc<T>);
+                    impl<
+                        T: GeckoAdmin,
+                    > tonic::server::UnaryService<super::DeleteBanRequest>
+                    for DeleteBanSvc<T> {
+                        type Response = super::DeleteBanResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::DeleteBanRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).delete_ban(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = DeleteBanSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => {
+                    Box::pin(async move {
+                        Ok(
+                            http::Response::builder()
+                                .status(200)
+                                .header("grpc-status", "12")
+                                .header("content-type", "application/grpc")
+                                .body(empty_body())
+                                .unwrap(),
+                        )
+                    })
+                }
+            }
+        }
+    }
+    impl<T: GeckoAdmin> Clone for GeckoAdminServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+            }
+        }
+    }
+    impl<T: GeckoAdmin> Clone for _Inner<T> {
+        fn clone(&self) -> Self {
+            Self(self.0.clone())
+        }
+    }
+    impl<T: std::fmt::Debug> std::fmt::Debug for _Inner<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:?}", self.0)
+        }
+    }
+    impl<T: GeckoAdmin> tonic::server::NamedService for GeckoAdminServer<T> {
+        const NAME: &'static str = "admin.GeckoAdmin";
+    }
+}
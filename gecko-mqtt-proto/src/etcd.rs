@@ -0,0 +1,6 @@
+//! etcd v3 api 的子集，与 etcd 服务端协议兼容
+
+#[rustfmt::skip]
+pub mod mvccpb;
+#[rustfmt::skip]
+pub mod etcdserverpb;
@@ -0,0 +1,45 @@
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyValue {
+    #[prost(bytes="vec", tag="1")]
+    pub key: ::prost::alloc::vec::Vec<u8>,
+    #[prost(int64, tag="2")]
+    pub create_revision: i64,
+    #[prost(int64, tag="3")]
+    pub mod_revision: i64,
+    #[prost(int64, tag="4")]
+    pub version: i64,
+    #[prost(bytes="vec", tag="5")]
+    pub value: ::prost::alloc::vec::Vec<u8>,
+    #[prost(int64, tag="6")]
+    pub lease: i64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Event {
+    #[prost(enumeration="event::EventType", tag="1")]
+    pub r#type: i32,
+    #[prost(message, optional, tag="2")]
+    pub kv: ::core::option::Option<KeyValue>,
+    #[prost(message, optional, tag="3")]
+    pub prev_kv: ::core::option::Option<KeyValue>,
+}
+/// Nested message and enum types in `Event`.
+pub mod event {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum EventType {
+        Put = 0,
+        Delete = 1,
+    }
+    impl EventType {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                EventType::Put => "PUT",
+                EventType::Delete => "DELETE",
+            }
+        }
+    }
+}
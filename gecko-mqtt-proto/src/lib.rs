@@ -1,4 +1,7 @@
 #[rustfmt::skip]
 mod peer;
+pub mod etcd;
+#[rustfmt::skip]
+pub mod admin;
 
 pub use peer::*;
@@ -1,14 +1,253 @@
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct TransferPacketRequest {
+pub struct UserProperty {
+    #[prost(string, tag="1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub value: ::prost::alloc::string::String,
+}
+/// v5 消息属性，主题别名和订阅标识符只在单个连接上有效，不转发
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PublishProperties {
+    #[prost(uint32, optional, tag="1")]
+    pub payload_format_indicator: ::core::option::Option<u32>,
+    #[prost(uint32, optional, tag="2")]
+    pub message_expiry_interval: ::core::option::Option<u32>,
+    #[prost(string, optional, tag="3")]
+    pub response_topic: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(bytes="vec", optional, tag="4")]
+    pub correlation_data: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    #[prost(message, repeated, tag="5")]
+    pub user_properties: ::prost::alloc::vec::Vec<UserProperty>,
+    #[prost(string, optional, tag="6")]
+    pub content_type: ::core::option::Option<::prost::alloc::string::String>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ForwardPublishRequest {
+    /// 消息来源节点
+    #[prost(uint64, tag="1")]
+    pub origin_node_id: u64,
+    #[prost(string, tag="2")]
+    pub topic: ::prost::alloc::string::String,
+    #[prost(bytes="vec", tag="3")]
+    pub payload: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint32, tag="4")]
+    pub qos: u32,
+    #[prost(bool, tag="5")]
+    pub retain: bool,
+    #[prost(message, optional, tag="6")]
+    pub properties: ::core::option::Option<PublishProperties>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ForwardPublishBatch {
+    #[prost(message, repeated, tag="1")]
+    pub publishes: ::prost::alloc::vec::Vec<ForwardPublishRequest>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ForwardPublishResponse {
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateRouteRequest {
+    /// 路由所在节点
     #[prost(uint64, tag="1")]
     pub node_id: u64,
+    #[prost(string, tag="2")]
+    pub filter: ::prost::alloc::string::String,
+    #[prost(enumeration="RouteAction", tag="3")]
+    pub action: i32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateRouteResponse {
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PingRequest {
+    #[prost(uint64, tag="1")]
+    pub node_id: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PingResponse {
+    #[prost(uint64, tag="1")]
+    pub node_id: u64,
+    /// 节点的启动时间戳，变化说明节点重启过
+    #[prost(uint64, tag="2")]
+    pub incarnation: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateSessionRequest {
+    #[prost(string, tag="1")]
+    pub client_id: ::prost::alloc::string::String,
+    /// 会话所在节点
     #[prost(uint64, tag="2")]
-    pub request_id: u64,
+    pub node_id: u64,
+    /// 会话已删除
+    #[prost(bool, tag="3")]
+    pub removed: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateSessionResponse {
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TakeoverSessionRequest {
+    #[prost(string, tag="1")]
+    pub client_id: ::prost::alloc::string::String,
+    /// 接管会话的节点
+    #[prost(uint64, tag="2")]
+    pub node_id: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct TransferPacketResponse {
+pub struct TakeoverSessionResponse {
+    /// 旧节点上存在此会话
+    #[prost(bool, tag="1")]
+    pub found: bool,
+    #[prost(message, repeated, tag="3")]
+    pub subscriptions: ::prost::alloc::vec::Vec<Subscription>,
+}
+/// 会话中的订阅和订阅选项
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Subscription {
+    #[prost(string, tag="1")]
+    pub filter: ::prost::alloc::string::String,
+    /// 授予的最大 QoS
+    #[prost(uint32, tag="2")]
+    pub qos: u32,
+    #[prost(bool, tag="3")]
+    pub no_local: bool,
+    #[prost(bool, tag="4")]
+    pub retain_as_published: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReleaseSessionRequest {
+    #[prost(string, tag="1")]
+    pub client_id: ::prost::alloc::string::String,
+    /// 接管会话的节点
+    #[prost(uint64, tag="2")]
+    pub node_id: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct InflightPublish {
+    #[prost(uint32, tag="1")]
+    pub packet_id: u32,
+    #[prost(message, optional, tag="2")]
+    pub publish: ::core::option::Option<ForwardPublishRequest>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReleaseSessionResponse {
+    #[prost(bool, tag="1")]
+    pub found: bool,
+    /// 已发送给客户端，等待确认的消息
+    #[prost(message, repeated, tag="2")]
+    pub inflight: ::prost::alloc::vec::Vec<InflightPublish>,
+    /// 客户端离线期间积压的消息
+    #[prost(message, repeated, tag="3")]
+    pub queued: ::prost::alloc::vec::Vec<ForwardPublishRequest>,
+    /// 已收到 qos2 publish，等待 pubrel 的 packet id
+    #[prost(uint32, repeated, tag="4")]
+    pub received: ::prost::alloc::vec::Vec<u32>,
+    /// 已收到 pubrec，等待 pubcomp 的 packet id
+    #[prost(uint32, repeated, tag="5")]
+    pub released: ::prost::alloc::vec::Vec<u32>,
+    #[prost(uint32, tag="6")]
+    pub next_packet_id: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RetainMessage {
+    /// payload 为空表示删除此 topic 的保留消息
+    #[prost(message, optional, tag="1")]
+    pub publish: ::core::option::Option<ForwardPublishRequest>,
+    /// 版本向量, key = node_id, value = 该节点上的写入次数
+    #[prost(map="uint64, uint64", tag="2")]
+    pub version: ::std::collections::HashMap<u64, u64>,
+    /// 写入此消息的节点，版本并发时用于决定胜者
+    #[prost(uint64, tag="3")]
+    pub writer: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateRetainRequest {
+    #[prost(message, repeated, tag="1")]
+    pub retains: ::prost::alloc::vec::Vec<RetainMessage>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateRetainResponse {
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SyncRetainRequest {
     #[prost(uint64, tag="1")]
-    pub request_id: u64,
+    pub node_id: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SyncRetainResponse {
+    #[prost(message, repeated, tag="1")]
+    pub retains: ::prost::alloc::vec::Vec<RetainMessage>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Ban {
+    #[prost(enumeration="BanKind", tag="1")]
+    pub kind: i32,
+    /// client id、用户名，或者来源地址 IP/CIDR，如 10.0.0.0/8
+    #[prost(string, tag="2")]
+    pub value: ::prost::alloc::string::String,
+    /// 过期时间戳（秒），0 表示永久
+    #[prost(uint64, tag="3")]
+    pub until: u64,
+    #[prost(string, tag="4")]
+    pub reason: ::prost::alloc::string::String,
+    /// 添加时间戳（秒）
+    #[prost(uint64, tag="5")]
+    pub created_at: u64,
+    /// 每次添加或删除加一，合并时版本大的生效，版本相同时 writer 大的生效
+    #[prost(uint64, tag="6")]
+    pub version: u64,
+    /// 最后一次修改的节点
+    #[prost(uint64, tag="7")]
+    pub writer: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateBanRequest {
+    #[prost(message, repeated, tag="1")]
+    pub added: ::prost::alloc::vec::Vec<Ban>,
+    /// 删除标记，和 added 一样按版本合并，避免离线的节点重新上线后恢复已删除的规则
+    #[prost(message, repeated, tag="2")]
+    pub removed: ::prost::alloc::vec::Vec<Ban>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UpdateBanResponse {
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum RouteAction {
+    RouteAdd = 0,
+    RouteDelete = 1,
+}
+impl RouteAction {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            RouteAction::RouteAdd => "ROUTE_ADD",
+            RouteAction::RouteDelete => "ROUTE_DELETE",
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum BanKind {
+    BanClientId = 0,
+    BanUsername = 1,
+    BanAddr = 2,
+}
+impl BanKind {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            BanKind::BanClientId => "BAN_CLIENT_ID",
+            BanKind::BanUsername => "BAN_USERNAME",
+            BanKind::BanAddr => "BAN_ADDR",
+        }
+    }
 }
 /// Generated client implementations.
 pub mod gecko_peer_client {
@@ -79,10 +318,173 @@ pub mod gecko_peer_client {
             self.inner = self.inner.accept_compressed(encoding);
             self
         }
-        pub async fn transfer_packet(
+        /// 转发 publish 消息到订阅者所在节点
+        pub async fn forward_publish(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ForwardPublishRequest>,
+        ) -> Result<tonic::Response<super::ForwardPublishResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/peer.GeckoPeer/ForwardPublish",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 批量转发 publish 消息，单个连接上持续发送
+        pub async fn forward_publish_stream(
+            &mut self,
+            request: impl tonic::IntoStreamingRequest<
+                Message = super::ForwardPublishBatch,
+            >,
+        ) -> Result<tonic::Response<super::ForwardPublishResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/peer.GeckoPeer/ForwardPublishStream",
+            );
+            self.inner
+                .client_streaming(request.into_streaming_request(), path, codec)
+                .await
+        }
+        /// 同步路由表变更
+        pub async fn update_route(
+            &mut self,
+            request: impl tonic::IntoRequest<super::UpdateRouteRequest>,
+        ) -> Result<tonic::Response<super::UpdateRouteResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/peer.GeckoPeer/UpdateRoute",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 节点间健康检查
+        pub async fn ping(
+            &mut self,
+            request: impl tonic::IntoRequest<super::PingRequest>,
+        ) -> Result<tonic::Response<super::PingResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/peer.GeckoPeer/Ping");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 同步客户端会话所在节点
+        pub async fn update_session(
+            &mut self,
+            request: impl tonic::IntoRequest<super::UpdateSessionRequest>,
+        ) -> Result<tonic::Response<super::UpdateSessionResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/peer.GeckoPeer/UpdateSession",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 客户端连接到新节点，踢掉旧节点上的连接，获取会话的订阅信息
+        pub async fn takeover_session(
+            &mut self,
+            request: impl tonic::IntoRequest<super::TakeoverSessionRequest>,
+        ) -> Result<tonic::Response<super::TakeoverSessionResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/peer.GeckoPeer/TakeoverSession",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 新节点更新路由表后，旧节点交出会话中未完成的消息并删除会话
+        pub async fn release_session(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ReleaseSessionRequest>,
+        ) -> Result<tonic::Response<super::ReleaseSessionResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/peer.GeckoPeer/ReleaseSession",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 同步保留消息的变更
+        pub async fn update_retain(
+            &mut self,
+            request: impl tonic::IntoRequest<super::UpdateRetainRequest>,
+        ) -> Result<tonic::Response<super::UpdateRetainResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/peer.GeckoPeer/UpdateRetain",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        /// 拉取对等节点上所有的保留消息，用于节点启动和断线后的反熵
+        pub async fn sync_retain(
             &mut self,
-            request: impl tonic::IntoRequest<super::TransferPacketRequest>,
-        ) -> Result<tonic::Response<super::TransferPacketResponse>, tonic::Status> {
+            request: impl tonic::IntoRequest<super::SyncRetainRequest>,
+        ) -> Result<tonic::Response<super::SyncRetainResponse>, tonic::Status> {
             self.inner
                 .ready()
                 .await
@@ -94,10 +496,28 @@ pub mod gecko_peer_client {
                 })?;
             let codec = tonic::codec::ProstCodec::default();
             let path = http::uri::PathAndQuery::from_static(
-                "/peer.GeckoPeer/TransferPacket",
+                "/peer.GeckoPeer/SyncRetain",
             );
             self.inner.unary(request.into_request(), path, codec).await
         }
+        /// 同步禁止连接的客户端
+        pub async fn update_ban(
+            &mut self,
+            request: impl tonic::IntoRequest<super::UpdateBanRequest>,
+        ) -> Result<tonic::Response<super::UpdateBanResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/peer.GeckoPeer/UpdateBan");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -107,10 +527,56 @@ pub mod gecko_peer_server {
     ///Generated trait containing gRPC methods that should be implemented for use with GeckoPeerServer.
     #[async_trait]
     pub trait GeckoPeer: Send + Sync + 'static {
-        async fn transfer_packet(
+        /// 转发 publish 消息到订阅者所在节点
+        async fn forward_publish(
             &self,
-            request: tonic::Request<super::TransferPacketRequest>,
-        ) -> Result<tonic::Response<super::TransferPacketResponse>, tonic::Status>;
+            request: tonic::Request<super::ForwardPublishRequest>,
+        ) -> Result<tonic::Response<super::ForwardPublishResponse>, tonic::Status>;
+        /// 批量转发 publish 消息，单个连接上持续发送
+        async fn forward_publish_stream(
+            &self,
+            request: tonic::Request<tonic::Streaming<super::ForwardPublishBatch>>,
+        ) -> Result<tonic::Response<super::ForwardPublishResponse>, tonic::Status>;
+        /// 同步路由表变更
+        async fn update_route(
+            &self,
+            request: tonic::Request<super::UpdateRouteRequest>,
+        ) -> Result<tonic::Response<super::UpdateRouteResponse>, tonic::Status>;
+        /// 节点间健康检查
+        async fn ping(
+            &self,
+            request: tonic::Request<super::PingRequest>,
+        ) -> Result<tonic::Response<super::PingResponse>, tonic::Status>;
+        /// 同步客户端会话所在节点
+        async fn update_session(
+            &self,
+            request: tonic::Request<super::UpdateSessionRequest>,
+        ) -> Result<tonic::Response<super::UpdateSessionResponse>, tonic::Status>;
+        /// 客户端连接到新节点，踢掉旧节点上的连接，获取会话的订阅信息
+        async fn takeover_session(
+            &self,
+            request: tonic::Request<super::TakeoverSessionRequest>,
+        ) -> Result<tonic::Response<super::TakeoverSessionResponse>, tonic::Status>;
+        /// 新节点更新路由表后，旧节点交出会话中未完成的消息并删除会话
+        async fn release_session(
+            &self,
+            request: tonic::Request<super::ReleaseSessionRequest>,
+        ) -> Result<tonic::Response<super::ReleaseSessionResponse>, tonic::Status>;
+        /// 同步保留消息的变更
+        async fn update_retain(
+            &self,
+            request: tonic::Request<super::UpdateRetainRequest>,
+        ) -> Result<tonic::Response<super::UpdateRetainResponse>, tonic::Status>;
+        /// 拉取对等节点上所有的保留消息，用于节点启动和断线后的反熵
+        async fn sync_retain(
+            &self,
+            request: tonic::Request<super::SyncRetainRequest>,
+        ) -> Result<tonic::Response<super::SyncRetainResponse>, tonic::Status>;
+        /// 同步禁止连接的客户端
+        async fn update_ban(
+            &self,
+            request: tonic::Request<super::UpdateBanRequest>,
+        ) -> Result<tonic::Response<super::UpdateBanResponse>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct GeckoPeerServer<T: GeckoPeer> {
@@ -171,25 +637,25 @@ pub mod gecko_peer_server {
         fn call(&mut self, req: http::Request<B>) -> Self::Future {
             let inner = self.inner.clone();
             match req.uri().path() {
-                "/peer.GeckoPeer/TransferPacket" => {
+                "/peer.GeckoPeer/ForwardPublish" => {
                     #[allow(non_camel_case_types)]
-                    struct TransferPacketSvc<T: GeckoPeer>(pub Arc<T>);
+                    struct ForwardPublishSvc<T: GeckoPeer>(pub Arc<T>);
                     impl<
                         T: GeckoPeer,
-                    > tonic::server::UnaryService<super::TransferPacketRequest>
-                    for TransferPacketSvc<T> {
-                        type Response = super::TransferPacketResponse;
+                    > tonic::server::UnaryService<super::ForwardPublishRequest>
+                    for ForwardPublishSvc<T> {
+                        type Response = super::ForwardPublishResponse;
                         type Future = BoxFuture<
                             tonic::Response<Self::Response>,
                             tonic::Status,
                         >;
                         fn call(
                             &mut self,
-                            request: tonic::Request<super::TransferPacketRequest>,
+                            request: tonic::Request<super::ForwardPublishRequest>,
                         ) -> Self::Future {
                             let inner = self.0.clone();
                             let fut = async move {
-                                (*inner).transfer_packet(request).await
+                                (*inner).forward_publish(request).await
                             };
                             Box::pin(fut)
                         }
@@ -199,7 +665,361 @@ pub mod gecko_peer_server {
                     let inner = self.inner.clone();
                     let fut = async move {
                         let inner = inner.0;
-                        let method = TransferPacketSvc(inner);
+                        let method = ForwardPublishSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/peer.GeckoPeer/ForwardPublishStream" => {
+                    #[allow(non_camel_case_types)]
+                    struct ForwardPublishStreamSvc<T: GeckoPeer>(pub Arc<T>);
+                    impl<
+                        T: GeckoPeer,
+                    > tonic::server::ClientStreamingService<super::ForwardPublishBatch>
+                    for ForwardPublishStreamSvc<T> {
+                        type Response = super::ForwardPublishResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                tonic::Streaming<super::ForwardPublishBatch>,
+                            >,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).forward_publish_stream(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ForwardPublishStreamSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.client_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/peer.GeckoPeer/UpdateRoute" => {
+                    #[allow(non_camel_case_types)]
+                    struct UpdateRouteSvc<T: GeckoPeer>(pub Arc<T>);
+                    impl<
+                        T: GeckoPeer,
+                    > tonic::server::UnaryService<super::UpdateRouteRequest>
+                    for UpdateRouteSvc<T> {
+                        type Response = super::UpdateRouteResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::UpdateRouteRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).update_route(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = UpdateRouteSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/peer.GeckoPeer/Ping" => {
+                    #[allow(non_camel_case_types)]
+                    struct PingSvc<T: GeckoPeer>(pub Arc<T>);
+                    impl<T: GeckoPeer> tonic::server::UnaryService<super::PingRequest>
+                    for PingSvc<T> {
+                        type Response = super::PingResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PingRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).ping(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = PingSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/peer.GeckoPeer/UpdateSession" => {
+                    #[allow(non_camel_case_types)]
+                    struct UpdateSessionSvc<T: GeckoPeer>(pub Arc<T>);
+                    impl<
+                        T: GeckoPeer,
+                    > tonic::server::UnaryService<super::UpdateSessionRequest>
+                    for UpdateSessionSvc<T> {
+                        type Response = super::UpdateSessionResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::UpdateSessionRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).update_session(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = UpdateSessionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/peer.GeckoPeer/TakeoverSession" => {
+                    #[allow(non_camel_case_types)]
+                    struct TakeoverSessionSvc<T: GeckoPeer>(pub Arc<T>);
+                    impl<
+                        T: GeckoPeer,
+                    > tonic::server::UnaryService<super::TakeoverSessionRequest>
+                    for TakeoverSessionSvc<T> {
+                        type Response = super::TakeoverSessionResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::TakeoverSessionRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).takeover_session(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = TakeoverSessionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/peer.GeckoPeer/ReleaseSession" => {
+                    #[allow(non_camel_case_types)]
+                    struct ReleaseSessionSvc<T: GeckoPeer>(pub Arc<T>);
+                    impl<
+                        T: GeckoPeer,
+                    > tonic::server::UnaryService<super::ReleaseSessionRequest>
+                    for ReleaseSessionSvc<T> {
+                        type Response = super::ReleaseSessionResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ReleaseSessionRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).release_session(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ReleaseSessionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/peer.GeckoPeer/UpdateRetain" => {
+                    #[allow(non_camel_case_types)]
+                    struct UpdateRetainSvc<T: GeckoPeer>(pub Arc<T>);
+                    impl<
+                        T: GeckoPeer,
+                    > tonic::server::UnaryService<super::UpdateRetainRequest>
+                    for UpdateRetainSvc<T> {
+                        type Response = super::UpdateRetainResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::UpdateRetainRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).update_retain(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = UpdateRetainSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/peer.GeckoPeer/SyncRetain" => {
+                    #[allow(non_camel_case_types)]
+                    struct SyncRetainSvc<T: GeckoPeer>(pub Arc<T>);
+                    impl<
+                        T: GeckoPeer,
+                    > tonic::server::UnaryService<super::SyncRetainRequest>
+                    for SyncRetainSvc<T> {
+                        type Response = super::SyncRetainResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::SyncRetainRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).sync_retain(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = SyncRetainSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/peer.GeckoPeer/UpdateBan" => {
+                    #[allow(non_camel_case_types)]
+                    struct UpdateBanSvc<T: GeckoPeer>(pub Arc<T>);
+                    impl<
+                        T: GeckoPeer,
+                    > tonic::server::UnaryService<super::UpdateBanRequest>
+                    for UpdateBanSvc<T> {
+                        type Response = super::UpdateBanResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::UpdateBanRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).update_ban(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = UpdateBanSvc(inner);
                         let codec = tonic::codec::ProstCodec::default();
                         let mut grpc = tonic::server::Grpc::new(codec)
                             .apply_compression_config(
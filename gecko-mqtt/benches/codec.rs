@@ -0,0 +1,144 @@
+//! 编解码吞吐量基准测试
+//!
+//! cargo bench -p gecko-mqtt --features bench --bench codec
+
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use gecko_mqtt::codec::{self, ByteStr, Packet, Publish, PublishProperties, QoS};
+
+const PAYLOAD_SIZES: [usize; 3] = [16, 1024, 64 * 1024];
+/// 分发时较大的报文体不拷贝到写缓冲区，只比较会拷贝的大小
+const FANOUT_PAYLOAD_SIZES: [usize; 2] = [16, 1024];
+const TOPIC: &str = "devices/7d1e2f5a/telemetry/temperature";
+
+fn publish(payload_size: usize) -> Publish {
+    Publish {
+        dup: false,
+        qos: QoS::AtLeastOnce,
+        retain: false,
+        topic: ByteStr::from(TOPIC),
+        packet_id: 1,
+        payload: Bytes::from(vec![0x5a; payload_size]),
+        properties: None,
+        encoded: Default::default(),
+    }
+}
+
+/// 连续编码的多个 publish 报文
+fn encoded(payload_size: usize, count: usize) -> BytesMut {
+    let packet = Packet::Publish(publish(payload_size));
+    let mut stream = BytesMut::new();
+    for _ in 0..count {
+        codec::write(&packet, &mut stream).unwrap();
+    }
+    stream
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_publish");
+    for size in PAYLOAD_SIZES {
+        let stream = encoded(size, 100);
+        group.throughput(Throughput::Bytes(stream.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &stream, |b, stream| {
+            // 拷贝输入不计入耗时
+            b.iter_batched(
+                || stream.clone(),
+                |mut stream| {
+                    while !stream.is_empty() {
+                        black_box(codec::read(&mut stream).unwrap());
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_publish");
+    for size in PAYLOAD_SIZES {
+        let packet = Packet::Publish(publish(size));
+        let mut stream = BytesMut::with_capacity(size * 2);
+        group.throughput(Throughput::Bytes(encoded(size, 1).len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &packet, |b, packet| {
+            b.iter(|| {
+                stream.clear();
+                codec::write(black_box(packet), &mut stream).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+/// 带属性的 v5 消息
+fn publish_v5(payload_size: usize) -> Publish {
+    Publish {
+        properties: Some(Arc::new(PublishProperties {
+            message_expiry_interval: Some(60),
+            content_type: Some("application/json".into()),
+            user_properties: vec![("trace-id".into(), "6f1c2a9e".into())],
+            ..Default::default()
+        })),
+        ..publish(payload_size)
+    }
+}
+
+/// 一条 v5 消息分发给多个订阅者，每个订阅者分配各自的 packet id 并编码
+/// * unshared：每个订阅者重新编码属性和 payload
+/// * shared：属性和 payload 只编码一次，每个订阅者只编码固定头、主题和 packet id
+fn fanout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fanout_publish_v5");
+    let subscribers = 100;
+    group.throughput(Throughput::Elements(subscribers));
+    for size in FANOUT_PAYLOAD_SIZES {
+        let mut stream = BytesMut::with_capacity(size * 2);
+        group.bench_with_input(BenchmarkId::new("unshared", size), &size, |b, &size| {
+            let publish = publish_v5(size);
+            b.iter(|| {
+                for packet_id in 1..=subscribers as u16 {
+                    let mut publish = publish.clone();
+                    publish.packet_id = packet_id;
+                    stream.clear();
+                    codec::write_v5_unshared(black_box(&publish), &mut stream).unwrap();
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("shared", size), &size, |b, &size| {
+            b.iter_batched(
+                // 每条新消息都有自己的缓存，第一个订阅者负责编码
+                || publish_v5(size),
+                |publish| {
+                    for packet_id in 1..=subscribers as u16 {
+                        let mut publish = publish.clone();
+                        publish.packet_id = packet_id;
+                        stream.clear();
+                        codec::write_v5(black_box(&publish), &mut stream).unwrap();
+                    }
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// 主题解码：拷贝为 String 与共享报文内存对比
+fn topic(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_topic");
+    let bytes = Bytes::from_static(TOPIC.as_bytes());
+    group.bench_function("copy", |b| {
+        b.iter(|| String::from_utf8(black_box(&bytes).to_vec()).unwrap())
+    });
+    group.bench_function("view", |b| {
+        b.iter(|| ByteStr::from_utf8(black_box(&bytes).clone()).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode, encode, fanout, topic);
+criterion_main!(benches);
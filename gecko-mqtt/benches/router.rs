@@ -0,0 +1,176 @@
+//! router 吞吐量基准测试，比较不同 worker 数量下的 publish 转发吞吐量
+//!
+//! 每对客户端一个发布者、一个订阅者，各自使用不同的 topic，
+//! 发布者每批发送 BATCH 条消息，等订阅者全部收到后再发送下一批
+//!
+//! cargo bench -p gecko-mqtt --features bench --bench router
+
+use std::thread;
+
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::future;
+use gecko_mqtt::{
+    broker::Broker,
+    codec::{self, ByteStr, Packet, Publish, QoS},
+    config,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    runtime::Runtime,
+    time::Instant,
+};
+
+/// 客户端对数
+const PAIRS: usize = 16;
+/// 每批发送的消息数，小于连接的消息队列长度，不会触发慢消费者策略
+const BATCH: usize = 100;
+const PAYLOAD_SIZE: usize = 64;
+
+/// 启动一个使用 workers 个 worker 的 broker，返回客户端端口
+fn start_broker(runtime: &Runtime, workers: usize) -> u16 {
+    let broker = runtime
+        .block_on(
+            Broker::builder()
+                .broker(config::Broker {
+                    workers,
+                    ..Default::default()
+                })
+                .bind("127.0.0.1:0")
+                .peer_bind("127.0.0.1:0")
+                .build(),
+        )
+        .unwrap();
+    let port = broker.local_addrs()[0].port();
+    runtime.spawn(broker.start());
+    port
+}
+
+fn encode_str(stream: &mut Vec<u8>, s: &str) {
+    stream.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    stream.extend_from_slice(s.as_bytes());
+}
+
+fn frame(byte1: u8, body: Vec<u8>) -> Vec<u8> {
+    // 报文都小于 128 字节，剩余长度只占一个字节
+    let mut packet = vec![byte1, body.len() as u8];
+    packet.extend(body);
+    packet
+}
+
+async fn connect(port: u16, client_id: &str) -> TcpStream {
+    // 端口在 build 时已绑定，broker 启动前的连接在 backlog 中等待
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut body = Vec::new();
+    encode_str(&mut body, "MQTT");
+    body.extend_from_slice(&[4, 0x02, 0, 0]);
+    encode_str(&mut body, client_id);
+    stream.write_all(&frame(0x10, body)).await.unwrap();
+    let mut connack = [0; 4];
+    stream.read_exact(&mut connack).await.unwrap();
+    assert_eq!(connack, [0x20, 2, 0, 0]);
+    stream
+}
+
+async fn subscribe(stream: &mut TcpStream, filter: &str) {
+    let mut body = vec![0, 1];
+    encode_str(&mut body, filter);
+    body.push(0);
+    stream.write_all(&frame(0x82, body)).await.unwrap();
+    let mut suback = [0; 5];
+    stream.read_exact(&mut suback).await.unwrap();
+}
+
+/// 一对客户端，发布者的消息只有对应的订阅者收到
+struct Pair {
+    publisher: TcpStream,
+    subscriber: TcpStream,
+    /// 一批 publish 报文
+    batch: Bytes,
+}
+
+impl Pair {
+    async fn new(port: u16, index: usize) -> Self {
+        let topic = format!("bench/{}/telemetry", index);
+        let mut subscriber = connect(port, &format!("sub-{}", index)).await;
+        subscribe(&mut subscriber, &topic).await;
+        let publisher = connect(port, &format!("pub-{}", index)).await;
+
+        let packet = Packet::Publish(Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            topic: ByteStr::from(topic),
+            packet_id: 0,
+            payload: Bytes::from(vec![0x5a; PAYLOAD_SIZE]),
+            properties: None,
+            encoded: Default::default(),
+        });
+        let mut batch = BytesMut::new();
+        for _ in 0..BATCH {
+            codec::write(&packet, &mut batch).unwrap();
+        }
+        Self {
+            publisher,
+            subscriber,
+            batch: batch.freeze(),
+        }
+    }
+
+    /// 发送一批消息，等待订阅者全部收到
+    async fn round_trip(&mut self, buf: &mut [u8]) {
+        self.publisher.write_all(&self.batch).await.unwrap();
+        let mut received = 0;
+        while received < self.batch.len() {
+            received += self.subscriber.read(buf).await.unwrap();
+        }
+    }
+}
+
+fn fanout(c: &mut Criterion) {
+    let cores = thread::available_parallelism().map_or(1, usize::from);
+    let mut worker_counts = vec![1, 2, 4, cores];
+    worker_counts.retain(|&n| n <= cores);
+    worker_counts.dedup();
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("router_publish");
+    group.throughput(Throughput::Elements((PAIRS * BATCH) as u64));
+    group.sample_size(20);
+    for workers in worker_counts {
+        let port = start_broker(&runtime, workers);
+        let mut pairs = runtime.block_on(future::join_all((0..PAIRS).map(|i| Pair::new(port, i))));
+
+        group.bench_with_input(BenchmarkId::new("workers", workers), &workers, |b, _| {
+            b.iter_custom(|iters| {
+                runtime.block_on(async {
+                    // 每对客户端一个任务，客户端不成为瓶颈
+                    let start = Instant::now();
+                    let tasks = pairs.drain(..).map(|mut pair| {
+                        tokio::spawn(async move {
+                            let mut buf = vec![0; 64 * 1024];
+                            for _ in 0..iters {
+                                pair.round_trip(&mut buf).await;
+                            }
+                            pair
+                        })
+                    });
+                    let finished = future::join_all(tasks.collect::<Vec<_>>()).await;
+                    let elapsed = start.elapsed();
+                    pairs.extend(finished.into_iter().map(Result::unwrap));
+                    elapsed
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fanout);
+criterion_main!(benches);
@@ -0,0 +1,429 @@
+//! 禁止连接的客户端
+//! * 按 client id、用户名或来源地址（IP/CIDR）禁止，可以设置过期时间
+//! * 客户端连接时在认证之前检查，被禁止的 v5 客户端收到 Banned，3.1/3.1.1 收到 NotAuthorized
+//! * 通过管理接口增删，router 断开匹配的连接并同步给对等节点，节点上线时同步全部
+//! * 每条规则带版本，删除后保留删除标记，合并时版本大的生效，离线期间删除的规则不会被重新上线的节点恢复
+//! * 配置了 [`config::Broker::ban_file`] 时，每次变更后写入文件，启动时加载
+//!
+//! [`config::Broker::ban_file`]: crate::config::Broker::ban_file
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use gecko_mqtt_proto as proto;
+use parking_lot::RwLock;
+use tokio::fs;
+
+use crate::cluster::NodeId;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Read ban file {0} error: {1}")]
+    Read(String, io::Error),
+    #[error("Write ban file {0} error: {1}")]
+    Write(String, io::Error),
+    #[error("Parse ban file error: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Serialize ban list error: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Empty ban value")]
+    EmptyValue,
+    #[error("Invalid ban addr: {0}")]
+    InvalidAddr(String),
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BanKind {
+    ClientId,
+    Username,
+    /// 来源地址，IP 或 CIDR
+    Addr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Ban {
+    pub kind: BanKind,
+    pub value: String,
+    /// 过期时间戳（秒），None 表示永久
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+    #[serde(default)]
+    pub reason: String,
+    /// 添加时间戳（秒）
+    #[serde(default)]
+    pub created_at: u64,
+    /// 每次添加或删除加一
+    #[serde(default)]
+    pub version: u64,
+    /// 最后一次修改的节点
+    #[serde(default)]
+    pub writer: NodeId,
+}
+
+impl Ban {
+    fn expired(&self, now: u64) -> bool {
+        self.until.is_some_and(|until| until <= now)
+    }
+
+    fn newer_than(&self, other: &Ban) -> bool {
+        (self.version, self.writer) > (other.version, other.writer)
+    }
+}
+
+/// 持久化文件的格式
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct BanFile {
+    #[serde(default)]
+    bans: Vec<Ban>,
+    /// 删除标记
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    deleted: Vec<Ban>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    ban: Ban,
+    /// 来源地址的规则，kind 为 Addr 且没有删除时存在
+    cidr: Option<Cidr>,
+    deleted: bool,
+}
+
+impl Entry {
+    fn active(&self, now: u64) -> bool {
+        !self.deleted && !self.ban.expired(now)
+    }
+}
+
+/// 禁止连接的客户端，连接、router 和管理接口共享
+#[derive(Debug, Default)]
+pub(crate) struct Bans {
+    entries: RwLock<HashMap<(BanKind, String), Entry>>,
+}
+
+impl Bans {
+    /// 从文件加载，文件不存在时为空
+    pub async fn load(path: &str) -> Result<Self, Error> {
+        let s = match fs::read_to_string(path).await {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(Error::Read(path.into(), e)),
+        };
+        let file: BanFile = toml::from_str(&s)?;
+        let bans = Self::default();
+        for ban in file.bans {
+            bans.merge(ban, false)?;
+        }
+        for ban in file.deleted {
+            bans.merge(ban, true)?;
+        }
+        Ok(bans)
+    }
+
+    /// 写入文件，先写临时文件再替换，避免写到一半时进程退出
+    pub async fn save(&self, path: &str) -> Result<(), Error> {
+        let (bans, deleted) = self.snapshot();
+        let s = toml::to_string(&BanFile { bans, deleted })?;
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, s)
+            .await
+            .map_err(|e| Error::Write(tmp.clone(), e))?;
+        fs::rename(&tmp, path)
+            .await
+            .map_err(|e| Error::Write(path.into(), e))
+    }
+
+    /// 本节点添加或替换，同时清理已过期的
+    /// 返回需要同步给对等节点的规则
+    pub fn insert(&self, mut ban: Ban, node_id: NodeId) -> Result<Ban, Error> {
+        validate(&ban)?;
+        let mut entries = self.entries.write();
+        let version = entries
+            .get(&(ban.kind, ban.value.clone()))
+            .map_or(0, |entry| entry.ban.version);
+        ban.version = version + 1;
+        ban.writer = node_id;
+        put(&mut entries, ban.clone(), false);
+        Ok(ban)
+    }
+
+    /// 本节点删除，保留删除标记，不存在时返回 None
+    /// 返回需要同步给对等节点的删除标记
+    pub fn remove(&self, kind: BanKind, value: &str, node_id: NodeId) -> Option<Ban> {
+        let mut entries = self.entries.write();
+        let entry = entries
+            .get_mut(&(kind, value.to_string()))
+            .filter(|entry| !entry.deleted)?;
+        entry.ban.version += 1;
+        entry.ban.writer = node_id;
+        entry.cidr = None;
+        entry.deleted = true;
+        Some(entry.ban.clone())
+    }
+
+    /// 合并对等节点同步的规则或删除标记，比本地的版本新时替换
+    /// 返回本地是否有变化
+    pub fn merge(&self, ban: Ban, deleted: bool) -> Result<bool, Error> {
+        validate(&ban)?;
+        let mut entries = self.entries.write();
+        if let Some(entry) = entries.get(&(ban.kind, ban.value.clone())) {
+            if !ban.newer_than(&entry.ban) {
+                return Ok(false);
+            }
+        }
+        put(&mut entries, ban, deleted);
+        Ok(true)
+    }
+
+    /// 没有过期的，按类型和值排序
+    pub fn list(&self) -> Vec<Ban> {
+        self.snapshot().0
+    }
+
+    /// 没有过期的规则和删除标记，用于同步给对等节点和持久化
+    pub fn snapshot(&self) -> (Vec<Ban>, Vec<Ban>) {
+        let now = now();
+        let mut bans = Vec::new();
+        let mut deleted = Vec::new();
+        for entry in self.entries.read().values() {
+            if entry.ban.expired(now) {
+                continue;
+            }
+            match entry.deleted {
+                true => deleted.push(entry.ban.clone()),
+                false => bans.push(entry.ban.clone()),
+            }
+        }
+        bans.sort_by(|a, b| (a.kind, &a.value).cmp(&(b.kind, &b.value)));
+        deleted.sort_by(|a, b| (a.kind, &a.value).cmp(&(b.kind, &b.value)));
+        (bans, deleted)
+    }
+
+    /// 客户端是否被禁止，ip 为连接的来源地址，没有时不检查地址规则
+    pub fn banned(&self, client_id: &str, username: Option<&str>, ip: Option<IpAddr>) -> bool {
+        let entries = self.entries.read();
+        if entries.is_empty() {
+            return false;
+        }
+        let now = now();
+        let active = |kind, value: &str| {
+            entries
+                .get(&(kind, value.to_string()))
+                .is_some_and(|entry| entry.active(now))
+        };
+        if active(BanKind::ClientId, client_id) {
+            return true;
+        }
+        if username.is_some_and(|username| active(BanKind::Username, username)) {
+            return true;
+        }
+        let Some(ip) = ip else {
+            return false;
+        };
+        entries.values().any(|entry| match &entry.cidr {
+            Some(cidr) => entry.active(now) && cidr.contains(ip),
+            None => false,
+        })
+    }
+}
+
+/// 连接来源地址中的 ip，自定义连接等不是 ip:port 的地址没有 ip
+pub fn addr_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<SocketAddr>().ok().map(|addr| addr.ip())
+}
+
+/// 写入规则或删除标记，同时清理已过期的
+fn put(entries: &mut HashMap<(BanKind, String), Entry>, ban: Ban, deleted: bool) {
+    let cidr = match ban.kind {
+        BanKind::Addr if !deleted => Cidr::parse(&ban.value),
+        _ => None,
+    };
+    let now = now();
+    entries.retain(|_, entry| !entry.ban.expired(now));
+    entries.insert((ban.kind, ban.value.clone()), Entry { ban, cidr, deleted });
+}
+
+/// 校验值，addr 必须是 IP 或 CIDR
+pub(crate) fn validate(ban: &Ban) -> Result<(), Error> {
+    if ban.value.is_empty() {
+        return Err(Error::EmptyValue);
+    }
+    if ban.kind == BanKind::Addr && Cidr::parse(&ban.value).is_none() {
+        return Err(Error::InvalidAddr(ban.value.clone()));
+    }
+    Ok(())
+}
+
+/// 当前时间戳（秒）
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+pub(crate) fn to_proto(ban: &Ban) -> proto::Ban {
+    let kind = match ban.kind {
+        BanKind::ClientId => proto::BanKind::BanClientId,
+        BanKind::Username => proto::BanKind::BanUsername,
+        BanKind::Addr => proto::BanKind::BanAddr,
+    };
+    proto::Ban {
+        kind: kind as i32,
+        value: ban.value.clone(),
+        until: ban.until.unwrap_or(0),
+        reason: ban.reason.clone(),
+        created_at: ban.created_at,
+        version: ban.version,
+        writer: ban.writer,
+    }
+}
+
+pub(crate) fn from_proto(ban: proto::Ban) -> Ban {
+    Ban {
+        kind: kind_from_proto(ban.kind()),
+        until: (ban.until > 0).then_some(ban.until),
+        value: ban.value,
+        reason: ban.reason,
+        created_at: ban.created_at,
+        version: ban.version,
+        writer: ban.writer,
+    }
+}
+
+pub(crate) fn kind_from_proto(kind: proto::BanKind) -> BanKind {
+    match kind {
+        proto::BanKind::BanClientId => BanKind::ClientId,
+        proto::BanKind::BanUsername => BanKind::Username,
+        proto::BanKind::BanAddr => BanKind::Addr,
+    }
+}
+
+/// IP 地址段，没有前缀长度时只匹配这一个地址
+#[derive(Debug, Clone, Copy)]
+struct Cidr {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse().ok()?)),
+            None => (s.parse().ok()?, None),
+        };
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Self { addr, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ban(kind: BanKind, value: &str, until: Option<u64>) -> Ban {
+        Ban {
+            kind,
+            value: value.into(),
+            until,
+            reason: String::new(),
+            created_at: 0,
+            version: 0,
+            writer: 0,
+        }
+    }
+
+    #[test]
+    fn banned_by_client_id_username_and_cidr() {
+        let bans = Bans::default();
+        bans.insert(ban(BanKind::ClientId, "dev-1", None), 1)
+            .unwrap();
+        bans.insert(ban(BanKind::Username, "spam", None), 1)
+            .unwrap();
+        bans.insert(ban(BanKind::Addr, "10.1.0.0/16", None), 1)
+            .unwrap();
+        bans.insert(ban(BanKind::Addr, "::1", None), 1).unwrap();
+        // 已过期的不生效
+        bans.insert(ban(BanKind::ClientId, "dev-2", Some(1)), 1)
+            .unwrap();
+
+        assert!(bans.banned("dev-1", None, None));
+        assert!(bans.banned("x", Some("spam"), addr_ip("127.0.0.1:1")));
+        assert!(bans.banned("x", None, addr_ip("10.1.200.3:5000")));
+        assert!(bans.banned("x", None, addr_ip("[::ffff:10.1.0.1]:5000")));
+        assert!(bans.banned("x", None, addr_ip("[::1]:5000")));
+        assert!(!bans.banned("x", None, addr_ip("10.2.0.1:5000")));
+        assert!(!bans.banned("dev-2", None, None));
+        // 自定义连接没有 ip，不检查地址规则
+        assert_eq!(addr_ip("incoming"), None);
+        assert_eq!(bans.list().len(), 4);
+
+        assert!(bans.remove(BanKind::ClientId, "dev-1", 1).is_some());
+        assert!(bans.remove(BanKind::ClientId, "dev-1", 1).is_none());
+        assert!(!bans.banned("dev-1", None, None));
+        assert!(bans
+            .insert(ban(BanKind::Addr, "10.0.0.0/33", None), 1)
+            .is_err());
+    }
+
+    /// 模拟两个节点互相同步全部规则
+    fn sync(from: &Bans, to: &Bans) {
+        let (bans, deleted) = from.snapshot();
+        for ban in bans {
+            to.merge(ban, false).unwrap();
+        }
+        for ban in deleted {
+            to.merge(ban, true).unwrap();
+        }
+    }
+
+    #[test]
+    fn deleted_while_peer_down_not_resurrected() {
+        let node1 = Bans::default();
+        let node2 = Bans::default();
+        let added = node1
+            .insert(ban(BanKind::ClientId, "dev-1", None), 1)
+            .unwrap();
+        node2.merge(added, false).unwrap();
+        assert!(node2.banned("dev-1", None, None));
+
+        // node2 离线期间 node1 删除，node2 重新上线后双方互相同步
+        node1.remove(BanKind::ClientId, "dev-1", 1).unwrap();
+        sync(&node2, &node1);
+        sync(&node1, &node2);
+        assert!(!node1.banned("dev-1", None, None));
+        assert!(!node2.banned("dev-1", None, None));
+        assert!(node1.list().is_empty());
+        assert!(node2.list().is_empty());
+
+        // 删除后重新添加，版本更大，可以覆盖删除标记
+        let added = node2
+            .insert(ban(BanKind::ClientId, "dev-1", None), 2)
+            .unwrap();
+        assert!(node1.merge(added, false).unwrap());
+        assert!(node1.banned("dev-1", None, None));
+    }
+}
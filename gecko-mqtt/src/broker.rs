@@ -1,20 +1,37 @@
-use std::sync::Arc;
+use std::{io, net::SocketAddr, sync::Arc, thread, time::Duration};
 
-use futures::{FutureExt, TryFutureExt};
-use log::{debug, error, info};
+use futures::{future, FutureExt, TryFutureExt};
+use log::{debug, error, info, warn};
 use tokio::{
     net::TcpListener,
-    sync::mpsc::{self, Sender},
+    select,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        watch,
+    },
+    time,
 };
+use tokio_stream::wrappers::TcpListenerStream;
+
+pub use builder::BrokerBuilder;
 
 use crate::{
+    ban::{self, Bans},
+    cluster::EtcdManager,
     config::Config,
-    network::{conn, ClientEventLoop, PeerConnection},
-    protocol::{router, Incoming, Router},
-    server::PeerServer,
-    Hook, HookNoop,
+    hook::Hooks,
+    local::LocalClients,
+    metrics::{prometheus::Exporter, Metrics},
+    network::{conn, ClientEventLoop, ConnLimiter, ConnOptions, PeerConnection},
+    protocol::{rewrite, router, Incoming, Rewrite, Router, State, Workers},
+    server::{AdminServer, PeerServer},
+    Hook,
 };
 
+use self::builder::Listener;
+
+mod builder;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Router Error: {0}")]
@@ -23,40 +40,250 @@ pub enum Error {
     Grpc(#[from] tonic::transport::Error),
     #[error("Peer conn error: {0}")]
     PeerConn(#[from] conn::Error),
+    #[error("Bind {0} error: {1}")]
+    Bind(String, io::Error),
+    #[error("Load bans error: {0}")]
+    Ban(#[from] ban::Error),
+    #[error("Rewrite rule error: {0}")]
+    Rewrite(#[from] rewrite::Error),
 }
 
 /// 代表一个 mqtts 节点
 pub struct Broker {
     cfg: Config,
+    /// 接收客户端连接的监听器
+    listeners: Vec<Listener>,
+    /// 对等节点 grpc 服务的监听器，没有时在启动时绑定 peer_addr
+    peer_listener: Option<TcpListener>,
+    /// prometheus 指标导出的监听器，没有时在启动时绑定配置中的地址
+    metrics_listener: Option<TcpListener>,
+    hooks: Vec<Arc<dyn Hook>>,
+    /// 启动时合并后的钩子，进程内客户端连接时等待启动后获取
+    hook_tx: watch::Sender<Option<Arc<dyn Hook>>>,
+    /// 在 broker 创建时就建立 router 的消息队列，启动前就可以获取进程内客户端
+    router_tx: Sender<Incoming>,
+    router_rx: Receiver<Incoming>,
+    /// 处理客户端报文的 worker，进程内客户端直接发送报文给 worker
+    workers: Workers,
+    worker_rxs: Vec<Receiver<Incoming>>,
+    /// 本节点的消息统计
+    metrics: Arc<Metrics>,
+    /// 关闭通知
+    shutdown_tx: Arc<watch::Sender<bool>>,
+    /// 关闭完成通知
+    done_tx: watch::Sender<bool>,
 }
 
 impl Broker {
+    /// 使用配置文件创建，启动时才绑定配置中的地址
     pub fn new(cfg: Config) -> Self {
-        Self { cfg }
+        let listeners = vec![Listener::Bind(cfg.broker.client_addr.clone())];
+        Self::with_listeners(cfg, listeners, None, None, Vec::new())
     }
 
-    pub async fn start(self) -> Result<(), Error> {
-        self.start_with_hook(Arc::new(HookNoop)).await
+    pub fn builder() -> BrokerBuilder {
+        BrokerBuilder::new()
     }
 
-    pub async fn start_with_hook(self, hook: Arc<impl Hook>) -> Result<(), Error> {
-        // router 后台协程
+    fn with_listeners(
+        cfg: Config,
+        listeners: Vec<Listener>,
+        peer_listener: Option<TcpListener>,
+        metrics_listener: Option<TcpListener>,
+        hooks: Vec<Arc<dyn Hook>>,
+    ) -> Self {
         let (router_tx, router_rx) = mpsc::channel(1000);
+        // 会话按 worker 数量分片，worker 之间并行处理客户端报文
+        let worker_count = match cfg.broker.workers {
+            0 => thread::available_parallelism().map_or(1, usize::from),
+            n => n,
+        };
+        let (workers, worker_rxs) = Workers::new(worker_count);
+        let (shutdown_tx, _) = watch::channel(false);
+        let (done_tx, _) = watch::channel(false);
+        let (hook_tx, _) = watch::channel(None);
+        Self {
+            cfg,
+            listeners,
+            peer_listener,
+            metrics_listener,
+            hooks,
+            hook_tx,
+            router_tx,
+            router_rx,
+            workers,
+            worker_rxs,
+            metrics: Arc::default(),
+            shutdown_tx: Arc::new(shutdown_tx),
+            done_tx,
+        }
+    }
+
+    /// 进程内客户端，不经过网络直接发布和订阅消息
+    pub fn local_clients(&self) -> LocalClients {
+        LocalClients::new(
+            self.router_tx.clone(),
+            self.workers.clone(),
+            self.hook_tx.subscribe(),
+            self.cfg.broker.slow_consumer,
+            self.metrics.clone(),
+        )
+    }
+
+    /// 已绑定的客户端监听地址，绑定端口 0 时用于获取实际的端口
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(Listener::local_addr)
+            .collect()
+    }
+
+    /// 已绑定的对等节点 grpc 地址
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// 已绑定的 prometheus 指标导出地址
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// 用于关闭 broker 的句柄，需要在 start 之前获取
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shutdown_tx: self.shutdown_tx.clone(),
+            done_rx: self.done_tx.subscribe(),
+        }
+    }
+
+    /// 在已添加的钩子之后再添加一个钩子，然后启动
+    pub async fn start_with_hook(mut self, hook: Arc<impl Hook>) -> Result<(), Error> {
+        self.hooks.push(hook);
+        self.start().await
+    }
+
+    /// 启动 broker，直到通过 [`ShutdownHandle`] 关闭，或者某个后台任务出错
+    pub async fn start(mut self) -> Result<(), Error> {
+        let mut listeners = Vec::with_capacity(self.listeners.len());
+        for listener in self.listeners.drain(..) {
+            listeners.push(listener.bind().await?);
+        }
+        let peer_listener = match self.peer_listener.take() {
+            Some(listener) => listener,
+            None => builder::bind(&self.cfg.broker.peer_addr).await?,
+        };
+        let peer_addr = peer_listener
+            .local_addr()
+            .map_err(|e| Error::Bind(self.cfg.broker.peer_addr.clone(), e))?;
+        let metrics_listener = match (self.metrics_listener.take(), &self.cfg.metrics) {
+            (Some(listener), _) => Some(listener),
+            (None, Some(metrics)) => Some(builder::bind(&metrics.addr).await?),
+            (None, None) => None,
+        };
+        let hook = Arc::new(Hooks::new(std::mem::take(&mut self.hooks)));
+        self.hook_tx.send_replace(Some(hook.clone()));
+        let bans = Arc::new(match &self.cfg.broker.ban_file {
+            Some(path) => Bans::load(path).await?,
+            None => Bans::default(),
+        });
+        let rewrite = Arc::new(Rewrite::new(&self.cfg.rewrite)?);
+
+        // router 后台协程
+        let router_tx = self.router_tx.clone();
         let router_hook = hook.clone();
-        let session_cfg = self.cfg.session.clone();
+
+        // 配置了 etcd 时，由集群管理器同步节点、路由和会话
+        let (manager_tx, manager_handle) = match self.cfg.cluster.etcd.clone() {
+            Some(etcd) => {
+                let (manager_tx, manager_rx) = mpsc::unbounded_channel();
+                let addr = etcd
+                    .advertise_addr
+                    .clone()
+                    .unwrap_or_else(|| format!("http://{}", peer_addr));
+                debug!("start etcd cluster manager");
+                let manager = EtcdManager::new(
+                    etcd,
+                    self.cfg.cluster.node_id,
+                    addr,
+                    manager_rx,
+                    router_tx.clone(),
+                );
+                let manager_handle = tokio::spawn(manager.start());
+                (Some(manager_tx), Some(manager_handle))
+            }
+            None => (None, None),
+        };
+
+        let workers = self.workers.clone();
+        let state = Arc::new(State::new(
+            self.cfg.cluster.node_id,
+            workers.count(),
+            self.metrics.clone(),
+            hook.clone(),
+        ));
+        debug!("start {} router workers", workers.count());
+        let (worker_router_tx, worker_router_rx) = mpsc::unbounded_channel();
+        Workers::start(
+            std::mem::take(&mut self.worker_rxs),
+            state.clone(),
+            worker_router_tx,
+            rewrite,
+        );
 
         debug!("start router loop");
-        let router = Router::new(session_cfg, router_hook, router_rx);
+        let router = Router::new(
+            &self.cfg,
+            router_hook,
+            self.router_rx,
+            manager_tx,
+            state.clone(),
+            worker_router_rx,
+            bans.clone(),
+        );
         let (router_task, router_handle) = router.start().map_err(Error::Router).remote_handle();
         tokio::spawn(router_task);
 
-        // 开启 grpc peer server
+        let shutdown_rx = self.shutdown_tx.subscribe();
+
+        // 开启 prometheus 指标导出，出错时不影响 broker 运行
+        if let (Some(listener), Some(cfg)) = (metrics_listener, &self.cfg.metrics) {
+            debug!("start metrics server");
+            let exporter = Exporter {
+                metrics: self.metrics.clone(),
+                state,
+                router_tx: router_tx.clone(),
+            };
+            let serve = exporter.serve(
+                listener,
+                cfg.path.clone(),
+                wait_shutdown(shutdown_rx.clone()),
+            );
+            tokio::spawn(async move {
+                if let Err(e) = serve.await {
+                    error!("metrics server error: {:#}", e);
+                }
+            });
+        }
+
+        // 开启 grpc peer server，管理接口和对等节点共用一个端口
         let (peer_tx, peer_rx) = mpsc::channel(1000);
-        let grpc_addr = self.cfg.broker.peer_addr.parse().unwrap();
         debug!("start peer server loop");
+        let admin = self
+            .cfg
+            .broker
+            .admin_api
+            .then(|| AdminServer::new_server(&self.cfg.cluster, router_tx.clone()));
         let (grpc_task, grpc_handle) = tonic::transport::Server::builder()
-            .add_service(PeerServer::new_server(peer_tx))
-            .serve(grpc_addr)
+            .add_service(PeerServer::new_server(self.cfg.cluster.node_id, peer_tx))
+            .add_optional_service(admin)
+            .serve_with_incoming_shutdown(
+                TcpListenerStream::new(peer_listener),
+                wait_shutdown(shutdown_rx.clone()),
+            )
             .map_err(Error::Grpc)
             .remote_handle();
         tokio::spawn(grpc_task);
@@ -72,57 +299,648 @@ impl Broker {
 
         // 开启客户端连接监听
         debug!("start client server loop");
-        let (tcp_task, tcp_handle) = self.start_tcp(router_tx, hook).remote_handle();
+        // 每个客户端连接持有一个 drain_tx，全部断开后 drain_rx 返回 None
+        let (drain_tx, mut drain_rx) = mpsc::channel::<()>(1);
+        let options = ConnOptions::new(&self.cfg, self.metrics.clone(), bans);
+        let accepts = listeners.into_iter().map(|listener| {
+            start_listener(
+                listener,
+                options.clone(),
+                router_tx.clone(),
+                workers.clone(),
+                hook.clone(),
+                shutdown_rx.clone(),
+                drain_tx.clone(),
+            )
+        });
+        let (tcp_task, tcp_handle) = future::try_join_all(accepts).map_ok(drop).remote_handle();
         tokio::spawn(tcp_task);
+        drop(drain_tx);
 
-        tokio::try_join!(router_handle, grpc_handle, peer_handle, tcp_handle)?;
+        // 运行直到收到关闭通知，任何一个任务出错都直接退出
+        // 对等节点的 grpc 流不会主动结束，关闭时不等待，随 broker 退出而取消
+        let cluster = async { tokio::try_join!(grpc_handle, peer_handle).map(drop) };
+        let clients = async { tokio::try_join!(router_handle, tcp_handle).map(drop) };
+        tokio::pin!(cluster, clients);
+        select! {
+            result = &mut cluster => return result,
+            result = &mut clients => return result,
+            _ = wait_shutdown(shutdown_rx) => {}
+        }
 
+        // 已停止接收新连接，由 router 断开所有客户端连接并从集群中注销
+        info!("broker shutting down");
+        let _ = router_tx.send(Incoming::Shutdown).await;
+        drop(router_tx);
+        let drained = async {
+            clients.await?;
+            let _ = drain_rx.recv().await;
+            if let Some(manager_handle) = manager_handle {
+                let _ = manager_handle.await;
+            }
+            Ok::<_, Error>(())
+        };
+        let timeout = Duration::from_secs(self.cfg.broker.shutdown_timeout);
+        match time::timeout(timeout, drained).await {
+            Ok(result) => result?,
+            Err(_) => warn!("broker shutdown timed out after {:?}", timeout),
+        }
+        info!("broker shutdown");
+        self.done_tx.send_replace(true);
         Ok(())
     }
+}
+
+/// 关闭 broker 的句柄，可以克隆给多个任务
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    shutdown_tx: Arc<watch::Sender<bool>>,
+    done_rx: watch::Receiver<bool>,
+}
+
+impl ShutdownHandle {
+    /// 通知 broker 关闭，不等待关闭完成
+    pub fn trigger(&self) {
+        self.shutdown_tx.send_replace(true);
+    }
+
+    /// 通知 broker 关闭，等待客户端连接全部断开、集群注销完成，或者超过 shutdown_timeout
+    /// broker 没有启动或已经退出时立即返回
+    pub async fn shutdown(mut self) {
+        self.trigger();
+        while !*self.done_rx.borrow_and_update() {
+            if self.done_rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// 等待关闭通知
+async fn wait_shutdown(mut shutdown_rx: watch::Receiver<bool>) {
+    while !*shutdown_rx.borrow_and_update() {
+        if shutdown_rx.changed().await.is_err() {
+            // broker 已退出，不会再有关闭通知
+            return futures::future::pending().await;
+        }
+    }
+}
+
+/// 接收客户端连接，收到关闭通知或监听器结束后停止
+async fn start_listener(
+    mut listener: Listener,
+    options: ConnOptions,
+    router_tx: Sender<Incoming>,
+    workers: Workers,
+    hook: Arc<impl Hook>,
+    shutdown_rx: watch::Receiver<bool>,
+    drain_tx: Sender<()>,
+) -> Result<(), Error> {
+    // 统计在线连接时使用的监听器名称
+    let name = listener
+        .local_addr()
+        .map_or_else(|| "incoming".to_string(), |addr| addr.to_string());
+    let mut limiter = ConnLimiter::new(&options.limit);
+    loop {
+        // 获取到连接
+        let accepted = select! {
+            accepted = async {
+                limiter.acquire().await;
+                listener.accept().await
+            } => accepted,
+            _ = wait_shutdown(shutdown_rx.clone()) => return Ok(()),
+        };
+        let (stream, addr) = match accepted {
+            Some(Ok((s, a))) => (s, a),
+            Some(Err(_)) => {
+                log::error!("accept tcp stream err");
+                continue;
+            }
+            None => return Ok(()),
+        };
+        info!("new stream comming in: {}", addr);
+        if !limiter.allow(&addr, time::Instant::now()) {
+            warn!("connection rate of {} exceeded, closing", addr);
+            continue;
+        }
+
+        // 事件循环
+        let client_router_tx = router_tx.clone();
+        let client_workers = workers.clone();
+        let client_hook = hook.clone();
+        let client_shutdown_rx = shutdown_rx.clone();
+        let client_drain_tx = drain_tx.clone();
+        let client_options = options.clone();
+        let listener_name = name.clone();
+        tokio::spawn(async move {
+            let metrics = client_options.metrics.clone();
+            let event_loop = ClientEventLoop::new(
+                stream,
+                addr,
+                client_router_tx.clone(),
+                client_workers,
+                client_hook.clone(),
+                client_options,
+            );
+            // 还没有建立会话的连接，关闭时直接断开
+            let event_loop = select! {
+                event_loop = event_loop => event_loop,
+                _ = wait_shutdown(client_shutdown_rx) => return,
+            };
+            match event_loop {
+                Ok(event_loop) => {
+                    let client_id = event_loop.client_id.clone();
+                    let outbound = event_loop.outbound();
+                    let _connection = metrics.connection(&listener_name, event_loop.protocol);
+                    if let Err(e) = event_loop.start().await {
+                        if let Err(e) = client_router_tx
+                            .send(Incoming::Disconnect {
+                                client_id: client_id.clone(),
+                                outbound,
+                            })
+                            .await
+                        {
+                            error!("send disconnect to router channel error {:#}", e);
+                        }
+                        error!("eventloop on client {0} exit error: {1:#}", client_id, e)
+                    }
+                    // 已接受的连接结束，调用断开回调
+                    client_hook.disconnect(&client_id).await;
+                }
+                Err(e) => {
+                    error!("eventloop read first connect packet err: {:#}", e)
+                }
+            }
+            drop(client_drain_tx);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio::{
+        io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use tokio_stream::wrappers::ReceiverStream;
+
+    use crate::{
+        config::SlowConsumer,
+        network::{
+            outbound,
+            packet::{self, v5, Protocol, QoS},
+        },
+    };
+
+    use super::*;
+
+    /// 3.1.1 clean session 连接，返回 connack
+    async fn connect(
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        client_id: &str,
+    ) -> [u8; 4] {
+        connect_as(stream, client_id, None).await
+    }
+
+    async fn connect_as(
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        client_id: &str,
+        username: Option<&str>,
+    ) -> [u8; 4] {
+        let flags = if username.is_some() { 0x82 } else { 0x02 };
+        let mut body = vec![0, 4, b'M', b'Q', b'T', b'T', 4, flags, 0, 60];
+        for s in [Some(client_id), username].into_iter().flatten() {
+            body.extend_from_slice(&(s.len() as u16).to_be_bytes());
+            body.extend_from_slice(s.as_bytes());
+        }
+        let mut packet = vec![0x10, body.len() as u8];
+        packet.extend(body);
+        stream.write_all(&packet).await.unwrap();
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack).await.unwrap();
+        connack
+    }
+
+    #[tokio::test]
+    async fn builder_accepts_tcp_and_custom_streams() {
+        let (stream_tx, stream_rx) = mpsc::channel(1);
+        let broker = Broker::builder()
+            .bind("127.0.0.1:0")
+            .peer_bind("127.0.0.1:0")
+            .incoming(ReceiverStream::new(stream_rx))
+            .build()
+            .await
+            .unwrap();
+        let addr = broker.local_addrs()[0];
+        assert_ne!(addr.port(), 0);
+        assert_ne!(broker.peer_addr().unwrap().port(), 0);
+        let shutdown = broker.shutdown_handle();
+        let running = tokio::spawn(broker.start());
+
+        let mut tcp = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(connect(&mut tcp, "tcp").await, [0x20, 2, 0, 0]);
+        let (mut duplex, server) = io::duplex(1024);
+        stream_tx.send(server).await.unwrap();
+        assert_eq!(connect(&mut duplex, "duplex").await, [0x20, 2, 0, 0]);
+
+        // 关闭后 3.1.1 连接直接断开
+        shutdown.shutdown().await;
+        running.await.unwrap().unwrap();
+        assert_eq!(tcp.read(&mut [0; 8]).await.unwrap(), 0);
+        assert_eq!(duplex.read(&mut [0; 8]).await.unwrap(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn subscribe_right_after_connack() {
+        let broker = Broker::builder()
+            .bind("127.0.0.1:0")
+            .peer_bind("127.0.0.1:0")
+            .build()
+            .await
+            .unwrap();
+        let addr = broker.local_addrs()[0];
+        let shutdown = broker.shutdown_handle();
+        let running = tokio::spawn(broker.start());
+
+        // 收到 connack 后会话已经建立，紧接着的订阅一定有 suback
+        for i in 0..20 {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            assert_eq!(
+                connect(&mut stream, &format!("c{}", i)).await,
+                [0x20, 2, 0, 0]
+            );
+            stream
+                .write_all(&[0x82, 6, 0, 1, 0, 1, b'a', 0])
+                .await
+                .unwrap();
+            let mut suback = [0; 5];
+            stream.read_exact(&mut suback).await.unwrap();
+            assert_eq!(suback, [0x90, 3, 0, 1, 0]);
+        }
+
+        shutdown.shutdown().await;
+        running.await.unwrap().unwrap();
+    }
 
-    async fn start_tcp(
-        self,
-        router_tx: Sender<Incoming>,
-        hook: Arc<impl Hook>,
-    ) -> Result<(), Error> {
-        let listener = TcpListener::bind(&self.cfg.broker.client_addr)
+    #[tokio::test]
+    async fn stale_disconnect_keeps_new_connection() {
+        let broker = Broker::builder()
+            .bind("127.0.0.1:0")
+            .peer_bind("127.0.0.1:0")
+            .build()
             .await
             .unwrap();
+        let addr = broker.local_addrs()[0];
+        let router_tx = broker.router_tx.clone();
+        let shutdown = broker.shutdown_handle();
+        let running = tokio::spawn(broker.start());
+
+        let mut device = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(connect(&mut device, "device").await, [0x20, 2, 0, 0]);
+        // 之前的连接迟到的断开通知不影响当前连接
+        let (_, _, stale) = outbound::channel(SlowConsumer::default(), Arc::default());
+        router_tx
+            .send(Incoming::Disconnect {
+                client_id: "device".into(),
+                outbound: stale,
+            })
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+        device
+            .write_all(&[0x82, 6, 0, 1, 0, 1, b'a', 0])
+            .await
+            .unwrap();
+        let mut suback = [0; 5];
+        device.read_exact(&mut suback).await.unwrap();
+        assert_eq!(suback, [0x90, 3, 0, 1, 0]);
+
+        shutdown.shutdown().await;
+        running.await.unwrap().unwrap();
+    }
+
+    /// 写入一个 v5 报文
+    async fn write_v5(stream: &mut TcpStream, packet: v5::Packet) {
+        let mut buf = BytesMut::new();
+        packet.write(&mut buf).unwrap();
+        stream.write_all(&buf).await.unwrap();
+    }
+
+    /// 读取一个 v5 报文
+    async fn read_v5(stream: &mut TcpStream) -> v5::Packet {
+        read_v5_buf(stream, &mut BytesMut::new()).await
+    }
+
+    /// 读取一个 v5 报文，多读到的数据留在 buf 中
+    async fn read_v5_buf(stream: &mut TcpStream, buf: &mut BytesMut) -> v5::Packet {
         loop {
-            // 获取到连接
-            let (stream, addr) = match listener.accept().await {
-                Ok((s, a)) => (s, a),
-                Err(_) => {
-                    log::error!("accept tcp stream err");
-                    continue;
+            match v5::Packet::read(buf, usize::MAX) {
+                Ok(packet) => return packet,
+                Err(packet::Error::InsufficientBytes(_)) => {
+                    assert_ne!(stream.read_buf(buf).await.unwrap(), 0);
                 }
-            };
-            info!("new stream comming in: {}", addr);
+                Err(e) => panic!("invalid v5 packet: {}", e),
+            }
+        }
+    }
 
-            // 事件循环
-            let client_router_tx = router_tx.clone();
-            let client_hook = hook.clone();
-            tokio::spawn(async move {
-                match ClientEventLoop::new(stream, client_router_tx.clone(), client_hook).await {
-                    Ok(event_loop) => {
-                        let client_id = event_loop.client_id.clone();
-                        if let Err(e) = event_loop.start().await {
-                            if let Err(e) = client_router_tx
-                                .send(Incoming::Disconnect {
-                                    client_id: client_id.clone(),
-                                })
-                                .await
-                            {
-                                error!("send disconnect to router channel error {:#}", e);
-                            }
-                            error!("eventloop on client {0} exit error: {1:#}", client_id, e)
-                        }
-                    }
-                    Err(e) => {
-                        error!("eventloop read first connect packet err: {:#}", e)
-                    }
+    async fn connect_v5(addr: SocketAddr, client_id: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let connect = v5::Connect {
+            protocol: Protocol::V5,
+            keepalive: 60,
+            client_id: client_id.into(),
+            clean_start: true,
+            last_will: None,
+            login: v5::Login {
+                username: None,
+                password: None,
+            },
+            properties: None,
+        };
+        write_v5(&mut stream, v5::Packet::Connect(connect)).await;
+        match read_v5(&mut stream).await {
+            v5::Packet::ConnAck(ack) => assert_eq!(ack.code, v5::ConnectReturnCode::Success),
+            packet => panic!("unexpected {:?}", packet),
+        }
+        stream
+    }
+
+    #[tokio::test]
+    async fn v5_clients_publish_with_properties() {
+        let broker = Broker::builder()
+            .bind("127.0.0.1:0")
+            .peer_bind("127.0.0.1:0")
+            .build()
+            .await
+            .unwrap();
+        let addr = broker.local_addrs()[0];
+        let shutdown = broker.shutdown_handle();
+        let running = tokio::spawn(broker.start());
+
+        let mut subscriber = connect_v5(addr, "subscriber").await;
+        let subscribe = v5::Subscribe {
+            packet_id: 1,
+            filters: vec![v5::SubscribeFilter {
+                filter: "v5/#".into(),
+                qos: QoS::AtLeastOnce,
+                nolocal: false,
+                preserve_retain: false,
+                retain_forward_rule: v5::RetainForwardRule::OnEverySubscribe,
+            }],
+            properties: None,
+        };
+        write_v5(&mut subscriber, v5::Packet::Subscribe(subscribe)).await;
+        match read_v5(&mut subscriber).await {
+            v5::Packet::SubAck(ack) => {
+                assert_eq!(ack.return_codes, [v5::SubscribeReasonCode::QoS1])
+            }
+            packet => panic!("unexpected {:?}", packet),
+        }
+
+        let mut publisher = connect_v5(addr, "publisher").await;
+        let properties = v5::PublishProperties {
+            content_type: Some("text/plain".into()),
+            user_properties: vec![("trace".into(), "t1".into())],
+            ..Default::default()
+        };
+        let publish = v5::Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic: "v5/a".into(),
+            packet_id: 7,
+            properties: Some(properties.clone()),
+            payload: "hello".into(),
+        };
+        write_v5(&mut publisher, v5::Packet::Publish(publish)).await;
+        match read_v5(&mut publisher).await {
+            v5::Packet::PubAck(ack) => {
+                assert_eq!((ack.packet_id, ack.reason), (7, v5::PubAckReason::Success))
+            }
+            packet => panic!("unexpected {:?}", packet),
+        }
+
+        // 订阅者收到发布者设置的属性
+        match read_v5(&mut subscriber).await {
+            v5::Packet::Publish(publish) => {
+                assert_eq!(publish.topic, "v5/a");
+                assert_eq!(publish.payload, "hello");
+                assert_eq!(publish.properties, Some(properties));
+            }
+            packet => panic!("unexpected {:?}", packet),
+        }
+
+        // broker 关闭时 v5 客户端收到原因
+        shutdown.shutdown().await;
+        running.await.unwrap().unwrap();
+        match read_v5(&mut subscriber).await {
+            v5::Packet::Disconnect(disconnect) => assert_eq!(
+                disconnect.reason_code,
+                v5::DisconnectReasonCode::ServerShuttingDown
+            ),
+            packet => panic!("unexpected {:?}", packet),
+        }
+    }
+
+    #[tokio::test]
+    async fn v5_subscribe_retain_handling() {
+        let broker = Broker::builder()
+            .bind("127.0.0.1:0")
+            .peer_bind("127.0.0.1:0")
+            .build()
+            .await
+            .unwrap();
+        let addr = broker.local_addrs()[0];
+        let clients = broker.local_clients();
+        let shutdown = broker.shutdown_handle();
+        let running = tokio::spawn(broker.start());
+
+        let publisher = clients.connect("publisher").await.unwrap();
+        publisher
+            .publish("r/a", "retained", QoS::AtLeastOnce, true)
+            .await
+            .unwrap();
+
+        let mut subscriber = connect_v5(addr, "subscriber").await;
+        let mut buf = BytesMut::new();
+        // 每次订阅后先读 suback，收到保留消息时返回 true
+        // 没有保留消息时下一次订阅的 suback 紧跟在这次的 suback 之后
+        let steps = [
+            ("r/#", v5::RetainForwardRule::Never, false),
+            ("r/a", v5::RetainForwardRule::OnNewSubscribe, true),
+            ("r/a", v5::RetainForwardRule::OnNewSubscribe, false),
+            ("r/a", v5::RetainForwardRule::OnEverySubscribe, true),
+        ];
+        for (packet_id, (filter, rule, retained)) in (1..).zip(steps) {
+            let subscribe = v5::Subscribe {
+                packet_id,
+                filters: vec![v5::SubscribeFilter {
+                    filter: filter.into(),
+                    qos: QoS::AtMostOnce,
+                    nolocal: false,
+                    preserve_retain: false,
+                    retain_forward_rule: rule,
+                }],
+                properties: None,
+            };
+            write_v5(&mut subscriber, v5::Packet::Subscribe(subscribe)).await;
+            match read_v5_buf(&mut subscriber, &mut buf).await {
+                v5::Packet::SubAck(ack) => assert_eq!(ack.packet_id, packet_id),
+                packet => panic!("unexpected {:?} after subscribe {}", packet, packet_id),
+            }
+            if retained {
+                match read_v5_buf(&mut subscriber, &mut buf).await {
+                    v5::Packet::Publish(publish) => assert_eq!(publish.topic, "r/a"),
+                    packet => panic!("unexpected {:?}", packet),
                 }
-            });
+            }
+        }
+
+        shutdown.shutdown().await;
+        running.await.unwrap().unwrap();
+    }
+
+    /// 拒绝 intruder 登录，记录上下线回调
+    #[derive(Default, Clone)]
+    struct DenyUser(Arc<parking_lot::Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl Hook for DenyUser {
+        async fn authenticate(&self, login: crate::Login) -> crate::AuthResult {
+            (login.username.as_deref() != Some("intruder")).into()
+        }
+        async fn connected(&self, client_id: &str) {
+            self.0.lock().push(format!("connected {}", client_id));
+        }
+        async fn disconnect(&self, client_id: &str) {
+            self.0.lock().push(format!("disconnect {}", client_id));
         }
     }
+
+    #[tokio::test]
+    async fn denied_login_keeps_existing_session() {
+        let hook = DenyUser::default();
+        let broker = Broker::builder()
+            .bind("127.0.0.1:0")
+            .peer_bind("127.0.0.1:0")
+            .hook(hook.clone())
+            .build()
+            .await
+            .unwrap();
+        let addr = broker.local_addrs()[0];
+        let shutdown = broker.shutdown_handle();
+        let running = tokio::spawn(broker.start());
+
+        let mut device = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(connect(&mut device, "device").await, [0x20, 2, 0, 0]);
+        let mut intruder = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(
+            connect_as(&mut intruder, "device", Some("intruder")).await,
+            [0x20, 2, 0, 5]
+        );
+        assert_eq!(intruder.read(&mut [0; 8]).await.unwrap(), 0);
+
+        // 已连接的客户端不受影响
+        device.write_all(&[0xC0, 0]).await.unwrap();
+        let mut pingresp = [0; 2];
+        device.read_exact(&mut pingresp).await.unwrap();
+        assert_eq!(pingresp, [0xD0, 0]);
+        // 被拒绝的登录不调用连接回调
+        assert_eq!(*hook.0.lock(), ["connected device"]);
+
+        // 已接受的连接断开后调用断开回调
+        drop(device);
+        for _ in 0..100 {
+            if hook.0.lock().len() == 2 {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(*hook.0.lock(), ["connected device", "disconnect device"]);
+
+        shutdown.shutdown().await;
+        running.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn admin_api_manages_clients() {
+        use gecko_mqtt_proto::admin::{
+            gecko_admin_client::GeckoAdminClient, KickClientRequest, ListClientsRequest,
+            PublishRequest, SubscribeRequest,
+        };
+
+        let broker = Broker::builder()
+            .bind("127.0.0.1:0")
+            .peer_bind("127.0.0.1:0")
+            .build()
+            .await
+            .unwrap();
+        let addr = broker.local_addrs()[0];
+        let peer_addr = broker.peer_addr().unwrap();
+        let shutdown = broker.shutdown_handle();
+        let running = tokio::spawn(broker.start());
+
+        let mut tcp = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(connect(&mut tcp, "device").await, [0x20, 2, 0, 0]);
+        let mut admin = GeckoAdminClient::connect(format!("http://{}", peer_addr))
+            .await
+            .unwrap();
+
+        let clients = admin
+            .list_clients(ListClientsRequest {
+                search: "dev".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .clients;
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].client_id, "device");
+        assert_eq!(clients[0].addr, tcp.local_addr().unwrap().to_string());
+        assert_eq!(clients[0].protocol, 4);
+
+        // 代替客户端订阅，发布的消息投递给客户端
+        let subscribed = admin
+            .subscribe(SubscribeRequest {
+                client_id: "device".into(),
+                filters: vec!["iot/+".into()],
+            })
+            .await
+            .unwrap();
+        assert!(subscribed.into_inner().found);
+        admin
+            .publish(PublishRequest {
+                topic: "iot/a".into(),
+                payload: b"hi".to_vec(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let mut publish = [0; 11];
+        tcp.read_exact(&mut publish).await.unwrap();
+        assert_eq!(&publish, b"\x30\x09\x00\x05iot/ahi");
+
+        let invalid = admin
+            .publish(PublishRequest {
+                topic: "iot/#".into(),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(invalid.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+        let kicked = admin
+            .kick_client(KickClientRequest {
+                client_id: "device".into(),
+            })
+            .await
+            .unwrap();
+        assert!(kicked.into_inner().found);
+        assert_eq!(tcp.read(&mut [0; 8]).await.unwrap(), 0);
+
+        shutdown.shutdown().await;
+        running.await.unwrap().unwrap();
+    }
 }
@@ -0,0 +1,212 @@
+//! 以代码的方式构建 broker，不依赖配置文件
+//! 可以使用已绑定的 TcpListener 或任意字节流接收客户端连接，便于嵌入到其它服务和集成测试中
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+use futures::{stream::BoxStream, Stream, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+
+use crate::{
+    config::{self, Config},
+    network::ClientStream,
+    Hook,
+};
+
+use super::{Broker, Error};
+
+/// 接收客户端连接的监听器
+pub(crate) enum Listener {
+    /// 启动时绑定的地址
+    Bind(String),
+    Tcp(TcpListener),
+    /// 用户提供的连接，如 tls 连接或内存中的 duplex
+    Incoming(BoxStream<'static, ClientStream>),
+}
+
+impl Listener {
+    /// 绑定地址，其它监听器原样返回
+    pub async fn bind(self) -> Result<Self, Error> {
+        match self {
+            Listener::Bind(addr) => Ok(Listener::Tcp(bind(&addr).await?)),
+            listener => Ok(listener),
+        }
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            _ => None,
+        }
+    }
+
+    /// 接收一个连接，返回 None 表示不会再有新的连接
+    pub async fn accept(&mut self) -> Option<io::Result<(ClientStream, String)>> {
+        match self {
+            Listener::Tcp(listener) => Some(
+                listener
+                    .accept()
+                    .await
+                    .map(|(stream, addr)| (stream.into(), addr.to_string())),
+            ),
+            Listener::Incoming(incoming) => incoming
+                .next()
+                .await
+                .map(|stream| Ok((stream, "incoming".into()))),
+            // 启动前已经绑定
+            Listener::Bind(_) => None,
+        }
+    }
+}
+
+pub(crate) async fn bind(addr: &str) -> Result<TcpListener, Error> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| Error::Bind(addr.into(), e))
+}
+
+/// broker 构建器
+///
+/// ```no_run
+/// # async fn run() -> Result<(), gecko_mqtt::broker::Error> {
+/// use gecko_mqtt::broker::Broker;
+///
+/// let broker = Broker::builder()
+///     .bind("127.0.0.1:0")
+///     .peer_bind("127.0.0.1:0")
+///     .build()
+///     .await?;
+/// let addr = broker.local_addrs()[0];
+/// broker.start().await
+/// # }
+/// ```
+#[derive(Default)]
+pub struct BrokerBuilder {
+    cfg: Config,
+    listeners: Vec<Listener>,
+    peer_listener: Option<TcpListener>,
+    hooks: Vec<Arc<dyn Hook>>,
+}
+
+impl BrokerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 使用完整的配置，之后的设置会覆盖其中对应的部分
+    pub fn config(mut self, cfg: Config) -> Self {
+        self.cfg = cfg;
+        self
+    }
+
+    /// broker 配置，没有添加监听器时使用其中的 client_addr
+    pub fn broker(mut self, cfg: config::Broker) -> Self {
+        self.cfg.broker = cfg;
+        self
+    }
+
+    pub fn session(mut self, cfg: config::Session) -> Self {
+        self.cfg.session = cfg;
+        self
+    }
+
+    /// 集群配置，不配置 etcd 时路由和会话表只保存在各节点的内存中，通过对等节点同步
+    pub fn cluster(mut self, cfg: config::Cluster) -> Self {
+        self.cfg.cluster = cfg;
+        self
+    }
+
+    /// 使用 etcd 保存集群的节点、路由和会话表
+    pub fn etcd(mut self, cfg: config::Etcd) -> Self {
+        self.cfg.cluster.etcd = Some(cfg);
+        self
+    }
+
+    /// 通过 http 导出 prometheus 指标
+    pub fn metrics(mut self, cfg: config::Metrics) -> Self {
+        self.cfg.metrics = Some(cfg);
+        self
+    }
+
+    /// 连接和发布的限流
+    pub fn limit(mut self, cfg: config::Limit) -> Self {
+        self.cfg.limit = cfg;
+        self
+    }
+
+    /// 监听客户端连接的地址，可以多次调用监听多个地址
+    pub fn bind(mut self, addr: impl Into<String>) -> Self {
+        self.listeners.push(Listener::Bind(addr.into()));
+        self
+    }
+
+    /// 使用已绑定的 TcpListener 接收客户端连接
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listeners.push(Listener::Tcp(listener));
+        self
+    }
+
+    /// 从字节流的 Stream 中接收客户端连接，Stream 结束后不再接收
+    pub fn incoming<I, S>(mut self, incoming: I) -> Self
+    where
+        I: Stream<Item = S> + Send + 'static,
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        self.listeners
+            .push(Listener::Incoming(incoming.map(ClientStream::new).boxed()));
+        self
+    }
+
+    /// 对等节点 grpc 服务的地址
+    pub fn peer_bind(mut self, addr: impl Into<String>) -> Self {
+        self.cfg.broker.peer_addr = addr.into();
+        self.peer_listener = None;
+        self
+    }
+
+    /// 使用已绑定的 TcpListener 提供对等节点 grpc 服务
+    pub fn peer_listener(mut self, listener: TcpListener) -> Self {
+        self.peer_listener = Some(listener);
+        self
+    }
+
+    /// 添加钩子，多个钩子按 [`Hook::priority`] 调用
+    pub fn hook(mut self, hook: impl Hook) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// 绑定所有地址，之后可以通过 [`Broker::local_addrs`] 获取实际绑定的地址
+    pub async fn build(self) -> Result<Broker, Error> {
+        let Self {
+            cfg,
+            mut listeners,
+            peer_listener,
+            hooks,
+        } = self;
+        if listeners.is_empty() {
+            listeners.push(Listener::Bind(cfg.broker.client_addr.clone()));
+        }
+        let mut bound = Vec::with_capacity(listeners.len());
+        for listener in listeners {
+            bound.push(listener.bind().await?);
+        }
+        let peer_listener = match peer_listener {
+            Some(listener) => listener,
+            None => bind(&cfg.broker.peer_addr).await?,
+        };
+        let metrics_listener = match &cfg.metrics {
+            Some(metrics) => Some(bind(&metrics.addr).await?),
+            None => None,
+        };
+        Ok(Broker::with_listeners(
+            cfg,
+            bound,
+            Some(peer_listener),
+            metrics_listener,
+            hooks,
+        ))
+    }
+}
@@ -8,7 +8,247 @@
 
 // pub(crate) use connection::Connection;
 pub(crate) use dispatcher::Dispatcher;
+pub(crate) use manager::{EtcdManager, ManagerRequest};
+pub(crate) use retain::RetainStore;
+pub(crate) use storage::Storage;
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use gecko_mqtt_proto::{
+    ForwardPublishRequest, InflightPublish, PublishProperties, ReleaseSessionResponse,
+    Subscription, UserProperty,
+};
+
+use crate::{
+    network::{
+        packet::{self, v5},
+        v4::Publish,
+    },
+    protocol::{subscripton::SubscribeOptions, SessionState},
+};
 
 mod dispatcher;
 mod manager;
+mod retain;
 mod storage;
+
+/// 集群节点 id
+pub(crate) type NodeId = u64;
+
+/// 对等节点的状态，由心跳结果决定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NodeStatus {
+    Up,
+    /// 心跳失败，但还没有达到宕机的阈值，路由和会话保留
+    Suspect,
+    Down,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid forward publish: {0}")]
+    InvalidForwardPublish(#[from] packet::Error),
+    #[error("Missing publish in retain message")]
+    MissingRetainPublish,
+}
+
+/// 将 publish 消息封装为节点间转发的请求
+pub(crate) fn forward_request(origin_node_id: NodeId, publish: &Publish) -> ForwardPublishRequest {
+    ForwardPublishRequest {
+        origin_node_id,
+        topic: publish.topic.to_string(),
+        payload: publish.payload.to_vec(),
+        qos: publish.qos as u32,
+        retain: publish.retain,
+        properties: publish.properties.as_deref().map(forward_properties),
+    }
+}
+
+/// v5 消息属性，主题别名和订阅标识符只在单个连接上有效，不转发
+fn forward_properties(properties: &v5::PublishProperties) -> PublishProperties {
+    PublishProperties {
+        payload_format_indicator: properties.payload_format_indicator.map(u32::from),
+        message_expiry_interval: properties.message_expiry_interval,
+        response_topic: properties.response_topic.clone(),
+        correlation_data: properties
+            .correlation_data
+            .as_ref()
+            .map(|data| data.to_vec()),
+        user_properties: properties
+            .user_properties
+            .iter()
+            .map(|(key, value)| UserProperty {
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect(),
+        content_type: properties.content_type.clone(),
+    }
+}
+
+fn forwarded_properties(properties: PublishProperties) -> v5::PublishProperties {
+    v5::PublishProperties {
+        payload_format_indicator: properties
+            .payload_format_indicator
+            .map(|indicator| (indicator != 0) as u8),
+        message_expiry_interval: properties.message_expiry_interval,
+        topic_alias: None,
+        response_topic: properties.response_topic,
+        correlation_data: properties.correlation_data.map(Bytes::from),
+        user_properties: properties
+            .user_properties
+            .into_iter()
+            .map(|property| (property.key, property.value))
+            .collect(),
+        subscription_identifiers: Vec::new(),
+        content_type: properties.content_type,
+    }
+}
+
+/// 从节点间转发的请求中还原 publish 消息
+/// packet_id 由订阅端的 session 重新分配
+pub(crate) fn forwarded_publish(request: ForwardPublishRequest) -> Result<Publish, Error> {
+    let qos = u8::try_from(request.qos).map_err(|_| packet::Error::InvalidQoS(u8::MAX))?;
+    Ok(Publish {
+        dup: false,
+        qos: qos.try_into()?,
+        retain: request.retain,
+        topic: request.topic.into(),
+        packet_id: 0,
+        payload: Bytes::from(request.payload),
+        properties: request
+            .properties
+            .map(|properties| Arc::new(forwarded_properties(properties))),
+        encoded: Default::default(),
+    })
+}
+
+/// 将会话中的订阅封装为迁移会话的响应
+pub(crate) fn subscription(filter: String, options: SubscribeOptions) -> Subscription {
+    Subscription {
+        filter,
+        qos: options.qos as u32,
+        no_local: options.no_local,
+        retain_as_published: options.retain_as_published,
+    }
+}
+
+/// 从迁移会话的响应中还原订阅
+pub(crate) fn subscribed(subscription: Subscription) -> Result<(String, SubscribeOptions), Error> {
+    let qos = u8::try_from(subscription.qos).map_err(|_| packet::Error::InvalidQoS(u8::MAX))?;
+    let options = SubscribeOptions {
+        qos: qos.try_into()?,
+        no_local: subscription.no_local,
+        retain_as_published: subscription.retain_as_published,
+    };
+    Ok((subscription.filter, options))
+}
+
+/// 将会话中未完成的消息封装为迁移会话的响应
+pub(crate) fn release_response(node_id: NodeId, state: SessionState) -> ReleaseSessionResponse {
+    ReleaseSessionResponse {
+        found: true,
+        inflight: state
+            .inflight
+            .iter()
+            .map(|publish| InflightPublish {
+                packet_id: publish.packet_id as u32,
+                publish: Some(forward_request(node_id, publish)),
+            })
+            .collect(),
+        queued: state
+            .queued
+            .iter()
+            .map(|publish| forward_request(node_id, publish))
+            .collect(),
+        received: state.received.into_iter().map(u32::from).collect(),
+        released: state.released.into_iter().map(u32::from).collect(),
+        next_packet_id: state.next_packet_id as u32,
+    }
+}
+
+/// 从迁移会话的响应中还原会话中未完成的消息
+pub(crate) fn released_state(response: ReleaseSessionResponse) -> Result<SessionState, Error> {
+    let mut inflight = Vec::with_capacity(response.inflight.len());
+    for message in response.inflight {
+        if let Some(request) = message.publish {
+            let mut publish = forwarded_publish(request)?;
+            publish.packet_id = message.packet_id as u16;
+            publish.dup = true;
+            inflight.push(publish);
+        }
+    }
+    let queued = response
+        .queued
+        .into_iter()
+        .map(forwarded_publish)
+        .collect::<Result<Vec<Publish>, Error>>()?;
+
+    Ok(SessionState {
+        inflight,
+        queued,
+        received: response.received.into_iter().map(|id| id as u16).collect(),
+        released: response.released.into_iter().map(|id| id as u16).collect(),
+        next_packet_id: response.next_packet_id as u16,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::packet::QoS;
+
+    use super::*;
+
+    #[test]
+    fn forward_keeps_properties() {
+        let properties = v5::PublishProperties {
+            payload_format_indicator: Some(1),
+            message_expiry_interval: Some(60),
+            topic_alias: Some(2),
+            response_topic: Some("reply".into()),
+            correlation_data: Some(Bytes::from("id")),
+            user_properties: vec![("k".into(), "v".into())],
+            subscription_identifiers: vec![3],
+            content_type: Some("json".into()),
+        };
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic: "t".into(),
+            packet_id: 1,
+            payload: Bytes::from("p"),
+            properties: Some(Arc::new(properties.clone())),
+            encoded: Default::default(),
+        };
+
+        let forwarded = forwarded_publish(forward_request(1, &publish)).unwrap();
+        assert_eq!(
+            forwarded.properties.as_deref(),
+            Some(&v5::PublishProperties {
+                topic_alias: None,
+                subscription_identifiers: Vec::new(),
+                ..properties
+            })
+        );
+    }
+
+    #[test]
+    fn takeover_keeps_subscribe_options() {
+        let options = SubscribeOptions {
+            qos: QoS::AtLeastOnce,
+            no_local: true,
+            retain_as_published: false,
+        };
+        let subscription = subscription("cmd/+".into(), options);
+        assert_eq!(subscribed(subscription).unwrap(), ("cmd/+".into(), options));
+
+        let invalid = Subscription {
+            filter: "cmd/+".into(),
+            qos: 3,
+            ..Default::default()
+        };
+        assert!(subscribed(invalid).is_err());
+    }
+}
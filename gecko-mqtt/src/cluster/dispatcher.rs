@@ -1,15 +1,306 @@
 //! 分布层
 
-struct GrpcChannel {}
+use std::{collections::HashMap, sync::Arc};
+
+use gecko_mqtt_proto::{
+    Ban, ForwardPublishRequest, ReleaseSessionRequest, ReleaseSessionResponse, RetainMessage,
+    RouteAction, SyncRetainRequest, SyncRetainResponse, TakeoverSessionRequest,
+    TakeoverSessionResponse, UpdateBanRequest, UpdateRetainRequest, UpdateRouteRequest,
+    UpdateSessionRequest,
+};
+use log::{debug, error, info};
+use tokio::{
+    sync::{
+        mpsc::{error::TrySendError, UnboundedSender},
+        oneshot,
+    },
+    time::Instant,
+};
+
+use crate::{config, metrics::Metrics};
+
+use self::channel::{GrpcChannel, PeerMessage};
+
+/// 对等节点请求的响应，节点不可用时 sender 被丢弃
+pub(crate) type Response<T> = oneshot::Receiver<Result<T, tonic::Status>>;
+
+use super::{ManagerRequest, NodeId, NodeStatus};
+
+mod channel;
 
 /// 借助于集群管理器，维护对所有其他对等节点的 tcp 连接
 pub(crate) struct Dispatcher {
-    // /// 对等节点, key = nodeid
-    // conns: HashMap<usize, GrpcChannel>,
+    cfg: config::Cluster,
+    /// 对等节点地址, key = nodeid
+    peers: HashMap<NodeId, String>,
+    /// 对等节点, key = nodeid
+    /// 懒加载，第一次发送消息时才建立连接
+    conns: HashMap<NodeId, GrpcChannel>,
+    /// 对等节点状态变更时通知 router
+    status_tx: UnboundedSender<(NodeId, NodeStatus)>,
+    /// 配置了集群管理器时，对等节点由管理器发现，路由和会话也通过管理器同步
+    manager_tx: Option<UnboundedSender<ManagerRequest>>,
+    /// 记录转发消息的排队时间
+    metrics: Arc<Metrics>,
 }
 
 impl Dispatcher {
-    pub(crate) fn new() -> Self {
-        Self {}
+    pub(crate) fn new(
+        cfg: &config::Cluster,
+        status_tx: UnboundedSender<(NodeId, NodeStatus)>,
+        manager_tx: Option<UnboundedSender<ManagerRequest>>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let peers = match manager_tx {
+            Some(_) => HashMap::new(),
+            None => cfg
+                .peers
+                .iter()
+                .filter(|peer| peer.id != cfg.node_id)
+                .map(|peer| (peer.id, peer.addr.clone()))
+                .collect(),
+        };
+        Self {
+            cfg: cfg.clone(),
+            peers,
+            conns: HashMap::new(),
+            status_tx,
+            manager_tx,
+            metrics,
+        }
+    }
+
+    pub(crate) fn node_id(&self) -> NodeId {
+        self.cfg.node_id
+    }
+
+    /// 所有对等节点的 id
+    pub(crate) fn peer_ids(&self) -> Vec<NodeId> {
+        self.peers.keys().cloned().collect()
+    }
+
+    /// 所有对等节点的 id 和地址
+    pub(crate) fn peers(&self) -> Vec<(NodeId, String)> {
+        self.peers
+            .iter()
+            .map(|(node_id, addr)| (*node_id, addr.clone()))
+            .collect()
+    }
+
+    /// 获取到对等节点的连接，不存在时创建
+    fn channel(&mut self, node_id: NodeId) -> Option<&GrpcChannel> {
+        if !self.conns.contains_key(&node_id) {
+            let addr = self.peers.get(&node_id)?;
+            // 由集群管理器判断节点状态，不使用心跳的结果
+            let status_tx = match self.manager_tx {
+                Some(_) => None,
+                None => Some(self.status_tx.clone()),
+            };
+            let channel = GrpcChannel::new(
+                node_id,
+                addr.clone(),
+                &self.cfg,
+                status_tx,
+                self.metrics.clone(),
+            );
+            self.conns.insert(node_id, channel);
+        }
+        self.conns.get(&node_id)
+    }
+
+    /// 建立到所有对等节点的连接，开始心跳检测
+    pub(crate) fn connect_peers(&mut self) {
+        for node_id in self.peer_ids() {
+            self.channel(node_id);
+        }
+    }
+
+    /// 集群管理器发现了新的对等节点，地址变化时重新建立连接
+    pub(crate) fn add_peer(&mut self, node_id: NodeId, addr: String) {
+        if self.peers.get(&node_id) == Some(&addr) {
+            return;
+        }
+        info!("peer node {0} added, addr: {1}", node_id, addr);
+        self.conns.remove(&node_id);
+        self.peers.insert(node_id, addr);
+        self.channel(node_id);
+    }
+
+    /// 对等节点已宕机，断开连接
+    pub(crate) fn remove_peer(&mut self, node_id: NodeId) {
+        info!("peer node {0} removed", node_id);
+        self.peers.remove(&node_id);
+        self.conns.remove(&node_id);
+    }
+
+    /// 发送请求给集群管理器
+    fn send_manager(&self, request: ManagerRequest) {
+        if let Some(manager_tx) = &self.manager_tx {
+            if manager_tx.send(request).is_err() {
+                error!("cluster manager closed");
+            }
+        }
+    }
+
+    /// 发送消息给对等节点，不等待
+    /// 节点宕机或者队列已满时丢弃，节点恢复后 router 会重新同步路由、会话、保留消息和禁止规则
+    /// 需要响应的请求被丢弃时，调用方收到节点不可用
+    fn send(&mut self, node_id: NodeId, message: PeerMessage) {
+        let channel = match self.channel(node_id) {
+            Some(channel) => channel,
+            None => {
+                error!("peer node {0} not found", node_id);
+                return;
+            }
+        };
+        if channel.is_down() {
+            self.metrics.cluster_dropped(1);
+            return;
+        }
+        match channel.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                debug!("peer node {0} queue full, message dropped", node_id);
+                self.metrics.cluster_dropped(1);
+            }
+            Err(TrySendError::Closed(_)) => {
+                // 后台任务已退出，下次发送时重新创建
+                error!("peer node {0} channel closed", node_id);
+                self.conns.remove(&node_id);
+            }
+        }
+    }
+
+    /// 将 publish 消息转发给对等节点
+    pub(crate) fn forward_publish(&mut self, node_id: NodeId, request: ForwardPublishRequest) {
+        self.send(node_id, PeerMessage::Publish(request, Instant::now()))
+    }
+
+    /// 将当前节点的路由变更同步给对等节点
+    /// 使用集群管理器时，对等节点从管理器获取路由，不需要同步
+    pub(crate) fn update_route(&mut self, node_id: NodeId, filter: &str, action: RouteAction) {
+        if self.manager_tx.is_some() {
+            return;
+        }
+        let request = UpdateRouteRequest {
+            node_id: self.node_id(),
+            filter: filter.into(),
+            action: action as i32,
+        };
+        self.send(node_id, PeerMessage::Route(request));
+    }
+
+    /// 将当前节点的路由变更同步给所有对等节点
+    pub(crate) fn broadcast_route(&mut self, filter: &str, action: RouteAction) {
+        if self.manager_tx.is_some() {
+            let request = match action {
+                RouteAction::RouteAdd => ManagerRequest::AddRoute(filter.into()),
+                RouteAction::RouteDelete => ManagerRequest::DeleteRoute(filter.into()),
+            };
+            return self.send_manager(request);
+        }
+        for node_id in self.peer_ids() {
+            self.update_route(node_id, filter, action);
+        }
+    }
+
+    /// 将当前节点的会话变更同步给对等节点
+    /// 使用集群管理器时，对等节点从管理器获取会话，不需要同步
+    pub(crate) fn update_session(&mut self, node_id: NodeId, client_id: &str, removed: bool) {
+        if self.manager_tx.is_some() {
+            return;
+        }
+        let request = UpdateSessionRequest {
+            client_id: client_id.into(),
+            node_id: self.node_id(),
+            removed,
+        };
+        self.send(node_id, PeerMessage::Session(request));
+    }
+
+    /// 将当前节点的会话变更同步给所有对等节点
+    /// previous 为本节点拥有会话之前记录的拥有者，删除会话时忽略
+    pub(crate) fn broadcast_session(
+        &mut self,
+        client_id: &str,
+        removed: bool,
+        previous: Option<NodeId>,
+    ) {
+        if self.manager_tx.is_some() {
+            let request = match removed {
+                true => ManagerRequest::RemoveSessionOwner(client_id.into()),
+                false => ManagerRequest::SetSessionOwner {
+                    client_id: client_id.into(),
+                    previous,
+                },
+            };
+            return self.send_manager(request);
+        }
+        for node_id in self.peer_ids() {
+            self.update_session(node_id, client_id, removed);
+        }
+    }
+
+    /// 踢掉旧节点上的客户端连接，获取会话的订阅信息
+    pub(crate) fn takeover_session(
+        &mut self,
+        node_id: NodeId,
+        client_id: &str,
+    ) -> Response<TakeoverSessionResponse> {
+        let (tx, rx) = oneshot::channel();
+        let request = TakeoverSessionRequest {
+            client_id: client_id.into(),
+            node_id: self.node_id(),
+        };
+        self.send(node_id, PeerMessage::Takeover(request, tx));
+        rx
+    }
+
+    /// 旧节点删除会话，并返回会话中未完成的消息
+    pub(crate) fn release_session(
+        &mut self,
+        node_id: NodeId,
+        client_id: &str,
+    ) -> Response<ReleaseSessionResponse> {
+        let (tx, rx) = oneshot::channel();
+        let request = ReleaseSessionRequest {
+            client_id: client_id.into(),
+            node_id: self.node_id(),
+        };
+        self.send(node_id, PeerMessage::Release(request, tx));
+        rx
+    }
+
+    /// 将本节点写入的保留消息同步给所有对等节点
+    pub(crate) fn broadcast_retain(&mut self, message: RetainMessage) {
+        for node_id in self.peer_ids() {
+            let request = UpdateRetainRequest {
+                retains: vec![message.clone()],
+            };
+            self.send(node_id, PeerMessage::Retain(request));
+        }
+    }
+
+    /// 将禁止规则的变更同步给对等节点
+    pub(crate) fn update_ban(&mut self, node_id: NodeId, added: Vec<Ban>, removed: Vec<Ban>) {
+        let request = UpdateBanRequest { added, removed };
+        self.send(node_id, PeerMessage::Ban(request));
+    }
+
+    /// 将本节点上禁止规则的变更同步给所有对等节点
+    pub(crate) fn broadcast_ban(&mut self, added: Vec<Ban>, removed: Vec<Ban>) {
+        for node_id in self.peer_ids() {
+            self.update_ban(node_id, added.clone(), removed.clone());
+        }
+    }
+
+    /// 拉取对等节点上所有的保留消息
+    pub(crate) fn sync_retain(&mut self, node_id: NodeId) -> Response<SyncRetainResponse> {
+        let (tx, rx) = oneshot::channel();
+        let request = SyncRetainRequest {
+            node_id: self.node_id(),
+        };
+        self.send(node_id, PeerMessage::SyncRetain(request, tx));
+        rx
     }
 }
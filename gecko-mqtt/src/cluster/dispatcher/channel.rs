@@ -0,0 +1,437 @@
+//! 到单个对等节点的 grpc 连接
+//!
+//! 每个对等节点有一个后台任务，负责建立连接、断线重连、健康检查，
+//! 并将队列中的 publish 消息批量通过 stream rpc 发送出去
+//!
+//! router 放入队列时不等待，节点宕机或者队列已满时直接丢弃，节点恢复后由 router 重新同步；
+//! 其它请求都有超时，避免两个节点的 router 互相等待
+
+use std::{
+    cmp,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::stream;
+use gecko_mqtt_proto::{
+    gecko_peer_client::GeckoPeerClient, ForwardPublishBatch, ForwardPublishRequest, PingRequest,
+    ReleaseSessionRequest, ReleaseSessionResponse, SyncRetainRequest, SyncRetainResponse,
+    TakeoverSessionRequest, TakeoverSessionResponse, UpdateBanRequest, UpdateRetainRequest,
+    UpdateRouteRequest, UpdateSessionRequest,
+};
+use log::{debug, error, warn};
+use tokio::{
+    select,
+    sync::mpsc::{
+        self,
+        error::{TryRecvError, TrySendError},
+        Receiver, Sender, UnboundedSender,
+    },
+    sync::oneshot,
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tonic::transport::{Channel, Endpoint};
+
+use crate::{
+    cluster::{NodeId, NodeStatus},
+    config,
+    metrics::Metrics,
+};
+
+/// 重连的初始退避时间
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// 批量发送 stream 的缓冲长度
+const STREAM_BUFFER_SIZE: usize = 16;
+
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    #[error("Invalid peer address: {0}")]
+    InvalidAddr(String),
+    #[error("Grpc transport error: {0}")]
+    Transport(#[from] tonic::transport::Error),
+    #[error("Grpc status: {0}")]
+    Status(#[from] tonic::Status),
+    #[error("Health check timeout")]
+    HealthCheckTimeout,
+    #[error("Request timeout")]
+    RequestTimeout,
+    #[error("Forward stream closed")]
+    StreamClosed,
+}
+
+impl Error {
+    /// 需要响应的请求，把错误返回给调用方
+    fn into_status(self) -> tonic::Status {
+        match self {
+            Error::Status(status) => status,
+            Error::RequestTimeout => tonic::Status::deadline_exceeded("peer request timeout"),
+            e => tonic::Status::unavailable(e.to_string()),
+        }
+    }
+}
+
+/// 请求的响应通过 oneshot 返回给调用方
+pub(crate) type Reply<T> = oneshot::Sender<Result<T, tonic::Status>>;
+
+/// 发往对等节点的消息
+#[derive(Debug)]
+pub(crate) enum PeerMessage {
+    /// 转发的消息和放入队列的时间
+    Publish(ForwardPublishRequest, Instant),
+    Route(UpdateRouteRequest),
+    Session(UpdateSessionRequest),
+    Takeover(TakeoverSessionRequest, Reply<TakeoverSessionResponse>),
+    Release(ReleaseSessionRequest, Reply<ReleaseSessionResponse>),
+    Retain(UpdateRetainRequest),
+    SyncRetain(SyncRetainRequest, Reply<SyncRetainResponse>),
+    Ban(UpdateBanRequest),
+}
+
+/// 对等节点连接的句柄，消息通过有界队列交给后台任务发送
+pub(crate) struct GrpcChannel {
+    tx: Sender<PeerMessage>,
+    /// 节点是否被判定为宕机，由后台任务更新
+    down: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl GrpcChannel {
+    pub(crate) fn new(
+        node_id: NodeId,
+        addr: String,
+        cfg: &config::Cluster,
+        status_tx: Option<UnboundedSender<(NodeId, NodeStatus)>>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(cfg.peer_queue_size);
+        let down = Arc::new(AtomicBool::new(false));
+        let down_threshold = cmp::max(cfg.down_threshold, 1);
+        let worker = ChannelWorker {
+            node_id,
+            local_node_id: cfg.node_id,
+            addr,
+            rx,
+            pending: None,
+            status_tx,
+            status: NodeStatus::Down,
+            down: down.clone(),
+            incarnation: None,
+            failures: 0,
+            suspect_threshold: cmp::min(cmp::max(cfg.suspect_threshold, 1), down_threshold),
+            down_threshold,
+            batch_size: cmp::max(cfg.forward_batch_size, 1),
+            health_check_interval: Duration::from_secs(cmp::max(cfg.health_check_interval, 1)),
+            request_timeout: Duration::from_secs(cmp::max(cfg.peer_request_timeout, 1)),
+            max_backoff: Duration::from_secs(cfg.reconnect_max_backoff),
+            metrics,
+        };
+        Self {
+            tx,
+            down,
+            handle: tokio::spawn(worker.run()),
+        }
+    }
+
+    /// 放入发送队列，不等待，失败时丢弃消息
+    pub(crate) fn try_send(&self, message: PeerMessage) -> Result<(), TrySendError<()>> {
+        self.tx.try_send(message).map_err(|e| match e {
+            TrySendError::Full(_) => TrySendError::Full(()),
+            TrySendError::Closed(_) => TrySendError::Closed(()),
+        })
+    }
+
+    /// 节点被判定为宕机，还没有连接成功过的节点照常排队
+    pub(crate) fn is_down(&self) -> bool {
+        self.down.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for GrpcChannel {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+struct ChannelWorker {
+    /// 对等节点 id
+    node_id: NodeId,
+    /// 当前节点 id
+    local_node_id: NodeId,
+    /// 对等节点地址
+    addr: String,
+    /// 待发送的消息
+    rx: Receiver<PeerMessage>,
+    /// 组装批量消息时取出的非 publish 消息，下一轮优先发送
+    pending: Option<PeerMessage>,
+    /// 节点状态变更时通知 router，由集群管理器判断节点状态时为 None
+    status_tx: Option<UnboundedSender<(NodeId, NodeStatus)>>,
+    /// 还没有连接成功过的节点视为宕机，但不通知
+    status: NodeStatus,
+    /// 和句柄共享，宕机时句柄不再接收消息
+    down: Arc<AtomicBool>,
+    /// 对等节点的启动时间戳
+    incarnation: Option<u64>,
+    /// 连续心跳失败的次数
+    failures: u32,
+    suspect_threshold: u32,
+    down_threshold: u32,
+    batch_size: usize,
+    health_check_interval: Duration,
+    /// 单次请求的超时时间
+    request_timeout: Duration,
+    max_backoff: Duration,
+    /// 记录转发消息的排队时间
+    metrics: Arc<Metrics>,
+}
+
+impl ChannelWorker {
+    /// 连接 -> 发送 -> 断开后按指数退避重连，直到句柄被丢弃
+    async fn run(mut self) {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            match self.connect().await {
+                Ok((client, incarnation)) => {
+                    debug!("peer node {0} connected", self.node_id);
+                    backoff = INITIAL_BACKOFF;
+                    self.heartbeat_succeeded(incarnation);
+                    match self.serve(client).await {
+                        Ok(()) => return,
+                        Err(e) => {
+                            error!("peer node {0} connection error: {1:#}", self.node_id, e);
+                            self.heartbeat_failed();
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("connect to peer node {0} error: {1:#}", self.node_id, e);
+                    self.heartbeat_failed();
+                }
+            }
+
+            // 连接不可用期间，丢弃积压的消息，避免阻塞 router
+            let (dropped, closed) = self.drain();
+            if dropped > 0 {
+                self.metrics.cluster_dropped(dropped);
+                warn!(
+                    "peer node {0} unavailable, dropped {1} messages",
+                    self.node_id, dropped
+                );
+            }
+            if closed {
+                return;
+            }
+            time::sleep(backoff).await;
+            backoff = cmp::min(backoff * 2, cmp::max(self.max_backoff, INITIAL_BACKOFF));
+        }
+    }
+
+    /// 连接成功，返回对等节点的启动时间戳
+    async fn connect(&self) -> Result<(GeckoPeerClient<Channel>, u64), Error> {
+        let channel = Endpoint::from_shared(self.addr.clone())
+            .map_err(|_| Error::InvalidAddr(self.addr.clone()))?
+            .connect_timeout(self.health_check_interval)
+            // 不设置连接级的请求超时，批量转发的 stream 是长期存在的，断线由 keepalive 检测
+            // 其它请求单独设置超时
+            .http2_keep_alive_interval(self.health_check_interval)
+            .keep_alive_timeout(self.health_check_interval)
+            .connect()
+            .await?;
+        let mut client = GeckoPeerClient::new(channel);
+        let incarnation = self.ping(&mut client).await?;
+        Ok((client, incarnation))
+    }
+
+    async fn ping(&self, client: &mut GeckoPeerClient<Channel>) -> Result<u64, Error> {
+        let request = PingRequest {
+            node_id: self.local_node_id,
+        };
+        match time::timeout(self.health_check_interval, client.ping(request)).await {
+            Ok(res) => Ok(res?.into_inner().incarnation),
+            Err(_) => Err(Error::HealthCheckTimeout),
+        }
+    }
+
+    /// 心跳成功，节点重启过时先按宕机处理，清理它旧的路由和会话
+    fn heartbeat_succeeded(&mut self, incarnation: u64) {
+        self.failures = 0;
+        if self
+            .incarnation
+            .replace(incarnation)
+            .is_some_and(|i| i != incarnation)
+        {
+            warn!("peer node {0} restarted", self.node_id);
+            self.set_status(NodeStatus::Down);
+        }
+        self.set_status(NodeStatus::Up);
+    }
+
+    /// 心跳失败，连续失败次数达到阈值时变更节点状态
+    fn heartbeat_failed(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= self.down_threshold {
+            self.set_status(NodeStatus::Down);
+        } else if self.failures >= self.suspect_threshold {
+            self.set_status(NodeStatus::Suspect);
+        }
+    }
+
+    /// 通知 router 不等待，router 向本节点发送消息时也不会等待
+    fn set_status(&mut self, status: NodeStatus) {
+        // 宕机的节点只能通过连接成功恢复
+        if self.status == status
+            || (self.status == NodeStatus::Down && status == NodeStatus::Suspect)
+        {
+            return;
+        }
+        self.status = status;
+        self.down
+            .store(status == NodeStatus::Down, Ordering::Relaxed);
+        let status_tx = match &self.status_tx {
+            Some(status_tx) => status_tx,
+            None => return,
+        };
+        if status_tx.send((self.node_id, status)).is_err() {
+            error!("send peer node {0} status to router error", self.node_id);
+        }
+    }
+
+    /// 在一个已建立的连接上持续发送消息
+    /// 返回 Ok 表示句柄已丢弃，任务结束
+    async fn serve(&mut self, mut client: GeckoPeerClient<Channel>) -> Result<(), Error> {
+        let (stream_tx, stream_rx) = mpsc::channel::<ForwardPublishBatch>(STREAM_BUFFER_SIZE);
+        let batches = stream::unfold(stream_rx, |mut rx| async move {
+            rx.recv().await.map(|batch| (batch, rx))
+        });
+        let mut stream_client = client.clone();
+        let mut stream_task =
+            tokio::spawn(async move { stream_client.forward_publish_stream(batches).await });
+
+        let mut health_check = time::interval(self.health_check_interval);
+        health_check.tick().await;
+        loop {
+            let message = match self.pending.take() {
+                Some(message) => Some(message),
+                None => select! {
+                    message = self.rx.recv() => message,
+                    _ = health_check.tick() => {
+                        let incarnation = self.ping(&mut client).await?;
+                        self.heartbeat_succeeded(incarnation);
+                        continue;
+                    }
+                    res = &mut stream_task => {
+                        return match res {
+                            Ok(Err(status)) => Err(Error::Status(status)),
+                            _ => Err(Error::StreamClosed),
+                        };
+                    }
+                },
+            };
+
+            match message {
+                Some(PeerMessage::Publish(publish, queued_at)) => {
+                    let batch = self.collect_batch(publish, queued_at);
+                    if stream_tx.send(batch).await.is_err() {
+                        return Err(Error::StreamClosed);
+                    }
+                }
+                Some(PeerMessage::Route(route)) => {
+                    let request = self.request(route);
+                    self.call(client.update_route(request)).await?;
+                }
+                Some(PeerMessage::Session(session)) => {
+                    let request = self.request(session);
+                    self.call(client.update_session(request)).await?;
+                }
+                Some(PeerMessage::Takeover(request, reply)) => {
+                    let request = self.request(request);
+                    let res = self.call(client.takeover_session(request)).await;
+                    let _ = reply.send(res.map_err(Error::into_status));
+                }
+                Some(PeerMessage::Release(request, reply)) => {
+                    let request = self.request(request);
+                    let res = self.call(client.release_session(request)).await;
+                    let _ = reply.send(res.map_err(Error::into_status));
+                }
+                Some(PeerMessage::Retain(retain)) => {
+                    let request = self.request(retain);
+                    self.call(client.update_retain(request)).await?;
+                }
+                Some(PeerMessage::SyncRetain(request, reply)) => {
+                    let request = self.request(request);
+                    let res = self.call(client.sync_retain(request)).await;
+                    let _ = reply.send(res.map_err(Error::into_status));
+                }
+                Some(PeerMessage::Ban(ban)) => {
+                    let request = self.request(ban);
+                    self.call(client.update_ban(request)).await?;
+                }
+                None => {
+                    stream_task.abort();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// 带超时的请求，对方按超时时间放弃处理
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request.set_timeout(self.request_timeout);
+        request
+    }
+
+    /// 等待请求的响应，超时后返回错误
+    async fn call<T>(
+        &self,
+        response: impl Future<Output = Result<tonic::Response<T>, tonic::Status>>,
+    ) -> Result<T, Error> {
+        match time::timeout(self.request_timeout, response).await {
+            Ok(res) => Ok(res?.into_inner()),
+            Err(_) => Err(Error::RequestTimeout),
+        }
+    }
+
+    /// 从队列中取出已就绪的 publish 消息，组成一批
+    /// 记录每条消息从放入队列到交给 stream 的时间
+    fn collect_batch(
+        &mut self,
+        first: ForwardPublishRequest,
+        queued_at: Instant,
+    ) -> ForwardPublishBatch {
+        let now = Instant::now();
+        self.metrics.forward_latency(now - queued_at);
+        let mut publishes = vec![first];
+        while publishes.len() < self.batch_size {
+            match self.rx.try_recv() {
+                Ok(PeerMessage::Publish(publish, queued_at)) => {
+                    self.metrics.forward_latency(now - queued_at);
+                    publishes.push(publish)
+                }
+                Ok(message) => {
+                    // 保持消息顺序，先发送当前批次
+                    self.pending = Some(message);
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        ForwardPublishBatch { publishes }
+    }
+
+    /// 丢弃队列中所有消息，返回丢弃的数量以及句柄是否已丢弃
+    fn drain(&mut self) -> (usize, bool) {
+        let mut count = self.pending.take().map_or(0, |_| 1);
+        loop {
+            match self.rx.try_recv() {
+                Ok(_) => count += 1,
+                Err(TryRecvError::Empty) => return (count, false),
+                Err(TryRecvError::Disconnected) => return (count, true),
+            }
+        }
+    }
+}
@@ -1,5 +1,27 @@
 //! 提供集群节点管理的功能
 //! 如 get_nodes_by_ids 接口
+//!
+//! 配置 etcd 后，节点注册、路由表和会话表都通过集群管理器同步，
+//! router 只需要把本节点的变更发给管理器，其它节点的变更由管理器以 Incoming 的形式发回 router
 
+pub(crate) use etcd::EtcdManager;
+
+use super::NodeId;
+
+mod etcd;
 mod raft;
 mod standalone;
+
+/// router 发给集群管理器的请求，同步本节点的路由和会话
+/// 使用无界队列发送，管理器等待 router 时 router 不会反过来等待管理器
+#[derive(Debug)]
+pub(crate) enum ManagerRequest {
+    AddRoute(String),
+    DeleteRoute(String),
+    /// previous 为 router 接管会话前记录的拥有者，用于检查并发修改
+    SetSessionOwner {
+        client_id: String,
+        previous: Option<NodeId>,
+    },
+    RemoveSessionOwner(String),
+}
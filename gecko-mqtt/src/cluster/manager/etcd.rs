@@ -0,0 +1,730 @@
+//! etcd 协调模式，适用于节点较多的集群
+//!
+//! * 节点：`{prefix}/nodes/{node_id}` = grpc 地址
+//! * 路由：`{prefix}/routes/{node_id}/{filter}`
+//! * 会话：`{prefix}/sessions/{client_id}` = node_id，只在拥有者没有被其它节点并发修改时写入
+//!
+//! 所有数据都绑定在节点租约上，节点宕机后租约过期，数据由 etcd 自动删除。
+//! 启动时先读取全量数据，再从读取时的 revision 开始 watch 之后的变更；
+//! 与 etcd 的连接断开后重新注册，把本节点的路由和会话重新写入，并与全量数据对比，补上断开期间的变更
+
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use futures::{stream, Stream};
+use gecko_mqtt_proto::etcd::{
+    etcdserverpb::{
+        compare::{CompareResult, CompareTarget, TargetUnion},
+        kv_client::KvClient,
+        lease_client::LeaseClient,
+        request_op::Request,
+        response_op::Response,
+        watch_client::WatchClient,
+        watch_request::RequestUnion,
+        Compare, DeleteRangeRequest, LeaseGrantRequest, LeaseKeepAliveRequest, LeaseRevokeRequest,
+        PutRequest, RangeRequest, RequestOp, TxnRequest, WatchCreateRequest, WatchRequest,
+    },
+    mvccpb::{event::EventType, Event, KeyValue},
+};
+use log::{error, info, warn};
+use tokio::{
+    select,
+    sync::mpsc::{self, Receiver, Sender, UnboundedReceiver},
+    time,
+};
+use tonic::transport::{Channel, Endpoint};
+
+use crate::{cluster::NodeId, config, protocol::Incoming};
+
+use super::ManagerRequest;
+
+#[cfg(test)]
+mod fake;
+
+/// 重连的初始退避时间
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// 重连的最大退避时间
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// 写入会话拥有者时，拥有者并发变化的最大重试次数
+const SET_SESSION_OWNER_RETRIES: usize = 3;
+
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    #[error("Invalid etcd endpoint: {0}")]
+    InvalidEndpoint(String),
+    #[error("No etcd endpoint available")]
+    NoEndpoint,
+    #[error("Grpc transport error: {0}")]
+    Transport(#[from] tonic::transport::Error),
+    #[error("Grpc status: {0}")]
+    Status(#[from] tonic::Status),
+    #[error("Lease expired")]
+    LeaseExpired,
+    #[error("Watch canceled: {0}")]
+    WatchCanceled(String),
+    #[error("Etcd stream closed")]
+    StreamClosed,
+    #[error("Router closed")]
+    RouterClosed,
+}
+
+/// etcd 中一条集群数据的 key
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Node(NodeId),
+    Route(NodeId, String),
+    Session(String),
+}
+
+pub(crate) struct EtcdManager {
+    cfg: config::Etcd,
+    /// 当前节点 id
+    node_id: NodeId,
+    /// 当前节点的 grpc 地址
+    addr: String,
+    manager_rx: UnboundedReceiver<ManagerRequest>,
+    router_tx: Sender<Incoming>,
+    /// 当前节点的租约，重新注册后撤销旧的租约
+    lease_id: Option<i64>,
+    /// 本节点的路由和会话，重新注册时写回 etcd
+    local_routes: HashSet<String>,
+    local_sessions: HashSet<String>,
+    /// 已通知 router 的其它节点的数据，value 为 etcd 中的值
+    known: HashMap<Key, String>,
+}
+
+impl EtcdManager {
+    pub(crate) fn new(
+        cfg: config::Etcd,
+        node_id: NodeId,
+        addr: String,
+        manager_rx: UnboundedReceiver<ManagerRequest>,
+        router_tx: Sender<Incoming>,
+    ) -> Self {
+        Self {
+            cfg,
+            node_id,
+            addr,
+            manager_rx,
+            router_tx,
+            lease_id: None,
+            local_routes: HashSet::new(),
+            local_sessions: HashSet::new(),
+            known: HashMap::new(),
+        }
+    }
+
+    /// 注册 -> 同步 -> 断开后按指数退避重新注册，直到 router 退出
+    pub(crate) async fn start(mut self) {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            match self.serve(&mut backoff).await {
+                Err(Error::RouterClosed) => return,
+                Err(e) => error!("etcd cluster manager error: {:#}", e),
+                Ok(()) => return,
+            }
+            if !self.wait(backoff).await {
+                return;
+            }
+            backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+        }
+    }
+
+    /// 等待重连期间，继续接收 router 的请求，只记录在本地
+    /// 返回 false 表示 router 已退出
+    async fn wait(&mut self, duration: Duration) -> bool {
+        let sleep = time::sleep(duration);
+        tokio::pin!(sleep);
+        loop {
+            select! {
+                _ = &mut sleep => return true,
+                request = self.manager_rx.recv() => match request {
+                    Some(request) => self.apply_local(&request),
+                    None => return false,
+                },
+            }
+        }
+    }
+
+    async fn serve(&mut self, backoff: &mut Duration) -> Result<(), Error> {
+        let ttl = cmp::max(self.cfg.lease_ttl, 1);
+        let channel = self.connect(Duration::from_secs(ttl)).await?;
+        let mut kv = KvClient::new(channel.clone());
+        let mut lease = LeaseClient::new(channel.clone());
+        let mut watch = WatchClient::new(channel);
+
+        // 注册节点，写入本节点的路由和会话
+        let lease_id = lease
+            .lease_grant(LeaseGrantRequest {
+                ttl: ttl as i64,
+                id: 0,
+            })
+            .await?
+            .into_inner()
+            .id;
+        self.put(
+            &mut kv,
+            &Key::Node(self.node_id),
+            &self.addr.clone(),
+            lease_id,
+        )
+        .await?;
+        for filter in self.local_routes.clone() {
+            self.put(&mut kv, &Key::Route(self.node_id, filter), "", lease_id)
+                .await?;
+        }
+        for client_id in self.local_sessions.clone() {
+            self.set_session_owner(&mut kv, client_id, None, lease_id)
+                .await?;
+        }
+        // 旧租约上只剩下断开期间删除的数据，直接撤销
+        if let Some(old) = self.lease_id.replace(lease_id) {
+            let _ = lease.lease_revoke(LeaseRevokeRequest { id: old }).await;
+        }
+        info!("registered to etcd with lease {}", lease_id);
+        *backoff = INITIAL_BACKOFF;
+
+        // 读取全量数据
+        let prefix = self.prefix();
+        let response = kv
+            .range(RangeRequest {
+                key: prefix.clone(),
+                range_end: prefix_end(&prefix),
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let revision = response.header.map_or(0, |header| header.revision);
+        self.apply_snapshot(response.kvs).await?;
+
+        // watch 读取之后的变更，请求需要先于 rpc 发出，etcd 收到请求后才会响应
+        let (watch_tx, watch_rx) = mpsc::channel(1);
+        let create = WatchRequest {
+            request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                key: prefix.clone(),
+                range_end: prefix_end(&prefix),
+                start_revision: revision + 1,
+                ..Default::default()
+            })),
+        };
+        let _ = watch_tx.send(create).await;
+        let mut events = watch.watch(receiver_stream(watch_rx)).await?.into_inner();
+
+        // 定期续约
+        let (keepalive_tx, keepalive_rx) = mpsc::channel(1);
+        let _ = keepalive_tx
+            .send(LeaseKeepAliveRequest { id: lease_id })
+            .await;
+        let mut keepalives = lease
+            .lease_keep_alive(receiver_stream(keepalive_rx))
+            .await?
+            .into_inner();
+        let mut keepalive = time::interval(Duration::from_secs(ttl) / 3);
+        keepalive.tick().await;
+
+        loop {
+            select! {
+                request = self.manager_rx.recv() => match request {
+                    Some(request) => self.handle_request(&mut kv, request, lease_id).await?,
+                    None => {
+                        // router 已退出，撤销租约，让其它节点立即感知
+                        let _ = lease.lease_revoke(LeaseRevokeRequest { id: lease_id }).await;
+                        return Ok(());
+                    }
+                },
+                response = events.message() => match response? {
+                    Some(response) if response.canceled => {
+                        return Err(Error::WatchCanceled(response.cancel_reason));
+                    }
+                    Some(response) => {
+                        for event in response.events {
+                            self.apply_event(event).await?;
+                        }
+                    }
+                    None => return Err(Error::StreamClosed),
+                },
+                _ = keepalive.tick() => {
+                    if keepalive_tx.send(LeaseKeepAliveRequest { id: lease_id }).await.is_err() {
+                        return Err(Error::StreamClosed);
+                    }
+                }
+                response = keepalives.message() => match response? {
+                    Some(response) if response.ttl > 0 => {}
+                    Some(_) => return Err(Error::LeaseExpired),
+                    None => return Err(Error::StreamClosed),
+                },
+            }
+        }
+    }
+
+    /// 依次尝试连接配置的 etcd 地址
+    async fn connect(&self, timeout: Duration) -> Result<Channel, Error> {
+        for endpoint in self.cfg.endpoints.iter() {
+            let channel = Endpoint::from_shared(endpoint.clone())
+                .map_err(|_| Error::InvalidEndpoint(endpoint.clone()))?
+                .connect_timeout(timeout)
+                .http2_keep_alive_interval(timeout / 3)
+                .keep_alive_timeout(timeout / 3)
+                .connect()
+                .await;
+            match channel {
+                Ok(channel) => return Ok(channel),
+                Err(e) => warn!("connect to etcd {0} error: {1:#}", endpoint, e),
+            }
+        }
+        Err(Error::NoEndpoint)
+    }
+
+    /// 将本节点的变更写入 etcd
+    async fn handle_request(
+        &mut self,
+        kv: &mut KvClient<Channel>,
+        request: ManagerRequest,
+        lease_id: i64,
+    ) -> Result<(), Error> {
+        self.apply_local(&request);
+        match request {
+            ManagerRequest::AddRoute(filter) => {
+                self.put(kv, &Key::Route(self.node_id, filter), "", lease_id)
+                    .await
+            }
+            ManagerRequest::DeleteRoute(filter) => {
+                let request = DeleteRangeRequest {
+                    key: self.key(&Key::Route(self.node_id, filter)),
+                    ..Default::default()
+                };
+                kv.delete_range(request).await?;
+                Ok(())
+            }
+            ManagerRequest::SetSessionOwner {
+                client_id,
+                previous,
+            } => {
+                self.set_session_owner(kv, client_id, previous, lease_id)
+                    .await
+            }
+            ManagerRequest::RemoveSessionOwner(client_id) => {
+                // 会话可能已经被其它节点接管，只删除仍属于本节点的会话
+                let key = self.key(&Key::Session(client_id));
+                let request = TxnRequest {
+                    compare: vec![Compare {
+                        result: CompareResult::Equal as i32,
+                        target: CompareTarget::Value as i32,
+                        key: key.clone(),
+                        target_union: Some(TargetUnion::Value(
+                            self.node_id.to_string().into_bytes(),
+                        )),
+                    }],
+                    success: vec![RequestOp {
+                        request: Some(Request::RequestDeleteRange(DeleteRangeRequest {
+                            key,
+                            ..Default::default()
+                        })),
+                    }],
+                    failure: vec![],
+                };
+                kv.txn(request).await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// 记录本节点的路由和会话
+    fn apply_local(&mut self, request: &ManagerRequest) {
+        match request {
+            ManagerRequest::AddRoute(filter) => {
+                self.local_routes.insert(filter.clone());
+            }
+            ManagerRequest::DeleteRoute(filter) => {
+                self.local_routes.remove(filter);
+            }
+            ManagerRequest::SetSessionOwner { client_id, .. } => {
+                self.local_sessions.insert(client_id.clone());
+            }
+            ManagerRequest::RemoveSessionOwner(client_id) => {
+                self.local_sessions.remove(client_id);
+            }
+        }
+    }
+
+    /// 写入本节点拥有的会话，按 etcd 中当前的拥有者比较后写入
+    /// 拥有者不存在、是本节点或者是 previous 时写入，否则说明会话已被其它节点接管，
+    /// 避免延迟处理的请求覆盖其它节点的记录
+    async fn set_session_owner(
+        &mut self,
+        kv: &mut KvClient<Channel>,
+        client_id: String,
+        previous: Option<NodeId>,
+        lease_id: i64,
+    ) -> Result<(), Error> {
+        let key = self.key(&Key::Session(client_id.clone()));
+        let node_id = self.node_id.to_string().into_bytes();
+        let previous = previous.map(|previous| previous.to_string().into_bytes());
+        // 先假设是 previous，不一致时按读到的拥有者重试，直到写入或者确认已被接管
+        let mut expected = previous.clone();
+        for _ in 0..SET_SESSION_OWNER_RETRIES {
+            let compare = match &expected {
+                Some(owner) => Compare {
+                    result: CompareResult::Equal as i32,
+                    target: CompareTarget::Value as i32,
+                    key: key.clone(),
+                    target_union: Some(TargetUnion::Value(owner.clone())),
+                },
+                None => Compare {
+                    result: CompareResult::Equal as i32,
+                    target: CompareTarget::Version as i32,
+                    key: key.clone(),
+                    target_union: Some(TargetUnion::Version(0)),
+                },
+            };
+            let request = TxnRequest {
+                compare: vec![compare],
+                success: vec![RequestOp {
+                    request: Some(Request::RequestPut(PutRequest {
+                        key: key.clone(),
+                        value: node_id.clone(),
+                        lease: lease_id,
+                        prev_kv: false,
+                    })),
+                }],
+                failure: vec![RequestOp {
+                    request: Some(Request::RequestRange(RangeRequest {
+                        key: key.clone(),
+                        ..Default::default()
+                    })),
+                }],
+            };
+            let response = kv.txn(request).await?.into_inner();
+            if response.succeeded {
+                return Ok(());
+            }
+            let owner = response
+                .responses
+                .into_iter()
+                .find_map(|op| match op.response {
+                    Some(Response::ResponseRange(range)) => range.kvs.into_iter().next(),
+                    _ => None,
+                })
+                .map(|kv| kv.value);
+            match owner {
+                Some(owner) if owner != node_id && Some(&owner) != previous.as_ref() => {
+                    warn!(
+                        "session {0} already taken over by node {1}",
+                        client_id,
+                        String::from_utf8_lossy(&owner)
+                    );
+                    self.local_sessions.remove(&client_id);
+                    return Ok(());
+                }
+                owner => expected = owner,
+            }
+        }
+        warn!(
+            "set owner of session {} conflicted too many times",
+            client_id
+        );
+        Ok(())
+    }
+
+    async fn put(
+        &self,
+        kv: &mut KvClient<Channel>,
+        key: &Key,
+        value: &str,
+        lease_id: i64,
+    ) -> Result<(), Error> {
+        let request = PutRequest {
+            key: self.key(key),
+            value: value.as_bytes().to_vec(),
+            lease: lease_id,
+            prev_kv: false,
+        };
+        kv.put(request).await?;
+        Ok(())
+    }
+
+    /// 用全量数据替换已知的数据，把差异通知 router
+    async fn apply_snapshot(&mut self, kvs: Vec<KeyValue>) -> Result<(), Error> {
+        let snapshot = kvs
+            .iter()
+            .filter_map(|kv| self.parse(kv))
+            .collect::<HashMap<Key, String>>();
+
+        let removed = self
+            .known
+            .iter()
+            .filter(|(key, value)| snapshot.get(*key) != Some(*value))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<Vec<(Key, String)>>();
+        for (key, value) in removed {
+            self.known.remove(&key);
+            self.notify(key, value, true).await?;
+        }
+
+        for (key, value) in snapshot {
+            if self.known.get(&key) != Some(&value) {
+                self.known.insert(key.clone(), value.clone());
+                self.notify(key, value, false).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 处理 watch 到的变更
+    async fn apply_event(&mut self, event: Event) -> Result<(), Error> {
+        let kv = match &event.kv {
+            Some(kv) => kv,
+            None => return Ok(()),
+        };
+        match event.r#type() {
+            EventType::Put => match self.parse(kv) {
+                Some((key, value)) => {
+                    if self.known.get(&key) == Some(&value) {
+                        return Ok(());
+                    }
+                    self.known.insert(key.clone(), value.clone());
+                    self.notify(key, value, false).await
+                }
+                // 本节点接管了会话，不再需要其它节点的记录
+                None => {
+                    if let Some(key) = self.parse_key(&kv.key) {
+                        self.known.remove(&key);
+                    }
+                    Ok(())
+                }
+            },
+            EventType::Delete => {
+                let removed = self
+                    .parse_key(&kv.key)
+                    .and_then(|key| self.known.remove_entry(&key));
+                match removed {
+                    Some((key, value)) => self.notify(key, value, true).await,
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// 将其它节点的变更转为 router 的消息
+    async fn notify(&self, key: Key, value: String, removed: bool) -> Result<(), Error> {
+        let incoming = match key {
+            Key::Node(node_id) if removed => Incoming::NodeDown { node_id },
+            Key::Node(node_id) => Incoming::NodeUp {
+                node_id,
+                addr: value,
+            },
+            Key::Route(node_id, filter) if removed => Incoming::DeleteRoute { node_id, filter },
+            Key::Route(node_id, filter) => Incoming::AddRoute { node_id, filter },
+            Key::Session(client_id) => match value.parse() {
+                Ok(node_id) => Incoming::UpdateSession {
+                    client_id,
+                    node_id,
+                    removed,
+                },
+                Err(_) => {
+                    warn!("invalid owner {0} of session {1}", value, client_id);
+                    return Ok(());
+                }
+            },
+        };
+        self.router_tx
+            .send(incoming)
+            .await
+            .map_err(|_| Error::RouterClosed)
+    }
+
+    fn prefix(&self) -> Vec<u8> {
+        format!("{}/", self.cfg.prefix).into_bytes()
+    }
+
+    fn key(&self, key: &Key) -> Vec<u8> {
+        let prefix = &self.cfg.prefix;
+        match key {
+            Key::Node(node_id) => format!("{}/nodes/{}", prefix, node_id),
+            Key::Route(node_id, filter) => format!("{}/routes/{}/{}", prefix, node_id, filter),
+            Key::Session(client_id) => format!("{}/sessions/{}", prefix, client_id),
+        }
+        .into_bytes()
+    }
+
+    fn parse_key(&self, key: &[u8]) -> Option<Key> {
+        let key = std::str::from_utf8(key).ok()?;
+        let rest = key
+            .strip_prefix(self.cfg.prefix.as_str())?
+            .strip_prefix('/')?;
+        let (kind, rest) = rest.split_once('/')?;
+        match kind {
+            "nodes" => rest.parse().ok().map(Key::Node),
+            "routes" => {
+                let (node_id, filter) = rest.split_once('/')?;
+                Some(Key::Route(node_id.parse().ok()?, filter.into()))
+            }
+            "sessions" => Some(Key::Session(rest.into())),
+            _ => None,
+        }
+    }
+
+    /// 解析其它节点的数据，本节点自己的数据返回 None
+    fn parse(&self, kv: &KeyValue) -> Option<(Key, String)> {
+        let key = self.parse_key(&kv.key)?;
+        let value = String::from_utf8(kv.value.clone()).ok()?;
+        let local = match &key {
+            Key::Node(node_id) | Key::Route(node_id, _) => *node_id == self.node_id,
+            Key::Session(_) => value == self.node_id.to_string(),
+        };
+        (!local).then_some((key, value))
+    }
+}
+
+/// 前缀查询的 range_end，最后一个字节加一
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    // 全部是 0xff，查询所有大于等于 prefix 的 key
+    vec![0]
+}
+
+fn receiver_stream<T: Send + 'static>(rx: Receiver<T>) -> impl Stream<Item = T> {
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|message| (message, rx))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{sync::mpsc::UnboundedSender, time::timeout};
+
+    use super::*;
+
+    async fn start(
+        endpoint: &str,
+        node_id: NodeId,
+    ) -> (UnboundedSender<ManagerRequest>, Receiver<Incoming>) {
+        let cfg = config::Etcd {
+            endpoints: vec![endpoint.into()],
+            prefix: "/gecko".into(),
+            lease_ttl: 10,
+            advertise_addr: None,
+        };
+        let (manager_tx, manager_rx) = mpsc::unbounded_channel();
+        let (router_tx, router_rx) = mpsc::channel(10);
+        let addr = format!("http://127.0.0.1:{}", 1890 + node_id);
+        let manager = EtcdManager::new(cfg, node_id, addr, manager_rx, router_tx);
+        tokio::spawn(manager.start());
+        (manager_tx, router_rx)
+    }
+
+    fn set_owner(client_id: &str, previous: Option<NodeId>) -> ManagerRequest {
+        ManagerRequest::SetSessionOwner {
+            client_id: client_id.into(),
+            previous,
+        }
+    }
+
+    async fn next(router_rx: &mut Receiver<Incoming>) -> Incoming {
+        timeout(Duration::from_secs(5), router_rx.recv())
+            .await
+            .expect("no incoming from etcd manager")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn etcd_manager_works() {
+        let (etcd, endpoint) = fake::FakeEtcd::serve().await;
+        let (manager1, mut router1) = start(&endpoint, 1).await;
+        let (_manager2, mut router2) = start(&endpoint, 2).await;
+
+        // 节点相互发现
+        assert!(matches!(
+            next(&mut router2).await,
+            Incoming::NodeUp { node_id: 1, .. }
+        ));
+        assert!(matches!(
+            next(&mut router1).await,
+            Incoming::NodeUp { node_id: 2, .. }
+        ));
+
+        // 路由和会话同步给其它节点
+        manager1
+            .send(ManagerRequest::AddRoute("iot/+/dn".into()))
+            .unwrap();
+        match next(&mut router2).await {
+            Incoming::AddRoute { node_id, filter } => {
+                assert_eq!((node_id, filter.as_str()), (1, "iot/+/dn"))
+            }
+            incoming => panic!("unexpected {:?}", incoming),
+        }
+        manager1.send(set_owner("c1", None)).unwrap();
+        manager1
+            .send(ManagerRequest::RemoveSessionOwner("c1".into()))
+            .unwrap();
+        assert!(matches!(
+            next(&mut router2).await,
+            Incoming::UpdateSession {
+                node_id: 1,
+                removed: false,
+                ..
+            }
+        ));
+        assert!(matches!(
+            next(&mut router2).await,
+            Incoming::UpdateSession {
+                node_id: 1,
+                removed: true,
+                ..
+            }
+        ));
+
+        // 租约过期后，节点和它的路由被删除
+        etcd.expire(b"/gecko/nodes/1");
+        let mut down = false;
+        let mut route_deleted = false;
+        for _ in 0..2 {
+            match next(&mut router2).await {
+                Incoming::NodeDown { node_id: 1 } => down = true,
+                Incoming::DeleteRoute { node_id: 1, .. } => route_deleted = true,
+                incoming => panic!("unexpected {:?}", incoming),
+            }
+        }
+        assert!(down && route_deleted);
+    }
+
+    #[tokio::test]
+    async fn stale_session_owner_not_written() {
+        let (_etcd, endpoint) = fake::FakeEtcd::serve().await;
+        let (manager1, mut router1) = start(&endpoint, 1).await;
+        let (manager2, mut router2) = start(&endpoint, 2).await;
+        assert!(matches!(next(&mut router2).await, Incoming::NodeUp { .. }));
+        assert!(matches!(next(&mut router1).await, Incoming::NodeUp { .. }));
+
+        // 节点 2 从节点 1 接管会话
+        manager1.send(set_owner("c1", None)).unwrap();
+        assert!(matches!(
+            next(&mut router2).await,
+            Incoming::UpdateSession { node_id: 1, .. }
+        ));
+        manager2.send(set_owner("c1", Some(1))).unwrap();
+        assert!(matches!(
+            next(&mut router1).await,
+            Incoming::UpdateSession { node_id: 2, .. }
+        ));
+
+        // 节点 1 延迟处理的写入不覆盖节点 2 的记录，之后的变更照常同步
+        manager1.send(set_owner("c1", None)).unwrap();
+        manager1
+            .send(ManagerRequest::AddRoute("iot/+/dn".into()))
+            .unwrap();
+        match next(&mut router2).await {
+            Incoming::AddRoute { node_id: 1, .. } => {}
+            incoming => panic!("unexpected {:?}", incoming),
+        }
+    }
+}
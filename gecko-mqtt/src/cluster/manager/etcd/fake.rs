@@ -0,0 +1,405 @@
+//! 测试用的 etcd 服务端，只实现集群管理用到的接口，数据保存在内存中
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use futures::Stream;
+use gecko_mqtt_proto::etcd::{
+    etcdserverpb::{
+        compare::{CompareResult, CompareTarget, TargetUnion},
+        kv_server::{Kv, KvServer},
+        lease_server::{Lease, LeaseServer},
+        request_op::Request,
+        response_op::Response,
+        watch_request::RequestUnion,
+        watch_server::{Watch, WatchServer},
+        Compare, DeleteRangeRequest, DeleteRangeResponse, LeaseGrantRequest, LeaseGrantResponse,
+        LeaseKeepAliveRequest, LeaseKeepAliveResponse, LeaseRevokeRequest, LeaseRevokeResponse,
+        PutRequest, PutResponse, RangeRequest, RangeResponse, ResponseHeader, ResponseOp,
+        TxnRequest, TxnResponse, WatchRequest, WatchResponse,
+    },
+    mvccpb::{event::EventType, Event, KeyValue},
+};
+use tokio::{net::TcpListener, sync::broadcast, sync::mpsc};
+use tokio_stream::wrappers::TcpListenerStream;
+
+use super::receiver_stream;
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, tonic::Status>> + Send>>;
+
+#[derive(Default)]
+struct State {
+    revision: i64,
+    kvs: BTreeMap<Vec<u8>, KeyValue>,
+    next_lease_id: i64,
+    /// key = lease id, value = ttl
+    leases: HashMap<i64, i64>,
+    /// 所有的变更，用于从指定 revision 开始 watch
+    history: Vec<Event>,
+}
+
+impl State {
+    fn header(&self) -> Option<ResponseHeader> {
+        Some(ResponseHeader {
+            revision: self.revision,
+            ..Default::default()
+        })
+    }
+
+    fn range(&self, key: &[u8], range_end: &[u8]) -> Vec<KeyValue> {
+        self.kvs
+            .values()
+            .filter(|kv| in_range(&kv.key, key, range_end))
+            .cloned()
+            .collect()
+    }
+
+    fn put(&mut self, request: PutRequest, events: &broadcast::Sender<Event>) -> PutResponse {
+        self.revision += 1;
+        let prev = self.kvs.get(&request.key).cloned();
+        let kv = KeyValue {
+            key: request.key.clone(),
+            create_revision: prev.as_ref().map_or(self.revision, |kv| kv.create_revision),
+            mod_revision: self.revision,
+            version: prev.as_ref().map_or(1, |kv| kv.version + 1),
+            value: request.value,
+            lease: request.lease,
+        };
+        self.kvs.insert(request.key, kv.clone());
+        self.commit(EventType::Put, kv, events);
+        PutResponse {
+            header: self.header(),
+            prev_kv: prev.filter(|_| request.prev_kv),
+        }
+    }
+
+    fn delete(
+        &mut self,
+        request: DeleteRangeRequest,
+        events: &broadcast::Sender<Event>,
+    ) -> DeleteRangeResponse {
+        let deleted = self.range(&request.key, &request.range_end);
+        if !deleted.is_empty() {
+            self.revision += 1;
+        }
+        for kv in deleted.iter() {
+            self.kvs.remove(&kv.key);
+            let tombstone = KeyValue {
+                key: kv.key.clone(),
+                mod_revision: self.revision,
+                ..Default::default()
+            };
+            self.commit(EventType::Delete, tombstone, events);
+        }
+        DeleteRangeResponse {
+            header: self.header(),
+            deleted: deleted.len() as i64,
+            prev_kvs: if request.prev_kv { deleted } else { vec![] },
+        }
+    }
+
+    fn compare(&self, compare: &Compare) -> bool {
+        let kv = self.kvs.get(&compare.key);
+        let ordering = match (compare.target(), &compare.target_union) {
+            (CompareTarget::Value, Some(TargetUnion::Value(value))) => {
+                kv.map_or(&[][..], |kv| &kv.value[..]).cmp(&value[..])
+            }
+            (CompareTarget::Version, Some(TargetUnion::Version(version))) => {
+                kv.map_or(0, |kv| kv.version).cmp(version)
+            }
+            _ => return false,
+        };
+        match compare.result() {
+            CompareResult::Equal => ordering.is_eq(),
+            CompareResult::Greater => ordering.is_gt(),
+            CompareResult::Less => ordering.is_lt(),
+            CompareResult::NotEqual => ordering.is_ne(),
+        }
+    }
+
+    /// 删除租约以及绑定在租约上的所有 key
+    fn revoke(&mut self, lease_id: i64, events: &broadcast::Sender<Event>) {
+        self.leases.remove(&lease_id);
+        let keys = self
+            .kvs
+            .values()
+            .filter(|kv| kv.lease == lease_id)
+            .map(|kv| kv.key.clone())
+            .collect::<Vec<Vec<u8>>>();
+        for key in keys {
+            let request = DeleteRangeRequest {
+                key,
+                ..Default::default()
+            };
+            self.delete(request, events);
+        }
+    }
+
+    /// 记录变更，通知所有 watch
+    fn commit(&mut self, r#type: EventType, kv: KeyValue, events: &broadcast::Sender<Event>) {
+        let event = Event {
+            r#type: r#type as i32,
+            kv: Some(kv),
+            prev_kv: None,
+        };
+        self.history.push(event.clone());
+        let _ = events.send(event);
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct FakeEtcd {
+    state: Arc<Mutex<State>>,
+    events: broadcast::Sender<Event>,
+}
+
+impl FakeEtcd {
+    /// 在随机端口上启动，返回 etcd 地址
+    pub(crate) async fn serve() -> (Self, String) {
+        let etcd = Self {
+            state: Arc::new(Mutex::new(State::default())),
+            events: broadcast::channel(1000).0,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let server = tonic::transport::Server::builder()
+            .add_service(KvServer::new(etcd.clone()))
+            .add_service(WatchServer::new(etcd.clone()))
+            .add_service(LeaseServer::new(etcd.clone()))
+            .serve_with_incoming(TcpListenerStream::new(listener));
+        tokio::spawn(server);
+        (etcd, endpoint)
+    }
+
+    /// 模拟 key 所在的租约过期
+    pub(crate) fn expire(&self, key: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(lease_id) = state.kvs.get(key).map(|kv| kv.lease) {
+            state.revoke(lease_id, &self.events);
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Kv for FakeEtcd {
+    async fn range(
+        &self,
+        request: tonic::Request<RangeRequest>,
+    ) -> Result<tonic::Response<RangeResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let state = self.state.lock().unwrap();
+        let kvs = state.range(&request.key, &request.range_end);
+        Ok(tonic::Response::new(RangeResponse {
+            header: state.header(),
+            count: kvs.len() as i64,
+            kvs,
+            more: false,
+        }))
+    }
+
+    async fn put(
+        &self,
+        request: tonic::Request<PutRequest>,
+    ) -> Result<tonic::Response<PutResponse>, tonic::Status> {
+        let mut state = self.state.lock().unwrap();
+        Ok(tonic::Response::new(
+            state.put(request.into_inner(), &self.events),
+        ))
+    }
+
+    async fn delete_range(
+        &self,
+        request: tonic::Request<DeleteRangeRequest>,
+    ) -> Result<tonic::Response<DeleteRangeResponse>, tonic::Status> {
+        let mut state = self.state.lock().unwrap();
+        Ok(tonic::Response::new(
+            state.delete(request.into_inner(), &self.events),
+        ))
+    }
+
+    async fn txn(
+        &self,
+        request: tonic::Request<TxnRequest>,
+    ) -> Result<tonic::Response<TxnResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let mut state = self.state.lock().unwrap();
+        let succeeded = request.compare.iter().all(|compare| state.compare(compare));
+        let ops = if succeeded {
+            request.success
+        } else {
+            request.failure
+        };
+        let mut responses = Vec::with_capacity(ops.len());
+        for op in ops {
+            let response = match op.request {
+                Some(Request::RequestRange(request)) => {
+                    let kvs = state.range(&request.key, &request.range_end);
+                    Response::ResponseRange(RangeResponse {
+                        header: state.header(),
+                        count: kvs.len() as i64,
+                        kvs,
+                        more: false,
+                    })
+                }
+                Some(Request::RequestPut(request)) => {
+                    Response::ResponsePut(state.put(request, &self.events))
+                }
+                Some(Request::RequestDeleteRange(request)) => {
+                    Response::ResponseDeleteRange(state.delete(request, &self.events))
+                }
+                None => return Err(tonic::Status::invalid_argument("empty request op")),
+            };
+            responses.push(ResponseOp {
+                response: Some(response),
+            });
+        }
+        Ok(tonic::Response::new(TxnResponse {
+            header: state.header(),
+            succeeded,
+            responses,
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl Watch for FakeEtcd {
+    type WatchStream = ResponseStream<WatchResponse>;
+
+    async fn watch(
+        &self,
+        request: tonic::Request<tonic::Streaming<WatchRequest>>,
+    ) -> Result<tonic::Response<Self::WatchStream>, tonic::Status> {
+        let mut requests = request.into_inner();
+        let (tx, rx) = mpsc::channel(1000);
+        let etcd = self.clone();
+        tokio::spawn(async move {
+            let mut watch_id = 0;
+            while let Ok(Some(request)) = requests.message().await {
+                let create = match request.request_union {
+                    Some(RequestUnion::CreateRequest(create)) => create,
+                    _ => continue,
+                };
+                watch_id += 1;
+                // 持有锁时订阅，保证历史和之后的变更之间没有遗漏
+                let (history, mut events) = {
+                    let state = etcd.state.lock().unwrap();
+                    let history = state
+                        .history
+                        .iter()
+                        .filter(|event| revision(event) >= create.start_revision)
+                        .filter(|event| matches(event, &create.key, &create.range_end))
+                        .cloned()
+                        .collect::<Vec<Event>>();
+                    (history, etcd.events.subscribe())
+                };
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let created = WatchResponse {
+                        watch_id,
+                        created: true,
+                        events: history,
+                        ..Default::default()
+                    };
+                    if tx.send(Ok(created)).await.is_err() {
+                        return;
+                    }
+                    while let Ok(event) = events.recv().await {
+                        if !matches(&event, &create.key, &create.range_end) {
+                            continue;
+                        }
+                        let response = WatchResponse {
+                            watch_id,
+                            events: vec![event],
+                            ..Default::default()
+                        };
+                        if tx.send(Ok(response)).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        Ok(tonic::Response::new(Box::pin(receiver_stream(rx))))
+    }
+}
+
+#[tonic::async_trait]
+impl Lease for FakeEtcd {
+    async fn lease_grant(
+        &self,
+        request: tonic::Request<LeaseGrantRequest>,
+    ) -> Result<tonic::Response<LeaseGrantResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let mut state = self.state.lock().unwrap();
+        state.next_lease_id += 1;
+        let id = state.next_lease_id;
+        state.leases.insert(id, request.ttl);
+        Ok(tonic::Response::new(LeaseGrantResponse {
+            header: state.header(),
+            id,
+            ttl: request.ttl,
+            error: String::new(),
+        }))
+    }
+
+    async fn lease_revoke(
+        &self,
+        request: tonic::Request<LeaseRevokeRequest>,
+    ) -> Result<tonic::Response<LeaseRevokeResponse>, tonic::Status> {
+        let mut state = self.state.lock().unwrap();
+        state.revoke(request.into_inner().id, &self.events);
+        Ok(tonic::Response::new(LeaseRevokeResponse {
+            header: state.header(),
+        }))
+    }
+
+    type LeaseKeepAliveStream = ResponseStream<LeaseKeepAliveResponse>;
+
+    async fn lease_keep_alive(
+        &self,
+        request: tonic::Request<tonic::Streaming<LeaseKeepAliveRequest>>,
+    ) -> Result<tonic::Response<Self::LeaseKeepAliveStream>, tonic::Status> {
+        let mut requests = request.into_inner();
+        let (tx, rx) = mpsc::channel(1000);
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            while let Ok(Some(request)) = requests.message().await {
+                let response = {
+                    let state = state.lock().unwrap();
+                    LeaseKeepAliveResponse {
+                        header: state.header(),
+                        id: request.id,
+                        // 租约不存在时返回 0
+                        ttl: state.leases.get(&request.id).cloned().unwrap_or_default(),
+                    }
+                };
+                if tx.send(Ok(response)).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(tonic::Response::new(Box::pin(receiver_stream(rx))))
+    }
+}
+
+fn in_range(key: &[u8], start: &[u8], end: &[u8]) -> bool {
+    match end {
+        [] => key == start,
+        [0] => key >= start,
+        _ => key >= start && key < end,
+    }
+}
+
+fn revision(event: &Event) -> i64 {
+    event.kv.as_ref().map_or(0, |kv| kv.mod_revision)
+}
+
+fn matches(event: &Event, start: &[u8], end: &[u8]) -> bool {
+    event
+        .kv
+        .as_ref()
+        .is_some_and(|kv| in_range(&kv.key, start, end))
+}
@@ -0,0 +1,261 @@
+//! 集群全局的保留消息
+//!
+//! 每个节点保存一份完整的保留消息，本地写入时广播给所有对等节点，
+//! 并定期从对等节点拉取全量数据做反熵，弥补节点不可用期间丢失的变更。
+//! 每个 topic 带一个版本向量，用于判断两次写入的先后，
+//! 并发写入时按固定规则选出胜者，保证所有节点最终一致
+//! 删除的消息保留删除标记，超过保留时间后清理
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use gecko_mqtt_proto::RetainMessage;
+
+use crate::network::{topic, v4::Publish};
+
+use super::{Error, NodeId};
+
+/// 版本向量, key = node_id, value = 该节点上的写入次数
+type Version = HashMap<NodeId, u64>;
+
+struct Retained {
+    /// payload 为空表示已删除，保留版本用于和其它节点比较
+    publish: Publish,
+    version: Version,
+    /// 写入此消息的节点
+    writer: NodeId,
+    /// 本节点写入或收到删除的时间，没有删除时为 None
+    deleted_at: Option<Instant>,
+}
+
+impl Retained {
+    fn set(&mut self, publish: Publish, writer: NodeId) {
+        self.deleted_at = publish.payload.is_empty().then(Instant::now);
+        self.publish = publish;
+        self.writer = writer;
+    }
+}
+
+pub(crate) struct RetainStore {
+    /// 当前节点 id
+    node_id: NodeId,
+    /// key = topic
+    retains: HashMap<String, Retained>,
+}
+
+impl RetainStore {
+    pub(crate) fn new(node_id: NodeId) -> Self {
+        Self {
+            node_id,
+            retains: HashMap::new(),
+        }
+    }
+
+    /// 本节点写入保留消息，payload 为空时删除
+    /// 返回需要广播给对等节点的消息
+    pub(crate) fn insert(&mut self, publish: &Publish) -> RetainMessage {
+        let publish = Publish {
+            dup: false,
+            retain: true,
+            packet_id: 0,
+            ..publish.clone()
+        };
+        let retained = self
+            .retains
+            .entry(publish.topic.to_string())
+            .or_insert_with(|| Retained {
+                publish: publish.clone(),
+                version: Version::new(),
+                writer: self.node_id,
+                deleted_at: None,
+            });
+        *retained.version.entry(self.node_id).or_default() += 1;
+        retained.set(publish, self.node_id);
+        retain_message(self.node_id, retained)
+    }
+
+    /// 合并对等节点发来的保留消息，返回本地的消息是否有变化
+    pub(crate) fn merge(&mut self, message: RetainMessage) -> Result<bool, Error> {
+        let publish = message.publish.ok_or(Error::MissingRetainPublish)?;
+        let publish = Publish {
+            retain: true,
+            ..super::forwarded_publish(publish)?
+        };
+        let retained = match self.retains.get_mut(publish.topic.as_str()) {
+            Some(retained) => retained,
+            None => {
+                self.retains.insert(
+                    publish.topic.to_string(),
+                    Retained {
+                        deleted_at: publish.payload.is_empty().then(Instant::now),
+                        publish,
+                        version: message.version,
+                        writer: message.writer,
+                    },
+                );
+                return Ok(true);
+            }
+        };
+
+        match compare(&message.version, &retained.version) {
+            Some(Ordering::Greater) => {
+                retained.set(publish, message.writer);
+                retained.version = message.version;
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+            None => {
+                // 并发写入，写入次数多的胜出，相同时 node_id 大的胜出
+                let wins = (sum(&message.version), message.writer)
+                    > (sum(&retained.version), retained.writer);
+                // 合并后的版本比双方都新，之后再收到其中任何一方都会被忽略
+                for (node_id, count) in message.version {
+                    let current = retained.version.entry(node_id).or_default();
+                    *current = (*current).max(count);
+                }
+                if wins {
+                    retained.set(publish, message.writer);
+                }
+                Ok(wins)
+            }
+        }
+    }
+
+    /// 与 filter 匹配的保留消息，不包含已删除的
+    pub(crate) fn matches(&self, filter: &str) -> Vec<&Publish> {
+        self.retains
+            .values()
+            .filter(|retained| !retained.publish.payload.is_empty())
+            .filter(|retained| topic::matches(&retained.publish.topic, filter))
+            .map(|retained| &retained.publish)
+            .collect()
+    }
+
+    /// 保留消息的数量，不包含已删除的
+    pub(crate) fn len(&self) -> usize {
+        self.retains
+            .values()
+            .filter(|retained| !retained.publish.payload.is_empty())
+            .count()
+    }
+
+    /// 清理超过保留时间的删除标记，返回清理的数量
+    pub(crate) fn purge(&mut self, ttl: Duration) -> usize {
+        let len = self.retains.len();
+        self.retains.retain(|_, retained| {
+            retained
+                .deleted_at
+                .is_none_or(|deleted_at| deleted_at.elapsed() < ttl)
+        });
+        len - self.retains.len()
+    }
+
+    /// 所有的保留消息，包含已删除的，用于对等节点同步
+    pub(crate) fn messages(&self) -> Vec<RetainMessage> {
+        self.retains
+            .values()
+            .map(|retained| retain_message(self.node_id, retained))
+            .collect()
+    }
+}
+
+fn retain_message(node_id: NodeId, retained: &Retained) -> RetainMessage {
+    RetainMessage {
+        publish: Some(super::forward_request(node_id, &retained.publish)),
+        version: retained.version.clone(),
+        writer: retained.writer,
+    }
+}
+
+/// 比较两个版本向量，并发时返回 None
+fn compare(a: &Version, b: &Version) -> Option<Ordering> {
+    let (mut greater, mut less) = (false, false);
+    for node_id in a.keys().chain(b.keys()) {
+        let x = a.get(node_id).copied().unwrap_or_default();
+        let y = b.get(node_id).copied().unwrap_or_default();
+        greater |= x > y;
+        less |= x < y;
+    }
+    match (greater, less) {
+        (true, true) => None,
+        (true, false) => Some(Ordering::Greater),
+        (false, true) => Some(Ordering::Less),
+        (false, false) => Some(Ordering::Equal),
+    }
+}
+
+fn sum(version: &Version) -> u64 {
+    version.values().sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::network::packet::QoS;
+
+    use super::*;
+
+    fn publish(payload: &'static str) -> Publish {
+        Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            topic: "iot/pid/dn".into(),
+            packet_id: 1,
+            payload: Bytes::from(payload),
+            properties: None,
+            encoded: Default::default(),
+        }
+    }
+
+    #[test]
+    fn retain_store_converges() {
+        let mut node1 = RetainStore::new(1);
+        let mut node2 = RetainStore::new(2);
+
+        // 顺序写入，新版本覆盖旧版本
+        let first = node1.insert(&publish("a"));
+        assert!(node2.merge(first.clone()).unwrap());
+        let second = node2.insert(&publish("b"));
+        assert!(node1.merge(second).unwrap());
+        assert!(!node1.merge(first).unwrap());
+        assert_eq!(node1.matches("iot/+/dn")[0].payload, Bytes::from("b"));
+
+        // 并发写入，两个节点选出相同的胜者
+        let from1 = node1.insert(&publish("c"));
+        let from2 = node2.insert(&publish("d"));
+        node1.merge(from2).unwrap();
+        node2.merge(from1).unwrap();
+        assert_eq!(node1.matches("iot/#")[0].payload, Bytes::from("d"));
+        assert_eq!(node2.matches("iot/#")[0].payload, Bytes::from("d"));
+
+        // 删除后不再匹配
+        let deleted = node2.insert(&publish(""));
+        assert!(node1.merge(deleted).unwrap());
+        assert!(node1.matches("iot/#").is_empty());
+        assert_eq!(node1.messages().len(), 1);
+    }
+
+    #[test]
+    fn purge_expired_tombstones() {
+        let mut node1 = RetainStore::new(1);
+        let mut node2 = RetainStore::new(2);
+        node1.insert(&publish("a"));
+        let deleted = node2.insert(&Publish {
+            topic: "iot/other".into(),
+            ..publish("")
+        });
+        node1.merge(deleted).unwrap();
+        assert_eq!(node1.messages().len(), 2);
+
+        // 未过期的删除标记和没有删除的消息都保留
+        assert_eq!(node1.purge(Duration::from_secs(60)), 0);
+        assert_eq!(node1.purge(Duration::ZERO), 1);
+        assert_eq!(node1.messages().len(), 1);
+        assert_eq!(node1.len(), 1);
+    }
+}
@@ -4,6 +4,213 @@
 //！async fn close(&self) -> Result<(), Self::Error>;
 //! async fn add_new_session(session: SessionState) -> Result<(), Self::Error>;
 
-pub(crate) struct Storage {}
+use std::collections::{HashMap, HashSet};
 
-impl Storage {}
+use crate::{network::topic, protocol::subscripton::SubscriptionTree};
+
+use super::{NodeId, NodeStatus};
+
+/// 路由表: topic-filter -> node_id
+/// 会话表: client_id -> node_id
+/// 节点表: node_id -> 节点状态
+/// 每个节点保存一份完整的路由表和会话表，通过节点间同步变更保持一致
+pub(crate) struct Storage {
+    /// 当前节点 id
+    node_id: NodeId,
+    /// 当前节点上每个 filter 的订阅数量，key = topic-filter
+    /// 只有在第一个订阅加入/最后一个订阅移除时，才需要同步给其它节点
+    local_routes: HashMap<String, usize>,
+    /// 全局精确路由, key = topic-filter, value = node_id
+    concrete_routes: HashMap<String, HashSet<NodeId>>,
+    /// 全局模糊路由, T = node_id
+    wild_routes: SubscriptionTree<NodeId>,
+    /// 模糊路由在订阅树中的 token, key = (topic-filter, node_id)
+    wild_tokens: HashMap<(String, NodeId), u64>,
+    /// 客户端会话所在的节点, key = client_id
+    sessions: HashMap<String, NodeId>,
+    /// 对等节点的状态, key = node_id
+    nodes: HashMap<NodeId, NodeStatus>,
+}
+
+impl Storage {
+    pub(crate) fn new(node_id: NodeId) -> Self {
+        Self {
+            node_id,
+            local_routes: HashMap::new(),
+            concrete_routes: HashMap::new(),
+            wild_routes: SubscriptionTree::new(),
+            wild_tokens: HashMap::new(),
+            sessions: HashMap::new(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// 当前节点新增一个 filter 的订阅
+    /// 返回 true 表示这是当前节点上该 filter 的第一个订阅，需要同步给其它节点
+    pub(crate) fn add_local_route(&mut self, filter: &str) -> bool {
+        let count = self.local_routes.entry(filter.into()).or_insert(0);
+        *count += 1;
+        if *count > 1 {
+            return false;
+        }
+        self.add_route(filter, self.node_id);
+        true
+    }
+
+    /// 当前节点移除一个 filter 的订阅
+    /// 返回 true 表示当前节点上已没有该 filter 的订阅，需要同步给其它节点
+    pub(crate) fn remove_local_route(&mut self, filter: &str) -> bool {
+        match self.local_routes.get_mut(filter) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                self.local_routes.remove(filter);
+                self.remove_route(filter, self.node_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 添加一条路由
+    pub(crate) fn add_route(&mut self, filter: &str, node_id: NodeId) {
+        if topic::filter_has_wildcards(filter) {
+            let key = (filter.to_owned(), node_id);
+            if !self.wild_tokens.contains_key(&key) {
+                let token = self.wild_routes.insert(filter, node_id);
+                self.wild_tokens.insert(key, token);
+            }
+        } else {
+            self.concrete_routes
+                .entry(filter.into())
+                .or_default()
+                .insert(node_id);
+        }
+    }
+
+    /// 删除一条路由
+    pub(crate) fn remove_route(&mut self, filter: &str, node_id: NodeId) {
+        if topic::filter_has_wildcards(filter) {
+            if let Some(token) = self.wild_tokens.remove(&(filter.to_owned(), node_id)) {
+                self.wild_routes.remove(filter, token);
+            }
+        } else if let Some(nodes) = self.concrete_routes.get_mut(filter) {
+            nodes.remove(&node_id);
+            if nodes.is_empty() {
+                self.concrete_routes.remove(filter);
+            }
+        }
+    }
+
+    /// 客户端会话所在节点
+    pub(crate) fn session_owner(&self, client_id: &str) -> Option<NodeId> {
+        self.sessions.get(client_id).cloned()
+    }
+
+    /// 会话迁移到了 node_id 节点，返回之前记录的节点
+    pub(crate) fn set_session_owner(&mut self, client_id: &str, node_id: NodeId) -> Option<NodeId> {
+        self.sessions.insert(client_id.into(), node_id)
+    }
+
+    /// 删除会话所有权，只有会话仍属于 node_id 时才删除
+    /// 避免迁移过程中，旧节点的删除消息覆盖新节点的记录
+    pub(crate) fn remove_session_owner(&mut self, client_id: &str, node_id: NodeId) {
+        if self.sessions.get(client_id) == Some(&node_id) {
+            self.sessions.remove(client_id);
+        }
+    }
+
+    /// 更新对等节点的状态，返回之前的状态
+    pub(crate) fn set_node_status(
+        &mut self,
+        node_id: NodeId,
+        status: NodeStatus,
+    ) -> Option<NodeStatus> {
+        self.nodes.insert(node_id, status)
+    }
+
+    pub(crate) fn node_status(&self, node_id: NodeId) -> Option<NodeStatus> {
+        self.nodes.get(&node_id).copied()
+    }
+
+    /// 删除 node_id 节点的所有路由和会话，节点宕机时调用
+    pub(crate) fn remove_node(&mut self, node_id: NodeId) {
+        self.concrete_routes.retain(|_, nodes| {
+            nodes.remove(&node_id);
+            !nodes.is_empty()
+        });
+        let filters = self
+            .wild_tokens
+            .keys()
+            .filter(|(_, id)| *id == node_id)
+            .map(|(filter, _)| filter.clone())
+            .collect::<Vec<String>>();
+        for filter in filters {
+            self.remove_route(&filter, node_id);
+        }
+        self.sessions.retain(|_, owner| *owner != node_id);
+    }
+
+    /// 当前节点上有订阅的 filter，节点重新加入时同步给它
+    pub(crate) fn local_filters(&self) -> Vec<String> {
+        self.local_routes.keys().cloned().collect()
+    }
+
+    /// 当前节点上的会话，节点重新加入时同步给它
+    pub(crate) fn local_sessions(&self) -> Vec<String> {
+        self.sessions
+            .iter()
+            .filter(|(_, owner)| **owner == self.node_id)
+            .map(|(client_id, _)| client_id.clone())
+            .collect()
+    }
+
+    /// 查找订阅了 topic 的其它节点（不包含当前节点）
+    pub(crate) fn remote_nodes(&self, topic: &str) -> HashSet<NodeId> {
+        let mut nodes = HashSet::new();
+        if let Some(concrete) = self.concrete_routes.get(topic) {
+            nodes.extend(concrete);
+        }
+        nodes.extend(self.wild_routes.matches(topic));
+        nodes.remove(&self.node_id);
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_table_works() {
+        let mut storage = Storage::new(1);
+
+        // 本地订阅只在第一次添加时需要同步
+        assert!(storage.add_local_route("iot/pid/dn"));
+        assert!(!storage.add_local_route("iot/pid/dn"));
+
+        storage.add_route("iot/pid/dn", 2);
+        storage.add_route("iot/+/dn", 3);
+        storage.add_route("iot/+/dn", 3);
+
+        let nodes = storage.remote_nodes("iot/pid/dn");
+        assert_eq!(nodes, HashSet::from_iter([2, 3]));
+
+        // 本地订阅在最后一次移除时需要同步
+        assert!(!storage.remove_local_route("iot/pid/dn"));
+        assert!(storage.remove_local_route("iot/pid/dn"));
+        assert!(!storage.remove_local_route("iot/pid/dn"));
+
+        storage.remove_route("iot/+/dn", 3);
+        assert_eq!(storage.remote_nodes("iot/pid/dn"), HashSet::from_iter([2]));
+
+        // 节点宕机后，删除它的所有路由和会话
+        storage.add_route("iot/#", 2);
+        storage.set_session_owner("c1", 2);
+        storage.remove_node(2);
+        assert!(storage.remote_nodes("iot/pid/dn").is_empty());
+        assert_eq!(storage.session_owner("c1"), None);
+    }
+}
@@ -0,0 +1,40 @@
+//! 报文编解码入口，仅供基准测试使用，需要开启 bench feature
+
+use bytes::BytesMut;
+
+pub use crate::network::{
+    packet::{v5::PublishProperties, ByteStr, Error, QoS},
+    v4::{EncodedBody, Packet, Publish},
+};
+
+use crate::network::packet::{v5, Protocol};
+
+/// 严格模式解码一个 3.1.1 报文
+pub fn read(stream: &mut BytesMut) -> Result<Packet, Error> {
+    Packet::read(stream, true, usize::MAX)
+}
+
+/// 按 3.1.1 编码一个报文
+pub fn write(packet: &Packet, stream: &mut BytesMut) -> Result<(), Error> {
+    packet.write(stream, Protocol::V4)
+}
+
+/// 按 v5 编码一个 publish，报文体在所有 clone 之间共享，只编码一次
+pub fn write_v5(publish: &Publish, stream: &mut BytesMut) -> Result<(), Error> {
+    publish.write(stream, Protocol::V5)
+}
+
+/// 按 v5 编码一个 publish，每次都重新编码属性，作为共享报文体的对照
+pub fn write_v5_unshared(publish: &Publish, stream: &mut BytesMut) -> Result<(), Error> {
+    let properties = publish.properties.as_ref().map_or(0, |p| p.len());
+    let body_len = v5::properties_len(properties) + publish.payload.len();
+    publish.write_header(stream, body_len)?;
+    let empty = PublishProperties::default();
+    publish
+        .properties
+        .as_deref()
+        .unwrap_or(&empty)
+        .write(stream)?;
+    stream.extend_from_slice(&publish.payload);
+    Ok(())
+}
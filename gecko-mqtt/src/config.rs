@@ -1,29 +1,467 @@
-use tokio::{fs, io::AsyncReadExt};
+use std::{io, str::FromStr};
 
-#[derive(Debug, serde::Deserialize)]
+use tokio::fs;
+
+use crate::network::packet::QoS;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Read config file {0} error: {1}")]
+    Read(String, io::Error),
+    #[error("Parse config error: {0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
 pub struct Config {
     pub broker: Broker,
     pub session: Session,
+    #[serde(default)]
+    pub cluster: Cluster,
+    /// prometheus 指标导出，不配置时不导出
+    #[serde(default)]
+    pub metrics: Option<Metrics>,
+    /// 连接和发布的限流，默认不限制
+    #[serde(default)]
+    pub limit: Limit,
+    /// 主题重写规则，按顺序匹配，只使用第一条匹配的规则
+    #[serde(default)]
+    pub rewrite: Vec<RewriteRule>,
+    /// 创建新会话后自动订阅的主题
+    #[serde(default)]
+    pub auto_subscribe: Vec<AutoSubscribe>,
 }
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Deserialize)]
 pub struct Broker {
     pub client_addr: String,
     pub peer_addr: String,
+    /// 是否按 3.1.1 规范严格校验客户端报文，关闭后兼容不规范的客户端
+    #[serde(default = "default_strict")]
+    pub strict: bool,
+    /// 客户端报文的最大长度（字节），超过时断开连接，同时限制了每个连接读缓冲区的大小
+    #[serde(default = "default_max_packet_size")]
+    pub max_packet_size: usize,
+    /// 每个连接写缓冲区的高水位（字节），超过后暂停从 router 接收消息，直到写入 socket
+    #[serde(default = "default_write_high_water")]
+    pub write_high_water: usize,
+    /// 客户端消费太慢，发往它的消息队列已满时的处理策略
+    #[serde(default)]
+    pub slow_consumer: SlowConsumer,
+    /// 处理客户端报文的 worker 数量，会话按 client id 分片到各个 worker，0 表示使用 CPU 核数
+    #[serde(default)]
+    pub workers: usize,
+    /// 关闭 broker 时等待客户端连接断开的最长时间（秒），超时后不再等待
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// 发布 $SYS 主题统计消息的间隔（秒），0 表示不发布
+    #[serde(default = "default_sys_interval")]
+    pub sys_interval: u64,
+    /// 是否在 peer_addr 的 grpc 服务上提供管理接口
+    #[serde(default = "default_admin_api")]
+    pub admin_api: bool,
+    /// 禁止规则的持久化文件，启动时加载，变更后写入，不配置时只保存在内存中
+    #[serde(default)]
+    pub ban_file: Option<String>,
+}
+
+impl Default for Broker {
+    fn default() -> Self {
+        Self {
+            client_addr: "0.0.0.0:1883".into(),
+            peer_addr: "0.0.0.0:1888".into(),
+            strict: default_strict(),
+            max_packet_size: default_max_packet_size(),
+            write_high_water: default_write_high_water(),
+            slow_consumer: SlowConsumer::default(),
+            workers: 0,
+            shutdown_timeout: default_shutdown_timeout(),
+            sys_interval: default_sys_interval(),
+            admin_api: default_admin_api(),
+            ban_file: None,
+        }
+    }
+}
+
+/// 客户端消费太慢时的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowConsumer {
+    /// 丢弃 QoS0 消息，QoS1/QoS2 消息保存在会话中，队列空闲后再发送
+    #[default]
+    DropQos0,
+    /// 所有消息都保存在会话中，队列空闲后再发送
+    Pause,
+    /// 断开连接
+    Disconnect,
+}
+
+fn default_strict() -> bool {
+    true
+}
+
+fn default_max_packet_size() -> usize {
+    1024 * 1024
+}
+
+fn default_write_high_water() -> usize {
+    64 * 1024
+}
+
+fn default_shutdown_timeout() -> u64 {
+    10
+}
+
+fn default_sys_interval() -> u64 {
+    60
+}
+
+fn default_admin_api() -> bool {
+    true
+}
+
+/// 连接和发布的限流，使用令牌桶，桶的容量为一秒的速率
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct Limit {
+    /// 每个监听器每秒接受的新连接数，超过时暂停接受连接，0 表示不限制
+    #[serde(default)]
+    pub listener_conn_rate: u32,
+    /// 每个来源 IP 每秒的新连接数，超过时直接关闭连接，0 表示不限制
+    #[serde(default)]
+    pub ip_conn_rate: u32,
+    /// 每个客户端的发布限流，认证时可以通过 [`AuthResult`] 为单个客户端指定
+    ///
+    /// [`AuthResult`]: crate::AuthResult
+    #[serde(default)]
+    pub client: ClientLimit,
 }
 
+/// 客户端发布消息的限流
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+pub struct ClientLimit {
+    /// 每秒允许发布的消息数，0 表示不限制
+    #[serde(default)]
+    pub messages_per_sec: u32,
+    /// 每秒允许发布的 payload 字节数，0 表示不限制
+    #[serde(default)]
+    pub bytes_per_sec: u64,
+    /// 超过限制时的处理策略
+    #[serde(default)]
+    pub policy: LimitPolicy,
+}
+
+impl ClientLimit {
+    pub fn unlimited(&self) -> bool {
+        self.messages_per_sec == 0 && self.bytes_per_sec == 0
+    }
+}
+
+/// 客户端发布超过限制时的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitPolicy {
+    /// 暂停读取客户端的报文，直到令牌恢复，由 tcp 流控反压客户端
+    #[default]
+    Pause,
+    /// 断开连接，v5 客户端收到原因码 Quota Exceeded
+    Disconnect,
+}
+
+/// 主题重写规则，在订阅匹配和路由之前替换客户端使用的主题
 #[derive(Debug, Clone, serde::Deserialize)]
+pub struct RewriteRule {
+    /// 规则应用于哪些报文
+    #[serde(default)]
+    pub action: RewriteAction,
+    /// 匹配主题的正则表达式，需要匹配整个主题时使用 ^ 和 $
+    pub re: String,
+    /// 替换后的主题，$1 或 ${name} 引用捕获组，%c 为 client id，%u 为用户名
+    /// 包含 %u 的规则不应用于没有用户名的客户端
+    pub dest: String,
+}
+
+/// 主题重写规则应用于哪些报文
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RewriteAction {
+    /// publish 的主题
+    Publish,
+    /// subscribe 和 unsubscribe 的 filter
+    Subscribe,
+    /// 都应用
+    #[default]
+    All,
+}
+
+/// 自动订阅的主题
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct AutoSubscribe {
+    /// filter 模板，%c 为 client id，%u 为用户名，包含 %u 时不应用于没有用户名的客户端
+    pub topic: String,
+    /// 授予的最大 QoS，和客户端订阅一样，投递时取消息和订阅中较小的一个
+    #[serde(default = "default_auto_subscribe_qos")]
+    pub qos: QoS,
+    /// 订阅后是否发送匹配的保留消息，对应 v5 订阅选项中的 Retain Handling
+    #[serde(default)]
+    pub retain_handling: RetainHandling,
+    /// 不接收自己发布的消息，对应 v5 订阅选项中的 No Local
+    #[serde(default)]
+    pub no_local: bool,
+    /// 投递时保留消息的 retain 标记，对应 v5 订阅选项中的 Retain As Published
+    #[serde(default)]
+    pub retain_as_published: bool,
+}
+
+impl AutoSubscribe {
+    pub fn new(topic: impl Into<String>, qos: QoS) -> Self {
+        Self {
+            topic: topic.into(),
+            qos,
+            retain_handling: RetainHandling::default(),
+            no_local: false,
+            retain_as_published: false,
+        }
+    }
+
+    /// 替换占位符后的 filter
+    /// 缺少用户名，或者 client id、用户名中有通配符时返回 None，避免客户端借此订阅其它主题
+    pub fn filter(&self, client_id: &str, username: Option<&str>) -> Option<String> {
+        let wildcard = |s: &str| s.contains(['+', '#']);
+        let mut filter = self.topic.clone();
+        if filter.contains("%c") {
+            if wildcard(client_id) {
+                return None;
+            }
+            filter = filter.replace("%c", client_id);
+        }
+        if filter.contains("%u") {
+            let username = username.filter(|username| !wildcard(username))?;
+            filter = filter.replace("%u", username);
+        }
+        Some(filter)
+    }
+}
+
+fn default_auto_subscribe_qos() -> QoS {
+    QoS::AtMostOnce
+}
+
+/// 订阅时是否发送匹配的保留消息
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetainHandling {
+    /// 订阅时发送
+    #[default]
+    Send,
+    /// 不发送
+    DoNotSend,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
 pub struct Session {
     #[serde(default)]
     pub expire_interval: Option<u64>,
 }
 
+/// 通过 http 以 prometheus 文本格式导出指标
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Metrics {
+    /// http 服务的监听地址，如 0.0.0.0:9090
+    pub addr: String,
+    /// 指标的路径
+    #[serde(default = "default_metrics_path")]
+    pub path: String,
+}
+
+fn default_metrics_path() -> String {
+    "/metrics".into()
+}
+
+/// 集群配置，不配置时为单机模式
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Cluster {
+    /// 当前节点 id
+    #[serde(default)]
+    pub node_id: u64,
+    /// 集群中的其它节点
+    #[serde(default)]
+    pub peers: Vec<Peer>,
+    /// 配置后使用 etcd 协调集群，节点和路由信息通过 etcd 同步，忽略 peers
+    #[serde(default)]
+    pub etcd: Option<Etcd>,
+    /// 发往每个对等节点的消息队列长度，队列满时丢弃新的消息
+    #[serde(default = "default_peer_queue_size")]
+    pub peer_queue_size: usize,
+    /// 每批转发给对等节点的最大 publish 消息数
+    #[serde(default = "default_forward_batch_size")]
+    pub forward_batch_size: usize,
+    /// 发给对等节点的单次请求的超时时间（秒）
+    #[serde(default = "default_peer_request_timeout")]
+    pub peer_request_timeout: u64,
+    /// 对等节点健康检查间隔（秒）
+    #[serde(default = "default_health_check_interval")]
+    pub health_check_interval: u64,
+    /// 对等节点重连的最大退避时间（秒）
+    #[serde(default = "default_reconnect_max_backoff")]
+    pub reconnect_max_backoff: u64,
+    /// 从对等节点拉取全量保留消息的间隔（秒）
+    #[serde(default = "default_retain_sync_interval")]
+    pub retain_sync_interval: u64,
+    /// 已删除保留消息的删除标记保留多久（秒），需要远大于同步间隔和节点可能失联的时间
+    /// 过期前没有同步到删除标记的节点，会把旧消息重新同步回来
+    #[serde(default = "default_retain_tombstone_ttl")]
+    pub retain_tombstone_ttl: u64,
+    /// 连续多少次心跳失败后，将对等节点标记为可疑
+    #[serde(default = "default_suspect_threshold")]
+    pub suspect_threshold: u32,
+    /// 连续多少次心跳失败后，将对等节点标记为宕机，并清理它的路由和会话
+    #[serde(default = "default_down_threshold")]
+    pub down_threshold: u32,
+}
+
+impl Default for Cluster {
+    fn default() -> Self {
+        Self {
+            node_id: 0,
+            peers: Vec::new(),
+            etcd: None,
+            peer_queue_size: default_peer_queue_size(),
+            forward_batch_size: default_forward_batch_size(),
+            peer_request_timeout: default_peer_request_timeout(),
+            health_check_interval: default_health_check_interval(),
+            reconnect_max_backoff: default_reconnect_max_backoff(),
+            retain_sync_interval: default_retain_sync_interval(),
+            retain_tombstone_ttl: default_retain_tombstone_ttl(),
+            suspect_threshold: default_suspect_threshold(),
+            down_threshold: default_down_threshold(),
+        }
+    }
+}
+
+fn default_peer_queue_size() -> usize {
+    1000
+}
+
+fn default_forward_batch_size() -> usize {
+    100
+}
+
+fn default_peer_request_timeout() -> u64 {
+    10
+}
+
+fn default_health_check_interval() -> u64 {
+    5
+}
+
+fn default_reconnect_max_backoff() -> u64 {
+    30
+}
+
+fn default_retain_sync_interval() -> u64 {
+    30
+}
+
+fn default_retain_tombstone_ttl() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_suspect_threshold() -> u32 {
+    1
+}
+
+fn default_down_threshold() -> u32 {
+    3
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Peer {
+    /// 节点 id
+    pub id: u64,
+    /// 节点 grpc 地址，如 http://127.0.0.1:1888
+    pub addr: String,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Etcd {
+    /// etcd 地址，如 http://127.0.0.1:2379
+    pub endpoints: Vec<String>,
+    /// 集群数据在 etcd 中的 key 前缀
+    #[serde(default = "default_etcd_prefix")]
+    pub prefix: String,
+    /// 节点租约的有效期（秒），节点宕机后超过此时间，其它节点才会感知到
+    #[serde(default = "default_etcd_lease_ttl")]
+    pub lease_ttl: u64,
+    /// 其它节点访问本节点使用的 grpc 地址，默认为 http://{对等节点 grpc 服务实际绑定的地址}
+    #[serde(default)]
+    pub advertise_addr: Option<String>,
+}
+
+fn default_etcd_prefix() -> String {
+    "/gecko".into()
+}
+
+fn default_etcd_lease_ttl() -> u64 {
+    10
+}
+
 impl Config {
-    pub async fn from_path(path: &str) -> Self {
-        let mut file = fs::File::open(path).await.unwrap();
-        let mut s = String::new();
-        file.read_to_string(&mut s).await.unwrap();
+    pub async fn from_path(path: &str) -> Result<Self, Error> {
+        let s = fs::read_to_string(path)
+            .await
+            .map_err(|e| Error::Read(path.into(), e))?;
+        s.parse()
+    }
+}
+
+impl FromStr for Config {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_subscribe_filter_placeholders() {
+        let cfg: Config = r#"
+            [broker]
+            client_addr = "0.0.0.0:1883"
+            peer_addr = "0.0.0.0:1888"
+            [session]
+            [[auto_subscribe]]
+            topic = "cmd/%c"
+            qos = 1
+            [[auto_subscribe]]
+            topic = "tenant/%u/cmd"
+            retain_handling = "do_not_send"
+            no_local = true
+            retain_as_published = true
+        "#
+        .parse()
+        .unwrap();
+        let [by_client, by_user] = &cfg.auto_subscribe[..] else {
+            panic!("unexpected {:?}", cfg.auto_subscribe);
+        };
+        assert_eq!(by_client.qos, QoS::AtLeastOnce);
+        assert_eq!(by_user.retain_handling, RetainHandling::DoNotSend);
+        assert!(!by_client.no_local && !by_client.retain_as_published);
+        assert!(by_user.no_local && by_user.retain_as_published);
 
-        toml::from_str::<Config>(&s).unwrap()
+        assert_eq!(by_client.filter("d1", None).as_deref(), Some("cmd/d1"));
+        assert_eq!(by_user.filter("d1", None), None);
+        assert_eq!(
+            by_user.filter("d1", Some("acme")).as_deref(),
+            Some("tenant/acme/cmd")
+        );
+        // 不能通过 client id 或用户名订阅通配符
+        assert_eq!(by_client.filter("#", None), None);
+        assert_eq!(by_user.filter("d1", Some("+")), None);
+        let err = toml::from_str::<AutoSubscribe>("topic = \"t\"\nqos = 3").unwrap_err();
+        assert!(err.to_string().contains("qos"), "{}", err);
     }
 }
@@ -0,0 +1,318 @@
+//! 多个钩子组成的调用链，按优先级从高到低依次调用，优先级相同时按添加的顺序
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::{
+    config::AutoSubscribe, AuthResult, DropReason, Hook, Login, Publish, PublishDecision, QoS,
+};
+
+#[derive(Default, Clone)]
+pub(crate) struct Hooks {
+    hooks: Vec<Arc<dyn Hook>>,
+}
+
+impl Hooks {
+    pub fn new(mut hooks: Vec<Arc<dyn Hook>>) -> Self {
+        hooks.sort_by_key(|hook| -hook.priority());
+        Self { hooks }
+    }
+}
+
+#[async_trait]
+impl Hook for Hooks {
+    /// 所有钩子都认证通过才允许连接，没有钩子时允许
+    /// 多个钩子指定了限流时，后面的覆盖前面的
+    async fn authenticate(&self, login: Login) -> AuthResult {
+        let mut result = AuthResult::Allow;
+        for hook in self.hooks.iter() {
+            match hook.authenticate(login.clone()).await {
+                AuthResult::Deny => return AuthResult::Deny,
+                AuthResult::Allow => {}
+                limit => result = limit,
+            }
+        }
+        result
+    }
+
+    async fn connected(&self, client_id: &str) {
+        for hook in self.hooks.iter() {
+            hook.connected(client_id).await;
+        }
+    }
+
+    async fn disconnect(&self, client_id: &str) {
+        for hook in self.hooks.iter() {
+            hook.disconnect(client_id).await;
+        }
+    }
+
+    async fn node_up(&self, node_id: u64) {
+        for hook in self.hooks.iter() {
+            hook.node_up(node_id).await;
+        }
+    }
+
+    async fn node_down(&self, node_id: u64) {
+        for hook in self.hooks.iter() {
+            hook.node_down(node_id).await;
+        }
+    }
+
+    /// 依次交给每个钩子，丢弃或拒绝后不再调用后面的钩子
+    /// 后面的钩子看到的是前面修改过的消息
+    async fn on_publish(&self, client_id: &str, publish: &mut Publish) -> PublishDecision {
+        let mut decision = PublishDecision::Allow;
+        for hook in self.hooks.iter() {
+            match hook.on_publish(client_id, publish).await {
+                PublishDecision::Allow => {}
+                PublishDecision::Modified => decision = PublishDecision::Modified,
+                rejected => return rejected,
+            }
+        }
+        decision
+    }
+
+    fn on_deliver(&self, client_id: &str, publish: &Publish) {
+        for hook in self.hooks.iter() {
+            hook.on_deliver(client_id, publish);
+        }
+    }
+
+    fn on_message_dropped(&self, client_id: &str, publish: &Publish, reason: DropReason) {
+        for hook in self.hooks.iter() {
+            hook.on_message_dropped(client_id, publish, reason);
+        }
+    }
+
+    async fn on_acked(&self, client_id: &str, publish: &Publish) {
+        for hook in self.hooks.iter() {
+            hook.on_acked(client_id, publish).await;
+        }
+    }
+
+    /// 所有钩子都允许才能订阅
+    async fn on_subscribe(&self, client_id: &str, filter: &str, qos: QoS) -> bool {
+        for hook in self.hooks.iter() {
+            if !hook.on_subscribe(client_id, filter, qos).await {
+                return false;
+            }
+        }
+        true
+    }
+
+    async fn on_unsubscribe(&self, client_id: &str, filter: &str) {
+        for hook in self.hooks.iter() {
+            hook.on_unsubscribe(client_id, filter).await;
+        }
+    }
+
+    async fn on_session_created(&self, client_id: &str) {
+        for hook in self.hooks.iter() {
+            hook.on_session_created(client_id).await;
+        }
+    }
+
+    async fn on_session_expired(&self, client_id: &str) {
+        for hook in self.hooks.iter() {
+            hook.on_session_expired(client_id).await;
+        }
+    }
+
+    /// 合并所有钩子返回的主题
+    async fn auto_subscribe(&self, client_id: &str, username: Option<&str>) -> Vec<AutoSubscribe> {
+        let mut subscribes = Vec::new();
+        for hook in self.hooks.iter() {
+            subscribes.extend(hook.auto_subscribe(client_id, username).await);
+        }
+        subscribes
+    }
+}
+
+/// 共享的钩子，如同时在 broker 和业务代码中使用
+#[async_trait]
+impl<H: Hook + ?Sized> Hook for Arc<H> {
+    fn priority(&self) -> i32 {
+        (**self).priority()
+    }
+
+    async fn authenticate(&self, login: Login) -> AuthResult {
+        (**self).authenticate(login).await
+    }
+
+    async fn connected(&self, client_id: &str) {
+        (**self).connected(client_id).await
+    }
+
+    async fn disconnect(&self, client_id: &str) {
+        (**self).disconnect(client_id).await
+    }
+
+    async fn node_up(&self, node_id: u64) {
+        (**self).node_up(node_id).await
+    }
+
+    async fn node_down(&self, node_id: u64) {
+        (**self).node_down(node_id).await
+    }
+
+    async fn on_publish(&self, client_id: &str, publish: &mut Publish) -> PublishDecision {
+        (**self).on_publish(client_id, publish).await
+    }
+
+    fn on_deliver(&self, client_id: &str, publish: &Publish) {
+        (**self).on_deliver(client_id, publish)
+    }
+
+    fn on_message_dropped(&self, client_id: &str, publish: &Publish, reason: DropReason) {
+        (**self).on_message_dropped(client_id, publish, reason)
+    }
+
+    async fn on_acked(&self, client_id: &str, publish: &Publish) {
+        (**self).on_acked(client_id, publish).await
+    }
+
+    async fn on_subscribe(&self, client_id: &str, filter: &str, qos: QoS) -> bool {
+        (**self).on_subscribe(client_id, filter, qos).await
+    }
+
+    async fn on_unsubscribe(&self, client_id: &str, filter: &str) {
+        (**self).on_unsubscribe(client_id, filter).await
+    }
+
+    async fn on_session_created(&self, client_id: &str) {
+        (**self).on_session_created(client_id).await
+    }
+
+    async fn on_session_expired(&self, client_id: &str) {
+        (**self).on_session_expired(client_id).await
+    }
+
+    async fn auto_subscribe(&self, client_id: &str, username: Option<&str>) -> Vec<AutoSubscribe> {
+        (**self).auto_subscribe(client_id, username).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    struct Counter {
+        allow: bool,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Hook for Counter {
+        async fn authenticate(&self, _login: Login) -> AuthResult {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.allow.into()
+        }
+        async fn connected(&self, _client_id: &str) {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+        }
+        async fn disconnect(&self, _client_id: &str) {}
+    }
+
+    /// 在 topic 后追加自己的名字，按 decision 处理消息
+    struct Tagger {
+        name: &'static str,
+        priority: i32,
+        decision: PublishDecision,
+    }
+
+    #[async_trait]
+    impl Hook for Tagger {
+        fn priority(&self) -> i32 {
+            self.priority
+        }
+        async fn authenticate(&self, _login: Login) -> AuthResult {
+            AuthResult::Allow
+        }
+        async fn connected(&self, _client_id: &str) {}
+        async fn disconnect(&self, _client_id: &str) {}
+        async fn on_publish(&self, _client_id: &str, publish: &mut Publish) -> PublishDecision {
+            publish.topic = format!("{}/{}", publish.topic, self.name).into();
+            self.decision.clone()
+        }
+    }
+
+    fn tagger(name: &'static str, priority: i32, decision: PublishDecision) -> Arc<dyn Hook> {
+        Arc::new(Tagger {
+            name,
+            priority,
+            decision,
+        })
+    }
+
+    fn login() -> Login {
+        Login {
+            username: None,
+            password: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn every_hook_must_authenticate() {
+        let allow = Arc::new(Counter {
+            allow: true,
+            calls: AtomicUsize::new(0),
+        });
+        let deny = Arc::new(Counter {
+            allow: false,
+            calls: AtomicUsize::new(0),
+        });
+        let hooks = Hooks::new(vec![allow.clone(), deny.clone(), allow.clone()]);
+
+        assert_eq!(hooks.authenticate(login()).await, AuthResult::Deny);
+        // 第一个拒绝的钩子之后不再调用
+        assert_eq!(allow.calls.load(Ordering::Relaxed), 1);
+        assert_eq!(deny.calls.load(Ordering::Relaxed), 1);
+
+        hooks.connected("c1").await;
+        assert_eq!(allow.calls.load(Ordering::Relaxed), 3);
+        assert_eq!(
+            Hooks::default().authenticate(login()).await,
+            AuthResult::Allow
+        );
+    }
+
+    #[tokio::test]
+    async fn on_publish_runs_in_priority_order() {
+        let mut publish = Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            topic: "t".into(),
+            packet_id: 0,
+            payload: Default::default(),
+            properties: None,
+            encoded: Default::default(),
+        };
+        let hooks = Hooks::new(vec![
+            tagger("low", -1, PublishDecision::Allow),
+            tagger("a", 0, PublishDecision::Modified),
+            tagger("high", 10, PublishDecision::Allow),
+            tagger("b", 0, PublishDecision::Allow),
+        ]);
+        assert_eq!(
+            hooks.on_publish("c1", &mut publish).await,
+            PublishDecision::Modified
+        );
+        assert_eq!(publish.topic, "t/high/a/b/low");
+
+        // 丢弃或拒绝后不再调用后面的钩子
+        let hooks = Hooks::new(vec![
+            tagger("a", 0, PublishDecision::Reject("spam".into())),
+            tagger("b", 0, PublishDecision::Modified),
+        ]);
+        assert_eq!(
+            hooks.on_publish("c1", &mut publish).await,
+            PublishDecision::Reject("spam".into())
+        );
+        assert_eq!(publish.topic, "t/high/a/b/low/a");
+    }
+}
@@ -3,38 +3,112 @@
 //! 一个 mqtt 服务端库，用户可以使用此库构建自己的 mqtt broker
 
 use async_trait::async_trait;
-pub use network::v4::Login;
+pub use metrics::DropReason;
+pub use network::{
+    packet::{v5::PublishProperties, QoS},
+    v4::{EncodedBody, Login, Publish},
+};
 
+mod ban;
 pub mod broker;
 mod cluster;
+#[cfg(feature = "bench")]
+pub mod codec;
 pub mod config;
 pub mod error;
+mod hook;
+pub mod local;
+mod metrics;
 mod network;
 mod protocol;
 mod server;
 
+/// 客户端认证的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthResult {
+    /// 拒绝连接
+    Deny,
+    /// 允许连接，使用配置中的发布限流
+    Allow,
+    /// 允许连接，使用指定的发布限流
+    AllowWithLimit(config::ClientLimit),
+}
+
+impl AuthResult {
+    pub fn allowed(&self) -> bool {
+        !matches!(self, AuthResult::Deny)
+    }
+}
+
+impl From<bool> for AuthResult {
+    fn from(allow: bool) -> Self {
+        match allow {
+            true => AuthResult::Allow,
+            false => AuthResult::Deny,
+        }
+    }
+}
+
+/// 客户端发布的消息经过 [`Hook::on_publish`] 后的处理方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublishDecision {
+    /// 继续投递
+    Allow,
+    /// 钩子修改了消息，修改后的 topic 不合法时丢弃
+    Modified,
+    /// 丢弃，发布者照常收到 ack
+    Drop,
+    /// 拒绝，v5 发布者在 puback/pubrec 中收到未授权和原因，3.1/3.1.1 没有拒绝的 ack，按丢弃处理
+    Reject(String),
+}
+
 /// mqtt事件发生时的回调，由用户实现
 ///
+/// 除了认证和上下线，其它回调都有默认实现
+/// on_deliver 和 on_message_dropped 在持有会话锁时调用，不能阻塞
 #[async_trait]
 pub trait Hook: Send + Sync + 'static {
-    /// 客户端认证
-    async fn authenticate(&self, login: Login) -> bool;
+    /// 多个钩子按优先级从高到低调用，优先级相同时按添加的顺序
+    fn priority(&self) -> i32 {
+        0
+    }
+    /// 客户端认证，允许时可以为这个客户端指定发布限流
+    async fn authenticate(&self, login: Login) -> AuthResult;
     /// 客户端上线
     async fn connected(&self, client_id: &str);
     /// 客户端连接断开
     async fn disconnect(&self, client_id: &str);
-}
-
-struct HookNoop;
-
-#[async_trait]
-impl Hook for HookNoop {
-    /// 客户端认证
-    async fn authenticate(&self, _login: Login) -> bool {
+    /// 集群中的对等节点上线，包括启动后第一次连接成功和宕机后重新加入
+    async fn node_up(&self, _node_id: u64) {}
+    /// 集群中的对等节点宕机
+    async fn node_down(&self, _node_id: u64) {}
+    /// 收到客户端发布的消息，在保存保留消息和投递之前调用，可以修改消息
+    async fn on_publish(&self, _client_id: &str, _publish: &mut Publish) -> PublishDecision {
+        PublishDecision::Allow
+    }
+    /// 消息投递给订阅了它的会话，已发送给客户端或保存在会话中
+    /// 在会话中被丢弃的消息不调用，只调用 on_message_dropped
+    fn on_deliver(&self, _client_id: &str, _publish: &Publish) {}
+    /// 发给客户端的消息被丢弃
+    fn on_message_dropped(&self, _client_id: &str, _publish: &Publish, _reason: DropReason) {}
+    /// 发给客户端的 QoS1/QoS2 消息收到了 puback/pubcomp
+    async fn on_acked(&self, _client_id: &str, _publish: &Publish) {}
+    /// 客户端订阅，返回 false 时这个 filter 订阅失败
+    async fn on_subscribe(&self, _client_id: &str, _filter: &str, _qos: QoS) -> bool {
         true
     }
-    /// 客户端上线
-    async fn connected(&self, _client_id: &str) {}
-    /// 客户端连接断开
-    async fn disconnect(&self, _client_id: &str) {}
+    /// 客户端取消订阅
+    async fn on_unsubscribe(&self, _client_id: &str, _filter: &str) {}
+    /// 创建了新的会话，恢复已有的会话时不调用
+    async fn on_session_created(&self, _client_id: &str) {}
+    /// 客户端断开后会话过期被删除
+    async fn on_session_expired(&self, _client_id: &str) {}
+    /// 创建新会话后为客户端自动订阅的主题，和配置中的自动订阅一起生效
+    async fn auto_subscribe(
+        &self,
+        _client_id: &str,
+        _username: Option<&str>,
+    ) -> Vec<config::AutoSubscribe> {
+        Vec::new()
+    }
 }
@@ -0,0 +1,639 @@
+//! 进程内客户端
+//! 和 broker 运行在同一个进程中的业务代码不经过网络，直接发布和订阅消息
+//!
+//! * 通过 [`Incoming::LocalConnect`] 在 router 中注册为一个普通的会话，同样经过认证和上线钩子
+//! * 后台任务代替网络连接读取发往此客户端的报文，自动回复 ack，把 publish 交给 [`LocalClient`]
+//! * 未读取的消息超过 [`MAX_PENDING_MESSAGES`] 时不再接收，由 session 按照 SlowConsumer 策略处理
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use bytes::Bytes;
+use futures::Stream;
+use parking_lot::Mutex;
+use tokio::{
+    select,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        oneshot, watch,
+    },
+};
+
+pub use crate::network::packet::{v5::PublishProperties, QoS};
+
+use crate::{
+    config::SlowConsumer,
+    metrics::Metrics,
+    network::{
+        outbound::{self, ConnInfo, Outbound},
+        packet::{v5::RetainForwardRule, Protocol},
+        topic,
+        v4::{
+            Connect, ConnectReturnCode, Packet, PubAck, PubComp, PubRec, PubRel, Publish,
+            Subscribe, SubscribeFilter, SubscribeReasonCode, Unsubscribe,
+        },
+    },
+    protocol::{Incoming, Outgoing, Workers},
+    Hook, Login,
+};
+
+/// 等待读取的消息数上限
+pub const MAX_PENDING_MESSAGES: usize = 1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Broker closed")]
+    BrokerClosed,
+    #[error("Connect refused: {0:?}")]
+    ConnectRefused(ConnectReturnCode),
+    #[error("Empty client id")]
+    EmptyClientId,
+    #[error("Invalid publish topic: {0}")]
+    InvalidTopic(String),
+    #[error("Invalid subscribe filter: {0}")]
+    InvalidFilter(String),
+    #[error("Subscribe {0} failed")]
+    SubscribeFailed(String),
+}
+
+/// 收到的消息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Bytes,
+    pub qos: QoS,
+    pub retain: bool,
+    /// 发布者设置的 v5 消息属性
+    pub properties: Option<Arc<PublishProperties>>,
+}
+
+/// 创建进程内客户端，由 [`Broker::local_clients`](crate::broker::Broker::local_clients) 获取
+#[derive(Clone)]
+pub struct LocalClients {
+    router_tx: Sender<Incoming>,
+    workers: Workers,
+    /// broker 启动后才有值
+    hook_rx: watch::Receiver<Option<Arc<dyn Hook>>>,
+    slow_consumer: SlowConsumer,
+    metrics: Arc<Metrics>,
+}
+
+impl fmt::Debug for LocalClients {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalClients")
+            .field("workers", &self.workers)
+            .field("slow_consumer", &self.slow_consumer)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LocalClients {
+    pub(crate) fn new(
+        router_tx: Sender<Incoming>,
+        workers: Workers,
+        hook_rx: watch::Receiver<Option<Arc<dyn Hook>>>,
+        slow_consumer: SlowConsumer,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            router_tx,
+            workers,
+            hook_rx,
+            slow_consumer,
+            metrics,
+        }
+    }
+
+    /// 等待 broker 启动，获取钩子
+    async fn hook(&self) -> Result<Arc<dyn Hook>, Error> {
+        let mut hook_rx = self.hook_rx.clone();
+        loop {
+            if let Some(hook) = hook_rx.borrow().clone() {
+                return Ok(hook);
+            }
+            hook_rx.changed().await.map_err(|_| Error::BrokerClosed)?;
+        }
+    }
+
+    /// 以 clean session 方式连接，不带登录凭证
+    pub async fn connect(&self, client_id: &str) -> Result<LocalClient, Error> {
+        let login = Login {
+            username: None,
+            password: None,
+        };
+        self.connect_with(client_id, true, login).await
+    }
+
+    /// 连接到 broker，broker 启动之前调用会等待启动
+    pub async fn connect_with(
+        &self,
+        client_id: &str,
+        clean_session: bool,
+        login: Login,
+    ) -> Result<LocalClient, Error> {
+        if client_id.is_empty() {
+            return Err(Error::EmptyClientId);
+        }
+        // 和网络连接一样在交给 router 之前认证，进程内客户端不限流
+        let hook = self.hook().await?;
+        if !hook.authenticate(login.clone()).await.allowed() {
+            self.metrics.auth_failed();
+            return Err(Error::ConnectRefused(ConnectReturnCode::NotAuthorized));
+        }
+        let (conn_tx, mut conn_rx, outbound) =
+            outbound::channel(self.slow_consumer, self.metrics.clone());
+        let connect = Connect {
+            protocol: Protocol::V4,
+            keep_alive: 0,
+            client_id: client_id.into(),
+            clean_session,
+            last_will: None,
+            login,
+            max_packet_size: None,
+        };
+        let conn_tx = conn_tx.with_info(ConnInfo {
+            addr: "local".into(),
+            username: connect.login.username.clone(),
+            protocol: connect.protocol,
+            keep_alive: connect.keep_alive,
+            connected_at: SystemTime::now(),
+        });
+        self.router_tx
+            .send(Incoming::LocalConnect { connect, conn_tx })
+            .await
+            .map_err(|_| Error::BrokerClosed)?;
+        match conn_rx.recv().await {
+            Some(Outgoing::ConnAck(ack)) => {
+                if !matches!(ack.code, ConnectReturnCode::Success) {
+                    return Err(Error::ConnectRefused(ack.code));
+                }
+            }
+            _ => return Err(Error::BrokerClosed),
+        }
+        hook.connected(client_id).await;
+
+        // 连接成功后，报文直接发给处理这个客户端的 worker
+        let worker_tx = self.workers.get(client_id).clone();
+        let acks = Arc::new(Mutex::new(HashMap::new()));
+        let (messages_tx, messages_rx) = mpsc::channel(1);
+        let conn = LocalConn {
+            client_id: client_id.into(),
+            worker_tx: worker_tx.clone(),
+            hook,
+            conn_rx,
+            outbound,
+            acks: acks.clone(),
+            pending: VecDeque::new(),
+            messages_tx,
+        };
+        tokio::spawn(conn.start());
+        Ok(LocalClient {
+            client_id: client_id.into(),
+            worker_tx,
+            packet_id: AtomicU16::new(0),
+            acks,
+            messages_rx,
+            disconnected: false,
+        })
+    }
+}
+
+/// 等待 broker 回复的 ack，key 为 packet id
+type Acks = Arc<Mutex<HashMap<u16, oneshot::Sender<Packet>>>>;
+
+/// 进程内客户端，作为 [`Stream`] 读取订阅到的消息
+/// 客户端 drop 时断开连接
+pub struct LocalClient {
+    client_id: String,
+    worker_tx: Sender<Incoming>,
+    packet_id: AtomicU16,
+    acks: Acks,
+    messages_rx: Receiver<Message>,
+    disconnected: bool,
+}
+
+impl LocalClient {
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// 发布消息，QoS1 等待 puback，QoS2 等待 pubcomp 后返回
+    pub async fn publish(
+        &self,
+        topic: &str,
+        payload: impl Into<Bytes>,
+        qos: QoS,
+        retain: bool,
+    ) -> Result<(), Error> {
+        self.send_publish(topic, payload.into(), qos, retain, None)
+            .await
+    }
+
+    /// 发布带 v5 属性的消息，属性原样投递给 v5 订阅者和进程内订阅者
+    pub async fn publish_with(
+        &self,
+        topic: &str,
+        payload: impl Into<Bytes>,
+        qos: QoS,
+        retain: bool,
+        properties: PublishProperties,
+    ) -> Result<(), Error> {
+        let properties = Some(Arc::new(properties));
+        self.send_publish(topic, payload.into(), qos, retain, properties)
+            .await
+    }
+
+    async fn send_publish(
+        &self,
+        topic: &str,
+        payload: Bytes,
+        qos: QoS,
+        retain: bool,
+        properties: Option<Arc<PublishProperties>>,
+    ) -> Result<(), Error> {
+        if !topic::valid_publish_topic(topic) {
+            return Err(Error::InvalidTopic(topic.into()));
+        }
+        let packet_id = match qos {
+            QoS::AtMostOnce => 0,
+            _ => self.next_packet_id(),
+        };
+        let publish = Packet::Publish(Publish {
+            dup: false,
+            qos,
+            retain,
+            topic: topic.to_string().into(),
+            packet_id,
+            payload,
+            properties,
+            encoded: Default::default(),
+        });
+        match qos {
+            QoS::AtMostOnce => self.send(publish).await,
+            _ => self.request(packet_id, publish).await.map(drop),
+        }
+    }
+
+    /// 订阅，返回 broker 授予的 QoS
+    pub async fn subscribe(&self, filter: &str, qos: QoS) -> Result<QoS, Error> {
+        if !topic::valid_subscribe_filter(filter) {
+            return Err(Error::InvalidFilter(filter.into()));
+        }
+        let packet_id = self.next_packet_id();
+        let subscribe = Packet::Subscribe(Subscribe {
+            packet_id,
+            filters: vec![SubscribeFilter {
+                path: filter.into(),
+                qos,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: RetainForwardRule::OnEverySubscribe,
+            }],
+        });
+        match self.request(packet_id, subscribe).await? {
+            Packet::SubAck(suback) => match suback.return_codes.first() {
+                Some(SubscribeReasonCode::Success(qos)) => Ok(*qos),
+                _ => Err(Error::SubscribeFailed(filter.into())),
+            },
+            _ => Err(Error::SubscribeFailed(filter.into())),
+        }
+    }
+
+    pub async fn unsubscribe(&self, filter: &str) -> Result<(), Error> {
+        let packet_id = self.next_packet_id();
+        let unsubscribe = Packet::Unsubscribe(Unsubscribe {
+            packet_id,
+            filters: vec![filter.into()],
+        });
+        self.request(packet_id, unsubscribe).await.map(drop)
+    }
+
+    /// 主动断开连接，之后 Stream 结束
+    pub async fn disconnect(mut self) -> Result<(), Error> {
+        self.disconnected = true;
+        self.send(Packet::Disconnect).await
+    }
+
+    /// 分配一个没有在使用的非 0 packet id
+    fn next_packet_id(&self) -> u16 {
+        loop {
+            let packet_id = self
+                .packet_id
+                .fetch_add(1, Ordering::Relaxed)
+                .wrapping_add(1);
+            if packet_id != 0 && !self.acks.lock().contains_key(&packet_id) {
+                return packet_id;
+            }
+        }
+    }
+
+    async fn send(&self, packet: Packet) -> Result<(), Error> {
+        self.worker_tx
+            .send(Incoming::Data {
+                client_id: self.client_id.clone(),
+                packets: vec![packet],
+            })
+            .await
+            .map_err(|_| Error::BrokerClosed)
+    }
+
+    /// 发送报文，等待 broker 回复对应 packet id 的 ack
+    async fn request(&self, packet_id: u16, packet: Packet) -> Result<Packet, Error> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.acks.lock().insert(packet_id, ack_tx);
+        if let Err(e) = self.send(packet).await {
+            self.acks.lock().remove(&packet_id);
+            return Err(e);
+        }
+        ack_rx.await.map_err(|_| Error::BrokerClosed)
+    }
+}
+
+impl Stream for LocalClient {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.messages_rx.poll_recv(cx)
+    }
+}
+
+impl Drop for LocalClient {
+    fn drop(&mut self) {
+        if !self.disconnected {
+            let _ = self.worker_tx.try_send(Incoming::Data {
+                client_id: self.client_id.clone(),
+                packets: vec![Packet::Disconnect],
+            });
+        }
+    }
+}
+
+/// 代替网络连接，读取 router/session 发往进程内客户端的报文
+struct LocalConn {
+    client_id: String,
+    worker_tx: Sender<Incoming>,
+    hook: Arc<dyn Hook>,
+    conn_rx: Receiver<Outgoing>,
+    outbound: Arc<Outbound>,
+    acks: Acks,
+    /// 还没有交给 LocalClient 的消息
+    pending: VecDeque<Message>,
+    messages_tx: Sender<Message>,
+}
+
+impl LocalConn {
+    /// 连接断开、broker 关闭或者 LocalClient drop 后退出，未完成的请求返回 BrokerClosed
+    async fn start(mut self) {
+        loop {
+            // 未读取的消息低于上限，恢复发送 session 中暂停的消息
+            if self.pending.len() < MAX_PENDING_MESSAGES
+                && self.outbound.take_resume()
+                && !self
+                    .send(Incoming::Resume {
+                        client_id: self.client_id.clone(),
+                    })
+                    .await
+            {
+                break;
+            }
+            let recv = select! {
+                recv = self.conn_rx.recv(), if self.pending.len() < MAX_PENDING_MESSAGES => recv,
+                permit = self.messages_tx.reserve(), if !self.pending.is_empty() => match permit {
+                    Ok(permit) => {
+                        permit.send(self.pending.pop_front().unwrap());
+                        continue;
+                    }
+                    Err(_) => break,
+                },
+                _ = self.outbound.kicked() => break,
+            };
+            let Some(outgoing) = recv else {
+                break;
+            };
+            if !self.handle_outgoing(outgoing).await {
+                break;
+            }
+        }
+        self.acks.lock().clear();
+        self.hook.disconnect(&self.client_id).await;
+    }
+
+    /// 返回 false 表示连接已断开
+    async fn handle_outgoing(&mut self, outgoing: Outgoing) -> bool {
+        match outgoing {
+            Outgoing::Packet(packet) => self.handle_packet(packet).await,
+            Outgoing::Packets(packets) => {
+                for packet in packets {
+                    if !self.handle_packet(packet).await {
+                        return false;
+                    }
+                }
+                true
+            }
+            Outgoing::ConnAck(_) => true,
+            // 进程内客户端的协议版本是 3.1.1，不会收到拒绝
+            Outgoing::PublishRejected { .. } => true,
+            Outgoing::Disconnect | Outgoing::Shutdown => false,
+        }
+    }
+
+    async fn handle_packet(&mut self, packet: Packet) -> bool {
+        let reply = match packet {
+            Packet::Publish(publish) => {
+                let reply = match publish.qos {
+                    QoS::AtMostOnce => None,
+                    QoS::AtLeastOnce => Some(Packet::PubAck(PubAck {
+                        packet_id: publish.packet_id,
+                    })),
+                    QoS::ExactlyOnce => Some(Packet::PubRec(PubRec {
+                        packet_id: publish.packet_id,
+                    })),
+                };
+                self.pending.push_back(Message {
+                    topic: publish.topic.to_string(),
+                    payload: publish.payload,
+                    qos: publish.qos,
+                    retain: publish.retain,
+                    properties: publish.properties,
+                });
+                reply
+            }
+            Packet::PubRel(pubrel) => Some(Packet::PubComp(PubComp {
+                packet_id: pubrel.packet_id,
+            })),
+            // 自己发布的 QoS2 消息，继续等待 pubcomp
+            Packet::PubRec(pubrec) => Some(Packet::PubRel(PubRel {
+                packet_id: pubrec.packet_id,
+            })),
+            // 交给等待 ack 的请求
+            ack => {
+                let packet_id = match &ack {
+                    Packet::SubAck(suback) => suback.packet_id,
+                    Packet::UnsubAck(unsuback) => unsuback.packet_id,
+                    Packet::PubAck(puback) => puback.packet_id,
+                    Packet::PubComp(pubcomp) => pubcomp.packet_id,
+                    _ => return true,
+                };
+                if let Some(ack_tx) = self.acks.lock().remove(&packet_id) {
+                    let _ = ack_tx.send(ack);
+                }
+                None
+            }
+        };
+        match reply {
+            Some(reply) => {
+                self.send(Incoming::Data {
+                    client_id: self.client_id.clone(),
+                    packets: vec![reply],
+                })
+                .await
+            }
+            None => true,
+        }
+    }
+
+    async fn send(&self, incoming: Incoming) -> bool {
+        self.worker_tx.send(incoming).await.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use tokio::time;
+
+    use crate::broker::Broker;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn local_clients_publish_and_subscribe() {
+        let broker = Broker::builder()
+            .bind("127.0.0.1:0")
+            .peer_bind("127.0.0.1:0")
+            .build()
+            .await
+            .unwrap();
+        let clients = broker.local_clients();
+        let shutdown = broker.shutdown_handle();
+        let running = tokio::spawn(broker.start());
+
+        let publisher = clients.connect("publisher").await.unwrap();
+        publisher
+            .publish("iot/retained", "r", QoS::AtLeastOnce, true)
+            .await
+            .unwrap();
+
+        let mut subscriber = clients.connect("subscriber").await.unwrap();
+        assert_eq!(
+            subscriber
+                .subscribe("iot/#", QoS::ExactlyOnce)
+                .await
+                .unwrap(),
+            QoS::ExactlyOnce
+        );
+        let retained = subscriber.next().await.unwrap();
+        assert_eq!(
+            (retained.topic.as_str(), retained.retain),
+            ("iot/retained", true)
+        );
+
+        let properties = PublishProperties {
+            content_type: Some("text/plain".into()),
+            user_properties: vec![("trace".into(), "t1".into())],
+            ..Default::default()
+        };
+        publisher
+            .publish_with(
+                "iot/1",
+                "hello",
+                QoS::ExactlyOnce,
+                false,
+                properties.clone(),
+            )
+            .await
+            .unwrap();
+        let message = subscriber.next().await.unwrap();
+        assert_eq!(message.topic, "iot/1");
+        assert_eq!(message.payload, Bytes::from("hello"));
+        assert_eq!(message.qos, QoS::ExactlyOnce);
+        assert_eq!(message.properties.as_deref(), Some(&properties));
+
+        // 取消订阅后不再收到消息
+        subscriber.unsubscribe("iot/#").await.unwrap();
+        publisher
+            .publish("iot/2", "", QoS::AtMostOnce, false)
+            .await
+            .unwrap();
+        assert!(time::timeout(Duration::from_millis(100), subscriber.next())
+            .await
+            .is_err());
+
+        assert!(matches!(
+            publisher.publish("iot/+", "", QoS::AtMostOnce, false).await,
+            Err(Error::InvalidTopic(_))
+        ));
+
+        // broker 关闭后消息流结束
+        shutdown.shutdown().await;
+        running.await.unwrap().unwrap();
+        assert!(subscriber.next().await.is_none());
+    }
+
+    struct DenyIntruder;
+
+    #[async_trait::async_trait]
+    impl Hook for DenyIntruder {
+        async fn authenticate(&self, login: Login) -> crate::AuthResult {
+            (login.username.as_deref() != Some("intruder")).into()
+        }
+        async fn connected(&self, _client_id: &str) {}
+        async fn disconnect(&self, _client_id: &str) {}
+    }
+
+    #[tokio::test]
+    async fn denied_local_login_keeps_existing_session() {
+        let broker = Broker::builder()
+            .bind("127.0.0.1:0")
+            .peer_bind("127.0.0.1:0")
+            .hook(DenyIntruder)
+            .build()
+            .await
+            .unwrap();
+        let clients = broker.local_clients();
+        let shutdown = broker.shutdown_handle();
+        let running = tokio::spawn(broker.start());
+
+        let mut device = clients.connect("device").await.unwrap();
+        device.subscribe("iot/#", QoS::AtMostOnce).await.unwrap();
+        let login = Login {
+            username: Some("intruder".into()),
+            password: None,
+        };
+        assert!(matches!(
+            clients.connect_with("device", true, login).await,
+            Err(Error::ConnectRefused(ConnectReturnCode::NotAuthorized))
+        ));
+
+        // 认证失败不交给 router，已连接的客户端不受影响
+        device
+            .publish("iot/1", "hello", QoS::AtLeastOnce, false)
+            .await
+            .unwrap();
+        assert_eq!(device.next().await.unwrap().topic, "iot/1");
+
+        shutdown.shutdown().await;
+        running.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+//! broker 运行统计
+//! 计数器由连接、worker、会话和 router 并发更新
+//! * router 定期读取快照发布到 $SYS 主题
+//! * 配置了 [`config::Metrics`] 时，通过 http 以 prometheus 文本格式导出，见 [`prometheus`]
+//!
+//! [`config::Metrics`]: crate::config::Metrics
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+
+use crate::network::{
+    packet::{PacketType, Protocol},
+    v4::Publish,
+};
+
+pub(crate) mod prometheus;
+
+/// 报文类型的数量，下标为报文类型的值
+const PACKET_TYPES: usize = 16;
+/// 每条 publish 匹配到的本节点订阅者数量的分桶
+const FANOUT_BUCKETS: &[u64] = &[0, 1, 2, 5, 10, 50, 100, 1000];
+/// 转发给对等节点的消息在队列中等待时间的分桶（微秒）
+const FORWARD_LATENCY_BUCKETS: &[u64] =
+    &[100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 1_000_000];
+
+/// 消息被丢弃的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// 客户端消费太慢，丢弃 QoS0 消息，或者断开连接时丢弃当前消息
+    SlowConsumer,
+    /// 会话中保存的消息已达上限
+    QueueFull,
+    /// 超过了客户端能接收的最大报文长度
+    PacketTooLarge,
+    /// 客户端离线，不保存 QoS0 消息
+    Offline,
+}
+
+impl DropReason {
+    const ALL: [DropReason; 4] = [
+        DropReason::SlowConsumer,
+        DropReason::QueueFull,
+        DropReason::PacketTooLarge,
+        DropReason::Offline,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            DropReason::SlowConsumer => "slow_consumer",
+            DropReason::QueueFull => "queue_full",
+            DropReason::PacketTooLarge => "packet_too_large",
+            DropReason::Offline => "offline",
+        }
+    }
+}
+
+/// 本节点的统计
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    /// 收到客户端发布的消息数
+    messages_received: AtomicU64,
+    /// 收到客户端发布的 payload 字节数
+    bytes_received: AtomicU64,
+    /// 发送给订阅端的消息数
+    messages_sent: AtomicU64,
+    /// 发送给订阅端的 payload 字节数
+    bytes_sent: AtomicU64,
+    /// 按原因统计丢弃的消息数
+    messages_dropped: [AtomicU64; DropReason::ALL.len()],
+    /// 按类型统计从客户端连接读到的报文数
+    packets_received: [AtomicU64; PACKET_TYPES],
+    /// 按类型统计写入客户端连接的报文数
+    packets_sent: [AtomicU64; PACKET_TYPES],
+    /// 从客户端连接读到的字节数
+    network_bytes_received: AtomicU64,
+    /// 写入客户端连接的字节数
+    network_bytes_sent: AtomicU64,
+    /// 认证失败的连接数
+    auth_failures: AtomicU64,
+    /// 对等节点不可用或者发送队列已满时丢弃的集群消息数
+    cluster_dropped: AtomicU64,
+    /// 按监听器和协议版本统计的在线连接数
+    connections: Mutex<HashMap<(String, Protocol), u64>>,
+    /// 每条 publish 匹配到的本节点订阅者数量
+    publish_fanout: Histogram,
+    /// 转发给对等节点的消息在队列中等待的时间
+    forward_latency: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            messages_received: AtomicU64::default(),
+            bytes_received: AtomicU64::default(),
+            messages_sent: AtomicU64::default(),
+            bytes_sent: AtomicU64::default(),
+            messages_dropped: Default::default(),
+            packets_received: Default::default(),
+            packets_sent: Default::default(),
+            network_bytes_received: AtomicU64::default(),
+            network_bytes_sent: AtomicU64::default(),
+            auth_failures: AtomicU64::default(),
+            cluster_dropped: AtomicU64::default(),
+            connections: Mutex::default(),
+            publish_fanout: Histogram::new(FANOUT_BUCKETS),
+            forward_latency: Histogram::new(FORWARD_LATENCY_BUCKETS),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn received(&self, publish: &Publish) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(publish.payload.len() as u64, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, publish: &Publish) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent
+            .fetch_add(publish.payload.len() as u64, Ordering::Relaxed);
+    }
+
+    pub fn dropped(&self, reason: DropReason) {
+        self.messages_dropped[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn packet_received(&self, packet_type: PacketType) {
+        self.packets_received[packet_type as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn packet_sent(&self, packet_type: PacketType) {
+        self.packets_sent[packet_type as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn network_received(&self, bytes: usize) {
+        self.network_bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn network_sent(&self, bytes: usize) {
+        self.network_bytes_sent
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn auth_failed(&self) {
+        self.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cluster_dropped(&self, messages: usize) {
+        self.cluster_dropped
+            .fetch_add(messages as u64, Ordering::Relaxed);
+    }
+
+    /// 记录一个在线连接，返回的守卫丢弃时减少计数
+    pub fn connection(self: &Arc<Self>, listener: &str, protocol: Protocol) -> ConnectionGuard {
+        let key = (listener.to_string(), protocol);
+        *self.connections.lock().entry(key.clone()).or_default() += 1;
+        ConnectionGuard {
+            metrics: self.clone(),
+            key,
+        }
+    }
+
+    pub fn publish_fanout(&self, subscribers: usize) {
+        self.publish_fanout.observe(subscribers as u64);
+    }
+
+    pub fn forward_latency(&self, latency: Duration) {
+        self.forward_latency.observe(latency.as_micros() as u64);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            messages_dropped: self
+                .messages_dropped
+                .iter()
+                .map(|dropped| dropped.load(Ordering::Relaxed))
+                .sum(),
+        }
+    }
+}
+
+/// 在线连接的计数，连接断开时丢弃
+pub(crate) struct ConnectionGuard {
+    metrics: Arc<Metrics>,
+    key: (String, Protocol),
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.metrics.connections.lock();
+        if let Some(count) = connections.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// 固定分桶的直方图，每个桶记录小于等于上界的观测次数
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [u64],
+    /// 最后一个桶为 +Inf
+    buckets: Box<[AtomicU64]>,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::default()).collect(),
+            sum: AtomicU64::default(),
+        }
+    }
+
+    fn observe(&self, value: u64) {
+        let index = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+}
+
+/// 消息统计的快照，启动以来的累计值
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct MetricsSnapshot {
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_dropped: u64,
+}
@@ -0,0 +1,385 @@
+//! 以 prometheus 文本格式导出统计
+//! 计数器来自 [`Metrics`]，会话、队列等状态在每次抓取时从 [`State`] 中统计
+
+use std::{
+    convert::Infallible,
+    fmt::{Display, Write},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use hyper::{
+    header,
+    server::accept,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use tokio::{net::TcpListener, sync::mpsc::Sender};
+use tokio_stream::wrappers::TcpListenerStream;
+
+use crate::{
+    network::packet::{PacketType, Protocol},
+    protocol::{Incoming, State},
+};
+
+use super::{DropReason, Histogram, Metrics, PACKET_TYPES};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// 抓取时需要的状态
+#[derive(Clone)]
+pub(crate) struct Exporter {
+    pub metrics: Arc<Metrics>,
+    pub state: Arc<State>,
+    /// 用于统计 router 队列中等待处理的消息数
+    pub router_tx: Sender<Incoming>,
+}
+
+impl Exporter {
+    /// 在 listener 上提供 http 服务，只响应 path 的 GET 请求，shutdown 完成后停止
+    pub async fn serve(
+        self,
+        listener: TcpListener,
+        path: String,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), hyper::Error> {
+        let path = Arc::new(path);
+        let make_service = make_service_fn(move |_| {
+            let exporter = self.clone();
+            let path = path.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let response = exporter.respond(&request, &path);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+        Server::builder(accept::from_stream(TcpListenerStream::new(listener)))
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown)
+            .await
+    }
+
+    fn respond(&self, request: &Request<Body>, path: &str) -> Response<Body> {
+        let status = if request.uri().path() != path {
+            StatusCode::NOT_FOUND
+        } else if request.method() != Method::GET {
+            StatusCode::METHOD_NOT_ALLOWED
+        } else {
+            return Response::builder()
+                .header(header::CONTENT_TYPE, CONTENT_TYPE)
+                .body(Body::from(self.encode()))
+                .unwrap();
+        };
+        Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    /// 所有指标的文本格式
+    pub fn encode(&self) -> String {
+        let metrics = &self.metrics;
+        let sessions = self.state.sessions.stats();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "gecko_connections",
+            "gauge",
+            "Connected client connections",
+        );
+        let mut connections = metrics
+            .connections
+            .lock()
+            .iter()
+            .map(|((listener, protocol), count)| (listener.clone(), *protocol, *count))
+            .collect::<Vec<_>>();
+        connections.sort_by(|a, b| a.0.cmp(&b.0));
+        for (listener, protocol, count) in connections {
+            let _ = writeln!(
+                out,
+                "gecko_connections{{listener=\"{}\",protocol=\"{}\"}} {}",
+                escape(&listener),
+                protocol_name(protocol),
+                count
+            );
+        }
+        single(
+            &mut out,
+            "gecko_sessions_connected",
+            "gauge",
+            "Sessions with a connected client",
+            sessions.connected,
+        );
+        single(
+            &mut out,
+            "gecko_sessions_disconnected",
+            "gauge",
+            "Disconnected sessions not yet expired",
+            sessions.disconnected,
+        );
+        single(
+            &mut out,
+            "gecko_subscriptions",
+            "gauge",
+            "Subscriptions of all sessions",
+            sessions.subscriptions,
+        );
+        single(
+            &mut out,
+            "gecko_retained_messages",
+            "gauge",
+            "Retained messages",
+            self.state.retains.read().len(),
+        );
+        single(
+            &mut out,
+            "gecko_inflight_messages",
+            "gauge",
+            "QoS 1/2 messages sent to clients and not yet acknowledged",
+            sessions.inflight,
+        );
+        single(
+            &mut out,
+            "gecko_queued_messages",
+            "gauge",
+            "Messages queued in sessions and connection outbound queues",
+            sessions.queued,
+        );
+        single(
+            &mut out,
+            "gecko_router_queue_depth",
+            "gauge",
+            "Messages waiting in the router channel",
+            self.router_tx.max_capacity() - self.router_tx.capacity(),
+        );
+
+        packets(
+            &mut out,
+            "gecko_packets_received_total",
+            "Packets received from clients",
+            &metrics.packets_received,
+        );
+        packets(
+            &mut out,
+            "gecko_packets_sent_total",
+            "Packets sent to clients",
+            &metrics.packets_sent,
+        );
+        single(
+            &mut out,
+            "gecko_bytes_received_total",
+            "counter",
+            "Bytes read from client connections",
+            load(&metrics.network_bytes_received),
+        );
+        single(
+            &mut out,
+            "gecko_bytes_sent_total",
+            "counter",
+            "Bytes written to client connections",
+            load(&metrics.network_bytes_sent),
+        );
+
+        let snapshot = metrics.snapshot();
+        single(
+            &mut out,
+            "gecko_messages_received_total",
+            "counter",
+            "Messages published by clients",
+            snapshot.messages_received,
+        );
+        single(
+            &mut out,
+            "gecko_messages_sent_total",
+            "counter",
+            "Messages delivered to subscribers",
+            snapshot.messages_sent,
+        );
+        header(
+            &mut out,
+            "gecko_messages_dropped_total",
+            "counter",
+            "Messages dropped",
+        );
+        for reason in DropReason::ALL {
+            let _ = writeln!(
+                out,
+                "gecko_messages_dropped_total{{reason=\"{}\"}} {}",
+                reason.as_str(),
+                load(&metrics.messages_dropped[reason as usize])
+            );
+        }
+        single(
+            &mut out,
+            "gecko_auth_failures_total",
+            "counter",
+            "Connections refused by authentication",
+            load(&metrics.auth_failures),
+        );
+        single(
+            &mut out,
+            "gecko_cluster_messages_dropped_total",
+            "counter",
+            "Messages to peers dropped because the peer is down or its queue is full",
+            load(&metrics.cluster_dropped),
+        );
+
+        histogram(
+            &mut out,
+            "gecko_publish_fanout",
+            "Local subscribers matched by each publish",
+            &metrics.publish_fanout,
+            1.0,
+        );
+        histogram(
+            &mut out,
+            "gecko_cluster_forward_latency_seconds",
+            "Time forwarded messages wait in the peer send queue",
+            &metrics.forward_latency,
+            1_000_000.0,
+        );
+        out
+    }
+}
+
+fn load(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// 没有标签的指标
+fn single(out: &mut String, name: &str, kind: &str, help: &str, value: impl Display) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// 按报文类型统计的计数器，跳过从未出现过的类型
+fn packets(out: &mut String, name: &str, help: &str, counters: &[AtomicU64; PACKET_TYPES]) {
+    header(out, name, "counter", help);
+    for (index, counter) in counters.iter().enumerate() {
+        let value = load(counter);
+        if let Some(packet_type) = packet_type_name(index).filter(|_| value > 0) {
+            let _ = writeln!(out, "{}{{type=\"{}\"}} {}", name, packet_type, value);
+        }
+    }
+}
+
+/// 直方图，观测值除以 scale 后输出
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram, scale: f64) {
+    header(out, name, "histogram", help);
+    let mut count = 0;
+    for (index, bucket) in histogram.buckets.iter().enumerate() {
+        count += load(bucket);
+        let le = match histogram.bounds.get(index) {
+            Some(bound) => (*bound as f64 / scale).to_string(),
+            None => "+Inf".into(),
+        };
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, count);
+    }
+    let _ = writeln!(out, "{}_sum {}", name, load(&histogram.sum) as f64 / scale);
+    let _ = writeln!(out, "{}_count {}", name, count);
+}
+
+fn protocol_name(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::V3 => "3.1",
+        Protocol::V4 => "3.1.1",
+        Protocol::V5 => "5.0",
+    }
+}
+
+fn packet_type_name(index: usize) -> Option<&'static str> {
+    const NAMES: [(PacketType, &str); 15] = [
+        (PacketType::Connect, "connect"),
+        (PacketType::ConnAck, "connack"),
+        (PacketType::Publish, "publish"),
+        (PacketType::PubAck, "puback"),
+        (PacketType::PubRec, "pubrec"),
+        (PacketType::PubRel, "pubrel"),
+        (PacketType::PubComp, "pubcomp"),
+        (PacketType::Subscribe, "subscribe"),
+        (PacketType::SubAck, "suback"),
+        (PacketType::Unsubscribe, "unsubscribe"),
+        (PacketType::UnsubAck, "unsuback"),
+        (PacketType::PingReq, "pingreq"),
+        (PacketType::PingResp, "pingresp"),
+        (PacketType::Disconnect, "disconnect"),
+        (PacketType::Auth, "auth"),
+    ];
+    NAMES
+        .iter()
+        .find(|(packet_type, _)| *packet_type as usize == index)
+        .map(|(_, name)| *name)
+}
+
+/// 标签值中的反斜杠、双引号和换行需要转义
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{hook::Hooks, network::v4::Publish};
+
+    use super::*;
+
+    #[test]
+    fn encode_text_format() {
+        let metrics = Arc::new(Metrics::default());
+        let state = Arc::new(State::new(
+            1,
+            2,
+            metrics.clone(),
+            Arc::new(Hooks::default()),
+        ));
+        let (router_tx, _router_rx) = tokio::sync::mpsc::channel(10);
+        let exporter = Exporter {
+            metrics: metrics.clone(),
+            state,
+            router_tx,
+        };
+
+        let _v4 = metrics.connection("0.0.0.0:1883", Protocol::V4);
+        let v5 = metrics.connection("0.0.0.0:1883", Protocol::V5);
+        drop(v5);
+        metrics.packet_received(PacketType::Publish);
+        metrics.received(&Publish {
+            dup: false,
+            qos: crate::network::packet::QoS::AtMostOnce,
+            retain: false,
+            topic: "iot".into(),
+            packet_id: 0,
+            payload: "hello".into(),
+            properties: None,
+            encoded: Default::default(),
+        });
+        metrics.dropped(DropReason::QueueFull);
+        metrics.publish_fanout(3);
+        metrics.cluster_dropped(2);
+
+        let text = exporter.encode();
+        assert!(
+            text.contains("gecko_connections{listener=\"0.0.0.0:1883\",protocol=\"3.1.1\"} 1\n")
+        );
+        assert!(!text.contains("protocol=\"5.0\""));
+        assert!(text.contains("gecko_packets_received_total{type=\"publish\"} 1\n"));
+        assert!(text.contains("gecko_messages_received_total 1\n"));
+        assert!(text.contains("gecko_messages_dropped_total{reason=\"queue_full\"} 1\n"));
+        assert!(text.contains("gecko_publish_fanout_bucket{le=\"2\"} 0\n"));
+        assert!(text.contains("gecko_publish_fanout_bucket{le=\"5\"} 1\n"));
+        assert!(text.contains("gecko_publish_fanout_count 1\n"));
+        assert!(text.contains("gecko_cluster_messages_dropped_total 2\n"));
+    }
+}
@@ -1,27 +1,43 @@
 //! 网络层
 //! 本层只关心网络读写优化，不包含任何协议相关逻辑
 
-use std::sync::Arc;
+use std::{sync::Arc, time::SystemTime};
 
-pub(crate) use conn::{ClientConnection, PeerConnection};
+pub(crate) use conn::{ClientConnection, ClientStream, PeerConnection};
+pub(crate) use limit::ConnLimiter;
+pub(crate) use outbound::{ConnInfo, ConnTx, Outbound};
 pub(crate) use packet::v4;
 
+use log::{debug, info, warn};
 use tokio::{
-    net::TcpStream,
     select,
-    sync::mpsc::{self, error::SendError, Receiver, Sender},
+    sync::mpsc::{
+        error::{SendError, TryRecvError},
+        Receiver, Sender,
+    },
     time,
 };
 
 use crate::{
-    network,
-    protocol::{Incoming, Outgoing},
-    Hook,
+    ban::{self, Bans},
+    config::{self, SlowConsumer},
+    metrics::{DropReason, Metrics},
+    protocol::{Incoming, Outgoing, Workers},
+    AuthResult, Hook,
 };
 
-use self::v4::{connack, ConnAck, ConnectReturnCode};
+use self::{
+    limit::{PublishLimiter, Throttle},
+    packet::{v5, PacketType, Protocol},
+    v4::{connack, ConnAck, ConnectReturnCode},
+};
+
+/// 3.1 协议客户端 id 的最大字符数
+const V3_MAX_CLIENT_ID_LEN: usize = 23;
 
 pub(crate) mod conn;
+pub(crate) mod limit;
+pub(crate) mod outbound;
 pub(crate) mod packet;
 pub(crate) mod topic;
 
@@ -37,42 +53,148 @@ pub enum Error {
     SendIncoming(#[from] SendError<Incoming>),
     #[error("Send message to conn self error: {0}")]
     SendOutgoing(#[from] SendError<Outgoing>),
+    #[error("Slow consumer, outbound queue full")]
+    SlowConsumer,
+    #[error("Router closed")]
+    RouterClosed,
+    #[error("Publish quota exceeded")]
+    QuotaExceeded,
+}
+
+/// 客户端连接的配置，来自 [`config::Broker`] 和 [`config::Limit`]
+#[derive(Debug, Clone)]
+pub(crate) struct ConnOptions {
+    /// 是否严格校验报文
+    pub strict: bool,
+    /// 允许客户端发送的最大报文长度
+    pub max_packet_size: usize,
+    /// 写缓冲区的高水位
+    pub write_high_water: usize,
+    /// 客户端消费太慢时的处理策略
+    pub slow_consumer: SlowConsumer,
+    /// broker 的消息统计，所有连接共享
+    pub metrics: Arc<Metrics>,
+    /// 禁止连接的客户端，和 router 共享
+    pub bans: Arc<Bans>,
+    /// 连接和发布的限流，认证钩子没有指定时使用其中客户端的限流
+    pub limit: config::Limit,
+}
+
+impl ConnOptions {
+    pub fn new(cfg: &config::Config, metrics: Arc<Metrics>, bans: Arc<Bans>) -> Self {
+        Self {
+            strict: cfg.broker.strict,
+            max_packet_size: cfg.broker.max_packet_size,
+            write_high_water: cfg.broker.write_high_water,
+            slow_consumer: cfg.broker.slow_consumer,
+            metrics,
+            bans,
+            limit: cfg.limit.clone(),
+        }
+    }
 }
 
 pub struct ClientEventLoop<H: Hook> {
     pub client_id: String,
+    /// 客户端使用的协议版本
+    pub protocol: Protocol,
     conn: ClientConnection,
     router_tx: Sender<Incoming>,
+    /// 处理当前客户端报文的 worker
+    worker_tx: Sender<Incoming>,
     hook: Arc<H>,
-    conn_tx: Sender<Outgoing>,
+    conn_tx: ConnTx,
     conn_rx: Receiver<Outgoing>,
     keepalive: time::Duration,
+    /// 客户端发布的限流，None 表示不限制
+    limiter: Option<PublishLimiter>,
+    /// 发布超过限流时暂停读取，到这个时间后恢复
+    resume_at: Option<time::Instant>,
 }
 
 impl<H: Hook> ClientEventLoop<H> {
     pub(crate) async fn new(
-        stream: TcpStream,
+        stream: ClientStream,
+        addr: String,
         router_tx: Sender<Incoming>,
+        workers: Workers,
         hook: Arc<H>,
+        options: ConnOptions,
     ) -> Result<Self, Error> {
-        let mut conn = ClientConnection::new(stream);
-
+        let ConnOptions {
+            strict,
+            max_packet_size,
+            write_high_water,
+            slow_consumer,
+            metrics,
+            bans,
+            limit,
+        } = options;
         // conn_tx 由 router/session 持有，用于给当前这个 connection 发送消息
-        let (conn_tx, mut conn_rx) = mpsc::channel(1000);
+        let (conn_tx, mut conn_rx, outbound) = outbound::channel(slow_consumer, metrics.clone());
+        let mut conn =
+            ClientConnection::new(stream, strict, max_packet_size, write_high_water, outbound);
 
         // 第一个报文，必须是 connect 报文
-        let connect = conn.read_connect().await?;
+        let connect = match conn.read_connect().await {
+            Ok(connect) => {
+                metrics.packet_received(PacketType::Connect);
+                connect
+            }
+            Err(e) => {
+                // 不支持的协议级别和不合法的客户端 id 需要回复 connack 后再断开
+                // [MQTT-3.1.2-2] [MQTT-3.1.3-8]
+                let code = match &e {
+                    conn::Error::Packet(packet::Error::InvalidProtocolLevel(_)) => {
+                        ConnectReturnCode::RefusedProtocolVersion
+                    }
+                    conn::Error::Packet(packet::Error::V4(v4::Error::EmptyClientId)) => {
+                        ConnectReturnCode::BadClientId
+                    }
+                    _ => return Err(e.into()),
+                };
+                conn.writer.write_connack(ConnAck::new(code, false)).await?;
+                return Err(Error::FirstConnectFailed(code));
+            }
+        };
+        // 3.1 要求客户端 id 为 1 到 23 个字符
+        let client_id_len = connect.client_id.chars().count();
+        if connect.protocol == Protocol::V3 && !(1..=V3_MAX_CLIENT_ID_LEN).contains(&client_id_len)
+        {
+            let code = ConnectReturnCode::BadClientId;
+            conn.writer.write_connack(ConnAck::new(code, false)).await?;
+            return Err(Error::FirstConnectFailed(code));
+        }
+        // 被禁止的客户端在认证之前拒绝
+        let username = connect.login.username.as_deref();
+        if bans.banned(&connect.client_id, username, ban::addr_ip(&addr)) {
+            info!("client {} from {} is banned", connect.client_id, addr);
+            let code = ConnectReturnCode::Banned;
+            conn.writer.write_connack(ConnAck::new(code, false)).await?;
+            return Err(Error::FirstConnectFailed(code));
+        }
         let client_id = connect.client_id.clone();
+        let protocol = connect.protocol;
+        let conn_tx = conn_tx.with_info(ConnInfo {
+            addr,
+            username: connect.login.username.clone(),
+            protocol,
+            keep_alive: connect.keep_alive,
+            connected_at: SystemTime::now(),
+        });
         // 调用回调，认证
-        let login = hook.authenticate(connect.login.clone()).await;
-        if !login {
+        let auth = hook.authenticate(connect.login.clone()).await;
+        let limit = match auth {
+            AuthResult::AllowWithLimit(limit) => limit,
+            _ => limit.client,
+        };
+        // 认证失败直接回复 connack 后断开，不交给 router，不影响同 id 的在线客户端
+        if !auth.allowed() {
+            metrics.auth_failed();
             // If a server sends a CONNACK packet containing a non-zero return code it MUST set Session Present to 0 [MQTT-3.2.2-4].
-            conn_tx
-                .send(Outgoing::ConnAck(ConnAck::new(
-                    ConnectReturnCode::NotAuthorized,
-                    false,
-                )))
-                .await?;
+            let code = ConnectReturnCode::NotAuthorized;
+            conn.writer.write_connack(ConnAck::new(code, false)).await?;
+            return Err(Error::FirstConnectFailed(code));
         }
         let keep_alive = time::Duration::from_secs(connect.keep_alive as u64);
         // 发送给 router 处理
@@ -81,68 +203,211 @@ impl<H: Hook> ClientEventLoop<H> {
                 connect,
                 conn_tx: conn_tx.clone(),
             })
-            .await
-            .unwrap();
-        // 获取 router 处理结果
-        let outcoming = conn_rx.recv().await.unwrap();
-        let ack = match outcoming {
-            Outgoing::ConnAck(packet) => packet,
-            _ => return Err(Error::UnexpectedRouterMessage),
+            .await?;
+        // 获取 router 处理结果，broker 关闭时 router 可能已退出
+        let ack = match conn_rx.recv().await {
+            Some(Outgoing::ConnAck(packet)) => packet,
+            Some(_) => return Err(Error::UnexpectedRouterMessage),
+            None => return Err(Error::RouterClosed),
         };
         let return_code = ack.code;
         // 发送给客户端
-        conn.write_connack(ack).await?;
-        // 调用回调，连接
-        hook.connected(&client_id).await;
+        conn.writer.write_connack(ack).await?;
         match return_code {
-            // router 处理成功，开启循环
-            connack::ConnectReturnCode::Success => Ok(Self {
-                client_id,
-                conn,
-                router_tx,
-                hook,
-                conn_tx,
-                conn_rx,
-                keepalive: keep_alive + keep_alive.mul_f32(0.5),
-            }),
+            // router 处理成功，调用连接回调后开启循环
+            connack::ConnectReturnCode::Success => {
+                hook.connected(&client_id).await;
+                Ok(Self {
+                    worker_tx: workers.get(&client_id).clone(),
+                    client_id,
+                    protocol,
+                    conn,
+                    router_tx,
+                    hook,
+                    conn_tx,
+                    conn_rx,
+                    keepalive: keep_alive + keep_alive.mul_f32(0.5),
+                    limiter: PublishLimiter::new(&limit),
+                    resume_at: None,
+                })
+            }
             // 返回失败结果，退出循环
             code => Err(Error::FirstConnectFailed(code)),
         }
     }
 
+    /// 当前连接的队列状态，同时用于区分同一个客户端的不同连接
+    pub(crate) fn outbound(&self) -> Arc<Outbound> {
+        self.conn_tx.outbound.clone()
+    }
+
     /// 开启事件循环
     /// * connect 报文已在 new 方法中处理过，这里如果收到 connect 报文，视为非法连接
     /// * 从 conn socket 网络层获取 packet 数据，发送给 router
-    /// * 接收 router 的回复，写入 conn socket 网络层
-    /// TODO 增加 Disconnect 错误类型，遇到这种错误，需要退出事件循环，关闭网络连接
+    /// * 接收 router 的回复，写入缓冲区，socket 可写时批量写入
+    /// * 缓冲区超过高水位时不再接收 router 的回复，由 session 按照 SlowConsumer 策略处理
     pub(crate) async fn start(mut self) -> Result<(), Error> {
+        let result = self.run().await;
+        debug!(
+            "client {} outbound {:?}",
+            self.client_id,
+            self.conn_tx.outbound.stats()
+        );
+        result
+    }
+
+    async fn run(&mut self) -> Result<(), Error> {
+        let mut deadline = self.next_deadline();
         loop {
+            let ClientConnection { reader, writer } = &mut self.conn;
             select! {
-                // 从网络层读数据
-                reads = self.conn.read_more(self.keepalive) => {
-                    match reads {
-                        Ok(packets) => {
-                            self.router_tx.send(Incoming::Data{
-                                client_id: self.client_id.clone(),
-                                packets
-                            }).await?;
-                        },
-                        Err(e) => return Err(network::Error::Connection(e)),
+                // 从网络层读数据，发布超过限流时暂停
+                reads = reader.read_more(deadline), if self.resume_at.is_none() => {
+                    let packets = match reads {
+                        Ok(packets) => packets,
+                        Err(e) => {
+                            writer.reject(&e).await;
+                            return Err(Error::Connection(e));
+                        }
+                    };
+                    deadline = self.next_deadline();
+                    let mut data = Vec::with_capacity(packets.len());
+                    let mut exceeded = false;
+                    let metrics = self.conn_tx.outbound.metrics().clone();
+                    for packet in packets {
+                        metrics.packet_received(packet.packet_type());
+                        match self.throttle(&packet) {
+                            Throttle::Allow => {}
+                            Throttle::Pause(wait) => {
+                                self.resume_at = Some(time::Instant::now() + wait);
+                            }
+                            // 超过限流的消息不再处理
+                            Throttle::Exceeded => {
+                                exceeded = true;
+                                break;
+                            }
+                        }
+                        match packet {
+                            v4::Packet::PingReq => self.enqueue(v4::Packet::PingResp)?,
+                            packet => data.push(packet),
+                        }
+                    }
+                    if !data.is_empty() {
+                        self.worker_tx.send(Incoming::Data{
+                            client_id: self.client_id.clone(),
+                            packets: data,
+                        }).await?;
                     }
+                    if exceeded {
+                        warn!("client {} publish quota exceeded", self.client_id);
+                        self.conn
+                            .writer
+                            .enqueue_disconnect(v5::DisconnectReasonCode::QuotaExceeded)?;
+                        self.conn.writer.flush().await?;
+                        return Err(Error::QuotaExceeded);
+                    }
+                }
+                // 令牌恢复后继续读取，暂停期间不检查 keepalive
+                _ = time::sleep_until(self.resume_at.unwrap_or_else(time::Instant::now)), if self.resume_at.is_some() => {
+                    self.resume_at = None;
+                    deadline = self.next_deadline();
+                }
+                // 缓冲区中的数据写入 socket
+                written = writer.write_some(), if writer.pending() > 0 => {
+                    written?;
+                    self.try_resume().await?;
                 }
-                // 从 router 读回复
-                recv = self.conn_rx.recv() => {
-                    match recv {
-                        Some(outgoing) => match outgoing {
-                            Outgoing::Packet(packet) => self.conn.write_packet(packet).await?,
-                            Outgoing::Packets(packets) => self.conn.write_packets(packets).await?,
-                            Outgoing::Disconnect => return Ok(()),
-                            _ => return Err(Error::UnexpectedRouterMessage)
-                        },
-                        None => todo!(),
+                // 从 router 读回复，缓冲区超过高水位时暂停
+                recv = self.conn_rx.recv(), if !writer.over_high_water() => {
+                    let Some(outgoing) = recv else {
+                        return Ok(());
+                    };
+                    if self.write_outgoing(outgoing)? {
+                        return Ok(self.conn.writer.flush().await?);
                     }
+                    // 合并队列中已有的回复，一次写入
+                    while !self.conn.writer.over_high_water() {
+                        match self.conn_rx.try_recv() {
+                            Ok(outgoing) => {
+                                if self.write_outgoing(outgoing)? {
+                                    return Ok(self.conn.writer.flush().await?);
+                                }
+                            }
+                            Err(TryRecvError::Empty) => {
+                                self.try_resume().await?;
+                                break;
+                            }
+                            Err(TryRecvError::Disconnected) => break,
+                        }
+                    }
+                }
+                // 队列已满，客户端消费太慢
+                _ = self.conn_tx.outbound.kicked() => return Err(Error::SlowConsumer),
+            }
+        }
+    }
+
+    /// 回复写入缓冲区，返回是否需要断开连接
+    #[allow(clippy::result_large_err)]
+    fn write_outgoing(&mut self, outgoing: Outgoing) -> Result<bool, Error> {
+        match outgoing {
+            Outgoing::Packet(packet) => self.enqueue(packet)?,
+            Outgoing::Packets(packets) => {
+                for packet in packets {
+                    self.enqueue(packet)?;
                 }
             }
+            Outgoing::PublishRejected {
+                qos,
+                packet_id,
+                reason,
+            } => self.conn.writer.enqueue_reject(qos, packet_id, reason)?,
+            Outgoing::Disconnect => return Ok(true),
+            Outgoing::Shutdown => {
+                self.conn
+                    .writer
+                    .enqueue_disconnect(v5::DisconnectReasonCode::ServerShuttingDown)?;
+                return Ok(true);
+            }
+            _ => return Err(Error::UnexpectedRouterMessage),
+        }
+        Ok(false)
+    }
+
+    /// 报文写入缓冲区，太大而丢弃的 publish 交给钩子
+    #[allow(clippy::result_large_err)]
+    fn enqueue(&mut self, packet: v4::Packet) -> Result<(), Error> {
+        if let Some(publish) = self.conn.writer.enqueue(packet)? {
+            self.hook
+                .on_message_dropped(&self.client_id, &publish, DropReason::PacketTooLarge);
+        }
+        Ok(())
+    }
+
+    /// 缓冲区低于高水位时，通知 router 恢复发送 session 中暂停的消息
+    async fn try_resume(&mut self) -> Result<(), Error> {
+        if !self.conn.writer.over_high_water() && self.conn_tx.outbound.take_resume() {
+            self.worker_tx
+                .send(Incoming::Resume {
+                    client_id: self.client_id.clone(),
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// 客户端发布的限流，其它报文不限制
+    fn throttle(&mut self, packet: &v4::Packet) -> Throttle {
+        match (&mut self.limiter, packet) {
+            (Some(limiter), v4::Packet::Publish(publish)) => {
+                limiter.publish(publish.payload.len(), time::Instant::now())
+            }
+            _ => Throttle::Allow,
         }
     }
+
+    /// 1.5 倍 keepalive 时间内没有收到报文，断开连接 [MQTT-3.1.2-24]
+    fn next_deadline(&self) -> Option<time::Instant> {
+        (!self.keepalive.is_zero()).then(|| time::Instant::now() + self.keepalive)
+    }
 }
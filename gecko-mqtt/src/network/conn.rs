@@ -1,5 +1,5 @@
-pub(crate) use client::ClientConnection;
-pub(crate) use peer::PeerConnection;
+pub(crate) use client::{ClientConnection, ClientStream};
+pub(crate) use peer::{PeerConnection, PeerRequest};
 use tokio::{io, time};
 
 use super::packet::{self, PacketType};
@@ -1,45 +1,154 @@
-use bytes::BytesMut;
+use std::{
+    collections::VecDeque,
+    io::IoSlice,
+    sync::{atomic::Ordering, Arc},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use log::debug;
 use packet::v4::ConnAck;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
     time,
 };
 
-use crate::network::{
-    packet::{self, v4::Packet, PacketType},
-    v4::Connect,
+use crate::{
+    metrics::{DropReason, Metrics},
+    network::{
+        outbound::Outbound,
+        packet::{self, v4::Packet, v5, PacketType, Protocol, QoS},
+        v4::{Connect, Publish},
+    },
 };
 
 use super::Error;
 
+/// 报文体超过此长度的 publish 不拷贝到写缓冲区，使用 vectored write 直接发送
+const VECTORED_BODY_SIZE: usize = 4096;
+/// 一次 vectored write 最多的分片数
+const MAX_IO_SLICES: usize = 64;
+
+/// 客户端连接的字节流，拆分为读写两端
+/// tcp 连接直接拆分，其它字节流（如 tls、内存中的 duplex）通过 io::split 拆分
+pub(crate) struct ClientStream {
+    read: Box<dyn AsyncRead + Send + Unpin>,
+    write: Box<dyn AsyncWrite + Send + Unpin>,
+}
+
+impl ClientStream {
+    pub(crate) fn new<S>(stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (read, write) = io::split(stream);
+        Self {
+            read: Box::new(read),
+            write: Box::new(write),
+        }
+    }
+}
+
+impl From<TcpStream> for ClientStream {
+    fn from(stream: TcpStream) -> Self {
+        let (read, write) = stream.into_split();
+        Self {
+            read: Box::new(read),
+            write: Box::new(write),
+        }
+    }
+}
+
 /// 设备或对等节点与服务器之间的连接
 /// 单纯的 tcp 读写管理
-/// 以 packet 为单位读写
+/// 以 packet 为单位读写，读写两端可以同时进行
 pub(crate) struct ClientConnection {
-    /// tcp 连接
-    stream: TcpStream,
+    pub reader: ConnReader,
+    pub writer: ConnWriter,
+}
+
+impl ClientConnection {
+    pub(crate) fn new(
+        stream: ClientStream,
+        strict: bool,
+        max_packet_size: usize,
+        write_high_water: usize,
+        outbound: Arc<Outbound>,
+    ) -> Self {
+        Self {
+            reader: ConnReader {
+                stream: stream.read,
+                read: BytesMut::new(),
+                protocol: Protocol::V4,
+                strict,
+                max_packet_size,
+                metrics: outbound.metrics().clone(),
+            },
+            writer: ConnWriter {
+                stream: stream.write,
+                buf: BytesMut::new(),
+                chunks: VecDeque::new(),
+                pending: 0,
+                protocol: Protocol::V4,
+                max_packet_size,
+                max_send_packet_size: None,
+                high_water: write_high_water,
+                outbound,
+            },
+        }
+    }
+
+    pub(crate) async fn read_connect(&mut self) -> Result<Connect, Error> {
+        let packet = match self.reader.read_packet().await {
+            Ok(packet) => packet,
+            Err(e) => {
+                self.writer.reject(&e).await;
+                return Err(e);
+            }
+        };
+
+        match packet {
+            Packet::Connect(connect) => {
+                self.reader.protocol = connect.protocol;
+                self.writer.protocol = connect.protocol;
+                self.writer.max_send_packet_size =
+                    connect.max_packet_size.map(|size| size as usize);
+                Ok(connect)
+            }
+            _ => Err(Error::FirstPacketNotConnect),
+        }
+    }
+}
+
+/// 连接的读端
+pub(crate) struct ConnReader {
+    stream: Box<dyn AsyncRead + Send + Unpin>,
     /// 读缓冲区
     /// 使用缓冲区而非按照字节 从 socket 读取数据
     read: BytesMut,
-    /// 写缓冲区
-    /// 先写入缓冲区再刷入 socket 而非按字节向 socket 写入数据
-    write: BytesMut,
+    /// 客户端的协议版本，收到 connect 报文后确定，v5 客户端之后的报文使用 v5 解码
+    protocol: Protocol,
+    /// 是否严格校验报文
+    strict: bool,
+    /// 允许客户端发送的最大报文长度，同时限制了读缓冲区的大小
+    max_packet_size: usize,
+    /// broker 的统计
+    metrics: Arc<Metrics>,
 }
 
-impl ClientConnection {
-    pub(crate) fn new(stream: TcpStream) -> Self {
-        Self {
-            stream,
-            read: BytesMut::new(),
-            write: BytesMut::new(),
+impl ConnReader {
+    /// 从缓冲区解码一个报文，v5 的报文转换为 broker 内部统一使用的报文
+    fn decode(&mut self) -> Result<Packet, packet::Error> {
+        match self.protocol {
+            Protocol::V5 => Packet::read_v5(&mut self.read, self.max_packet_size),
+            _ => Packet::read(&mut self.read, self.strict, self.max_packet_size),
         }
     }
 
     /// 读取一个 packet
     async fn read_packet(&mut self) -> Result<Packet, Error> {
         loop {
-            let required = match Packet::read(&mut self.read) {
+            let required = match self.decode() {
                 Ok(packet) => return Ok(packet),
                 Err(packet::Error::InsufficientBytes(required)) => required,
                 Err(e) => return Err(Error::Packet(e)),
@@ -50,19 +159,16 @@ impl ClientConnection {
         }
     }
 
+    /// 读取缓冲区中所有完整的报文，至少一个
+    /// 只在没有读到任何报文时等待 socket，可以安全地在 select 中取消
     pub async fn collect(&mut self) -> Result<Vec<Packet>, Error> {
-        let mut count = 0;
         let mut packets = Vec::new();
         loop {
-            match Packet::read(&mut self.read) {
-                Ok(packet) => {
-                    count += 1;
-                    match packet.packet_type() {
-                        PacketType::PingReq => self.write_packet(Packet::PingResp).await?,
-                        _ => packets.push(packet),
-                    }
+            match self.decode() {
+                Ok(packet) => packets.push(packet),
+                Err(packet::Error::InsufficientBytes(_)) if !packets.is_empty() => {
+                    return Ok(packets)
                 }
-                Err(packet::Error::InsufficientBytes(_)) if count > 0 => return Ok(packets),
                 Err(packet::Error::InsufficientBytes(required)) => {
                     self.read_bytes(required).await?
                 }
@@ -71,52 +177,26 @@ impl ClientConnection {
         }
     }
 
-    pub(crate) async fn read_connect(&mut self) -> Result<Connect, Error> {
-        let packet = self.read_packet().await?;
-
-        match packet {
-            Packet::Connect(connect) => Ok(connect),
-            _ => Err(Error::FirstPacketNotConnect),
-        }
-    }
-
-    pub(crate) async fn write_connack(&mut self, connack: ConnAck) -> Result<(), Error> {
-        connack.write(&mut self.write)?;
-        self.flush().await
-    }
-
-    pub(crate) async fn write_packet(&mut self, packet: Packet) -> Result<(), Error> {
-        packet.write(&mut self.write)?;
-        self.flush().await
-    }
-
-    /// TODO 统计已写入的字节数，防止发送 packets 的量太大
-    pub(crate) async fn write_packets(&mut self, packets: Vec<Packet>) -> Result<(), Error> {
-        for packet in packets {
-            packet.write(&mut self.write)?;
-        }
-        self.flush().await
-    }
-
-    /// 从 socket 读取更多数据
+    /// 从 socket 读取更多数据，deadline 之前没有读到完整报文时返回超时错误
     pub(crate) async fn read_more(
         &mut self,
-        timeout: time::Duration,
+        deadline: Option<time::Instant>,
     ) -> Result<Vec<Packet>, Error> {
-        if !timeout.is_zero() {
-            time::timeout(timeout, self.collect()).await?
-        } else {
-            self.collect().await
+        match deadline {
+            Some(deadline) => time::timeout_at(deadline, self.collect()).await?,
+            None => self.collect().await,
         }
     }
 
     /// 等待从 socket 读出至少所需长度的数据，放入缓冲区
     /// 如果读不到指定长度的数据，返回错误
     async fn read_bytes(&mut self, required: usize) -> Result<(), Error> {
-        // AsyncReadExt socket.read()
+        // 一次预留出剩余报文所需的空间，大报文不需要多次扩容
+        self.read.reserve(required);
         let mut total_read = 0;
         loop {
             let read = self.stream.read_buf(&mut self.read).await?;
+            self.metrics.network_received(read);
             if 0 == read {
                 return if self.read.is_empty() {
                     Err(Error::ConnectionAborted)
@@ -131,15 +211,228 @@ impl ClientConnection {
             }
         }
     }
+}
+
+/// 连接的写端
+/// 报文先编码到缓冲区，由事件循环在 socket 可写时批量写入
+pub(crate) struct ConnWriter {
+    stream: Box<dyn AsyncWrite + Send + Unpin>,
+    /// 写缓冲区，小报文编码后合并在一起
+    buf: BytesMut,
+    /// 等待写入 socket 的分片，大 payload 直接引用，不拷贝
+    chunks: VecDeque<Bytes>,
+    /// 等待写入 socket 的字节数
+    pending: usize,
+    /// 客户端的协议版本，收到 connect 报文后确定
+    protocol: Protocol,
+    /// 允许客户端发送的最大报文长度，v5 客户端在 connack 中获知
+    max_packet_size: usize,
+    /// 客户端能接收的最大报文长度，v5 客户端在 connect 中声明
+    max_send_packet_size: Option<usize>,
+    /// 写缓冲区的高水位
+    high_water: usize,
+    /// 队列统计
+    outbound: Arc<Outbound>,
+}
+
+impl ConnWriter {
+    pub(crate) async fn write_connack(&mut self, connack: ConnAck) -> Result<(), Error> {
+        let start = self.buf.len();
+        if self.protocol == Protocol::V5 {
+            // v5 在 connack 中告知客户端允许发送的最大报文长度，以及不支持的订阅标识符和共享订阅
+            let connack = v5::ConnAck {
+                session_present: connack.session_present,
+                code: connack.code.into(),
+                properties: Some(v5::ConnAckProperties {
+                    max_packet_size: u32::try_from(self.max_packet_size).ok(),
+                    subscription_identifiers_available: Some(0),
+                    shared_subscription_available: Some(0),
+                    ..Default::default()
+                }),
+            };
+            connack.write(&mut self.buf)?;
+        } else {
+            connack.write(&mut self.buf, self.protocol)?;
+        }
+        self.pending += self.buf.len() - start;
+        self.outbound.metrics().packet_sent(PacketType::ConnAck);
+        self.flush().await
+    }
+
+    /// 读取报文出错，断开前告知 v5 客户端原因，3.1.1 直接断开
+    pub(crate) async fn reject(&mut self, e: &Error) {
+        let reason_code = match e {
+            Error::Packet(packet::Error::PacketTooLarge(_)) => {
+                v5::DisconnectReasonCode::PacketTooLarge
+            }
+            _ => return,
+        };
+        if self.enqueue_disconnect(reason_code).is_ok() {
+            let _ = self.flush().await;
+        }
+    }
 
-    /// 协议层处理完一个或多个请求后，主动调用此方法
-    async fn flush(&mut self) -> Result<(), Error> {
-        if self.write.is_empty() {
+    /// 服务端主动断开，v5 客户端写入带原因码的 disconnect 报文，3.1.1 没有服务端 disconnect
+    pub(crate) fn enqueue_disconnect(
+        &mut self,
+        reason_code: v5::DisconnectReasonCode,
+    ) -> Result<(), Error> {
+        if self.protocol != Protocol::V5 {
             return Ok(());
         }
+        let disconnect = v5::Disconnect {
+            reason_code,
+            properties: None,
+        };
+        let start = self.buf.len();
+        disconnect.write(&mut self.buf)?;
+        self.pending += self.buf.len() - start;
+        self.outbound.metrics().packet_sent(PacketType::Disconnect);
+        Ok(())
+    }
+
+    /// 钩子拒绝了 v5 客户端发布的消息，回复带原因的 puback/pubrec
+    pub(crate) fn enqueue_reject(
+        &mut self,
+        qos: QoS,
+        packet_id: u16,
+        reason: String,
+    ) -> Result<(), Error> {
+        let properties = Some(v5::PacketProperties {
+            reason_string: Some(reason),
+            user_properties: Vec::new(),
+        });
+        let start = self.buf.len();
+        let packet_type = match qos {
+            QoS::ExactlyOnce => {
+                let pubrec = v5::PubRec {
+                    packet_id,
+                    reason: v5::PubRecReason::NotAuthorized,
+                    properties,
+                };
+                pubrec.write(&mut self.buf)?;
+                PacketType::PubRec
+            }
+            _ => {
+                let puback = v5::PubAck {
+                    packet_id,
+                    reason: v5::PubAckReason::NotAuthorized,
+                    properties,
+                };
+                puback.write(&mut self.buf)?;
+                PacketType::PubAck
+            }
+        };
+        self.pending += self.buf.len() - start;
+        self.outbound.metrics().packet_sent(packet_type);
+        Ok(())
+    }
 
-        self.stream.write_all(&self.write).await?;
-        self.write.clear();
+    /// 报文写入缓冲区，不立即写入 socket
+    /// 超过客户端能接收的最大长度的 publish 直接丢弃，当作已发送 [MQTT-3.1.2-25]，返回丢弃的 publish
+    /// publish 的报文体在订阅者之间共享，只有固定头、主题和 packet id 按连接编码
+    pub(crate) fn enqueue(&mut self, packet: Packet) -> Result<Option<Publish>, Error> {
+        let start = self.buf.len();
+        // 较大的报文体单独作为一个分片发送，不拷贝
+        let mut vectored = None;
+        let size = match &packet {
+            Packet::Publish(publish) => {
+                let body = publish.body(self.protocol)?;
+                publish.write_header(&mut self.buf, body.len())?;
+                let size = self.buf.len() - start + body.len();
+                if body.len() >= VECTORED_BODY_SIZE {
+                    vectored = Some(body);
+                } else {
+                    self.buf.extend_from_slice(&body);
+                }
+                size
+            }
+            _ => {
+                packet.write(&mut self.buf, self.protocol)?;
+                self.buf.len() - start
+            }
+        };
+
+        if let Some(max) = self.max_send_packet_size {
+            if size > max && packet.packet_type() == PacketType::Publish {
+                debug!("drop publish of {} bytes, client maximum {}", size, max);
+                self.buf.truncate(start);
+                self.outbound.drop_message(DropReason::PacketTooLarge);
+                return Ok(match packet {
+                    Packet::Publish(publish) => Some(publish),
+                    _ => None,
+                });
+            }
+        }
+
+        if let Some(body) = vectored {
+            self.chunks.push_back(self.buf.split().freeze());
+            self.chunks.push_back(body);
+        }
+        self.pending += size;
+        self.outbound.sent_packets.fetch_add(1, Ordering::Relaxed);
+        self.outbound.metrics().packet_sent(packet.packet_type());
+        self.outbound
+            .pending_bytes
+            .store(self.pending, Ordering::Relaxed);
+        Ok(None)
+    }
+
+    /// 等待写入 socket 的字节数
+    pub(crate) fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// 写缓冲区是否超过了高水位
+    pub(crate) fn over_high_water(&self) -> bool {
+        self.pending >= self.high_water
+    }
+
+    /// 一次 vectored write，返回写入的字节数，可以安全地在 select 中取消
+    pub(crate) async fn write_some(&mut self) -> Result<usize, Error> {
+        if !self.buf.is_empty() {
+            self.chunks.push_back(self.buf.split().freeze());
+        }
+
+        let slices = self
+            .chunks
+            .iter()
+            .take(MAX_IO_SLICES)
+            .map(|chunk| IoSlice::new(chunk))
+            .collect::<Vec<_>>();
+        let written = self.stream.write_vectored(&slices).await?;
+        if written == 0 {
+            return Err(Error::ConnectionAborted);
+        }
+
+        // 移除已写入的分片
+        let mut remaining = written;
+        while remaining > 0 {
+            let chunk = self.chunks.front_mut().expect("written more than pending");
+            if chunk.len() > remaining {
+                chunk.advance(remaining);
+                break;
+            }
+            remaining -= chunk.len();
+            self.chunks.pop_front();
+        }
+
+        self.pending -= written;
+        self.outbound
+            .pending_bytes
+            .store(self.pending, Ordering::Relaxed);
+        self.outbound
+            .sent_bytes
+            .fetch_add(written as u64, Ordering::Relaxed);
+        self.outbound.metrics().network_sent(written);
+        Ok(written)
+    }
+
+    /// 缓冲区中的数据全部写入 socket
+    pub(crate) async fn flush(&mut self) -> Result<(), Error> {
+        while self.pending > 0 {
+            self.write_some().await?;
+        }
         Ok(())
     }
 }
@@ -1,37 +1,112 @@
 //! 来自对等节点的连接
 
-use tokio::sync::mpsc::{Receiver, Sender};
+use gecko_mqtt_proto::{
+    ForwardPublishRequest, ReleaseSessionRequest, ReleaseSessionResponse, RouteAction,
+    SyncRetainRequest, SyncRetainResponse, TakeoverSessionRequest, TakeoverSessionResponse,
+    UpdateBanRequest, UpdateRetainRequest, UpdateRouteRequest, UpdateSessionRequest,
+};
+use log::error;
+use tokio::sync::{
+    mpsc::{Receiver, Sender},
+    oneshot,
+};
 
-use crate::cluster::Dispatcher;
-use crate::network::v4::Packet;
+use crate::cluster;
 use crate::protocol::Incoming;
 
 use super::Error;
 
+/// 对等节点通过 grpc 发送过来的请求
+#[derive(Debug)]
+pub(crate) enum PeerRequest {
+    ForwardPublish(ForwardPublishRequest),
+    UpdateRoute(UpdateRouteRequest),
+    UpdateSession(UpdateSessionRequest),
+    TakeoverSession(
+        TakeoverSessionRequest,
+        oneshot::Sender<TakeoverSessionResponse>,
+    ),
+    ReleaseSession(
+        ReleaseSessionRequest,
+        oneshot::Sender<ReleaseSessionResponse>,
+    ),
+    UpdateRetain(UpdateRetainRequest),
+    SyncRetain(SyncRetainRequest, oneshot::Sender<SyncRetainResponse>),
+    UpdateBan(UpdateBanRequest),
+}
+
 /// 计划使用 grpc Unary Rpc
 ///
 /// 接收到请求后再通过 channel 传过来
 pub(crate) struct PeerConnection {
     /// 接收到对等节点发来的数据
-    peer_rx: Receiver<()>,
-    /// 数据发送给对等节点
-    dispatcher: Dispatcher,
+    peer_rx: Receiver<PeerRequest>,
 }
 
 impl PeerConnection {
-    pub(crate) fn new(peer_rx: Receiver<()>) -> Self {
-        Self {
-            peer_rx,
-            dispatcher: Dispatcher::new(),
-        }
+    pub(crate) fn new(peer_rx: Receiver<PeerRequest>) -> Self {
+        Self { peer_rx }
     }
 
-    pub(crate) async fn start(self, _router_tx: Sender<Incoming>) -> Result<(), Error> {
+    /// 将对等节点发来的请求转交给 router 处理
+    pub(crate) async fn start(mut self, router_tx: Sender<Incoming>) -> Result<(), Error> {
+        while let Some(request) = self.peer_rx.recv().await {
+            let incoming = match request {
+                PeerRequest::ForwardPublish(request) => {
+                    let origin_node_id = request.origin_node_id;
+                    match cluster::forwarded_publish(request) {
+                        Ok(publish) => Incoming::ForwardPublish {
+                            origin_node_id,
+                            publish,
+                        },
+                        Err(e) => {
+                            error!("invalid publish from node {0}: {1:#}", origin_node_id, e);
+                            continue;
+                        }
+                    }
+                }
+                PeerRequest::UpdateRoute(request) => match request.action() {
+                    RouteAction::RouteAdd => Incoming::AddRoute {
+                        node_id: request.node_id,
+                        filter: request.filter,
+                    },
+                    RouteAction::RouteDelete => Incoming::DeleteRoute {
+                        node_id: request.node_id,
+                        filter: request.filter,
+                    },
+                },
+                PeerRequest::UpdateSession(request) => Incoming::UpdateSession {
+                    client_id: request.client_id,
+                    node_id: request.node_id,
+                    removed: request.removed,
+                },
+                PeerRequest::TakeoverSession(request, reply_tx) => Incoming::TakeoverSession {
+                    client_id: request.client_id,
+                    node_id: request.node_id,
+                    reply_tx,
+                },
+                PeerRequest::ReleaseSession(request, reply_tx) => Incoming::ReleaseSession {
+                    client_id: request.client_id,
+                    node_id: request.node_id,
+                    reply_tx,
+                },
+                PeerRequest::UpdateRetain(request) => Incoming::UpdateRetain {
+                    retains: request.retains,
+                },
+                PeerRequest::SyncRetain(request, reply_tx) => Incoming::SyncRetain {
+                    node_id: request.node_id,
+                    reply_tx,
+                },
+                PeerRequest::UpdateBan(request) => Incoming::UpdateBan {
+                    added: request.added,
+                    removed: request.removed,
+                },
+            };
+            // router 已退出，broker 正在关闭
+            if router_tx.send(incoming).await.is_err() {
+                return Ok(());
+            }
+        }
         Ok(())
     }
-
-    /// 从已读取的缓冲区中获取 packet 存入列表
-    pub(crate) async fn read_packets(&mut self) -> Result<Vec<Packet>, Error> {
-        todo!()
-    }
 }
@@ -0,0 +1,235 @@
+//! 连接和发布的限流
+//! * 监听器：令牌不足时暂停接受连接，连接留在内核的 backlog 中
+//! * 来源 IP：令牌不足时直接关闭连接
+//! * 客户端发布：按策略暂停读取客户端的报文，或者断开连接
+
+use std::{
+    cmp,
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::time::{self, Instant};
+
+use crate::config::{self, ClientLimit, LimitPolicy};
+
+/// 来源 IP 的令牌桶超过这个数量时，清理已经恢复满的
+const MAX_IDLE_IP_BUCKETS: usize = 4096;
+
+/// 令牌桶，容量为一秒的速率
+#[derive(Debug)]
+struct TokenBucket {
+    /// 每秒恢复的令牌数
+    rate: f64,
+    /// 当前令牌数，透支时为负数
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            tokens: rate,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+    }
+
+    fn available(&mut self, n: f64, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= n
+    }
+
+    /// 取出令牌，可以透支，返回令牌恢复到非负需要等待的时间
+    fn take(&mut self, n: f64, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= n;
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+
+    fn full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.rate
+    }
+}
+
+/// 监听器接受新连接的限流，每个监听器一个
+#[derive(Debug)]
+pub(crate) struct ConnLimiter {
+    listener: Option<TokenBucket>,
+    ip_rate: u32,
+    ips: HashMap<IpAddr, TokenBucket>,
+}
+
+impl ConnLimiter {
+    pub fn new(cfg: &config::Limit) -> Self {
+        Self {
+            listener: (cfg.listener_conn_rate > 0)
+                .then(|| TokenBucket::new(cfg.listener_conn_rate as f64)),
+            ip_rate: cfg.ip_conn_rate,
+            ips: HashMap::new(),
+        }
+    }
+
+    /// 等待监听器有令牌后再接受连接
+    pub async fn acquire(&mut self) {
+        if let Some(bucket) = &mut self.listener {
+            let wait = bucket.take(1.0, Instant::now());
+            if !wait.is_zero() {
+                time::sleep(wait).await;
+            }
+        }
+    }
+
+    /// 来源 IP 是否还有令牌，addr 不是 ip:port 时不限制
+    pub fn allow(&mut self, addr: &str, now: Instant) -> bool {
+        if self.ip_rate == 0 {
+            return true;
+        }
+        let Ok(addr) = addr.parse::<SocketAddr>() else {
+            return true;
+        };
+        if self.ips.len() > MAX_IDLE_IP_BUCKETS {
+            self.ips.retain(|_, bucket| !bucket.full(now));
+        }
+        let rate = self.ip_rate as f64;
+        let bucket = self
+            .ips
+            .entry(addr.ip().to_canonical())
+            .or_insert_with(|| TokenBucket::new(rate));
+        if !bucket.available(1.0, now) {
+            return false;
+        }
+        bucket.take(1.0, now);
+        true
+    }
+}
+
+/// 客户端发布报文的限流结果
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Throttle {
+    Allow,
+    /// 暂停读取，直到令牌恢复
+    Pause(Duration),
+    /// 断开连接
+    Exceeded,
+}
+
+/// 单个客户端发布报文的限流
+#[derive(Debug)]
+pub(crate) struct PublishLimiter {
+    messages: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    policy: LimitPolicy,
+}
+
+impl PublishLimiter {
+    /// 不限制时返回 None
+    pub fn new(limit: &ClientLimit) -> Option<Self> {
+        if limit.unlimited() {
+            return None;
+        }
+        Some(Self {
+            messages: (limit.messages_per_sec > 0)
+                .then(|| TokenBucket::new(limit.messages_per_sec as f64)),
+            bytes: (limit.bytes_per_sec > 0).then(|| TokenBucket::new(limit.bytes_per_sec as f64)),
+            policy: limit.policy,
+        })
+    }
+
+    /// 客户端发布了一条 payload 长度为 size 的消息
+    pub fn publish(&mut self, size: usize, now: Instant) -> Throttle {
+        let size = size as f64;
+        if self.policy == LimitPolicy::Disconnect {
+            let enough = self
+                .messages
+                .as_mut()
+                .is_none_or(|bucket| bucket.available(1.0, now))
+                && self
+                    .bytes
+                    .as_mut()
+                    .is_none_or(|bucket| bucket.available(size, now));
+            if !enough {
+                return Throttle::Exceeded;
+            }
+        }
+        let wait = cmp::max(
+            self.messages
+                .as_mut()
+                .map_or(Duration::ZERO, |bucket| bucket.take(1.0, now)),
+            self.bytes
+                .as_mut()
+                .map_or(Duration::ZERO, |bucket| bucket.take(size, now)),
+        );
+        match wait.is_zero() {
+            true => Throttle::Allow,
+            false => Throttle::Pause(wait),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_limiter_pauses_or_disconnects() {
+        let mut now = Instant::now();
+        let mut pause = PublishLimiter::new(&ClientLimit {
+            messages_per_sec: 2,
+            bytes_per_sec: 0,
+            policy: LimitPolicy::Pause,
+        })
+        .unwrap();
+        assert_eq!(pause.publish(10, now), Throttle::Allow);
+        assert_eq!(pause.publish(10, now), Throttle::Allow);
+        assert_eq!(
+            pause.publish(10, now),
+            Throttle::Pause(Duration::from_millis(500))
+        );
+        now += Duration::from_millis(500);
+        assert_eq!(
+            pause.publish(10, now),
+            Throttle::Pause(Duration::from_millis(500))
+        );
+
+        let mut disconnect = PublishLimiter::new(&ClientLimit {
+            messages_per_sec: 0,
+            bytes_per_sec: 100,
+            policy: LimitPolicy::Disconnect,
+        })
+        .unwrap();
+        let mut now = Instant::now();
+        assert_eq!(disconnect.publish(60, now), Throttle::Allow);
+        assert_eq!(disconnect.publish(60, now), Throttle::Exceeded);
+        now += Duration::from_millis(200);
+        assert_eq!(disconnect.publish(60, now), Throttle::Allow);
+
+        assert!(PublishLimiter::new(&ClientLimit::default()).is_none());
+    }
+
+    #[test]
+    fn conn_limiter_limits_each_ip() {
+        let mut limiter = ConnLimiter::new(&config::Limit {
+            ip_conn_rate: 1,
+            ..Default::default()
+        });
+        let mut now = Instant::now();
+        assert!(limiter.allow("10.0.0.1:1000", now));
+        assert!(!limiter.allow("10.0.0.1:1001", now));
+        assert!(limiter.allow("10.0.0.2:1000", now));
+        assert!(limiter.allow("incoming", now));
+        now += Duration::from_secs(1);
+        assert!(limiter.allow("10.0.0.1:1002", now));
+    }
+}
@@ -0,0 +1,209 @@
+//! 发往客户端连接的消息队列
+//! router/session 通过 ConnTx 向连接发送消息，连接写 socket 太慢时按照 SlowConsumer 策略处理
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use tokio::sync::{
+    mpsc::{
+        self,
+        error::{SendError, TrySendError},
+        Receiver, Sender,
+    },
+    Notify,
+};
+
+use crate::{
+    config::SlowConsumer,
+    metrics::{DropReason, Metrics},
+    protocol::Outgoing,
+};
+
+use super::{packet::Protocol, v4::Publish};
+
+/// 每个连接的消息队列长度
+const OUTBOUND_QUEUE_SIZE: usize = 1000;
+/// 队列中为 ack 等控制报文预留的位置，publish 不能占用
+const CONTROL_RESERVED: usize = OUTBOUND_QUEUE_SIZE / 10;
+
+/// 创建一个连接的消息队列，连接的统计同时计入 broker 的统计
+pub(crate) fn channel(
+    policy: SlowConsumer,
+    metrics: Arc<Metrics>,
+) -> (ConnTx, Receiver<Outgoing>, Arc<Outbound>) {
+    let (tx, rx) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+    let outbound = Arc::new(Outbound::new(metrics));
+    let conn_tx = ConnTx {
+        tx,
+        policy,
+        outbound: outbound.clone(),
+        info: None,
+    };
+    (conn_tx, rx, outbound)
+}
+
+/// 向客户端连接发送消息，由 router/session 持有
+#[derive(Debug, Clone)]
+pub struct ConnTx {
+    tx: Sender<Outgoing>,
+    /// 客户端消费太慢时的处理策略
+    pub policy: SlowConsumer,
+    pub outbound: Arc<Outbound>,
+    /// 读到 connect 报文后才有连接信息
+    pub info: Option<ConnInfo>,
+}
+
+/// 客户端连接的信息，用于管理接口查询
+#[derive(Debug, Clone)]
+pub struct ConnInfo {
+    /// 客户端地址，自定义连接为 incoming，进程内客户端为 local
+    pub addr: String,
+    pub username: Option<String>,
+    pub protocol: Protocol,
+    pub keep_alive: u16,
+    pub connected_at: SystemTime,
+}
+
+impl ConnTx {
+    /// 附带连接信息，随 connect 一起交给 router
+    pub fn with_info(self, info: ConnInfo) -> Self {
+        Self {
+            info: Some(info),
+            ..self
+        }
+    }
+
+    /// 等待队列有空位后发送，只在队列为空时使用，如 connack
+    pub async fn send(&self, outgoing: Outgoing) -> Result<(), SendError<Outgoing>> {
+        self.tx.send(outgoing).await
+    }
+
+    /// 队列中是否还有 publish 可用的位置
+    pub fn has_capacity(&self) -> bool {
+        self.tx.capacity() > CONTROL_RESERVED
+    }
+
+    /// 队列中等待连接处理的消息数
+    pub fn queued(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    /// 不等待，立即发送；连接已关闭时丢弃
+    /// 队列已满说明客户端长时间没有读取数据，断开连接
+    pub fn try_send(&self, outgoing: Outgoing) {
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(outgoing) {
+            self.outbound.kick();
+        }
+    }
+
+    /// 是否是这个连接的队列
+    pub fn is_conn(&self, outbound: &Arc<Outbound>) -> bool {
+        Arc::ptr_eq(&self.outbound, outbound)
+    }
+
+    /// 通知连接断开
+    pub fn disconnect(&self) {
+        self.try_send(Outgoing::Disconnect);
+    }
+
+    /// 通知连接 broker 正在关闭
+    pub fn shutdown(&self) {
+        self.try_send(Outgoing::Shutdown);
+    }
+}
+
+/// 连接消息队列的状态和统计，连接和 router 共同持有
+#[derive(Debug)]
+pub struct Outbound {
+    /// 写缓冲区中等待写入 socket 的字节数
+    pub pending_bytes: AtomicUsize,
+    /// 已写入 socket 的报文数
+    pub sent_packets: AtomicU64,
+    /// 已写入 socket 的字节数
+    pub sent_bytes: AtomicU64,
+    /// 因客户端消费太慢而丢弃的消息数
+    pub dropped: AtomicU64,
+    /// 因客户端消费太慢而暂停发送的次数
+    pub paused: AtomicU64,
+    /// session 中有暂停发送的消息，等待连接通知恢复
+    resume: AtomicBool,
+    /// 通知连接断开
+    kicked: Notify,
+    /// broker 的消息统计
+    metrics: Arc<Metrics>,
+}
+
+impl Outbound {
+    fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            pending_bytes: AtomicUsize::new(0),
+            sent_packets: AtomicU64::new(0),
+            sent_bytes: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            paused: AtomicU64::new(0),
+            resume: AtomicBool::new(false),
+            kicked: Notify::new(),
+            metrics,
+        }
+    }
+
+    pub fn pause(&self) {
+        if !self.resume.swap(true, Ordering::Relaxed) {
+            self.paused.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 连接的队列清空后调用，返回 session 是否需要恢复发送
+    pub fn take_resume(&self) -> bool {
+        self.resume.swap(false, Ordering::Relaxed)
+    }
+
+    pub(crate) fn drop_message(&self, reason: DropReason) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        self.metrics.dropped(reason);
+    }
+
+    /// publish 已交给连接发送
+    pub fn publish_sent(&self, publish: &Publish) {
+        self.metrics.sent(publish);
+    }
+
+    /// broker 的统计
+    pub(crate) fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    pub fn kick(&self) {
+        self.kicked.notify_one();
+    }
+
+    /// 等待断开通知
+    pub async fn kicked(&self) {
+        self.kicked.notified().await
+    }
+
+    pub fn stats(&self) -> OutboundStats {
+        OutboundStats {
+            pending_bytes: self.pending_bytes.load(Ordering::Relaxed),
+            sent_packets: self.sent_packets.load(Ordering::Relaxed),
+            sent_bytes: self.sent_bytes.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            paused: self.paused.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 连接消息队列的统计快照
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutboundStats {
+    pub pending_bytes: usize,
+    pub sent_packets: u64,
+    pub sent_bytes: u64,
+    pub dropped: u64,
+    pub paused: u64,
+}
@@ -2,6 +2,9 @@ use std::slice::Iter;
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
+pub use bytestr::ByteStr;
+
+mod bytestr;
 pub mod v4;
 pub mod v5;
 
@@ -19,6 +22,8 @@ pub enum Error {
     InvalidQoS(u8),
     #[error("Payload too large")]
     PayloadTooLarge,
+    #[error("Packet too large: {0} bytes")]
+    PacketTooLarge(usize),
     #[error("Invalid protocol")]
     InvalidProtocol,
     #[error("Invalid protocol level: {0}")]
@@ -27,23 +32,49 @@ pub enum Error {
     InvalidPacketType(u8),
     #[error("Miss packet id")]
     MissPacketId,
+    #[error("Invalid fixed header flags: {0:#010b}")]
+    InvalidHeaderFlags(u8),
+    #[error("String contains null character")]
+    NullCharacter,
     #[error("Invalid v4 packet: {0}")]
     V4(#[from] v4::Error),
     #[error("Invalid v5 packet: {0}")]
     V5(#[from] v5::Error),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Protocol {
+    /// v3.1
+    V3,
     /// v3.1.1
     V4,
     /// v5
     V5,
 }
 
+impl Protocol {
+    /// CONNECT 报文中的协议名
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Protocol::V3 => "MQIsdp",
+            Protocol::V4 | Protocol::V5 => "MQTT",
+        }
+    }
+
+    /// CONNECT 报文中的协议级别
+    pub(crate) fn level(&self) -> u8 {
+        match self {
+            Protocol::V3 => 3,
+            Protocol::V4 => 4,
+            Protocol::V5 => 5,
+        }
+    }
+}
+
 /// 服务质量
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+#[serde(try_from = "u8")]
 #[allow(clippy::enum_variant_names)]
 pub enum QoS {
     AtMostOnce = 0,
@@ -81,6 +112,8 @@ pub enum PacketType {
     PingReq,
     PingResp,
     Disconnect,
+    /// 只有 v5 协议有
+    Auth,
 }
 
 #[derive(Debug)]
@@ -112,10 +145,25 @@ impl FixedHeader {
             12 => Ok(PacketType::PingReq),
             13 => Ok(PacketType::PingResp),
             14 => Ok(PacketType::Disconnect),
+            15 => Ok(PacketType::Auth),
             n => Err(Error::InvalidPacketType(n)),
         }
     }
 
+    /// 固定头的标志位，除 publish 外都是固定值
+    fn check_flags(&self, packet_type: PacketType) -> Result<(), Error> {
+        let flags = self.byte1 & 0b0000_1111;
+        let expected_flags = match packet_type {
+            PacketType::Publish => flags,
+            PacketType::PubRel | PacketType::Subscribe | PacketType::Unsubscribe => 0b0010,
+            _ => 0,
+        };
+        if flags != expected_flags {
+            return Err(Error::InvalidHeaderFlags(self.byte1));
+        }
+        Ok(())
+    }
+
     /// 整个完整报文的字节长度
     #[inline]
     fn packet_len(&self) -> usize {
@@ -131,7 +179,7 @@ impl FixedHeader {
         }
         // 第一个字节
         let byte1 = stream.next().unwrap();
-        let (remaining_len, header_len) = length(stream)?;
+        let (header_len, remaining_len) = length(stream)?;
 
         Ok(Self {
             byte1: *byte1,
@@ -159,12 +207,13 @@ fn read_bytes(stream: &mut Bytes) -> Result<Bytes, Error> {
     Ok(stream.split_to(len))
 }
 
+/// 读取字符串，与报文共享内存，不拷贝
+fn read_str(stream: &mut Bytes) -> Result<ByteStr, Error> {
+    ByteStr::from_utf8(read_bytes(stream)?)
+}
+
 fn read_string(stream: &mut Bytes) -> Result<String, Error> {
-    let s = read_bytes(stream)?;
-    match String::from_utf8(s.to_vec()) {
-        Ok(v) => Ok(v),
-        Err(_) => Err(Error::MalformedString),
-    }
+    read_str(stream).map(String::from)
 }
 
 fn read_u16(stream: &mut Bytes) -> Result<u16, Error> {
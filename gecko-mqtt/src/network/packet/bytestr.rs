@@ -0,0 +1,90 @@
+use std::{borrow::Borrow, fmt, ops::Deref, str};
+
+use bytes::Bytes;
+
+use super::Error;
+
+/// 以 `Bytes` 保存的 UTF-8 字符串
+/// * 解码时只校验，不拷贝，与报文共享同一块内存
+/// * clone 只增加引用计数，一条 publish 分发给多个订阅者时不需要重新分配
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteStr(Bytes);
+
+impl ByteStr {
+    /// 校验 UTF-8，不拷贝数据
+    pub fn from_utf8(bytes: Bytes) -> Result<Self, Error> {
+        str::from_utf8(&bytes).map_err(|_| Error::MalformedString)?;
+        Ok(Self(bytes))
+    }
+
+    pub fn as_str(&self) -> &str {
+        // SAFETY: 构造时已校验过 UTF-8
+        unsafe { str::from_utf8_unchecked(&self.0) }
+    }
+
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.0
+    }
+}
+
+impl Deref for ByteStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for ByteStr {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for ByteStr {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&'static str> for ByteStr {
+    fn from(s: &'static str) -> Self {
+        Self(Bytes::from_static(s.as_bytes()))
+    }
+}
+
+impl From<String> for ByteStr {
+    fn from(s: String) -> Self {
+        Self(Bytes::from(s))
+    }
+}
+
+impl From<ByteStr> for String {
+    fn from(s: ByteStr) -> Self {
+        s.as_str().to_owned()
+    }
+}
+
+impl PartialEq<str> for ByteStr {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for ByteStr {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Debug for ByteStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for ByteStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
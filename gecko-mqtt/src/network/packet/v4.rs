@@ -1,6 +1,6 @@
-//! 3.1.1 协议版本报文
+//! 3.1.1 协议版本报文，兼容 3.1
 
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 
 pub use connack::*;
 pub use connect::*;
@@ -15,7 +15,7 @@ pub use subscribe::*;
 pub use unsuback::*;
 pub use unsubscribe::*;
 
-use super::PacketType;
+use super::{v5, PacketType, Protocol};
 
 pub mod connack;
 pub mod connect;
@@ -45,6 +45,21 @@ pub enum Error {
     InvalidPublishTopic,
     #[error("Invalid subscribe filter")]
     InvalidSubscribeFilter,
+    #[error("Subscribe failure is not supported by MQTT 3.1")]
+    UnsupportedSubscribeFailure,
+
+    #[error("Invalid connect flags: {0:#010b}")]
+    InvalidConnectFlags(u8),
+    #[error("Password flag set without username flag")]
+    PasswordWithoutUsername,
+    #[error("Empty client id requires clean session")]
+    EmptyClientId,
+    #[error("Invalid subscribe options: {0:#010b}")]
+    InvalidSubscribeOptions(u8),
+    #[error("Subscribe or unsubscribe without topic filter")]
+    EmptySubscription,
+    #[error("Dup flag set on QoS 0 publish")]
+    InvalidDup,
 }
 
 #[derive(Debug)]
@@ -66,11 +81,22 @@ pub enum Packet {
 }
 
 impl Packet {
-    pub(crate) fn read(stream: &mut BytesMut) -> Result<Self, super::Error> {
+    /// 读取一个报文
+    /// * strict 为 true 时，按 3.1.1 规范严格校验，不符合规范的报文都视为非法
+    /// * strict 为 false 时，只拒绝无法解析的报文，兼容不规范的客户端
+    /// * 报文总长度超过 max_packet_size 时，解析出固定头后立即返回错误，不等待读取报文的剩余部分
+    pub(crate) fn read(
+        stream: &mut BytesMut,
+        strict: bool,
+        max_packet_size: usize,
+    ) -> Result<Self, super::Error> {
         let stream_len = stream.len();
         let fixed_header = super::FixedHeader::read_from(stream.iter())?;
 
         let packet_len = fixed_header.packet_len();
+        if packet_len > max_packet_size {
+            return Err(super::Error::PacketTooLarge(packet_len));
+        }
         if stream_len < packet_len {
             return Err(super::Error::InsufficientBytes(packet_len - stream_len))?;
         }
@@ -81,6 +107,9 @@ impl Packet {
 
         // 报文类型
         let packet_type = fixed_header.packet_type()?;
+        if strict {
+            fixed_header.check_flags(packet_type)?;
+        }
         // 没有负载的 packet 类型，获取到报文头后，可以直接返回
         if fixed_header.remaining_len == 0 {
             return match packet_type {
@@ -98,28 +127,69 @@ impl Packet {
         stream.advance(variable_header_index);
 
         let packet = match packet_type {
-            PacketType::Connect => Packet::Connect(Connect::read(stream)?),
-            PacketType::Subscribe => Packet::Subscribe(Subscribe::read(stream)?),
-            PacketType::Publish => Packet::Publish(Publish::read(fixed_header, stream)?),
+            PacketType::Connect => Packet::Connect(Connect::read(stream, strict)?),
+            PacketType::Subscribe => Packet::Subscribe(Subscribe::read(stream, strict)?),
+            PacketType::Publish => Packet::Publish(Publish::read(fixed_header, stream, strict)?),
             PacketType::PubAck => Packet::PubAck(PubAck::read(fixed_header, stream)?),
             PacketType::PubComp => Packet::PubComp(PubComp::read(fixed_header, stream)?),
             PacketType::PubRec => Packet::PubRec(PubRec::read(fixed_header, stream)?),
             PacketType::PubRel => Packet::PubRel(PubRel::read(fixed_header, stream)?),
-            PacketType::Unsubscribe => {
-                Packet::Unsubscribe(Unsubscribe::read(fixed_header, stream)?)
-            }
+            PacketType::Unsubscribe => Packet::Unsubscribe(Unsubscribe::read(stream, strict)?),
+            _ => return Err(Error::UnexpectedPacketType)?,
+        };
+
+        Ok(packet)
+    }
+
+    /// 读取 v5 客户端的报文，转换为 broker 内部统一使用的报文
+    /// 只保留 broker 用到的字段，publish 的属性原样保留
+    pub(crate) fn read_v5(
+        stream: &mut BytesMut,
+        max_packet_size: usize,
+    ) -> Result<Self, super::Error> {
+        let packet = match v5::Packet::read(stream, max_packet_size)? {
+            v5::Packet::Connect(connect) => Packet::Connect(connect.into()),
+            v5::Packet::Publish(publish) => Packet::Publish(publish.try_into()?),
+            v5::Packet::PubAck(puback) => Packet::PubAck(PubAck {
+                packet_id: puback.packet_id,
+            }),
+            v5::Packet::PubRec(pubrec) => Packet::PubRec(PubRec {
+                packet_id: pubrec.packet_id,
+            }),
+            v5::Packet::PubRel(pubrel) => Packet::PubRel(PubRel {
+                packet_id: pubrel.packet_id,
+            }),
+            v5::Packet::PubComp(pubcomp) => Packet::PubComp(PubComp {
+                packet_id: pubcomp.packet_id,
+            }),
+            v5::Packet::Subscribe(subscribe) => Packet::Subscribe(subscribe.try_into()?),
+            v5::Packet::Unsubscribe(unsubscribe) => Packet::Unsubscribe(Unsubscribe {
+                packet_id: unsubscribe.packet_id,
+                filters: unsubscribe.filters,
+            }),
+            v5::Packet::PingReq => Packet::PingReq,
+            v5::Packet::Disconnect(_) => Packet::Disconnect,
+            // 服务端发送的报文，以及不支持的增强认证
             _ => return Err(Error::UnexpectedPacketType)?,
         };
 
         Ok(packet)
     }
 
-    pub(crate) fn write(&self, stream: &mut BytesMut) -> Result<(), super::Error> {
+    /// 按客户端的协议版本写入报文，3.1 与 3.1.1 只有 CONNACK 和 SUBACK 不同
+    pub(crate) fn write(
+        &self,
+        stream: &mut BytesMut,
+        protocol: Protocol,
+    ) -> Result<(), super::Error> {
+        if protocol == Protocol::V5 {
+            return self.write_v5(stream);
+        }
         match self {
-            Packet::ConnAck(ack) => ack.write(stream),
+            Packet::ConnAck(ack) => ack.write(stream, protocol),
             Packet::PingResp => PingResp.write(stream),
-            Packet::SubAck(ack) => ack.write(stream),
-            Packet::Publish(publish) => publish.write(stream),
+            Packet::SubAck(ack) => ack.write(stream, protocol),
+            Packet::Publish(publish) => publish.write(stream, protocol),
             Packet::PubAck(puback) => puback.write(stream),
             Packet::PubComp(pubcomp) => pubcomp.write(stream),
             Packet::PubRec(pubrec) => pubrec.write(stream),
@@ -129,6 +199,57 @@ impl Packet {
         }
     }
 
+    /// 按 v5 协议写入，ack 都回复成功
+    fn write_v5(&self, stream: &mut BytesMut) -> Result<(), super::Error> {
+        match self {
+            Packet::ConnAck(ack) => {
+                let connack = v5::ConnAck {
+                    session_present: ack.session_present,
+                    code: ack.code.into(),
+                    properties: None,
+                };
+                connack.write(stream)
+            }
+            Packet::PingResp => v5::PingResp.write(stream),
+            Packet::SubAck(ack) => ack.write_v5(stream),
+            Packet::Publish(publish) => publish.write(stream, Protocol::V5),
+            Packet::PubAck(puback) => {
+                let puback = v5::PubAck {
+                    packet_id: puback.packet_id,
+                    reason: v5::PubAckReason::Success,
+                    properties: None,
+                };
+                puback.write(stream)
+            }
+            Packet::PubComp(pubcomp) => {
+                let pubcomp = v5::PubComp {
+                    packet_id: pubcomp.packet_id,
+                    reason: v5::PubCompReason::Success,
+                    properties: None,
+                };
+                pubcomp.write(stream)
+            }
+            Packet::PubRec(pubrec) => {
+                let pubrec = v5::PubRec {
+                    packet_id: pubrec.packet_id,
+                    reason: v5::PubRecReason::Success,
+                    properties: None,
+                };
+                pubrec.write(stream)
+            }
+            Packet::PubRel(pubrel) => {
+                let pubrel = v5::PubRel {
+                    packet_id: pubrel.packet_id,
+                    reason: v5::PubRelReason::Success,
+                    properties: None,
+                };
+                pubrel.write(stream)
+            }
+            Packet::UnsubAck(unsuback) => unsuback.write_v5(stream),
+            _ => Err(Error::UnexpectedPacketType)?,
+        }
+    }
+
     #[inline]
     pub(crate) fn packet_type(&self) -> PacketType {
         match self {
@@ -149,3 +270,19 @@ impl Packet {
         }
     }
 }
+
+/// 读取字符串，严格模式下不允许包含 U+0000 [MQTT-1.5.3-2]
+fn read_str(stream: &mut Bytes, strict: bool) -> Result<super::ByteStr, super::Error> {
+    let s = super::read_str(stream)?;
+    if strict && s.as_bytes().contains(&0) {
+        return Err(super::Error::NullCharacter);
+    }
+    Ok(s)
+}
+
+fn read_string(stream: &mut Bytes, strict: bool) -> Result<String, super::Error> {
+    read_str(stream, strict).map(String::from)
+}
+
+#[cfg(test)]
+mod tests;
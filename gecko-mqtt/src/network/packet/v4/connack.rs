@@ -1,6 +1,6 @@
 use bytes::{BufMut, BytesMut};
 
-use crate::network::packet::{write_remaining_length, Error};
+use crate::network::packet::{v5, write_remaining_length, Error, Protocol};
 
 /// 连接返回码
 #[derive(Debug, Copy, Clone)]
@@ -18,6 +18,22 @@ pub enum ConnectReturnCode {
     BadUserNamePassword,
     /// 未授权
     NotAuthorized,
+    /// 客户端被禁止连接，3.1/3.1.1 没有这个返回码，回复未授权
+    Banned,
+}
+
+impl From<ConnectReturnCode> for v5::ConnectReturnCode {
+    fn from(code: ConnectReturnCode) -> Self {
+        match code {
+            ConnectReturnCode::Success => Self::Success,
+            ConnectReturnCode::RefusedProtocolVersion => Self::UnsupportedProtocolVersion,
+            ConnectReturnCode::BadClientId => Self::ClientIdentifierNotValid,
+            ConnectReturnCode::ServiceUnavailable => Self::ServerUnavailable,
+            ConnectReturnCode::BadUserNamePassword => Self::BadUserNamePassword,
+            ConnectReturnCode::NotAuthorized => Self::NotAuthorized,
+            ConnectReturnCode::Banned => Self::Banned,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -42,13 +58,19 @@ impl ConnAck {
         1 + 1
     }
 
-    pub fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+    pub fn write(&self, stream: &mut BytesMut, protocol: Protocol) -> Result<(), Error> {
         stream.put_u8(0x20);
 
         let len = self.len();
         write_remaining_length(stream, len)?;
-        stream.put_u8(self.session_present as u8);
-        stream.put_u8(self.code as u8);
+        // 3.1 中第一个字节是保留字节，没有 session present 标志
+        let session_present = self.session_present && protocol != Protocol::V3;
+        stream.put_u8(session_present as u8);
+        let code = match self.code {
+            ConnectReturnCode::Banned => ConnectReturnCode::NotAuthorized,
+            code => code,
+        };
+        stream.put_u8(code as u8);
 
         Ok(())
     }
@@ -1,6 +1,6 @@
 use bytes::Bytes;
 
-use crate::network::packet::{self, Error, Protocol, QoS};
+use crate::network::packet::{self, v5, Error, Protocol, QoS};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Connect {
@@ -16,29 +16,48 @@ pub struct Connect {
     pub last_will: Option<LastWill>,
     /// 登录凭证
     pub login: Login,
+    /// 客户端能接收的最大报文长度，只有 v5 有
+    pub max_packet_size: Option<u32>,
 }
 
 impl Connect {
-    pub(crate) fn read(mut stream: Bytes) -> Result<Self, Error> {
+    pub(crate) fn read(mut stream: Bytes, strict: bool) -> Result<Self, Error> {
+        let packet = stream.clone();
         // 可变报头
         let protocol_name = packet::read_string(&mut stream)?;
         let protocol_level = packet::read_u8(&mut stream)?;
-        if protocol_name != "MQTT" {
-            return Err(Error::InvalidProtocol);
-        }
-        let protocol = match protocol_level {
-            4 => Protocol::V4,
-            5 => Protocol::V5,
-            num => return Err(Error::InvalidProtocolLevel(num))?,
+        // 3.1 的协议名为 MQIsdp，3.1.1 之后为 MQTT
+        let protocol = match (protocol_name.as_str(), protocol_level) {
+            ("MQIsdp", 3) => Protocol::V3,
+            ("MQTT", 4) => Protocol::V4,
+            ("MQTT", 5) => Protocol::V5,
+            ("MQIsdp", num) | ("MQTT", num) => return Err(Error::InvalidProtocolLevel(num)),
+            _ => return Err(Error::InvalidProtocol),
         };
+        // v5 带有属性，使用 v5 的解码，只保留 broker 用到的字段
+        if protocol == Protocol::V5 {
+            return Ok(v5::Connect::read(packet)?.into());
+        }
 
         let connect_flags = packet::read_u8(&mut stream)?;
+        // 保留位必须为 0 [MQTT-3.1.2-3]
+        if strict && connect_flags & 0b0000_0001 != 0 {
+            return Err(super::Error::InvalidConnectFlags(connect_flags))?;
+        }
+        // 没有用户名时不能有密码 [MQTT-3.1.2-22]
+        if strict && connect_flags & 0b1100_0000 == 0b0100_0000 {
+            return Err(super::Error::PasswordWithoutUsername)?;
+        }
         let clean_session = (connect_flags & 0b10) != 0;
         let keep_alive = packet::read_u16(&mut stream)?;
 
-        let client_id = packet::read_string(&mut stream)?;
-        let last_will = LastWill::read(connect_flags, &mut stream)?;
-        let login = Login::read(connect_flags, &mut stream)?;
+        let client_id = super::read_string(&mut stream, strict)?;
+        // 空的客户端 id 必须开启新会话 [MQTT-3.1.3-7]
+        if strict && client_id.is_empty() && !clean_session {
+            return Err(super::Error::EmptyClientId)?;
+        }
+        let last_will = LastWill::read(connect_flags, &mut stream, strict)?;
+        let login = Login::read(connect_flags, &mut stream, strict)?;
 
         Ok(Self {
             protocol,
@@ -47,10 +66,35 @@ impl Connect {
             clean_session,
             last_will,
             login,
+            max_packet_size: None,
         })
     }
 }
 
+impl From<v5::Connect> for Connect {
+    fn from(connect: v5::Connect) -> Self {
+        Self {
+            protocol: connect.protocol,
+            keep_alive: connect.keepalive,
+            client_id: connect.client_id,
+            clean_session: connect.clean_start,
+            last_will: connect.last_will.map(|last_will| LastWill {
+                topic: last_will.topic,
+                message: last_will.message,
+                qos: last_will.qos,
+                retain: last_will.retain,
+            }),
+            login: Login {
+                username: connect.login.username,
+                password: connect.login.password,
+            },
+            max_packet_size: connect
+                .properties
+                .and_then(|properties| properties.max_packet_size),
+        }
+    }
+}
+
 /// 遗嘱设置
 #[derive(Debug, PartialEq, Eq)]
 pub struct LastWill {
@@ -65,14 +109,18 @@ pub struct LastWill {
 }
 
 impl LastWill {
-    fn read(connect_flags: u8, stream: &mut Bytes) -> Result<Option<LastWill>, Error> {
+    fn read(
+        connect_flags: u8,
+        stream: &mut Bytes,
+        strict: bool,
+    ) -> Result<Option<LastWill>, Error> {
         let last_will = match connect_flags & 0b100 {
             0 if (connect_flags & 0b0011_1000) != 0 => {
                 return Err(super::Error::IncorrectPacketFormat)?;
             }
             0 => None,
             _ => Some(LastWill {
-                topic: packet::read_string(stream)?,
+                topic: super::read_string(stream, strict)?,
                 message: packet::read_bytes(stream)?,
                 qos: QoS::try_from((connect_flags & 0b11000) >> 3)?,
                 retain: (connect_flags & 0b0010_0000) != 0,
@@ -93,10 +141,10 @@ pub struct Login {
 }
 
 impl Login {
-    fn read(connect_flags: u8, stream: &mut Bytes) -> Result<Self, Error> {
+    fn read(connect_flags: u8, stream: &mut Bytes, strict: bool) -> Result<Self, Error> {
         let username = match connect_flags & 0b1000_0000 {
             0 => None,
-            _ => Some(packet::read_string(stream)?),
+            _ => Some(super::read_string(stream, strict)?),
         };
 
         let password = match connect_flags & 0b0100_0000 {
@@ -172,7 +220,7 @@ mod tests {
 
         let variable_header_index = fixed_header.fixed_header_len;
         connect_bytes.advance(variable_header_index);
-        let packet = Connect::read(connect_bytes).unwrap();
+        let packet = Connect::read(connect_bytes, true).unwrap();
 
         assert_eq!(
             packet,
@@ -190,8 +238,54 @@ mod tests {
                 login: Login {
                     username: Some("rumq".into()),
                     password: Some("mq".into())
-                }
+                },
+                max_packet_size: None,
             }
         );
     }
+
+    #[test]
+    fn v3_connect_parsing_works() {
+        let mut stream = bytes::BytesMut::new();
+        let packetstream = &[
+            0x10,
+            16, // packet type, flags and remaining len
+            0x00,
+            0x06,
+            b'M',
+            b'Q',
+            b'I',
+            b's',
+            b'd',
+            b'p',        // protocol name
+            0x03,        // protocol level
+            0b0000_0010, // +clean_session
+            0x00,
+            0x3c, // keep alive = 60 sec
+            0x00,
+            0x02,
+            b'v',
+            b'3', // payload. client_id
+        ];
+
+        stream.extend_from_slice(&packetstream[..]);
+        let fixed_header = FixedHeader::read_from(stream.iter()).unwrap();
+        let mut connect_bytes = stream.split_to(fixed_header.packet_len()).freeze();
+        connect_bytes.advance(fixed_header.fixed_header_len);
+        let packet = Connect::read(connect_bytes, true).unwrap();
+
+        assert_eq!(packet.protocol, Protocol::V3);
+        assert_eq!(packet.client_id, "v3");
+        assert_eq!(packet.keep_alive, 60);
+
+        // 3.1 的协议名不能搭配其他协议级别
+        let mut stream = bytes::BytesMut::new();
+        packet::write_string(&mut stream, "MQIsdp");
+        stream.extend_from_slice(&[0x04, 0b0000_0010, 0x00, 0x3c]);
+        packet::write_string(&mut stream, "v3");
+        assert!(matches!(
+            Connect::read(stream.freeze(), true),
+            Err(Error::InvalidProtocolLevel(4))
+        ));
+    }
 }
@@ -1,7 +1,9 @@
+use std::sync::{Arc, OnceLock};
+
 use bytes::{BufMut, Bytes, BytesMut};
 
 use crate::network::{
-    packet::{self, Error, QoS},
+    packet::{self, v5, ByteStr, Error, Protocol, QoS},
     topic,
 };
 
@@ -15,32 +17,73 @@ pub struct Publish {
     pub qos: QoS,
     /// 消息保留
     pub retain: bool,
-    /// 主题
-    pub topic: String,
+    /// 主题，与报文共享内存
+    pub topic: ByteStr,
     /// 包 id
     pub packet_id: u16,
     /// 消息负载
     pub payload: Bytes,
+    /// v5 的消息属性，原样转发给 v5 订阅者，3.1/3.1.1 订阅者收到的消息不带属性
+    /// 投递给多个订阅者时共享，不拷贝
+    pub properties: Option<Arc<v5::PublishProperties>>,
+    /// v5 报文体的编码结果，clone 时共享，构造时使用默认值
+    /// 分发之后 properties 和 payload 不再修改
+    pub encoded: EncodedBody,
 }
 
+/// v5 报文中 packet id 之后的部分：属性和 payload
+/// 投递给多个 v5 订阅者时只编码一次，每个连接只写固定头、主题和 packet id
+#[derive(Debug, Clone, Default)]
+pub struct EncodedBody(Arc<OnceLock<Bytes>>);
+
 impl Publish {
-    fn len(&self) -> usize {
+    /// 固定头之后、报文体之前的长度
+    fn header_len(&self) -> usize {
         let mut len = 2 + self.topic.len();
         if self.qos != QoS::AtMostOnce && self.packet_id != 0 {
             len += 2;
         }
-        len += self.payload.len();
-
         len
     }
 
-    pub fn read(fixed_header: FixedHeader, mut stream: Bytes) -> Result<Self, Error> {
+    /// 报文体：3.1/3.1.1 为 payload，v5 为属性和 payload
+    pub fn body(&self, protocol: Protocol) -> Result<Bytes, Error> {
+        if protocol != Protocol::V5 {
+            return Ok(self.payload.clone());
+        }
+        if let Some(body) = self.encoded.0.get() {
+            return Ok(body.clone());
+        }
+
+        let properties = self.properties.as_ref().map_or(0, |p| p.len());
+        let mut stream =
+            BytesMut::with_capacity(v5::properties_len(properties) + self.payload.len());
+        match &self.properties {
+            Some(properties) => properties.write(&mut stream)?,
+            None => {
+                packet::write_remaining_length(&mut stream, 0)?;
+            }
+        }
+        stream.extend_from_slice(&self.payload);
+        // 多个连接同时编码时，只保留第一个结果
+        Ok(self.encoded.0.get_or_init(|| stream.freeze()).clone())
+    }
+
+    pub fn read(fixed_header: FixedHeader, mut stream: Bytes, strict: bool) -> Result<Self, Error> {
         let byte1 = fixed_header.byte1;
         let qos = ((byte1 & 0b0110) >> 1).try_into()?;
         let dup = (byte1 & 0b1000) != 0;
         let retain = (byte1 & 0b0001) != 0;
+        // QoS 0 的消息 dup 必须为 0 [MQTT-3.3.1-2]
+        if strict && dup && qos == QoS::AtMostOnce {
+            return Err(super::Error::InvalidDup)?;
+        }
 
-        let topic = packet::read_string(&mut stream)?;
+        let topic = super::read_str(&mut stream, strict)?;
+        // 主题至少包含一个字符 [MQTT-4.7.3-1]
+        if strict && topic.is_empty() {
+            return Err(super::Error::InvalidPublishTopic)?;
+        }
         if !topic::valid_publish_topic(&topic) {
             return Err(super::Error::InvalidPublishTopic)?;
         }
@@ -62,11 +105,22 @@ impl Publish {
             topic,
             packet_id,
             payload: stream,
+            properties: None,
+            encoded: EncodedBody::default(),
         })
     }
 
-    pub fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
-        let len = self.len();
+    pub fn write(&self, stream: &mut BytesMut, protocol: Protocol) -> Result<(), Error> {
+        let body = self.body(protocol)?;
+        self.write_header(stream, body.len())?;
+        stream.extend_from_slice(&body);
+
+        Ok(())
+    }
+
+    /// 写入报文体以外的部分，报文体由调用方单独写入，多个连接共享
+    pub fn write_header(&self, stream: &mut BytesMut, body_len: usize) -> Result<(), Error> {
+        let len = self.header_len() + body_len;
 
         let dup = self.dup as u8;
         let qos = self.qos as u8;
@@ -74,7 +128,7 @@ impl Publish {
         stream.put_u8(0b0011_0000 | retain | qos << 1 | dup << 3);
 
         packet::write_remaining_length(stream, len)?;
-        packet::write_string(stream, &self.topic);
+        packet::write_bytes(stream, self.topic.as_bytes());
 
         if self.qos != QoS::AtMostOnce {
             let pkid = self.packet_id;
@@ -85,8 +139,39 @@ impl Publish {
             stream.put_u16(pkid);
         }
 
-        stream.extend_from_slice(&self.payload);
-
         Ok(())
     }
 }
+
+impl TryFrom<v5::Publish> for Publish {
+    type Error = Error;
+
+    /// v5 客户端发布的消息
+    /// * 不支持主题别名，主题必须完整 [MQTT-3.3.2-8]
+    /// * 订阅标识符由服务端填写，去掉客户端发送的 [MQTT-3.3.4-6]
+    fn try_from(publish: v5::Publish) -> Result<Self, Self::Error> {
+        if publish.topic.is_empty() || !topic::valid_publish_topic(&publish.topic) {
+            return Err(super::Error::InvalidPublishTopic)?;
+        }
+        let properties = publish
+            .properties
+            .map(|properties| v5::PublishProperties {
+                topic_alias: None,
+                subscription_identifiers: Vec::new(),
+                ..properties
+            })
+            .filter(|properties| *properties != v5::PublishProperties::default())
+            .map(Arc::new);
+
+        Ok(Self {
+            dup: publish.dup,
+            qos: publish.qos,
+            retain: publish.retain,
+            topic: publish.topic.into(),
+            packet_id: publish.packet_id,
+            payload: publish.payload,
+            properties,
+            encoded: EncodedBody::default(),
+        })
+    }
+}
@@ -1,6 +1,6 @@
 use bytes::{BufMut, BytesMut};
 
-use crate::network::packet::{self, Error, QoS};
+use crate::network::packet::{self, v5, Error, Protocol, QoS};
 
 #[derive(Debug)]
 pub struct SubAck {
@@ -11,7 +11,16 @@ pub struct SubAck {
 }
 
 impl SubAck {
-    pub fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+    pub fn write(&self, stream: &mut BytesMut, protocol: Protocol) -> Result<(), Error> {
+        // 3.1 没有失败返回码，无法告知客户端订阅失败，只能断开连接
+        let failure = self
+            .return_codes
+            .iter()
+            .any(|code| matches!(code, SubscribeReasonCode::Failure));
+        if failure && protocol == Protocol::V3 {
+            return Err(super::Error::UnsupportedSubscribeFailure)?;
+        }
+
         stream.put_u8(0x90);
         let remaining_len = self.len();
         packet::write_remaining_length(stream, remaining_len)?;
@@ -32,6 +41,26 @@ impl SubAck {
     pub fn len(&self) -> usize {
         2 + self.return_codes.len()
     }
+
+    /// 钩子拒绝的订阅，v5 客户端收到未授权
+    pub fn write_v5(&self, stream: &mut BytesMut) -> Result<(), Error> {
+        let return_codes = self
+            .return_codes
+            .iter()
+            .map(|code| match code {
+                SubscribeReasonCode::Success(QoS::AtMostOnce) => v5::SubscribeReasonCode::QoS0,
+                SubscribeReasonCode::Success(QoS::AtLeastOnce) => v5::SubscribeReasonCode::QoS1,
+                SubscribeReasonCode::Success(QoS::ExactlyOnce) => v5::SubscribeReasonCode::QoS2,
+                SubscribeReasonCode::Failure => v5::SubscribeReasonCode::NotAuthorized,
+            })
+            .collect();
+        let suback = v5::SubAck {
+            packet_id: self.packet_id,
+            return_codes,
+            properties: None,
+        };
+        suback.write(stream)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
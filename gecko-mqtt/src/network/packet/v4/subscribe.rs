@@ -1,7 +1,7 @@
 use bytes::{Buf, Bytes};
 
 use crate::network::{
-    packet::{self, read_u8, Error, QoS},
+    packet::{self, read_u8, v5, Error, QoS},
     topic,
 };
 
@@ -12,24 +12,40 @@ pub struct Subscribe {
 }
 
 impl Subscribe {
-    pub fn read(mut stream: Bytes) -> Result<Self, Error> {
+    pub fn read(mut stream: Bytes, strict: bool) -> Result<Self, Error> {
         let packet_id = packet::read_u16(&mut stream)?;
+        // [MQTT-2.3.1-1]
+        if strict && packet_id == 0 {
+            return Err(Error::MissPacketId);
+        }
 
         let mut filters = Vec::new();
         while stream.has_remaining() {
-            let filter = packet::read_string(&mut stream)?;
+            let filter = super::read_string(&mut stream, strict)?;
             if !topic::valid_subscribe_filter(&filter) {
                 return Err(super::Error::InvalidSubscribeFilter)?;
             }
             let options = read_u8(&mut stream)?;
+            // 订阅选项的高 6 位是保留位 [MQTT-3.8.3-4]
+            if strict && options & 0b1111_1100 != 0 {
+                return Err(super::Error::InvalidSubscribeOptions(options))?;
+            }
             let qos = options & 0b0000_0011;
 
             filters.push(SubscribeFilter {
                 path: filter,
                 qos: qos.try_into()?,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: v5::RetainForwardRule::OnEverySubscribe,
             })
         }
 
+        // 至少包含一个订阅 [MQTT-3.8.3-3]
+        if strict && filters.is_empty() {
+            return Err(super::Error::EmptySubscription)?;
+        }
+
         Ok(Self { packet_id, filters })
     }
 }
@@ -38,4 +54,39 @@ impl Subscribe {
 pub struct SubscribeFilter {
     pub path: String,
     pub qos: QoS,
+    /// 不接收自己发布的消息，只有 v5 客户端可以设置
+    pub no_local: bool,
+    /// 投递时保留消息的 retain 标记，只有 v5 客户端可以设置
+    pub retain_as_published: bool,
+    /// 订阅时是否发送保留消息，只有 v5 客户端可以设置
+    pub retain_handling: v5::RetainForwardRule,
+}
+
+impl TryFrom<v5::Subscribe> for Subscribe {
+    type Error = Error;
+
+    /// v5 客户端的订阅
+    fn try_from(subscribe: v5::Subscribe) -> Result<Self, Self::Error> {
+        let filters = subscribe
+            .filters
+            .into_iter()
+            .map(|filter| {
+                if !topic::valid_subscribe_filter(&filter.filter) {
+                    return Err(super::Error::InvalidSubscribeFilter)?;
+                }
+                Ok(SubscribeFilter {
+                    path: filter.filter,
+                    qos: filter.qos,
+                    no_local: filter.nolocal,
+                    retain_as_published: filter.preserve_retain,
+                    retain_handling: filter.retain_forward_rule,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self {
+            packet_id: subscribe.packet_id,
+            filters,
+        })
+    }
 }
@@ -0,0 +1,398 @@
+//! 3.1.1 规范一致性测试，每一项对应规范中的一条规范性语句
+
+use bytes::BytesMut;
+
+use crate::network::packet::{self, Error};
+
+use super::Packet;
+
+struct Case {
+    /// 规范性语句编号
+    statement: &'static str,
+    /// 完整的报文
+    packet: Vec<u8>,
+    /// 只在严格模式下拒绝，宽松模式下可以正常解析
+    strict_only: bool,
+    /// 严格模式下期望的错误
+    expected: fn(&Error) -> bool,
+}
+
+/// 组装报文，剩余长度小于 128
+fn packet(byte1: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![byte1, body.len() as u8];
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn string(s: &[u8]) -> Vec<u8> {
+    let mut bytes = (s.len() as u16).to_be_bytes().to_vec();
+    bytes.extend_from_slice(s);
+    bytes
+}
+
+/// connect 报文，protocol 为协议名和协议级别
+fn connect(protocol: (&[u8], u8), flags: u8, payload: &[&[u8]]) -> Vec<u8> {
+    let mut body = string(protocol.0);
+    body.extend_from_slice(&[protocol.1, flags, 0x00, 0x3c]);
+    for field in payload {
+        body.extend(string(field));
+    }
+    packet(0x10, &body)
+}
+
+fn publish(byte1: u8, topic: &[u8], packet_id: Option<u16>) -> Vec<u8> {
+    let mut body = string(topic);
+    if let Some(packet_id) = packet_id {
+        body.extend_from_slice(&packet_id.to_be_bytes());
+    }
+    body.extend_from_slice(b"payload");
+    packet(byte1, &body)
+}
+
+fn subscribe(byte1: u8, packet_id: u16, filters: &[(&[u8], u8)]) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    for (filter, options) in filters {
+        body.extend(string(filter));
+        body.push(*options);
+    }
+    packet(byte1, &body)
+}
+
+fn unsubscribe(byte1: u8, packet_id: u16, filters: &[&[u8]]) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    for filter in filters {
+        body.extend(string(filter));
+    }
+    packet(byte1, &body)
+}
+
+const MQTT: (&[u8], u8) = (b"MQTT", 4);
+
+fn cases() -> Vec<Case> {
+    vec![
+        Case {
+            statement: "MQTT-1.5.3-1",
+            // UTF-16 代理项编码后不是合法的 UTF-8
+            packet: publish(0x30, b"a/\xED\xA0\x80", None),
+            strict_only: false,
+            expected: |e| matches!(e, Error::MalformedString),
+        },
+        Case {
+            statement: "MQTT-1.5.3-2",
+            packet: publish(0x30, b"a/\x00", None),
+            strict_only: true,
+            expected: |e| matches!(e, Error::NullCharacter),
+        },
+        Case {
+            statement: "MQTT-2.2.2-1",
+            packet: packet(0xC1, &[]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::InvalidHeaderFlags(0xC1)),
+        },
+        Case {
+            statement: "MQTT-2.3.1-1",
+            packet: publish(0x32, b"a", Some(0)),
+            strict_only: false,
+            expected: |e| matches!(e, Error::MissPacketId),
+        },
+        Case {
+            statement: "MQTT-2.3.1-1",
+            packet: subscribe(0x82, 0, &[(b"a", 0)]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::MissPacketId),
+        },
+        Case {
+            statement: "MQTT-2.3.1-1",
+            packet: unsubscribe(0xA2, 0, &[b"a"]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::MissPacketId),
+        },
+        Case {
+            statement: "MQTT-3.1.2-1",
+            packet: connect((b"MQTX", 4), 0b10, &[b"c"]),
+            strict_only: false,
+            expected: |e| matches!(e, Error::InvalidProtocol),
+        },
+        Case {
+            statement: "MQTT-3.1.2-2",
+            packet: connect((b"MQTT", 6), 0b10, &[b"c"]),
+            strict_only: false,
+            expected: |e| matches!(e, Error::InvalidProtocolLevel(6)),
+        },
+        Case {
+            statement: "MQTT-3.1.2-3",
+            packet: connect(MQTT, 0b11, &[b"c"]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::V4(super::Error::InvalidConnectFlags(0b11))),
+        },
+        Case {
+            statement: "MQTT-3.1.2-11",
+            // 没有遗嘱标志，但设置了遗嘱 QoS
+            packet: connect(MQTT, 0b0000_1010, &[b"c"]),
+            strict_only: false,
+            expected: |e| matches!(e, Error::V4(super::Error::IncorrectPacketFormat)),
+        },
+        Case {
+            statement: "MQTT-3.1.2-14",
+            packet: connect(MQTT, 0b0001_1110, &[b"c", b"will", b"bye"]),
+            strict_only: false,
+            expected: |e| matches!(e, Error::InvalidQoS(3)),
+        },
+        Case {
+            statement: "MQTT-3.1.2-22",
+            packet: connect(MQTT, 0b0100_0010, &[b"c", b"secret"]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::V4(super::Error::PasswordWithoutUsername)),
+        },
+        Case {
+            statement: "MQTT-3.1.3-7",
+            packet: connect(MQTT, 0b00, &[b""]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::V4(super::Error::EmptyClientId)),
+        },
+        Case {
+            statement: "MQTT-3.3.1-2",
+            packet: publish(0x38, b"a", None),
+            strict_only: true,
+            expected: |e| matches!(e, Error::V4(super::Error::InvalidDup)),
+        },
+        Case {
+            statement: "MQTT-3.3.1-4",
+            packet: publish(0x36, b"a", Some(1)),
+            strict_only: false,
+            expected: |e| matches!(e, Error::InvalidQoS(3)),
+        },
+        Case {
+            statement: "MQTT-3.3.2-2",
+            packet: publish(0x30, b"a/+", None),
+            strict_only: false,
+            expected: |e| matches!(e, Error::V4(super::Error::InvalidPublishTopic)),
+        },
+        Case {
+            statement: "MQTT-3.6.1-1",
+            packet: packet(0x60, &[0x00, 0x01]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::InvalidHeaderFlags(0x60)),
+        },
+        Case {
+            statement: "MQTT-3.8.1-1",
+            packet: subscribe(0x80, 1, &[(b"a", 0)]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::InvalidHeaderFlags(0x80)),
+        },
+        Case {
+            statement: "MQTT-3.8.3-3",
+            packet: subscribe(0x82, 1, &[]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::V4(super::Error::EmptySubscription)),
+        },
+        Case {
+            statement: "MQTT-3.8.3-4",
+            packet: subscribe(0x82, 1, &[(b"a", 0b0000_0101)]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::V4(super::Error::InvalidSubscribeOptions(0b101))),
+        },
+        Case {
+            statement: "MQTT-3.8.3-4",
+            packet: subscribe(0x82, 1, &[(b"a", 3)]),
+            strict_only: false,
+            expected: |e| matches!(e, Error::InvalidQoS(3)),
+        },
+        Case {
+            statement: "MQTT-3.10.1-1",
+            packet: unsubscribe(0xA0, 1, &[b"a"]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::InvalidHeaderFlags(0xA0)),
+        },
+        Case {
+            statement: "MQTT-3.10.3-2",
+            packet: unsubscribe(0xA2, 1, &[]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::V4(super::Error::EmptySubscription)),
+        },
+        Case {
+            statement: "MQTT-3.14.1-1",
+            packet: packet(0xE2, &[]),
+            strict_only: true,
+            expected: |e| matches!(e, Error::InvalidHeaderFlags(0xE2)),
+        },
+        Case {
+            statement: "MQTT-4.7.1-2",
+            packet: subscribe(0x82, 1, &[(b"a/#/b", 0)]),
+            strict_only: false,
+            expected: |e| matches!(e, Error::V4(super::Error::InvalidSubscribeFilter)),
+        },
+        Case {
+            statement: "MQTT-4.7.3-1",
+            packet: publish(0x30, b"", None),
+            strict_only: true,
+            expected: |e| matches!(e, Error::V4(super::Error::InvalidPublishTopic)),
+        },
+    ]
+}
+
+fn read(bytes: &[u8], strict: bool) -> Result<Packet, packet::Error> {
+    let mut stream = BytesMut::from(bytes);
+    let packet = Packet::read(&mut stream, strict, usize::MAX);
+    assert!(stream.is_empty(), "packet not fully consumed");
+    packet
+}
+
+#[test]
+fn v4_conformance() {
+    for case in cases() {
+        match read(&case.packet, true) {
+            Err(e) => assert!(
+                (case.expected)(&e),
+                "[{}] unexpected error: {:?}",
+                case.statement,
+                e
+            ),
+            Ok(packet) => panic!("[{}] accepted: {:?}", case.statement, packet),
+        }
+
+        let lenient = read(&case.packet, false);
+        if case.strict_only {
+            assert!(
+                lenient.is_ok(),
+                "[{}] lenient: {:?}",
+                case.statement,
+                lenient
+            );
+        } else {
+            assert!(lenient.is_err(), "[{}] lenient accepted", case.statement);
+        }
+    }
+}
+
+#[test]
+fn packet_size_checked_after_fixed_header() {
+    // 固定头声明 200 字节，还没有收到报文剩余部分时就拒绝
+    let mut stream = BytesMut::from(&[0x30, 0xC8, 0x01, 0x00, 0x01][..]);
+    assert!(matches!(
+        Packet::read(&mut stream, true, 100),
+        Err(Error::PacketTooLarge(203))
+    ));
+
+    let mut stream = BytesMut::from(&publish(0x30, b"a", None)[..]);
+    assert!(Packet::read(&mut stream, true, 12).is_ok());
+}
+
+#[test]
+fn v5_connect_keeps_max_packet_size() {
+    let connect = packet::v5::Connect {
+        protocol: packet::Protocol::V5,
+        keepalive: 30,
+        client_id: "c".into(),
+        clean_start: true,
+        last_will: None,
+        login: packet::v5::Login {
+            username: None,
+            password: None,
+        },
+        properties: Some(packet::v5::ConnectProperties {
+            max_packet_size: Some(1024),
+            ..Default::default()
+        }),
+    };
+    let mut stream = BytesMut::new();
+    connect.write(&mut stream).unwrap();
+
+    match read(&stream, true).unwrap() {
+        Packet::Connect(connect) => {
+            assert_eq!(connect.protocol, packet::Protocol::V5);
+            assert_eq!(connect.keep_alive, 30);
+            assert_eq!(connect.max_packet_size, Some(1024));
+        }
+        packet => panic!("unexpected packet: {:?}", packet),
+    }
+}
+
+#[test]
+fn v5_publish_converted() {
+    let publish = |topic: &str| packet::v5::Publish {
+        dup: false,
+        qos: packet::QoS::AtLeastOnce,
+        retain: false,
+        topic: topic.into(),
+        packet_id: 1,
+        properties: Some(packet::v5::PublishProperties {
+            topic_alias: Some(1),
+            subscription_identifiers: vec![3],
+            user_properties: vec![("k".into(), "v".into())],
+            ..Default::default()
+        }),
+        payload: "p".into(),
+    };
+    let read_v5 = |publish: packet::v5::Publish| {
+        let mut stream = BytesMut::new();
+        publish.write(&mut stream).unwrap();
+        Packet::read_v5(&mut stream, usize::MAX)
+    };
+
+    // 去掉主题别名和订阅标识符，其它属性保留
+    match read_v5(publish("a/b")).unwrap() {
+        Packet::Publish(publish) => {
+            let properties = publish.properties.unwrap();
+            assert_eq!(properties.topic_alias, None);
+            assert!(properties.subscription_identifiers.is_empty());
+            assert_eq!(properties.user_properties, [("k".into(), "v".into())]);
+        }
+        packet => panic!("unexpected packet: {:?}", packet),
+    }
+    // 不支持主题别名，主题不能为空
+    assert!(matches!(
+        read_v5(publish("")),
+        Err(Error::V4(super::Error::InvalidPublishTopic))
+    ));
+    assert!(matches!(
+        read_v5(publish("a/+")),
+        Err(Error::V4(super::Error::InvalidPublishTopic))
+    ));
+}
+
+#[test]
+fn v5_publish_body_shared() {
+    let publish = super::Publish {
+        dup: false,
+        qos: packet::QoS::AtLeastOnce,
+        retain: false,
+        topic: "a/b".into(),
+        packet_id: 1,
+        payload: "p".into(),
+        properties: Some(std::sync::Arc::new(packet::v5::PublishProperties {
+            user_properties: vec![("k".into(), "v".into())],
+            ..Default::default()
+        })),
+        encoded: Default::default(),
+    };
+    let mut other = publish.clone();
+    other.qos = packet::QoS::AtMostOnce;
+    other.packet_id = 0;
+
+    // 属性和 payload 只编码一次，每个订阅者的固定头和 packet id 不同
+    let body = publish.body(packet::Protocol::V5).unwrap();
+    assert_eq!(
+        other.body(packet::Protocol::V5).unwrap().as_ptr(),
+        body.as_ptr()
+    );
+    for (publish, packet_id) in [(publish, 1), (other, 0)] {
+        let mut stream = BytesMut::new();
+        publish.write(&mut stream, packet::Protocol::V5).unwrap();
+        match Packet::read_v5(&mut stream, usize::MAX).unwrap() {
+            Packet::Publish(read) => {
+                assert_eq!(read.packet_id, packet_id);
+                assert_eq!(read.payload, "p");
+                assert_eq!(read.properties, publish.properties);
+            }
+            packet => panic!("unexpected packet: {:?}", packet),
+        }
+        // 3.1.1 订阅者收到的消息不带属性
+        let mut stream = BytesMut::new();
+        publish.write(&mut stream, packet::Protocol::V4).unwrap();
+        match read(&stream, true).unwrap() {
+            Packet::Publish(read) => assert_eq!(read.payload, "p"),
+            packet => panic!("unexpected packet: {:?}", packet),
+        }
+    }
+}
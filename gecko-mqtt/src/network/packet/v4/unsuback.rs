@@ -1,11 +1,12 @@
 use bytes::{BufMut, BytesMut};
 
-use crate::network::packet::Error;
+use crate::network::packet::{v5, Error};
 
 #[derive(Debug)]
 pub struct UnsubAck {
-    /// 包 id
     pub packet_id: u16,
+    /// 每个 filter 取消之前是否订阅过，只在 v5 的 unsuback 中回复
+    pub subscribed: Vec<bool>,
 }
 
 impl UnsubAck {
@@ -14,4 +15,21 @@ impl UnsubAck {
         stream.put_u16(self.packet_id);
         Ok(())
     }
+
+    pub fn write_v5(&self, stream: &mut BytesMut) -> Result<(), Error> {
+        let reasons = self
+            .subscribed
+            .iter()
+            .map(|&subscribed| match subscribed {
+                true => v5::UnsubAckReason::Success,
+                false => v5::UnsubAckReason::NoSubscriptionExisted,
+            })
+            .collect();
+        let unsuback = v5::UnsubAck {
+            packet_id: self.packet_id,
+            reasons,
+            properties: None,
+        };
+        unsuback.write(stream)
+    }
 }
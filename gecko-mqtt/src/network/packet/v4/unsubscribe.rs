@@ -1,9 +1,7 @@
-use bytes::Bytes;
+use bytes::{Buf, Bytes};
 
 use crate::network::packet::{self, Error};
 
-use packet::FixedHeader;
-
 #[derive(Debug)]
 pub struct Unsubscribe {
     /// 包 id
@@ -13,17 +11,24 @@ pub struct Unsubscribe {
 }
 
 impl Unsubscribe {
-    pub fn read(fixed_header: FixedHeader, mut stream: Bytes) -> Result<Self, Error> {
+    pub fn read(mut stream: Bytes, strict: bool) -> Result<Self, Error> {
         let packet_id = packet::read_u16(&mut stream)?;
-        let mut payload_len = fixed_header.remaining_len - 2;
+        // [MQTT-2.3.1-1]
+        if strict && packet_id == 0 {
+            return Err(Error::MissPacketId);
+        }
         let mut filters = Vec::with_capacity(1);
 
-        while payload_len > 0 {
-            let filter = packet::read_string(&mut stream)?;
-            payload_len -= filter.len() + 2;
+        while stream.has_remaining() {
+            let filter = super::read_string(&mut stream, strict)?;
             filters.push(filter);
         }
 
+        // 至少包含一个主题 [MQTT-3.10.3-2]
+        if strict && filters.is_empty() {
+            return Err(super::Error::EmptySubscription)?;
+        }
+
         Ok(Self { packet_id, filters })
     }
 }
@@ -2,30 +2,46 @@
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
-use self::{
-    connack::ConnAck, connect::Connect, disconnect::Disconnect, puback::PubAck, pubcomp::PubComp,
-    publish::Publish, pubrec::PubRec, pubrel::PubRel, suback::SubAck, subscribe::Subscribe,
-    unsuback::UnsubAck, unsubscribe::Unsubscribe,
-};
-
-mod connack;
-mod connect;
-mod disconnect;
-mod pingresp;
-mod puback;
-mod pubcomp;
-mod publish;
-mod pubrec;
-mod pubrel;
-mod suback;
-mod subscribe;
-mod unsuback;
-mod unsubscribe;
+pub use auth::*;
+pub use connack::*;
+pub use connect::*;
+pub use disconnect::*;
+pub use pingresp::*;
+pub use puback::*;
+pub use pubcomp::*;
+pub use publish::*;
+pub use pubrec::*;
+pub use pubrel::*;
+pub use suback::*;
+pub use subscribe::*;
+pub use unsuback::*;
+pub use unsubscribe::*;
+
+use super::PacketType;
+
+pub mod auth;
+pub mod connack;
+pub mod connect;
+pub mod disconnect;
+pub mod pingresp;
+pub mod puback;
+pub mod pubcomp;
+pub mod publish;
+pub mod pubrec;
+pub mod pubrel;
+pub mod suback;
+pub mod subscribe;
+pub mod unsuback;
+pub mod unsubscribe;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Invalid property type: {0}")]
     UnexpectedPropertyType(u8),
+    #[error("Duplicate property: {0}")]
+    DuplicateProperty(u8),
+    #[error("Invalid value of property: {0}")]
+    InvalidPropertyValue(u8),
     #[error("Invalid reason code: {0}")]
     InvalidReasonCode(u8),
     #[error("Invalid retain forward rule: {0}")]
@@ -35,7 +51,7 @@ pub enum Error {
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum PropertyType {
     PayloadFormatIndicator = 1,
     MessageExpiryInterval = 2,
@@ -66,6 +82,16 @@ enum PropertyType {
     SharedSubscriptionAvailable = 42,
 }
 
+impl PropertyType {
+    /// 是否允许在同一个报文中出现多次
+    fn repeatable(&self) -> bool {
+        matches!(
+            self,
+            PropertyType::UserProperty | PropertyType::SubscriptionIdentifier
+        )
+    }
+}
+
 impl TryFrom<u8> for PropertyType {
     type Error = super::Error;
 
@@ -105,7 +131,7 @@ impl TryFrom<u8> for PropertyType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Packet {
     Connect(Connect),
     ConnAck(ConnAck),
@@ -121,9 +147,107 @@ pub enum Packet {
     PingReq,
     PingResp,
     Disconnect(Disconnect),
+    Auth(Auth),
 }
 
-#[derive(Debug)]
+impl Packet {
+    /// 读取一个报文，报文总长度超过 max_packet_size 时，解析出固定头后立即返回错误
+    pub(crate) fn read(
+        stream: &mut BytesMut,
+        max_packet_size: usize,
+    ) -> Result<Self, super::Error> {
+        let stream_len = stream.len();
+        let fixed_header = super::FixedHeader::read_from(stream.iter())?;
+
+        let packet_len = fixed_header.packet_len();
+        if packet_len > max_packet_size {
+            return Err(super::Error::PacketTooLarge(packet_len));
+        }
+        if stream_len < packet_len {
+            return Err(super::Error::InsufficientBytes(packet_len - stream_len));
+        }
+
+        // 根据固定头给出的长度信息，取出整个报文字节（包含报文头）
+        let packet = stream.split_to(packet_len);
+        let packet_type = fixed_header.packet_type()?;
+
+        fixed_header.check_flags(packet_type)?;
+
+        // 去掉固定头的报文
+        let mut stream = packet.freeze();
+        stream.advance(fixed_header.fixed_header_len);
+
+        let packet = match packet_type {
+            PacketType::Connect => Packet::Connect(Connect::read(stream)?),
+            PacketType::ConnAck => Packet::ConnAck(ConnAck::read(stream)?),
+            PacketType::Publish => Packet::Publish(Publish::read(fixed_header, stream)?),
+            PacketType::PubAck => Packet::PubAck(PubAck::read(fixed_header, stream)?),
+            PacketType::PubRec => Packet::PubRec(PubRec::read(fixed_header, stream)?),
+            PacketType::PubRel => Packet::PubRel(PubRel::read(fixed_header, stream)?),
+            PacketType::PubComp => Packet::PubComp(PubComp::read(fixed_header, stream)?),
+            PacketType::Subscribe => Packet::Subscribe(Subscribe::read(stream)?),
+            PacketType::SubAck => Packet::SubAck(SubAck::read(stream)?),
+            PacketType::Unsubscribe => Packet::Unsubscribe(Unsubscribe::read(stream)?),
+            PacketType::UnsubAck => Packet::UnsubAck(UnsubAck::read(stream)?),
+            PacketType::PingReq | PacketType::PingResp if fixed_header.remaining_len != 0 => {
+                return Err(super::Error::MalformedPacket)
+            }
+            PacketType::PingReq => Packet::PingReq,
+            PacketType::PingResp => Packet::PingResp,
+            PacketType::Disconnect => Packet::Disconnect(Disconnect::read(fixed_header, stream)?),
+            PacketType::Auth => Packet::Auth(Auth::read(fixed_header, stream)?),
+        };
+
+        Ok(packet)
+    }
+
+    pub(crate) fn write(&self, stream: &mut BytesMut) -> Result<(), super::Error> {
+        match self {
+            Packet::Connect(connect) => connect.write(stream),
+            Packet::ConnAck(ack) => ack.write(stream),
+            Packet::Publish(publish) => publish.write(stream),
+            Packet::PubAck(puback) => puback.write(stream),
+            Packet::PubRec(pubrec) => pubrec.write(stream),
+            Packet::PubRel(pubrel) => pubrel.write(stream),
+            Packet::PubComp(pubcomp) => pubcomp.write(stream),
+            Packet::Subscribe(subscribe) => subscribe.write(stream),
+            Packet::SubAck(ack) => ack.write(stream),
+            Packet::Unsubscribe(unsubscribe) => unsubscribe.write(stream),
+            Packet::UnsubAck(unsuback) => unsuback.write(stream),
+            Packet::PingReq => {
+                stream.put_slice(&[0xC0, 0x00]);
+                Ok(())
+            }
+            Packet::PingResp => PingResp.write(stream),
+            Packet::Disconnect(disconnect) => disconnect.write(stream),
+            Packet::Auth(auth) => auth.write(stream),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn packet_type(&self) -> PacketType {
+        match self {
+            Packet::Connect(_) => PacketType::Connect,
+            Packet::ConnAck(_) => PacketType::ConnAck,
+            Packet::Publish(_) => PacketType::Publish,
+            Packet::PubAck(_) => PacketType::PubAck,
+            Packet::PubRec(_) => PacketType::PubRec,
+            Packet::PubRel(_) => PacketType::PubRel,
+            Packet::PubComp(_) => PacketType::PubComp,
+            Packet::Subscribe(_) => PacketType::Subscribe,
+            Packet::SubAck(_) => PacketType::SubAck,
+            Packet::Unsubscribe(_) => PacketType::Unsubscribe,
+            Packet::UnsubAck(_) => PacketType::UnsubAck,
+            Packet::PingReq => PacketType::PingReq,
+            Packet::PingResp => PacketType::PingResp,
+            Packet::Disconnect(_) => PacketType::Disconnect,
+            Packet::Auth(_) => PacketType::Auth,
+        }
+    }
+}
+
+/// ack 类报文共用的属性
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct PacketProperties {
     pub reason_string: Option<String>,
     pub user_properties: Vec<(String, String)>,
@@ -144,41 +268,26 @@ impl PacketProperties {
         len
     }
 
-    pub fn read(stream: &mut Bytes) -> Result<Option<Self>, super::Error> {
-        let mut reason_string = None;
-        let mut user_properties = Vec::new();
-
-        let (properties_len_len, properties_len) = super::length(stream.iter())?;
-        stream.advance(properties_len_len);
-        if properties_len == 0 {
-            return Ok(None);
-        }
-
-        let mut cursor = 0;
-        while cursor < properties_len {
-            let prop = super::read_u8(stream)?;
-            cursor += 1;
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-            match prop.try_into()? {
+    pub fn read(stream: &mut Bytes) -> Result<Option<Self>, super::Error> {
+        let mut properties = Self::default();
+        let exists = read_properties(stream, |property, stream| {
+            match property {
                 PropertyType::ReasonString => {
-                    let reason = super::read_string(stream)?;
-                    cursor += 2 + reason.len();
-                    reason_string = Some(reason);
+                    properties.reason_string = Some(super::read_string(stream)?)
                 }
                 PropertyType::UserProperty => {
-                    let key = super::read_string(stream)?;
-                    let value = super::read_string(stream)?;
-                    cursor += 2 + key.len() + 2 + value.len();
-                    user_properties.push((key, value));
+                    properties.user_properties.push(read_user_property(stream)?)
                 }
-                _ => return Err(Error::UnexpectedPropertyType(prop))?,
+                _ => return Err(unexpected(property)),
             }
-        }
+            Ok(())
+        })?;
 
-        Ok(Some(Self {
-            reason_string,
-            user_properties,
-        }))
+        Ok(exists.then_some(properties))
     }
 
     pub fn write(&self, stream: &mut BytesMut) -> Result<(), super::Error> {
@@ -210,3 +319,95 @@ fn len_len(len: usize) -> usize {
         1
     }
 }
+
+/// 属性列表的长度，包含属性长度字段本身
+pub(crate) fn properties_len(len: usize) -> usize {
+    len_len(len) + len
+}
+
+/// 读取变长整数，报文已经完整，字节不足或者没有使用最短编码都是格式错误
+fn read_length(stream: &mut Bytes) -> Result<usize, super::Error> {
+    let (len_len, len) = super::length(stream.iter()).map_err(|_| super::Error::MalformedPacket)?;
+    if len_len != self::len_len(len) {
+        return Err(super::Error::MalformedPacket);
+    }
+    stream.advance(len_len);
+    Ok(len)
+}
+
+/// 读取属性列表，每个属性交给 f 解析，f 遇到不属于当前报文的属性时返回错误
+/// 属性列表不能超出报文，只允许出现一次的属性不能重复
+/// 属性列表为空时返回 false
+fn read_properties<F>(stream: &mut Bytes, mut f: F) -> Result<bool, super::Error>
+where
+    F: FnMut(PropertyType, &mut Bytes) -> Result<(), super::Error>,
+{
+    let len = read_length(stream)?;
+    if len > stream.len() {
+        return Err(super::Error::MalformedPacket);
+    }
+
+    let mut properties = stream.split_to(len);
+    // 已出现过的属性，属性标识符都小于 64
+    let mut seen = 0u64;
+    while properties.has_remaining() {
+        let id = super::read_u8(&mut properties)?;
+        let property = PropertyType::try_from(id)?;
+        if !property.repeatable() {
+            if seen & (1 << id) != 0 {
+                return Err(Error::DuplicateProperty(id).into());
+            }
+            seen |= 1 << id;
+        }
+        f(property, &mut properties)?;
+    }
+
+    Ok(len > 0)
+}
+
+fn unexpected(property: PropertyType) -> super::Error {
+    Error::UnexpectedPropertyType(property as u8).into()
+}
+
+fn read_user_property(stream: &mut Bytes) -> Result<(String, String), super::Error> {
+    let key = super::read_string(stream)?;
+    let value = super::read_string(stream)?;
+    Ok((key, value))
+}
+
+/// 读取只能为 0 或 1 的属性
+fn read_bool_property(property: PropertyType, stream: &mut Bytes) -> Result<u8, super::Error> {
+    match super::read_u8(stream)? {
+        value @ (0 | 1) => Ok(value),
+        _ => Err(Error::InvalidPropertyValue(property as u8))?,
+    }
+}
+
+/// 读取不能为 0 的属性
+fn read_nonzero_u16(property: PropertyType, stream: &mut Bytes) -> Result<u16, super::Error> {
+    match super::read_u16(stream)? {
+        0 => Err(Error::InvalidPropertyValue(property as u8))?,
+        value => Ok(value),
+    }
+}
+
+/// 读取不能为 0 的属性
+fn read_nonzero_u32(property: PropertyType, stream: &mut Bytes) -> Result<u32, super::Error> {
+    match super::read_u32(stream)? {
+        0 => Err(Error::InvalidPropertyValue(property as u8))?,
+        value => Ok(value),
+    }
+}
+
+/// 订阅标识符，取值范围 1 ~ 268,435,455
+fn read_subscription_id(stream: &mut Bytes) -> Result<usize, super::Error> {
+    match read_length(stream)? {
+        0 => Err(Error::InvalidPropertyValue(
+            PropertyType::SubscriptionIdentifier as u8,
+        ))?,
+        id => Ok(id),
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,179 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::network::packet::{self, Error, FixedHeader};
+
+use super::PropertyType;
+
+/// 增强认证报文，客户端和服务端都可以发送
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Auth {
+    pub reason: AuthReason,
+    pub properties: Option<AuthProperties>,
+}
+
+impl Auth {
+    fn len(&self) -> usize {
+        // 认证成功且没有属性时，剩余长度为 0
+        if self.reason == AuthReason::Success && self.properties.is_none() {
+            return 0;
+        }
+
+        let mut len = 1;
+        match &self.properties {
+            Some(properties) => len += super::properties_len(properties.len()),
+            None => len += 1,
+        }
+
+        len
+    }
+
+    pub fn read(fixed_header: FixedHeader, mut stream: Bytes) -> Result<Self, Error> {
+        if fixed_header.remaining_len == 0 {
+            return Ok(Self {
+                reason: AuthReason::Success,
+                properties: None,
+            });
+        }
+
+        let reason = packet::read_u8(&mut stream)?.try_into()?;
+        let properties = match fixed_header.remaining_len {
+            1 => None,
+            _ => AuthProperties::read(&mut stream)?,
+        };
+        if !stream.is_empty() {
+            return Err(Error::MalformedPacket);
+        }
+
+        Ok(Self { reason, properties })
+    }
+
+    pub fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+        stream.put_u8(0xF0);
+
+        let len = self.len();
+        packet::write_remaining_length(stream, len)?;
+        if len == 0 {
+            return Ok(());
+        }
+        stream.put_u8(self.reason as u8);
+
+        match &self.properties {
+            Some(properties) => properties.write(stream)?,
+            None => {
+                packet::write_remaining_length(stream, 0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AuthReason {
+    /// 认证成功
+    Success = 0x00,
+    /// 继续下一步认证
+    ContinueAuthentication = 0x18,
+    /// 重新认证（客户端使用）
+    ReAuthenticate = 0x19,
+}
+
+impl TryFrom<u8> for AuthReason {
+    type Error = super::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        let code = match value {
+            0x00 => AuthReason::Success,
+            0x18 => AuthReason::ContinueAuthentication,
+            0x19 => AuthReason::ReAuthenticate,
+            num => return Err(super::Error::InvalidReasonCode(num)),
+        };
+
+        Ok(code)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthProperties {
+    pub authentication_method: Option<String>,
+    pub authentication_data: Option<Bytes>,
+    pub reason_string: Option<String>,
+    pub user_properties: Vec<(String, String)>,
+}
+
+impl AuthProperties {
+    fn len(&self) -> usize {
+        let mut len = 0;
+
+        if let Some(authentication_method) = &self.authentication_method {
+            len += 1 + 2 + authentication_method.len();
+        }
+
+        if let Some(authentication_data) = &self.authentication_data {
+            len += 1 + 2 + authentication_data.len();
+        }
+
+        if let Some(reason) = &self.reason_string {
+            len += 1 + 2 + reason.len();
+        }
+
+        for (key, value) in &self.user_properties {
+            len += 1 + 2 + key.len() + 2 + value.len();
+        }
+
+        len
+    }
+
+    fn read(stream: &mut Bytes) -> Result<Option<Self>, Error> {
+        let mut properties = Self::default();
+        let exists = super::read_properties(stream, |property, stream| {
+            match property {
+                PropertyType::AuthenticationMethod => {
+                    properties.authentication_method = Some(packet::read_string(stream)?);
+                }
+                PropertyType::AuthenticationData => {
+                    properties.authentication_data = Some(packet::read_bytes(stream)?);
+                }
+                PropertyType::ReasonString => {
+                    properties.reason_string = Some(packet::read_string(stream)?);
+                }
+                PropertyType::UserProperty => {
+                    properties
+                        .user_properties
+                        .push(super::read_user_property(stream)?);
+                }
+                _ => return Err(super::unexpected(property)),
+            }
+            Ok(())
+        })?;
+
+        Ok(exists.then_some(properties))
+    }
+
+    fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+        packet::write_remaining_length(stream, self.len())?;
+
+        if let Some(authentication_method) = &self.authentication_method {
+            stream.put_u8(PropertyType::AuthenticationMethod as u8);
+            packet::write_string(stream, authentication_method);
+        }
+
+        if let Some(authentication_data) = &self.authentication_data {
+            stream.put_u8(PropertyType::AuthenticationData as u8);
+            packet::write_bytes(stream, authentication_data);
+        }
+
+        if let Some(reason) = &self.reason_string {
+            stream.put_u8(PropertyType::ReasonString as u8);
+            packet::write_string(stream, reason);
+        }
+
+        for (key, value) in &self.user_properties {
+            stream.put_u8(PropertyType::UserProperty as u8);
+            packet::write_string(stream, key);
+            packet::write_string(stream, value);
+        }
+
+        Ok(())
+    }
+}
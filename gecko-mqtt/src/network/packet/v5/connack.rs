@@ -4,7 +4,7 @@ use crate::network::packet::{self, Error};
 
 use super::PropertyType;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConnAck {
     /// 会话是否存在
     pub session_present: bool,
@@ -17,17 +17,33 @@ pub struct ConnAck {
 impl ConnAck {
     fn len(&self) -> usize {
         let mut len = 1 + 1;
-        if let Some(properties) = &self.properties {
-            let properties_len = properties.len();
-            let properties_len_len = super::len_len(len);
-            len += properties_len_len + properties_len;
-        } else {
-            len += 1;
+        match &self.properties {
+            Some(properties) => len += super::properties_len(properties.len()),
+            None => len += 1,
         }
 
         len
     }
 
+    pub fn read(mut stream: Bytes) -> Result<Self, Error> {
+        let flags = packet::read_u8(&mut stream)?;
+        // 除了 session present 外都是保留位
+        if flags & 0b1111_1110 != 0 {
+            return Err(Error::MalformedPacket);
+        }
+        let code = packet::read_u8(&mut stream)?.try_into()?;
+        let properties = ConnAckProperties::read(&mut stream)?;
+        if !stream.is_empty() {
+            return Err(Error::MalformedPacket);
+        }
+
+        Ok(Self {
+            session_present: flags == 1,
+            code,
+            properties,
+        })
+    }
+
     pub fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
         stream.put_u8(0x20);
 
@@ -44,7 +60,7 @@ impl ConnAck {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ConnectReturnCode {
     Success = 0,
@@ -71,7 +87,41 @@ pub enum ConnectReturnCode {
     ConnectionRateExceeded = 159,
 }
 
-#[derive(Debug)]
+impl TryFrom<u8> for ConnectReturnCode {
+    type Error = super::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        let code = match value {
+            0 => Self::Success,
+            128 => Self::UnspecifiedError,
+            129 => Self::MalformedPacket,
+            130 => Self::ProtocolError,
+            131 => Self::ImplementationSpecificError,
+            132 => Self::UnsupportedProtocolVersion,
+            133 => Self::ClientIdentifierNotValid,
+            134 => Self::BadUserNamePassword,
+            135 => Self::NotAuthorized,
+            136 => Self::ServerUnavailable,
+            137 => Self::ServerBusy,
+            138 => Self::Banned,
+            140 => Self::BadAuthenticationMethod,
+            144 => Self::TopicNameInvalid,
+            149 => Self::PacketTooLarge,
+            151 => Self::QuotaExceeded,
+            153 => Self::PayloadFormatInvalid,
+            154 => Self::RetainNotSupported,
+            155 => Self::QoSNotSupported,
+            156 => Self::UseAnotherServer,
+            157 => Self::ServerMoved,
+            159 => Self::ConnectionRateExceeded,
+            num => return Err(super::Error::InvalidReasonCode(num)),
+        };
+
+        Ok(code)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct ConnAckProperties {
     pub session_expiry_interval: Option<u32>,
     pub receive_max: Option<u16>,
@@ -145,7 +195,7 @@ impl ConnAckProperties {
         }
 
         if self.server_keep_alive.is_some() {
-            len += 1 + 1;
+            len += 1 + 2;
         }
 
         if let Some(info) = &self.response_information {
@@ -167,6 +217,75 @@ impl ConnAckProperties {
         len
     }
 
+    fn read(stream: &mut Bytes) -> Result<Option<Self>, Error> {
+        let mut properties = Self::default();
+        let exists = super::read_properties(stream, |property, stream| {
+            match property {
+                PropertyType::SessionExpiryInterval => {
+                    properties.session_expiry_interval = Some(packet::read_u32(stream)?);
+                }
+                PropertyType::ReceiveMaximum => {
+                    properties.receive_max = Some(super::read_nonzero_u16(property, stream)?);
+                }
+                PropertyType::MaximumQos => {
+                    properties.max_qos = Some(super::read_bool_property(property, stream)?);
+                }
+                PropertyType::RetainAvailable => {
+                    properties.retain_available =
+                        Some(super::read_bool_property(property, stream)?);
+                }
+                PropertyType::MaximumPacketSize => {
+                    properties.max_packet_size = Some(super::read_nonzero_u32(property, stream)?);
+                }
+                PropertyType::AssignedClientIdentifier => {
+                    properties.assigned_client_identifier = Some(packet::read_string(stream)?);
+                }
+                PropertyType::TopicAliasMaximum => {
+                    properties.topic_alias_max = Some(packet::read_u16(stream)?);
+                }
+                PropertyType::ReasonString => {
+                    properties.reason_string = Some(packet::read_string(stream)?);
+                }
+                PropertyType::UserProperty => {
+                    properties
+                        .user_properties
+                        .push(super::read_user_property(stream)?);
+                }
+                PropertyType::WildcardSubscriptionAvailable => {
+                    properties.wildcard_subscription_available =
+                        Some(super::read_bool_property(property, stream)?);
+                }
+                PropertyType::SubscriptionIdentifierAvailable => {
+                    properties.subscription_identifiers_available =
+                        Some(super::read_bool_property(property, stream)?);
+                }
+                PropertyType::SharedSubscriptionAvailable => {
+                    properties.shared_subscription_available =
+                        Some(super::read_bool_property(property, stream)?);
+                }
+                PropertyType::ServerKeepAlive => {
+                    properties.server_keep_alive = Some(packet::read_u16(stream)?);
+                }
+                PropertyType::ResponseInformation => {
+                    properties.response_information = Some(packet::read_string(stream)?);
+                }
+                PropertyType::ServerReference => {
+                    properties.server_reference = Some(packet::read_string(stream)?);
+                }
+                PropertyType::AuthenticationMethod => {
+                    properties.authentication_method = Some(packet::read_string(stream)?);
+                }
+                PropertyType::AuthenticationData => {
+                    properties.authentication_data = Some(packet::read_bytes(stream)?);
+                }
+                _ => return Err(super::unexpected(property)),
+            }
+            Ok(())
+        })?;
+
+        Ok(exists.then_some(properties))
+    }
+
     fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
         packet::write_remaining_length(stream, self.len())?;
 
@@ -1,10 +1,10 @@
-use bytes::{Buf, Bytes};
+use bytes::{BufMut, Bytes, BytesMut};
 
 use crate::network::packet::{self, Error, Protocol, QoS};
 
 use super::PropertyType;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Connect {
     /// mqtt 协议版本
     pub protocol: Protocol,
@@ -23,31 +23,57 @@ pub struct Connect {
 }
 
 impl Connect {
+    fn len(&self) -> usize {
+        // 协议名、协议级别、连接标志、keepalive
+        let mut len = 2 + self.protocol.name().len() + 1 + 1 + 2;
+
+        if self.protocol == Protocol::V5 {
+            len += match &self.properties {
+                Some(properties) => super::properties_len(properties.len()),
+                None => 1,
+            };
+        }
+
+        len += 2 + self.client_id.len();
+        if let Some(last_will) = &self.last_will {
+            len += last_will.len(self.protocol);
+        }
+        len += self.login.len();
+
+        len
+    }
+
     pub fn read(mut stream: Bytes) -> Result<Self, Error> {
         let protocol_name = packet::read_string(&mut stream)?;
-        if protocol_name != "MQTT" {
-            return Err(Error::InvalidProtocol);
-        }
         let protocol_level = packet::read_u8(&mut stream)?;
 
-        let protocol = match protocol_level {
-            4 => Protocol::V4,
-            5 => Protocol::V5,
-            num => return Err(Error::InvalidProtocolLevel(num)),
+        let protocol = match (protocol_name.as_str(), protocol_level) {
+            ("MQIsdp", 3) => Protocol::V3,
+            ("MQTT", 4) => Protocol::V4,
+            ("MQTT", 5) => Protocol::V5,
+            ("MQIsdp", num) | ("MQTT", num) => return Err(Error::InvalidProtocolLevel(num)),
+            _ => return Err(Error::InvalidProtocol),
         };
 
         let connect_flags = packet::read_u8(&mut stream)?;
+        // 保留位必须为 0
+        if connect_flags & 0b0000_0001 != 0 {
+            return Err(Error::MalformedPacket);
+        }
         let clean_start = (connect_flags & 0b10) != 0;
         let keepalive = packet::read_u16(&mut stream)?;
 
         let properties = match protocol {
-            Protocol::V4 => None,
+            Protocol::V3 | Protocol::V4 => None,
             Protocol::V5 => ConnectProperties::read(&mut stream)?,
         };
 
         let client_id = packet::read_string(&mut stream)?;
-        let last_will = LastWill::read(connect_flags, &mut stream)?;
+        let last_will = LastWill::read(connect_flags, protocol, &mut stream)?;
         let login = Login::read(connect_flags, &mut stream)?;
+        if !stream.is_empty() {
+            return Err(Error::MalformedPacket);
+        }
 
         Ok(Self {
             protocol,
@@ -59,9 +85,53 @@ impl Connect {
             properties,
         })
     }
+
+    pub fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+        stream.put_u8(0x10);
+        packet::write_remaining_length(stream, self.len())?;
+
+        packet::write_string(stream, self.protocol.name());
+        stream.put_u8(self.protocol.level());
+
+        let mut connect_flags = 0;
+        if self.clean_start {
+            connect_flags |= 0b10;
+        }
+        if let Some(last_will) = &self.last_will {
+            connect_flags |= 0b100 | (last_will.qos as u8) << 3;
+            if last_will.retain {
+                connect_flags |= 0b0010_0000;
+            }
+        }
+        if self.login.password.is_some() {
+            connect_flags |= 0b0100_0000;
+        }
+        if self.login.username.is_some() {
+            connect_flags |= 0b1000_0000;
+        }
+        stream.put_u8(connect_flags);
+        stream.put_u16(self.keepalive);
+
+        if self.protocol == Protocol::V5 {
+            match &self.properties {
+                Some(properties) => properties.write(stream)?,
+                None => {
+                    packet::write_remaining_length(stream, 0)?;
+                }
+            }
+        }
+
+        packet::write_string(stream, &self.client_id);
+        if let Some(last_will) = &self.last_will {
+            last_will.write(self.protocol, stream)?;
+        }
+        self.login.write(stream);
+
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LastWill {
     pub topic: String,
     pub message: Bytes,
@@ -71,12 +141,30 @@ pub struct LastWill {
 }
 
 impl LastWill {
-    fn read(connect_flags: u8, stream: &mut Bytes) -> Result<Option<Self>, Error> {
+    fn len(&self, protocol: Protocol) -> usize {
+        let mut len = 2 + self.topic.len() + 2 + self.message.len();
+        if protocol == Protocol::V5 {
+            len += match &self.properties {
+                Some(properties) => super::properties_len(properties.len()),
+                None => 1,
+            };
+        }
+        len
+    }
+
+    fn read(
+        connect_flags: u8,
+        protocol: Protocol,
+        stream: &mut Bytes,
+    ) -> Result<Option<Self>, Error> {
         let last_will = match connect_flags & 0b100 {
             0 if (connect_flags & 0b0011_1000) != 0 => return Err(Error::MalformedPacket),
             0 => None,
             _ => {
-                let properties = WillProperties::read(stream)?;
+                let properties = match protocol {
+                    Protocol::V3 | Protocol::V4 => None,
+                    Protocol::V5 => WillProperties::read(stream)?,
+                };
                 let topic = packet::read_string(stream)?;
                 let message = packet::read_bytes(stream)?;
                 let qos = ((connect_flags & 0b11000) >> 3).try_into()?;
@@ -94,9 +182,23 @@ impl LastWill {
 
         Ok(last_will)
     }
+
+    fn write(&self, protocol: Protocol, stream: &mut BytesMut) -> Result<(), Error> {
+        if protocol == Protocol::V5 {
+            match &self.properties {
+                Some(properties) => properties.write(stream)?,
+                None => {
+                    packet::write_remaining_length(stream, 0)?;
+                }
+            }
+        }
+        packet::write_string(stream, &self.topic);
+        packet::write_bytes(stream, &self.message);
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct WillProperties {
     pub delay_interval: Option<u32>,
     pub payload_format_indicator: Option<u8>,
@@ -108,82 +210,137 @@ pub struct WillProperties {
 }
 
 impl WillProperties {
+    fn len(&self) -> usize {
+        let mut len = 0;
+
+        if self.delay_interval.is_some() {
+            len += 1 + 4;
+        }
+
+        if self.payload_format_indicator.is_some() {
+            len += 1 + 1;
+        }
+
+        if self.message_expiry_interval.is_some() {
+            len += 1 + 4;
+        }
+
+        if let Some(typ) = &self.content_type {
+            len += 1 + 2 + typ.len();
+        }
+
+        if let Some(topic) = &self.response_topic {
+            len += 1 + 2 + topic.len();
+        }
+
+        if let Some(data) = &self.correlation_data {
+            len += 1 + 2 + data.len();
+        }
+
+        for (key, value) in &self.user_properties {
+            len += 1 + 2 + key.len() + 2 + value.len();
+        }
+
+        len
+    }
+
     fn read(stream: &mut Bytes) -> Result<Option<WillProperties>, Error> {
-        let mut delay_interval = None;
-        let mut payload_format_indicator = None;
-        let mut message_expiry_interval = None;
-        let mut content_type = None;
-        let mut response_topic = None;
-        let mut correlation_data = None;
-        let mut user_properties = Vec::new();
-
-        let (properties_len_len, properties_len) = packet::length(stream.iter())?;
-        stream.advance(properties_len_len);
-        if properties_len == 0 {
-            return Ok(None);
-        }
-
-        let mut cursor = 0;
-        while cursor < properties_len {
-            let prop = packet::read_u8(stream)?;
-            cursor += 1;
-
-            match prop.try_into()? {
+        let mut properties = Self::default();
+        let exists = super::read_properties(stream, |property, stream| {
+            match property {
                 PropertyType::WillDelayInterval => {
-                    delay_interval = Some(packet::read_u32(stream)?);
-                    cursor += 4;
+                    properties.delay_interval = Some(packet::read_u32(stream)?);
                 }
                 PropertyType::PayloadFormatIndicator => {
-                    payload_format_indicator = Some(packet::read_u8(stream)?);
-                    cursor += 1;
+                    properties.payload_format_indicator =
+                        Some(super::read_bool_property(property, stream)?);
                 }
                 PropertyType::MessageExpiryInterval => {
-                    message_expiry_interval = Some(packet::read_u32(stream)?);
-                    cursor += 4;
+                    properties.message_expiry_interval = Some(packet::read_u32(stream)?);
                 }
                 PropertyType::ContentType => {
-                    let typ = packet::read_string(stream)?;
-                    cursor += 2 + typ.len();
-                    content_type = Some(typ);
+                    properties.content_type = Some(packet::read_string(stream)?);
                 }
                 PropertyType::ResponseTopic => {
-                    let topic = packet::read_string(stream)?;
-                    cursor += 2 + topic.len();
-                    response_topic = Some(topic);
+                    properties.response_topic = Some(packet::read_string(stream)?);
                 }
                 PropertyType::CorrelationData => {
-                    let data = packet::read_bytes(stream)?;
-                    cursor += 2 + data.len();
-                    correlation_data = Some(data);
+                    properties.correlation_data = Some(packet::read_bytes(stream)?);
                 }
                 PropertyType::UserProperty => {
-                    let key = packet::read_string(stream)?;
-                    let value = packet::read_string(stream)?;
-                    cursor += 2 + key.len() + 2 + value.len();
-                    user_properties.push((key, value));
+                    properties
+                        .user_properties
+                        .push(super::read_user_property(stream)?);
                 }
-                _ => return Err(super::Error::UnexpectedPropertyType(prop))?,
+                _ => return Err(super::unexpected(property)),
             }
+            Ok(())
+        })?;
+
+        Ok(exists.then_some(properties))
+    }
+
+    fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+        packet::write_remaining_length(stream, self.len())?;
+
+        if let Some(delay_interval) = self.delay_interval {
+            stream.put_u8(PropertyType::WillDelayInterval as u8);
+            stream.put_u32(delay_interval);
+        }
+
+        if let Some(payload_format_indicator) = self.payload_format_indicator {
+            stream.put_u8(PropertyType::PayloadFormatIndicator as u8);
+            stream.put_u8(payload_format_indicator);
+        }
+
+        if let Some(message_expiry_interval) = self.message_expiry_interval {
+            stream.put_u8(PropertyType::MessageExpiryInterval as u8);
+            stream.put_u32(message_expiry_interval);
         }
-        Ok(Some(Self {
-            delay_interval,
-            payload_format_indicator,
-            message_expiry_interval,
-            content_type,
-            response_topic,
-            correlation_data,
-            user_properties,
-        }))
+
+        if let Some(typ) = &self.content_type {
+            stream.put_u8(PropertyType::ContentType as u8);
+            packet::write_string(stream, typ);
+        }
+
+        if let Some(topic) = &self.response_topic {
+            stream.put_u8(PropertyType::ResponseTopic as u8);
+            packet::write_string(stream, topic);
+        }
+
+        if let Some(data) = &self.correlation_data {
+            stream.put_u8(PropertyType::CorrelationData as u8);
+            packet::write_bytes(stream, data);
+        }
+
+        for (key, value) in &self.user_properties {
+            stream.put_u8(PropertyType::UserProperty as u8);
+            packet::write_string(stream, key);
+            packet::write_string(stream, value);
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Login {
     pub username: Option<String>,
     pub password: Option<String>,
 }
 
 impl Login {
+    fn len(&self) -> usize {
+        let mut len = 0;
+        if let Some(username) = &self.username {
+            len += 2 + username.len();
+        }
+        if let Some(password) = &self.password {
+            len += 2 + password.len();
+        }
+        len
+    }
+
     fn read(connect_flags: u8, stream: &mut Bytes) -> Result<Self, Error> {
         let username = match connect_flags & 0b1000_0000 {
             0 => None,
@@ -197,9 +354,18 @@ impl Login {
 
         Ok(Self { username, password })
     }
+
+    fn write(&self, stream: &mut BytesMut) {
+        if let Some(username) = &self.username {
+            packet::write_string(stream, username);
+        }
+        if let Some(password) = &self.password {
+            packet::write_string(stream, password);
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct ConnectProperties {
     pub session_expiry_interval: Option<u32>,
     pub receive_maximum: Option<u16>,
@@ -213,82 +379,140 @@ pub struct ConnectProperties {
 }
 
 impl ConnectProperties {
+    fn len(&self) -> usize {
+        let mut len = 0;
+
+        if self.session_expiry_interval.is_some() {
+            len += 1 + 4;
+        }
+
+        if self.receive_maximum.is_some() {
+            len += 1 + 2;
+        }
+
+        if self.max_packet_size.is_some() {
+            len += 1 + 4;
+        }
+
+        if self.topic_alias_max.is_some() {
+            len += 1 + 2;
+        }
+
+        if self.request_response_info.is_some() {
+            len += 1 + 1;
+        }
+
+        if self.request_problem_info.is_some() {
+            len += 1 + 1;
+        }
+
+        for (key, value) in &self.user_properties {
+            len += 1 + 2 + key.len() + 2 + value.len();
+        }
+
+        if let Some(authentication_method) = &self.authentication_method {
+            len += 1 + 2 + authentication_method.len();
+        }
+
+        if let Some(authentication_data) = &self.authentication_data {
+            len += 1 + 2 + authentication_data.len();
+        }
+
+        len
+    }
+
     fn read(stream: &mut Bytes) -> Result<Option<Self>, Error> {
-        let mut session_expiry_interval = None;
-        let mut receive_maximum = None;
-        let mut max_packet_size = None;
-        let mut topic_alias_max = None;
-        let mut request_response_info = None;
-        let mut request_problem_info = None;
-        let mut user_properties = Vec::new();
-        let mut authentication_method = None;
-        let mut authentication_data = None;
-
-        let (properties_len_len, properties_len) = packet::length(stream.iter())?;
-        stream.advance(properties_len_len);
-        if properties_len == 0 {
-            return Ok(None);
-        }
-
-        let mut cursor = 0;
-        while cursor < properties_len {
-            let prop = packet::read_u8(stream)?;
-            cursor += 1;
-            match prop.try_into()? {
+        let mut properties = Self::default();
+        let exists = super::read_properties(stream, |property, stream| {
+            match property {
                 PropertyType::SessionExpiryInterval => {
-                    session_expiry_interval = Some(packet::read_u32(stream)?);
-                    cursor += 4;
+                    properties.session_expiry_interval = Some(packet::read_u32(stream)?);
                 }
                 PropertyType::ReceiveMaximum => {
-                    receive_maximum = Some(packet::read_u16(stream)?);
-                    cursor += 2;
+                    properties.receive_maximum = Some(super::read_nonzero_u16(property, stream)?);
                 }
                 PropertyType::MaximumPacketSize => {
-                    max_packet_size = Some(packet::read_u32(stream)?);
-                    cursor += 4;
+                    properties.max_packet_size = Some(super::read_nonzero_u32(property, stream)?);
                 }
                 PropertyType::TopicAliasMaximum => {
-                    topic_alias_max = Some(packet::read_u16(stream)?);
-                    cursor += 2;
+                    properties.topic_alias_max = Some(packet::read_u16(stream)?);
                 }
                 PropertyType::RequestResponseInformation => {
-                    request_response_info = Some(packet::read_u8(stream)?);
-                    cursor += 1;
+                    properties.request_response_info =
+                        Some(super::read_bool_property(property, stream)?);
                 }
                 PropertyType::RequestProblemInformation => {
-                    request_problem_info = Some(packet::read_u8(stream)?);
-                    cursor += 1;
+                    properties.request_problem_info =
+                        Some(super::read_bool_property(property, stream)?);
                 }
                 PropertyType::UserProperty => {
-                    let key = packet::read_string(stream)?;
-                    let value = packet::read_string(stream)?;
-                    cursor += 2 + key.len() + 2 + value.len();
-                    user_properties.push((key, value));
+                    properties
+                        .user_properties
+                        .push(super::read_user_property(stream)?);
                 }
                 PropertyType::AuthenticationMethod => {
-                    let method = packet::read_string(stream)?;
-                    cursor += 2 + method.len();
-                    authentication_method = Some(method);
+                    properties.authentication_method = Some(packet::read_string(stream)?);
                 }
                 PropertyType::AuthenticationData => {
-                    let data = packet::read_bytes(stream)?;
-                    cursor += 2 + data.len();
-                    authentication_data = Some(data);
+                    properties.authentication_data = Some(packet::read_bytes(stream)?);
                 }
-                _ => return Err(super::Error::UnexpectedPropertyType(prop))?,
+                _ => return Err(super::unexpected(property)),
             }
+            Ok(())
+        })?;
+
+        Ok(exists.then_some(properties))
+    }
+
+    fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+        packet::write_remaining_length(stream, self.len())?;
+
+        if let Some(session_expiry_interval) = self.session_expiry_interval {
+            stream.put_u8(PropertyType::SessionExpiryInterval as u8);
+            stream.put_u32(session_expiry_interval);
+        }
+
+        if let Some(receive_maximum) = self.receive_maximum {
+            stream.put_u8(PropertyType::ReceiveMaximum as u8);
+            stream.put_u16(receive_maximum);
+        }
+
+        if let Some(max_packet_size) = self.max_packet_size {
+            stream.put_u8(PropertyType::MaximumPacketSize as u8);
+            stream.put_u32(max_packet_size);
+        }
+
+        if let Some(topic_alias_max) = self.topic_alias_max {
+            stream.put_u8(PropertyType::TopicAliasMaximum as u8);
+            stream.put_u16(topic_alias_max);
+        }
+
+        if let Some(request_response_info) = self.request_response_info {
+            stream.put_u8(PropertyType::RequestResponseInformation as u8);
+            stream.put_u8(request_response_info);
+        }
+
+        if let Some(request_problem_info) = self.request_problem_info {
+            stream.put_u8(PropertyType::RequestProblemInformation as u8);
+            stream.put_u8(request_problem_info);
+        }
+
+        for (key, value) in &self.user_properties {
+            stream.put_u8(PropertyType::UserProperty as u8);
+            packet::write_string(stream, key);
+            packet::write_string(stream, value);
+        }
+
+        if let Some(authentication_method) = &self.authentication_method {
+            stream.put_u8(PropertyType::AuthenticationMethod as u8);
+            packet::write_string(stream, authentication_method);
+        }
+
+        if let Some(authentication_data) = &self.authentication_data {
+            stream.put_u8(PropertyType::AuthenticationData as u8);
+            packet::write_bytes(stream, authentication_data);
         }
 
-        Ok(Some(Self {
-            session_expiry_interval,
-            receive_maximum,
-            max_packet_size,
-            topic_alias_max,
-            request_response_info,
-            request_problem_info,
-            user_properties,
-            authentication_method,
-            authentication_data,
-        }))
+        Ok(())
     }
 }
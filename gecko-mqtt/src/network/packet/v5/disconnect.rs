@@ -1,10 +1,10 @@
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 
 use crate::network::packet::{self, Error, FixedHeader};
 
 use super::PropertyType;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Disconnect {
     /// 断开连接原因码
     pub reason_code: DisconnectReasonCode,
@@ -14,24 +14,24 @@ pub struct Disconnect {
 
 impl Disconnect {
     pub fn len(&self) -> usize {
+        // 正常断开且没有属性时，剩余长度为 0
         if self.reason_code == DisconnectReasonCode::NormalDisconnection
             && self.properties.is_none()
         {
-            return 2;
+            return 0;
         }
 
-        let mut length = 0;
+        let mut len = 1;
         match &self.properties {
-            Some(properties) => {
-                length += 1;
-                let properties_len = properties.len();
-                let properties_len_len = super::len_len(properties_len);
-                length += properties_len_len + properties_len;
-            }
-            None => length += 1,
+            Some(properties) => len += super::properties_len(properties.len()),
+            None => len += 1,
         }
 
-        length
+        len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     pub fn read(fixed_header: FixedHeader, mut stream: Bytes) -> Result<Self, Error> {
@@ -46,9 +46,19 @@ impl Disconnect {
             });
         }
 
+        // 剩余长度为 1 时没有属性
+        let reason_code = packet::read_u8(&mut stream)?.try_into()?;
+        let properties = match fixed_header.remaining_len {
+            1 => None,
+            _ => DisconnectProperties::read(&mut stream)?,
+        };
+        if !stream.is_empty() {
+            return Err(Error::MalformedPacket);
+        }
+
         Ok(Self {
-            reason_code: packet::read_u8(&mut stream)?.try_into()?,
-            properties: DisconnectProperties::read(&mut stream)?,
+            reason_code,
+            properties,
         })
     }
 
@@ -56,11 +66,10 @@ impl Disconnect {
         stream.put_u8(0xE0);
 
         let len = self.len();
-        if len == 2 {
-            stream.put_u8(0x00);
+        packet::write_remaining_length(stream, len)?;
+        if len == 0 {
             return Ok(());
         }
-        packet::write_remaining_length(stream, len)?;
         stream.put_u8(self.reason_code as u8);
 
         match &self.properties {
@@ -75,7 +84,7 @@ impl Disconnect {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct DisconnectProperties {
     /// 会话过期时间（秒）
     pub session_expiry_interval: Option<u32>,
@@ -111,53 +120,29 @@ impl DisconnectProperties {
     }
 
     fn read(stream: &mut Bytes) -> Result<Option<Self>, Error> {
-        let (properties_len_len, properties_len) = packet::length(stream.iter())?;
-        stream.advance(properties_len_len);
-        if properties_len == 0 {
-            return Ok(None);
-        }
-
-        let mut session_expiry_interval = None;
-        let mut reason_string = None;
-        let mut user_properties = Vec::new();
-        let mut server_reference = None;
-
-        let mut cursor = 0;
-        while cursor < properties_len {
-            let prop = packet::read_u8(stream)?;
-            cursor += 1;
-
-            match prop.try_into()? {
+        let mut properties = Self::default();
+        let exists = super::read_properties(stream, |property, stream| {
+            match property {
                 PropertyType::SessionExpiryInterval => {
-                    session_expiry_interval = Some(packet::read_u32(stream)?);
-                    cursor += 4;
+                    properties.session_expiry_interval = Some(packet::read_u32(stream)?);
                 }
                 PropertyType::ReasonString => {
-                    let reason = packet::read_string(stream)?;
-                    cursor += 2 + reason.len();
-                    reason_string = Some(reason);
+                    properties.reason_string = Some(packet::read_string(stream)?);
                 }
                 PropertyType::UserProperty => {
-                    let key = packet::read_string(stream)?;
-                    let value = packet::read_string(stream)?;
-                    cursor += 2 + key.len() + 2 + value.len();
-                    user_properties.push((key, value));
+                    properties
+                        .user_properties
+                        .push(super::read_user_property(stream)?);
                 }
                 PropertyType::ServerReference => {
-                    let reference = packet::read_string(stream)?;
-                    cursor += 2 + reference.len();
-                    server_reference = Some(reference);
+                    properties.server_reference = Some(packet::read_string(stream)?);
                 }
-                _ => return Err(Error::InvalidPacketType(prop)),
+                _ => return Err(super::unexpected(property)),
             }
-        }
+            Ok(())
+        })?;
 
-        Ok(Some(Self {
-            session_expiry_interval,
-            reason_string,
-            user_properties,
-            server_reference,
-        }))
+        Ok(exists.then_some(properties))
     }
 
     fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
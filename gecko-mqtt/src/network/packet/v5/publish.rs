@@ -1,10 +1,10 @@
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 
 use crate::network::packet::{self, Error, FixedHeader, QoS};
 
 use super::PropertyType;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Publish {
     pub dup: bool,
     pub qos: QoS,
@@ -18,20 +18,16 @@ pub struct Publish {
 impl Publish {
     fn len(&self) -> usize {
         let mut len = 2 + self.topic.len();
-        if self.qos != QoS::AtMostOnce && self.packet_id != 0 {
+        if self.qos != QoS::AtMostOnce {
             len += 2;
         }
 
         match &self.properties {
-            Some(properties) => {
-                let properties_len = properties.len();
-                let properties_len_len = super::len_len(properties_len);
-                len += properties_len_len + properties_len;
-            }
+            Some(properties) => len += super::properties_len(properties.len()),
             None => len += 1,
         }
 
-        len
+        len + self.payload.len()
     }
 
     pub fn read(fixed_header: FixedHeader, mut stream: Bytes) -> Result<Self, Error> {
@@ -94,7 +90,7 @@ impl Publish {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct PublishProperties {
     pub payload_format_indicator: Option<u8>,
     pub message_expiry_interval: Option<u32>,
@@ -107,7 +103,7 @@ pub struct PublishProperties {
 }
 
 impl PublishProperties {
-    fn len(&self) -> usize {
+    pub(crate) fn len(&self) -> usize {
         let mut len = 0;
 
         if self.payload_format_indicator.is_some() {
@@ -146,83 +142,47 @@ impl PublishProperties {
     }
 
     fn read(stream: &mut Bytes) -> Result<Option<Self>, Error> {
-        let mut payload_format_indicator = None;
-        let mut message_expiry_interval = None;
-        let mut topic_alias = None;
-        let mut response_topic = None;
-        let mut correlation_data = None;
-        let mut user_properties = Vec::new();
-        let mut subscription_identifiers = Vec::new();
-        let mut content_type = None;
-
-        let (properties_len_len, properties_len) = packet::length(stream.iter())?;
-        stream.advance(properties_len_len);
-        if properties_len == 0 {
-            return Ok(None);
-        }
-
-        let mut cursor = 0;
-        while cursor < properties_len {
-            let prop = packet::read_u8(stream)?;
-            cursor += 1;
-
-            match prop.try_into()? {
+        let mut properties = Self::default();
+        let exists = super::read_properties(stream, |property, stream| {
+            match property {
                 PropertyType::PayloadFormatIndicator => {
-                    payload_format_indicator = Some(packet::read_u8(stream)?);
-                    cursor += 1;
+                    properties.payload_format_indicator =
+                        Some(super::read_bool_property(property, stream)?);
                 }
                 PropertyType::MessageExpiryInterval => {
-                    message_expiry_interval = Some(packet::read_u32(stream)?);
-                    cursor += 4;
+                    properties.message_expiry_interval = Some(packet::read_u32(stream)?);
                 }
                 PropertyType::TopicAlias => {
-                    topic_alias = Some(packet::read_u16(stream)?);
-                    cursor += 2;
+                    properties.topic_alias = Some(super::read_nonzero_u16(property, stream)?);
                 }
                 PropertyType::ResponseTopic => {
-                    let topic = packet::read_string(stream)?;
-                    cursor += 2 + topic.len();
-                    response_topic = Some(topic);
+                    properties.response_topic = Some(packet::read_string(stream)?);
                 }
                 PropertyType::CorrelationData => {
-                    let data = packet::read_bytes(stream)?;
-                    cursor += 2 + data.len();
-                    correlation_data = Some(data);
+                    properties.correlation_data = Some(packet::read_bytes(stream)?);
                 }
                 PropertyType::UserProperty => {
-                    let key = packet::read_string(stream)?;
-                    let value = packet::read_string(stream)?;
-                    cursor += 2 + key.len() + 2 + value.len();
-                    user_properties.push((key, value));
+                    properties
+                        .user_properties
+                        .push(super::read_user_property(stream)?);
                 }
                 PropertyType::SubscriptionIdentifier => {
-                    let (id_len, id) = packet::length(stream.iter())?;
-                    cursor += 1 + id_len;
-                    stream.advance(id_len);
-                    subscription_identifiers.push(id);
+                    properties
+                        .subscription_identifiers
+                        .push(super::read_subscription_id(stream)?);
                 }
                 PropertyType::ContentType => {
-                    let typ = packet::read_string(stream)?;
-                    cursor += 2 + typ.len();
-                    content_type = Some(typ);
+                    properties.content_type = Some(packet::read_string(stream)?);
                 }
-                _ => return Err(Error::InvalidPacketType(prop)),
+                _ => return Err(super::unexpected(property)),
             }
-        }
+            Ok(())
+        })?;
 
-        Ok(Some(Self {
-            payload_format_indicator,
-            message_expiry_interval,
-            topic_alias,
-            response_topic,
-            correlation_data,
-            user_properties,
-            subscription_identifiers,
-            content_type,
-        }))
+        Ok(exists.then_some(properties))
     }
 
-    fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+    pub(crate) fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
         packet::write_remaining_length(stream, self.len())?;
 
         if let Some(payload_format_indicator) = self.payload_format_indicator {
@@ -4,7 +4,7 @@ use crate::network::packet::{self, Error, FixedHeader};
 
 use super::PacketProperties;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PubRec {
     pub packet_id: u16,
     pub reason: PubRecReason,
@@ -19,11 +19,7 @@ impl PubRec {
 
         let mut len = 2 + 1;
         match &self.properties {
-            Some(properties) => {
-                let properties_len = properties.len();
-                let properties_len_len = super::len_len(properties_len);
-                len += properties_len_len + properties_len;
-            }
+            Some(properties) => len += super::properties_len(properties.len()),
             None => len += 1,
         }
 
@@ -40,19 +36,20 @@ impl PubRec {
             });
         }
 
-        let reason = packet::read_u8(&mut stream)?;
-        if fixed_header.remaining_len < 4 {
-            return Ok(Self {
-                packet_id,
-                reason: reason.try_into()?,
-                properties: None,
-            });
+        // 剩余长度小于 4 时没有属性
+        let reason = packet::read_u8(&mut stream)?.try_into()?;
+        let properties = match fixed_header.remaining_len {
+            3 => None,
+            _ => PacketProperties::read(&mut stream)?,
+        };
+        if !stream.is_empty() {
+            return Err(Error::MalformedPacket);
         }
 
         Ok(Self {
             packet_id,
-            reason: reason.try_into()?,
-            properties: PacketProperties::read(&mut stream)?,
+            reason,
+            properties,
         })
     }
 
@@ -66,6 +63,12 @@ impl PubRec {
         }
 
         stream.put_u8(self.reason as u8);
+        match &self.properties {
+            Some(properties) => properties.write(stream)?,
+            None => {
+                packet::write_remaining_length(stream, 0)?;
+            }
+        }
 
         Ok(())
     }
@@ -1,10 +1,10 @@
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 
 use crate::network::packet::{self, Error};
 
 use super::PacketProperties;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SubAck {
     pub packet_id: u16,
     pub return_codes: Vec<SubscribeReasonCode>,
@@ -16,17 +16,31 @@ impl SubAck {
         let mut len = 2 + self.return_codes.len();
 
         match &self.properties {
-            Some(properties) => {
-                let properties_len = properties.len();
-                let properties_len_len = super::len_len(properties_len);
-                len += properties_len_len + properties_len;
-            }
+            Some(properties) => len += super::properties_len(properties.len()),
             None => len += 1,
         }
 
         len
     }
 
+    pub fn read(mut stream: Bytes) -> Result<Self, Error> {
+        let packet_id = packet::read_u16(&mut stream)?;
+        let properties = PacketProperties::read(&mut stream)?;
+        if stream.is_empty() {
+            return Err(Error::MalformedPacket);
+        }
+        let return_codes = stream
+            .iter()
+            .map(|code| Ok(SubscribeReasonCode::try_from(*code)?))
+            .collect::<Result<Vec<SubscribeReasonCode>, Error>>()?;
+
+        Ok(Self {
+            packet_id,
+            return_codes,
+            properties,
+        })
+    }
+
     pub fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
         stream.put_u8(0x90);
         packet::write_remaining_length(stream, self.len())?;
@@ -51,7 +65,7 @@ impl SubAck {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum SubscribeReasonCode {
     QoS0 = 0,
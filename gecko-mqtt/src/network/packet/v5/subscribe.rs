@@ -1,10 +1,10 @@
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 use crate::network::packet::{self, Error, QoS};
 
 use super::PropertyType;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Subscribe {
     pub packet_id: u16,
     pub filters: Vec<SubscribeFilter>,
@@ -12,14 +12,36 @@ pub struct Subscribe {
 }
 
 impl Subscribe {
+    fn len(&self) -> usize {
+        let mut len = 2;
+
+        match &self.properties {
+            Some(properties) => len += super::properties_len(properties.len()),
+            None => len += 1,
+        }
+
+        for filter in self.filters.iter() {
+            len += 2 + filter.filter.len() + 1;
+        }
+
+        len
+    }
+
     pub fn read(mut stream: Bytes) -> Result<Self, Error> {
         let packet_id = packet::read_u16(&mut stream)?;
+        if packet_id == 0 {
+            return Err(Error::MissPacketId);
+        }
         let properties = SubscribeProperties::read(&mut stream)?;
 
         let mut filters = Vec::new();
         while stream.has_remaining() {
             let filter = packet::read_string(&mut stream)?;
             let flags = packet::read_u8(&mut stream)?;
+            // 订阅选项的高两位是保留位
+            if flags & 0b1100_0000 != 0 {
+                return Err(Error::MalformedPacket);
+            }
             let qos = flags & 0b0000_0011;
 
             let nolocal = flags >> 2 & 0b0000_0001;
@@ -55,9 +77,29 @@ impl Subscribe {
             properties,
         })
     }
+
+    pub fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+        stream.put_u8(0x82);
+        packet::write_remaining_length(stream, self.len())?;
+        stream.put_u16(self.packet_id);
+
+        match &self.properties {
+            Some(properties) => properties.write(stream)?,
+            None => {
+                packet::write_remaining_length(stream, 0)?;
+            }
+        }
+
+        for filter in self.filters.iter() {
+            packet::write_string(stream, &filter.filter);
+            stream.put_u8(filter.options());
+        }
+
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SubscribeFilter {
     pub filter: String,
     pub qos: QoS,
@@ -66,55 +108,87 @@ pub struct SubscribeFilter {
     pub retain_forward_rule: RetainForwardRule,
 }
 
-#[derive(Debug)]
+impl SubscribeFilter {
+    /// 订阅选项字节
+    fn options(&self) -> u8 {
+        let mut options = self.qos as u8;
+        if self.nolocal {
+            options |= 0b0000_0100;
+        }
+        if self.preserve_retain {
+            options |= 0b0000_1000;
+        }
+        options | (self.retain_forward_rule as u8) << 4
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RetainForwardRule {
     OnEverySubscribe,
     OnNewSubscribe,
     Never,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct SubscribeProperties {
     pub id: Option<usize>,
     pub user_properties: Vec<(String, String)>,
 }
 
 impl SubscribeProperties {
-    pub fn read(stream: &mut Bytes) -> Result<Option<Self>, Error> {
-        let mut id = None;
-        let mut user_properties = Vec::new();
+    fn len(&self) -> usize {
+        let mut len = 0;
+
+        if let Some(id) = self.id {
+            len += 1 + super::len_len(id);
+        }
 
-        let (properties_len_len, properties_len) = packet::length(stream.iter())?;
-        stream.advance(properties_len_len);
-        if properties_len == 0 {
-            return Ok(None);
+        for (key, value) in &self.user_properties {
+            len += 1 + 2 + key.len() + 2 + value.len();
         }
 
-        let mut cursor = 0;
-        while cursor < properties_len {
-            let prop = packet::read_u8(stream)?;
-            cursor += 1;
+        len
+    }
 
-            match prop.try_into()? {
+    pub fn read(stream: &mut Bytes) -> Result<Option<Self>, Error> {
+        let mut properties = Self::default();
+        let exists = super::read_properties(stream, |property, stream| {
+            match property {
+                // subscribe 报文中订阅标识符只能出现一次
+                PropertyType::SubscriptionIdentifier if properties.id.is_some() => {
+                    return Err(super::Error::DuplicateProperty(property as u8).into());
+                }
                 PropertyType::SubscriptionIdentifier => {
-                    let (id_len, sub_id) = packet::length(stream.iter())?;
-                    cursor += 1 + id_len;
-                    stream.advance(id_len);
-                    id = Some(sub_id);
+                    properties.id = Some(super::read_subscription_id(stream)?);
                 }
                 PropertyType::UserProperty => {
-                    let key = packet::read_string(stream)?;
-                    let value = packet::read_string(stream)?;
-                    cursor += 2 + key.len() + 2 + value.len();
-                    user_properties.push((key, value));
+                    properties
+                        .user_properties
+                        .push(super::read_user_property(stream)?);
                 }
-                _ => return Err(Error::InvalidPacketType(prop)),
+                _ => return Err(super::unexpected(property)),
             }
+            Ok(())
+        })?;
+
+        Ok(exists.then_some(properties))
+    }
+
+    fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+        packet::write_remaining_length(stream, self.len())?;
+
+        if let Some(id) = self.id {
+            stream.put_u8(PropertyType::SubscriptionIdentifier as u8);
+            packet::write_remaining_length(stream, id)?;
+        }
+
+        for (key, value) in &self.user_properties {
+            stream.put_u8(PropertyType::UserProperty as u8);
+            packet::write_string(stream, key);
+            packet::write_string(stream, value);
         }
 
-        Ok(Some(Self {
-            id,
-            user_properties,
-        }))
+        Ok(())
     }
 }
@@ -0,0 +1,534 @@
+use bytes::{Bytes, BytesMut};
+use proptest::{collection::vec, option, prelude::*, sample::select, strategy::Union};
+
+use crate::network::packet::{self, Protocol, QoS};
+
+use super::*;
+
+fn string() -> impl Strategy<Value = String> {
+    "[a-z0-9/+#]{0,8}"
+}
+
+fn bytes() -> impl Strategy<Value = Bytes> {
+    vec(any::<u8>(), 0..8).prop_map(Bytes::from)
+}
+
+fn user_properties() -> impl Strategy<Value = Vec<(String, String)>> {
+    vec((string(), string()), 0..3)
+}
+
+/// 只能为 0 或 1 的属性
+fn flag() -> impl Strategy<Value = Option<u8>> {
+    option::of(0..=1u8)
+}
+
+fn qos() -> impl Strategy<Value = QoS> {
+    select(vec![QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce])
+}
+
+fn packet_id() -> impl Strategy<Value = u16> {
+    1..=u16::MAX
+}
+
+/// 空属性列表编码后和没有属性相同
+fn non_empty<T: Default + PartialEq>(properties: T) -> Option<T> {
+    (properties != T::default()).then_some(properties)
+}
+
+fn packet_properties() -> impl Strategy<Value = Option<PacketProperties>> {
+    (option::of(string()), user_properties()).prop_map(|(reason_string, user_properties)| {
+        non_empty(PacketProperties {
+            reason_string,
+            user_properties,
+        })
+    })
+}
+
+fn last_will() -> impl Strategy<Value = LastWill> {
+    let properties = (
+        option::of(any::<u32>()),
+        flag(),
+        option::of(any::<u32>()),
+        option::of(string()),
+        option::of(string()),
+        option::of(bytes()),
+        user_properties(),
+    )
+        .prop_map(|properties| {
+            non_empty(WillProperties {
+                delay_interval: properties.0,
+                payload_format_indicator: properties.1,
+                message_expiry_interval: properties.2,
+                content_type: properties.3,
+                response_topic: properties.4,
+                correlation_data: properties.5,
+                user_properties: properties.6,
+            })
+        });
+    (string(), bytes(), qos(), any::<bool>(), properties).prop_map(
+        |(topic, message, qos, retain, properties)| LastWill {
+            topic,
+            message,
+            qos,
+            retain,
+            properties,
+        },
+    )
+}
+
+fn connect() -> impl Strategy<Value = Packet> {
+    let properties = (
+        option::of(any::<u32>()),
+        option::of(1..=u16::MAX),
+        option::of(1..=u32::MAX),
+        option::of(any::<u16>()),
+        flag(),
+        flag(),
+        user_properties(),
+        option::of(string()),
+        option::of(bytes()),
+    )
+        .prop_map(|properties| {
+            non_empty(ConnectProperties {
+                session_expiry_interval: properties.0,
+                receive_maximum: properties.1,
+                max_packet_size: properties.2,
+                topic_alias_max: properties.3,
+                request_response_info: properties.4,
+                request_problem_info: properties.5,
+                user_properties: properties.6,
+                authentication_method: properties.7,
+                authentication_data: properties.8,
+            })
+        });
+    (
+        select(vec![Protocol::V3, Protocol::V4, Protocol::V5]),
+        any::<u16>(),
+        string(),
+        any::<bool>(),
+        option::of(last_will()),
+        option::of(string()),
+        option::of(string()),
+        properties,
+    )
+        .prop_map(
+            |(
+                protocol,
+                keepalive,
+                client_id,
+                clean_start,
+                mut last_will,
+                username,
+                password,
+                properties,
+            )| {
+                // v5 之前的协议没有属性
+                let v5 = protocol == Protocol::V5;
+                if !v5 {
+                    if let Some(last_will) = last_will.as_mut() {
+                        last_will.properties = None;
+                    }
+                }
+                Packet::Connect(Connect {
+                    protocol,
+                    keepalive,
+                    client_id,
+                    clean_start,
+                    last_will,
+                    login: Login { username, password },
+                    properties: properties.filter(|_| v5),
+                })
+            },
+        )
+}
+
+fn connack() -> impl Strategy<Value = Packet> {
+    let code = select(vec![
+        ConnectReturnCode::Success,
+        ConnectReturnCode::UnspecifiedError,
+        ConnectReturnCode::MalformedPacket,
+        ConnectReturnCode::UnsupportedProtocolVersion,
+        ConnectReturnCode::BadUserNamePassword,
+        ConnectReturnCode::Banned,
+        ConnectReturnCode::ServerMoved,
+        ConnectReturnCode::ConnectionRateExceeded,
+    ]);
+    let limits = (
+        option::of(any::<u32>()),
+        option::of(1..=u16::MAX),
+        flag(),
+        flag(),
+        option::of(1..=u32::MAX),
+        option::of(string()),
+        option::of(any::<u16>()),
+        option::of(string()),
+        user_properties(),
+    );
+    let features = (
+        flag(),
+        flag(),
+        flag(),
+        option::of(any::<u16>()),
+        option::of(string()),
+        option::of(string()),
+        option::of(string()),
+        option::of(bytes()),
+    );
+    (any::<bool>(), code, limits, features).prop_map(|(session_present, code, limits, features)| {
+        let properties = non_empty(ConnAckProperties {
+            session_expiry_interval: limits.0,
+            receive_max: limits.1,
+            max_qos: limits.2,
+            retain_available: limits.3,
+            max_packet_size: limits.4,
+            assigned_client_identifier: limits.5,
+            topic_alias_max: limits.6,
+            reason_string: limits.7,
+            user_properties: limits.8,
+            wildcard_subscription_available: features.0,
+            subscription_identifiers_available: features.1,
+            shared_subscription_available: features.2,
+            server_keep_alive: features.3,
+            response_information: features.4,
+            server_reference: features.5,
+            authentication_method: features.6,
+            authentication_data: features.7,
+        });
+        Packet::ConnAck(ConnAck {
+            session_present,
+            code,
+            properties,
+        })
+    })
+}
+
+fn publish() -> impl Strategy<Value = Packet> {
+    let properties = (
+        flag(),
+        option::of(any::<u32>()),
+        option::of(1..=u16::MAX),
+        option::of(string()),
+        option::of(bytes()),
+        user_properties(),
+        vec(1..=268_435_455usize, 0..3),
+        option::of(string()),
+    )
+        .prop_map(|properties| {
+            non_empty(PublishProperties {
+                payload_format_indicator: properties.0,
+                message_expiry_interval: properties.1,
+                topic_alias: properties.2,
+                response_topic: properties.3,
+                correlation_data: properties.4,
+                user_properties: properties.5,
+                subscription_identifiers: properties.6,
+                content_type: properties.7,
+            })
+        });
+    (
+        any::<bool>(),
+        qos(),
+        any::<bool>(),
+        string(),
+        packet_id(),
+        properties,
+        bytes(),
+    )
+        .prop_map(
+            |(dup, qos, retain, topic, packet_id, properties, payload)| {
+                Packet::Publish(Publish {
+                    dup,
+                    qos,
+                    retain,
+                    topic,
+                    // qos 0 没有报文标识符
+                    packet_id: if qos == QoS::AtMostOnce { 0 } else { packet_id },
+                    properties,
+                    payload,
+                })
+            },
+        )
+}
+
+fn acks() -> impl Strategy<Value = Packet> {
+    let puback = (
+        any::<u16>(),
+        select(vec![
+            PubAckReason::Success,
+            PubAckReason::NoMatchingSubscribers,
+            PubAckReason::NotAuthorized,
+            PubAckReason::PayloadFormatInvalid,
+        ]),
+        packet_properties(),
+    )
+        .prop_map(|(packet_id, reason, properties)| {
+            Packet::PubAck(PubAck {
+                packet_id,
+                reason,
+                properties,
+            })
+        });
+    let pubrec = (
+        any::<u16>(),
+        select(vec![
+            PubRecReason::Success,
+            PubRecReason::UnspecifiedError,
+            PubRecReason::QuotaExceeded,
+        ]),
+        packet_properties(),
+    )
+        .prop_map(|(packet_id, reason, properties)| {
+            Packet::PubRec(PubRec {
+                packet_id,
+                reason,
+                properties,
+            })
+        });
+    let pubrel = (
+        any::<u16>(),
+        select(vec![
+            PubRelReason::Success,
+            PubRelReason::PacketIdentifierNotFound,
+        ]),
+        packet_properties(),
+    )
+        .prop_map(|(packet_id, reason, properties)| {
+            Packet::PubRel(PubRel {
+                packet_id,
+                reason,
+                properties,
+            })
+        });
+    let pubcomp = (
+        any::<u16>(),
+        select(vec![
+            PubCompReason::Success,
+            PubCompReason::PacketIdentifierNotFound,
+        ]),
+        packet_properties(),
+    )
+        .prop_map(|(packet_id, reason, properties)| {
+            Packet::PubComp(PubComp {
+                packet_id,
+                reason,
+                properties,
+            })
+        });
+    prop_oneof![puback, pubrec, pubrel, pubcomp]
+}
+
+fn subscribe() -> impl Strategy<Value = Packet> {
+    let filter = (
+        string(),
+        qos(),
+        any::<bool>(),
+        any::<bool>(),
+        select(vec![
+            RetainForwardRule::OnEverySubscribe,
+            RetainForwardRule::OnNewSubscribe,
+            RetainForwardRule::Never,
+        ]),
+    )
+        .prop_map(
+            |(filter, qos, nolocal, preserve_retain, retain_forward_rule)| SubscribeFilter {
+                filter,
+                qos,
+                nolocal,
+                preserve_retain,
+                retain_forward_rule,
+            },
+        );
+    let properties =
+        (option::of(1..=268_435_455usize), user_properties()).prop_map(|(id, user_properties)| {
+            non_empty(SubscribeProperties {
+                id,
+                user_properties,
+            })
+        });
+    (packet_id(), vec(filter, 1..4), properties).prop_map(|(packet_id, filters, properties)| {
+        Packet::Subscribe(Subscribe {
+            packet_id,
+            filters,
+            properties,
+        })
+    })
+}
+
+fn suback() -> impl Strategy<Value = Packet> {
+    let code = select(vec![
+        SubscribeReasonCode::QoS0,
+        SubscribeReasonCode::QoS1,
+        SubscribeReasonCode::QoS2,
+        SubscribeReasonCode::NotAuthorized,
+        SubscribeReasonCode::TopicFilterInvalid,
+        SubscribeReasonCode::WildcardSubscriptionsNotSupported,
+    ]);
+    (packet_id(), vec(code, 1..4), packet_properties()).prop_map(
+        |(packet_id, return_codes, properties)| {
+            Packet::SubAck(SubAck {
+                packet_id,
+                return_codes,
+                properties,
+            })
+        },
+    )
+}
+
+fn unsubscribe() -> impl Strategy<Value = Packet> {
+    let properties = user_properties()
+        .prop_map(|user_properties| non_empty(UnsubscribeProperties { user_properties }));
+    (packet_id(), vec(string(), 1..4), properties).prop_map(|(packet_id, filters, properties)| {
+        Packet::Unsubscribe(Unsubscribe {
+            packet_id,
+            filters,
+            properties,
+        })
+    })
+}
+
+fn unsuback() -> impl Strategy<Value = Packet> {
+    let reason = select(vec![
+        UnsubAckReason::Success,
+        UnsubAckReason::NoSubscriptionExisted,
+        UnsubAckReason::TopicFilterInvalid,
+    ]);
+    (packet_id(), vec(reason, 1..4), packet_properties()).prop_map(
+        |(packet_id, reasons, properties)| {
+            Packet::UnsubAck(UnsubAck {
+                packet_id,
+                reasons,
+                properties,
+            })
+        },
+    )
+}
+
+fn disconnect() -> impl Strategy<Value = Packet> {
+    let reason_code = select(vec![
+        DisconnectReasonCode::NormalDisconnection,
+        DisconnectReasonCode::DisconnectWithWillMessage,
+        DisconnectReasonCode::ServerShuttingDown,
+        DisconnectReasonCode::SessionTakenOver,
+        DisconnectReasonCode::PacketTooLarge,
+    ]);
+    let properties = (
+        option::of(any::<u32>()),
+        option::of(string()),
+        user_properties(),
+        option::of(string()),
+    )
+        .prop_map(|properties| {
+            non_empty(DisconnectProperties {
+                session_expiry_interval: properties.0,
+                reason_string: properties.1,
+                user_properties: properties.2,
+                server_reference: properties.3,
+            })
+        });
+    (reason_code, properties).prop_map(|(reason_code, properties)| {
+        Packet::Disconnect(Disconnect {
+            reason_code,
+            properties,
+        })
+    })
+}
+
+fn auth() -> impl Strategy<Value = Packet> {
+    let reason = select(vec![
+        AuthReason::Success,
+        AuthReason::ContinueAuthentication,
+        AuthReason::ReAuthenticate,
+    ]);
+    let properties = (
+        option::of(string()),
+        option::of(bytes()),
+        option::of(string()),
+        user_properties(),
+    )
+        .prop_map(|properties| {
+            non_empty(AuthProperties {
+                authentication_method: properties.0,
+                authentication_data: properties.1,
+                reason_string: properties.2,
+                user_properties: properties.3,
+            })
+        });
+    (reason, properties).prop_map(|(reason, properties)| Packet::Auth(Auth { reason, properties }))
+}
+
+fn packet() -> impl Strategy<Value = Packet> {
+    Union::new(vec![
+        connect().boxed(),
+        connack().boxed(),
+        publish().boxed(),
+        acks().boxed(),
+        subscribe().boxed(),
+        suback().boxed(),
+        unsubscribe().boxed(),
+        unsuback().boxed(),
+        Just(Packet::PingReq).boxed(),
+        Just(Packet::PingResp).boxed(),
+        disconnect().boxed(),
+        auth().boxed(),
+    ])
+}
+
+proptest! {
+    #[test]
+    fn v5_packet_roundtrip(packet in packet()) {
+        let mut stream = BytesMut::new();
+        packet.write(&mut stream).unwrap();
+        let read = Packet::read(&mut stream, usize::MAX).unwrap();
+        prop_assert_eq!(read, packet);
+        prop_assert!(stream.is_empty());
+    }
+}
+
+fn read(bytes: &[u8]) -> Result<Packet, packet::Error> {
+    Packet::read(&mut BytesMut::from(bytes), usize::MAX)
+}
+
+#[test]
+fn v5_properties_validated() {
+    // 重复的原因字符串
+    let duplicate = [0x40, 12, 0, 1, 0x00, 8, 31, 0, 1, b'a', 31, 0, 1, b'b'];
+    assert!(matches!(
+        read(&duplicate),
+        Err(packet::Error::V5(Error::DuplicateProperty(31)))
+    ));
+
+    // puback 中不允许出现主题别名
+    let unexpected = [0x40, 7, 0, 1, 0x00, 3, 35, 0, 1];
+    assert!(matches!(
+        read(&unexpected),
+        Err(packet::Error::V5(Error::UnexpectedPropertyType(35)))
+    ));
+
+    // 属性长度没有使用最短编码
+    let overlong = [0x40, 5, 0, 1, 0x00, 0x80, 0x00];
+    assert!(matches!(
+        read(&overlong),
+        Err(packet::Error::MalformedPacket)
+    ));
+
+    // 属性长度超出报文
+    let overflow = [0x40, 5, 0, 1, 0x00, 5, 31];
+    assert!(matches!(
+        read(&overflow),
+        Err(packet::Error::MalformedPacket)
+    ));
+
+    // 订阅标识符不能为 0
+    let zero_id = [0x30, 6, 0, 1, b't', 2, 11, 0];
+    assert!(matches!(
+        read(&zero_id),
+        Err(packet::Error::V5(Error::InvalidPropertyValue(11)))
+    ));
+
+    // subscribe 固定头标志位必须是 0b0010
+    let flags = [0x80, 6, 0, 1, 0, 0, 1, b't'];
+    assert!(matches!(
+        read(&flags),
+        Err(packet::Error::InvalidHeaderFlags(0x80))
+    ));
+}
@@ -1,10 +1,10 @@
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 
 use crate::network::packet::{self, Error};
 
 use super::PacketProperties;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UnsubAck {
     pub packet_id: u16,
     pub reasons: Vec<UnsubAckReason>,
@@ -16,19 +16,33 @@ impl UnsubAck {
         let mut len = 2 + self.reasons.len();
 
         match &self.properties {
-            Some(properties) => {
-                let properties_len = properties.len();
-                let properties_len_len = super::len_len(properties_len);
-                len += properties_len_len + properties_len;
-            }
+            Some(properties) => len += super::properties_len(properties.len()),
             None => len += 1,
         }
 
         len
     }
 
+    pub fn read(mut stream: Bytes) -> Result<Self, Error> {
+        let packet_id = packet::read_u16(&mut stream)?;
+        let properties = PacketProperties::read(&mut stream)?;
+        if stream.is_empty() {
+            return Err(Error::MalformedPacket);
+        }
+        let reasons = stream
+            .iter()
+            .map(|code| Ok(UnsubAckReason::try_from(*code)?))
+            .collect::<Result<Vec<UnsubAckReason>, Error>>()?;
+
+        Ok(Self {
+            packet_id,
+            reasons,
+            properties,
+        })
+    }
+
     pub fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
-        stream.put_u8(0x80);
+        stream.put_u8(0xB0);
         packet::write_remaining_length(stream, self.len())?;
 
         stream.put_u16(self.packet_id);
@@ -51,7 +65,7 @@ impl UnsubAck {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum UnsubAckReason {
     Success = 0x00,
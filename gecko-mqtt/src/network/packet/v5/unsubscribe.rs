@@ -1,10 +1,10 @@
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 use crate::network::packet::{self, Error};
 
 use super::PropertyType;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Unsubscribe {
     pub packet_id: u16,
     pub filters: Vec<String>,
@@ -12,8 +12,26 @@ pub struct Unsubscribe {
 }
 
 impl Unsubscribe {
+    fn len(&self) -> usize {
+        let mut len = 2;
+
+        match &self.properties {
+            Some(properties) => len += super::properties_len(properties.len()),
+            None => len += 1,
+        }
+
+        for filter in self.filters.iter() {
+            len += 2 + filter.len();
+        }
+
+        len
+    }
+
     pub fn read(mut stream: Bytes) -> Result<Self, Error> {
         let packet_id = packet::read_u16(&mut stream)?;
+        if packet_id == 0 {
+            return Err(Error::MissPacketId);
+        }
         let properties = UnsubscribeProperties::read(&mut stream)?;
 
         let mut filters = Vec::new();
@@ -22,45 +40,76 @@ impl Unsubscribe {
             filters.push(filter);
         }
 
+        if filters.is_empty() {
+            return Err(super::Error::EmptySubscription)?;
+        }
+
         Ok(Self {
             packet_id,
             filters,
             properties,
         })
     }
+
+    pub fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+        stream.put_u8(0xA2);
+        packet::write_remaining_length(stream, self.len())?;
+        stream.put_u16(self.packet_id);
+
+        match &self.properties {
+            Some(properties) => properties.write(stream)?,
+            None => {
+                packet::write_remaining_length(stream, 0)?;
+            }
+        }
+
+        for filter in self.filters.iter() {
+            packet::write_string(stream, filter);
+        }
+
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct UnsubscribeProperties {
     pub user_properties: Vec<(String, String)>,
 }
 
 impl UnsubscribeProperties {
-    fn read(stream: &mut Bytes) -> Result<Option<Self>, Error> {
-        let mut user_properties = Vec::new();
-
-        let (properties_len_len, properties_len) = packet::length(stream.iter())?;
-        stream.advance(properties_len_len);
-        if properties_len == 0 {
-            return Ok(None);
-        }
-
-        let mut cursor = 0;
-        while cursor < properties_len {
-            let prop = packet::read_u8(stream)?;
-            cursor += 1;
+    fn len(&self) -> usize {
+        self.user_properties
+            .iter()
+            .map(|(key, value)| 1 + 2 + key.len() + 2 + value.len())
+            .sum()
+    }
 
-            match prop.try_into()? {
+    fn read(stream: &mut Bytes) -> Result<Option<Self>, Error> {
+        let mut properties = Self::default();
+        let exists = super::read_properties(stream, |property, stream| {
+            match property {
                 PropertyType::UserProperty => {
-                    let key = packet::read_string(stream)?;
-                    let value = packet::read_string(stream)?;
-                    cursor += 2 + key.len() + 2 + value.len();
-                    user_properties.push((key, value));
+                    properties
+                        .user_properties
+                        .push(super::read_user_property(stream)?);
                 }
-                _ => return Err(Error::InvalidPacketType(prop)),
+                _ => return Err(super::unexpected(property)),
             }
+            Ok(())
+        })?;
+
+        Ok(exists.then_some(properties))
+    }
+
+    fn write(&self, stream: &mut BytesMut) -> Result<(), Error> {
+        packet::write_remaining_length(stream, self.len())?;
+
+        for (key, value) in &self.user_properties {
+            stream.put_u8(PropertyType::UserProperty as u8);
+            packet::write_string(stream, key);
+            packet::write_string(stream, value);
         }
 
-        Ok(Some(Self { user_properties }))
+        Ok(())
     }
 }
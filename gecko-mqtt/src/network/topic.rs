@@ -33,8 +33,8 @@ pub fn valid_subscribe_filter(filter: &str) -> bool {
 
 /// 匹配发布消息使用的 topic 和 订阅的 filter
 pub fn matches(topic: &str, filter: &str) -> bool {
-    // 以 $ 开头的topic不可以由用户publish
-    if !topic.is_empty() && topic.starts_with('$') {
+    // 以 $ 开头的 topic 不匹配以通配符开头的 filter，只能明确订阅，如 $SYS/# [MQTT-4.7.2-1]
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
         return false;
     }
     let mut topics = topic.split('/');
@@ -1,30 +1,136 @@
 //! 协议层
 //! 处理协议相关的逻辑，依赖于底层的网络层进行网络读写
 
-use tokio::sync::mpsc::Sender;
+use std::{collections::HashSet, sync::Arc};
 
-use crate::network::v4::{ConnAck, Connect, Packet};
+use gecko_mqtt_proto::{
+    Ban, ReleaseSessionResponse, RetainMessage, RouteAction, SyncRetainResponse,
+    TakeoverSessionResponse,
+};
+use tokio::sync::oneshot;
 
-pub(crate) use router::Router;
+use crate::{
+    cluster::NodeId,
+    network::{
+        packet::QoS,
+        v4::{ConnAck, Connect, Packet, Publish},
+        ConnTx, Outbound,
+    },
+};
+
+pub(crate) use rewrite::Rewrite;
+pub(crate) use router::{AdminRequest, Router};
+pub(crate) use session::SessionState;
+pub(crate) use state::State;
+pub(crate) use worker::Workers;
 
+pub(crate) mod rewrite;
 pub mod router;
 mod session;
-mod subscripton;
+mod state;
+pub(crate) mod subscripton;
+mod sys;
+pub mod worker;
 
 /// 发送给 router 的消息
 #[derive(Debug)]
 pub enum Incoming {
     Connect {
         connect: Connect,
-        conn_tx: Sender<Outgoing>,
+        conn_tx: ConnTx,
+    },
+    /// 进程内客户端连接，没有网络连接，由 router 检查封禁
+    LocalConnect {
+        connect: Connect,
+        conn_tx: ConnTx,
     },
     Data {
         client_id: String,
         packets: Vec<Packet>,
     },
+    /// 连接断开，同一个客户端的新连接可能已经替换了这个连接
     Disconnect {
         client_id: String,
+        outbound: Arc<Outbound>,
+    },
+    /// 连接的缓冲区已写出，恢复发送 session 中暂停的消息
+    Resume {
+        client_id: String,
+    },
+    /// worker 处理订阅后，本节点的路由变更，由 router 同步给对等节点
+    LocalRoute {
+        filter: String,
+        action: RouteAction,
+    },
+    /// worker 写入的保留消息，由 router 广播给对等节点
+    BroadcastRetain {
+        message: RetainMessage,
+    },
+    /// 其它节点也订阅了 worker 处理的 publish 消息，由 router 转发
+    ForwardRemote {
+        nodes: HashSet<NodeId>,
+        publish: Publish,
+    },
+    /// 对等节点转发过来的 publish 消息
+    ForwardPublish {
+        origin_node_id: NodeId,
+        publish: Publish,
+    },
+    /// 集群管理器发现了新的对等节点
+    NodeUp {
+        node_id: NodeId,
+        addr: String,
+    },
+    /// 集群管理器发现对等节点已宕机
+    NodeDown {
+        node_id: NodeId,
+    },
+    /// 对等节点新增路由
+    AddRoute {
+        node_id: NodeId,
+        filter: String,
+    },
+    /// 对等节点删除路由
+    DeleteRoute {
+        node_id: NodeId,
+        filter: String,
+    },
+    /// 对等节点上的会话变更
+    UpdateSession {
+        client_id: String,
+        node_id: NodeId,
+        removed: bool,
+    },
+    /// 客户端连接到了 node_id 节点，踢掉本节点上的连接
+    TakeoverSession {
+        client_id: String,
+        node_id: NodeId,
+        reply_tx: oneshot::Sender<TakeoverSessionResponse>,
     },
+    /// 会话已迁移到 node_id 节点，删除本节点上的会话
+    ReleaseSession {
+        client_id: String,
+        node_id: NodeId,
+        reply_tx: oneshot::Sender<ReleaseSessionResponse>,
+    },
+    /// 对等节点上写入的保留消息
+    UpdateRetain {
+        retains: Vec<RetainMessage>,
+    },
+    /// node_id 节点拉取本节点所有的保留消息
+    SyncRetain {
+        node_id: NodeId,
+        reply_tx: oneshot::Sender<SyncRetainResponse>,
+    },
+    /// 对等节点上添加和删除的禁止规则
+    UpdateBan {
+        added: Vec<Ban>,
+        removed: Vec<Ban>,
+    },
+    /// 管理接口的请求
+    Admin(AdminRequest),
+    /// 关闭 broker，断开所有客户端连接后 router 退出
+    Shutdown,
 }
 
 /// router 发送给客户端的回复
@@ -33,5 +139,13 @@ pub enum Outgoing {
     ConnAck(ConnAck),
     Packet(Packet),
     Packets(Vec<Packet>),
+    /// 钩子拒绝了 v5 客户端发布的 QoS1/QoS2 消息，回复带原因的 puback/pubrec
+    PublishRejected {
+        qos: QoS,
+        packet_id: u16,
+        reason: String,
+    },
     Disconnect,
+    /// broker 关闭，写完缓冲区后断开，v5 客户端会收到 Server Shutting Down
+    Shutdown,
 }
@@ -0,0 +1,157 @@
+//! 主题重写
+//! 按配置的正则规则替换客户端 publish 的主题和 subscribe/unsubscribe 的 filter
+//! * 在 worker 中处理报文之前重写，订阅者看到的是重写后的主题
+//! * 规则按顺序匹配，只使用第一条匹配的规则
+//! * 重写后不是合法的主题或 filter 时，保持原样
+
+use log::warn;
+use regex::Regex;
+
+use crate::{
+    config::{RewriteAction, RewriteRule},
+    network::topic,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid rewrite regex {0}: {1}")]
+    Regex(String, regex::Error),
+}
+
+#[derive(Debug)]
+struct Rule {
+    action: RewriteAction,
+    re: Regex,
+    dest: String,
+}
+
+impl Rule {
+    fn applies(&self, action: RewriteAction) -> bool {
+        self.action == RewriteAction::All || self.action == action
+    }
+
+    /// 没有匹配，或者需要用户名但客户端没有时返回 None
+    fn rewrite(&self, topic: &str, client_id: &str, username: Option<&str>) -> Option<String> {
+        let captures = self.re.captures(topic)?;
+        let mut dest = self.dest.replace("%c", &escape(client_id));
+        if dest.contains("%u") {
+            dest = dest.replace("%u", &escape(username?));
+        }
+        let mut rewritten = String::new();
+        captures.expand(&dest, &mut rewritten);
+        Some(rewritten)
+    }
+}
+
+/// 配置的所有重写规则
+#[derive(Debug, Default)]
+pub(crate) struct Rewrite {
+    rules: Vec<Rule>,
+}
+
+impl Rewrite {
+    pub fn new(rules: &[RewriteRule]) -> Result<Self, Error> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    action: rule.action,
+                    re: Regex::new(&rule.re).map_err(|e| Error::Regex(rule.re.clone(), e))?,
+                    dest: rule.dest.clone(),
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// 重写 publish 的主题，不需要重写时返回 None
+    pub fn publish(&self, topic: &str, client_id: &str, username: Option<&str>) -> Option<String> {
+        let rewritten = self.rewrite(RewriteAction::Publish, topic, client_id, username)?;
+        if !topic::valid_publish_topic(&rewritten) {
+            warn!(
+                "rewrite topic {0} to invalid {1}, ignored",
+                topic, rewritten
+            );
+            return None;
+        }
+        Some(rewritten)
+    }
+
+    /// 重写 subscribe/unsubscribe 的 filter，不需要重写时返回 None
+    pub fn filter(&self, filter: &str, client_id: &str, username: Option<&str>) -> Option<String> {
+        let rewritten = self.rewrite(RewriteAction::Subscribe, filter, client_id, username)?;
+        if !topic::valid_subscribe_filter(&rewritten) {
+            warn!(
+                "rewrite filter {0} to invalid {1}, ignored",
+                filter, rewritten
+            );
+            return None;
+        }
+        Some(rewritten)
+    }
+
+    fn rewrite(
+        &self,
+        action: RewriteAction,
+        topic: &str,
+        client_id: &str,
+        username: Option<&str>,
+    ) -> Option<String> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.applies(action))
+            .find_map(|rule| rule.rewrite(topic, client_id, username))
+    }
+}
+
+/// 替换进规则中的值不能被当作捕获组的引用
+fn escape(s: &str) -> String {
+    s.replace('$', "$$")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(action: RewriteAction, re: &str, dest: &str) -> RewriteRule {
+        RewriteRule {
+            action,
+            re: re.into(),
+            dest: dest.into(),
+        }
+    }
+
+    #[test]
+    fn rewrite_with_captures_and_placeholders() {
+        let rewrite = Rewrite::new(&[
+            rule(RewriteAction::Publish, r"^legacy/(\w+)/data$", "dev/%c/$1"),
+            rule(RewriteAction::All, r"^tenant/(.+)$", "%u/${1}"),
+            rule(RewriteAction::Subscribe, r"^cmd$", "cmd/%c"),
+            rule(RewriteAction::All, r"^bad$", "bad/#"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            rewrite.publish("legacy/temp/data", "d$1", None).as_deref(),
+            Some("dev/d$1/temp")
+        );
+        assert_eq!(
+            rewrite.publish("tenant/a/b", "d1", Some("acme")).as_deref(),
+            Some("acme/a/b")
+        );
+        // 没有用户名时跳过需要用户名的规则
+        assert_eq!(rewrite.publish("tenant/a/b", "d1", None), None);
+        assert_eq!(rewrite.publish("cmd", "d1", None), None);
+        assert_eq!(rewrite.filter("cmd", "d1", None).as_deref(), Some("cmd/d1"));
+        assert_eq!(rewrite.filter("legacy/temp/data", "d1", None), None);
+        // 重写后不合法的主题保持原样
+        assert_eq!(rewrite.publish("bad", "d1", None), None);
+        assert_eq!(rewrite.filter("bad", "d1", None).as_deref(), Some("bad/#"));
+
+        assert!(Rewrite::new(&[rule(RewriteAction::All, "(", "x")]).is_err());
+    }
+}
@@ -1,35 +1,68 @@
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    cmp,
+    collections::{HashSet, VecDeque},
     sync::Arc,
     time,
 };
 
+use gecko_mqtt_proto::{
+    ReleaseSessionResponse, RetainMessage, RouteAction, SyncRetainResponse, TakeoverSessionResponse,
+};
+use log::{debug, error, info, warn};
 use tokio::{
     select,
-    sync::mpsc::{error::SendError, Receiver, Sender},
+    sync::{
+        mpsc::{self, error::SendError, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
 };
 
 use crate::{
+    ban::{self, Bans},
+    cluster::{self, Dispatcher, ManagerRequest, NodeId, NodeStatus},
     config,
     network::{
-        packet::QoS,
         topic,
-        v4::{
-            ConnAck, Connect, ConnectReturnCode, Packet, PubAck, PubComp, PubRec, PubRel, Publish,
-            SubAck, Subscribe, SubscribeReasonCode, UnsubAck, Unsubscribe,
-        },
+        v4::{ConnAck, Connect, ConnectReturnCode, Publish},
+        ConnTx, Outbound,
     },
     Hook,
 };
 
 use super::{
     session::{self, Session},
-    subscripton::SubscriptionTree,
-    Incoming, Outgoing,
+    sys::SysTopics,
+    Incoming, Outgoing, State,
 };
 
+pub(crate) use admin::AdminRequest;
+
+mod admin;
+
 const SESSION_DEFAULT_EXPIRE_INTERVAL: u64 = 3600;
 
+/// 集群中异步请求完成后，交回 router 继续处理的事件
+#[derive(Debug)]
+enum ClusterEvent {
+    /// 旧节点已踢掉客户端连接，返回了会话的订阅信息
+    SessionTakenOver {
+        owner: NodeId,
+        connect: Connect,
+        conn_tx: ConnTx,
+        response: Option<TakeoverSessionResponse>,
+    },
+    /// 旧节点已删除会话，返回了会话中未完成的消息
+    SessionReleased {
+        client_id: String,
+        response: Option<ReleaseSessionResponse>,
+    },
+    /// 拉取到对等节点上所有的保留消息
+    RetainSynced {
+        node_id: NodeId,
+        response: Option<SyncRetainResponse>,
+    },
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Failed to send outgoing message: {0}")]
@@ -44,59 +77,120 @@ pub enum Error {
 
 /// 处理 mqtt 协议层运行时相关逻辑
 /// 接收消息，处理，发送到对应的设备/节点
+///
+/// router 只处理连接、会话迁移和集群同步等控制消息
+/// 客户端的报文由 worker 按 client id 分片并行处理，见 [`Workers`]
 pub(crate) struct Router<H: Hook> {
     session_cfg: config::Session,
     /// 各个客户端连接发送过来需要处理的数据
     router_rx: Receiver<Incoming>,
-    /// 管理客户端连接信息，key = client_id
+    /// 和 worker 共享的会话、订阅、保留消息和路由表
     /// session 清理
     /// 将需要清理的session放到一个队列中，队列顺序即代表需要清理的顺序
     /// 当有新的连接进来时，取出队列头的session进行判断清理直到过期时间不满足清理条件，如此，保持内存中的session不会引起大的内存泄漏
-    sessions: HashMap<String, Session>,
+    state: Arc<State>,
     /// 已经失效的 session，等待超时移除 (client_id, push_to_queue_time)
     ineffective_sessions: VecDeque<(String, time::Instant)>,
-
-    /// TODO 加速消息发布查找
-    /// 全局的精确订阅信息, key = topic-filter, value = client_id
-    concrete_subscriptions: HashMap<String, HashSet<String>>,
-    /// 全局的模糊订阅信息, T = client_id
-    wild_subscriptions: SubscriptionTree<String>,
-
-    /// 保留消息
-    retains: Vec<Publish>,
+    /// worker 发来的需要集群同步的变更，不限长度，worker 发送时不会等待 router
+    worker_rx: UnboundedReceiver<Incoming>,
+
+    /// 从对等节点拉取全量保留消息的间隔
+    retain_sync_interval: time::Duration,
+    /// 已删除保留消息的删除标记保留时间
+    retain_tombstone_ttl: time::Duration,
+    /// 发布 $SYS 主题的间隔，None 表示不发布
+    sys_interval: Option<time::Duration>,
+    sys_topics: SysTopics,
     /// 钩子函数
     hook: Arc<H>,
+    /// 禁止连接的客户端，和客户端连接共享
+    bans: Arc<Bans>,
+    /// 禁止规则的持久化文件
+    ban_file: Option<String>,
+    /// 创建新会话后自动订阅的主题
+    auto_subscribe: Vec<config::AutoSubscribe>,
+
+    /// 向对等节点发送消息
+    dispatcher: Dispatcher,
+    /// 集群异步请求的结果
+    cluster_tx: Sender<ClusterEvent>,
+    cluster_rx: Receiver<ClusterEvent>,
+    /// 对等节点的状态变更
+    status_rx: UnboundedReceiver<(NodeId, NodeStatus)>,
 }
 
 impl<H: Hook> Router<H> {
     pub(crate) fn new(
-        session_cfg: config::Session,
+        cfg: &config::Config,
         hook: Arc<H>,
         router_rx: Receiver<Incoming>,
+        manager_tx: Option<UnboundedSender<ManagerRequest>>,
+        state: Arc<State>,
+        worker_rx: UnboundedReceiver<Incoming>,
+        bans: Arc<Bans>,
     ) -> Self {
+        let (cluster_tx, cluster_rx) = mpsc::channel(1000);
+        let (status_tx, status_rx) = mpsc::unbounded_channel();
+        let cluster_cfg = &cfg.cluster;
+        let dispatcher = Dispatcher::new(cluster_cfg, status_tx, manager_tx, state.metrics.clone());
         Self {
-            session_cfg,
+            session_cfg: cfg.session.clone(),
             router_rx,
-            sessions: HashMap::new(),
+            state,
             ineffective_sessions: VecDeque::new(),
-            concrete_subscriptions: HashMap::new(),
-            wild_subscriptions: SubscriptionTree::new(),
-            retains: Vec::new(),
+            worker_rx,
+            retain_sync_interval: time::Duration::from_secs(cmp::max(
+                cluster_cfg.retain_sync_interval,
+                1,
+            )),
+            retain_tombstone_ttl: time::Duration::from_secs(cluster_cfg.retain_tombstone_ttl),
+            sys_interval: (cfg.broker.sys_interval > 0)
+                .then(|| time::Duration::from_secs(cfg.broker.sys_interval)),
+            sys_topics: SysTopics::new(cluster_cfg.node_id),
             hook,
+            bans,
+            ban_file: cfg.broker.ban_file.clone(),
+            auto_subscribe: cfg.auto_subscribe.clone(),
+            dispatcher,
+            cluster_tx,
+            cluster_rx,
+            status_rx,
         }
     }
 
     /// 开始 router 逻辑处理循环
     pub(crate) async fn start(mut self) -> Result<(), Error> {
+        // 启动时立即同步一次
+        let mut retain_sync = tokio::time::interval(self.retain_sync_interval);
+        // 不发布 $SYS 主题时不会等待这个定时器
+        let mut sys_tick =
+            tokio::time::interval(self.sys_interval.unwrap_or(self.retain_sync_interval));
+        // 和所有对等节点保持心跳
+        self.dispatcher.connect_peers();
         loop {
             select! {
                 // 接收客户端连接发来的消息
                 recv = self.router_rx.recv() => {
                     match recv {
+                        Some(Incoming::Shutdown) | None => {
+                            self.shutdown().await;
+                            return Ok(());
+                        }
                         Some(incoming) => self.handle_incoming(incoming).await?,
-                        None => todo!(),
                     }
                 }
+                // worker 处理客户端报文后的路由、保留消息、转发和断开
+                Some(incoming) = self.worker_rx.recv() => self.handle_incoming(incoming).await?,
+                // 集群异步请求的结果
+                Some(event) = self.cluster_rx.recv() => self.handle_cluster_event(event).await?,
+                // 定期从对等节点拉取保留消息
+                _ = retain_sync.tick() => self.sync_retains().await,
+                // 定期发布 $SYS 主题
+                _ = sys_tick.tick(), if self.sys_interval.is_some() => self.publish_sys().await,
+                // 对等节点上线、宕机
+                Some((node_id, status)) = self.status_rx.recv() => {
+                    self.handle_node_status(node_id, status).await
+                }
             }
         }
     }
@@ -105,56 +199,255 @@ impl<H: Hook> Router<H> {
     async fn handle_incoming(&mut self, incoming: Incoming) -> Result<(), Error> {
         match incoming {
             Incoming::Connect { connect, conn_tx } => self.handle_connect(connect, conn_tx).await,
-            Incoming::Data { client_id, packets } => {
-                for packet in packets.into_iter() {
-                    match packet {
-                        Packet::Subscribe(subscribe) => {
-                            self.handle_subscribe(&client_id, subscribe).await?
-                        }
-                        Packet::Publish(publish) => {
-                            self.handle_publish(&client_id, publish).await?
-                        }
-                        Packet::PubAck(puback) => self.handle_publish_ack(&client_id, puback),
-                        Packet::PubRel(pubrel) => {
-                            self.handle_publish_release(&client_id, pubrel).await?
-                        }
-                        Packet::PubRec(pubrec) => {
-                            self.handle_publish_receive(&client_id, pubrec).await?
-                        }
-                        Packet::PubComp(pubcomp) => {
-                            self.handle_publish_complete(&client_id, pubcomp)
-                        }
-                        Packet::Unsubscribe(unsubscribe) => {
-                            self.handle_unsubscribe(&client_id, unsubscribe).await?
-                        }
-                        Packet::Disconnect => self.handle_client_disconnect(&client_id).await?,
-                        _ => return Err(Error::UnexpectedPacket),
-                    }
+            Incoming::LocalConnect { connect, conn_tx } => {
+                self.handle_local_connect(connect, conn_tx).await
+            }
+            // 客户端的报文直接发给对应的 worker，不经过 router
+            Incoming::Data { client_id, .. } | Incoming::Resume { client_id } => {
+                warn!("unexpected client {} data in router", client_id);
+                Ok(())
+            }
+            Incoming::Disconnect {
+                client_id,
+                outbound,
+            } => self.handle_conn_disconnect(&client_id, &outbound).await,
+            Incoming::LocalRoute { filter, action } => {
+                self.handle_local_route(&filter, action).await;
+                Ok(())
+            }
+            Incoming::BroadcastRetain { message } => {
+                self.dispatcher.broadcast_retain(message);
+                Ok(())
+            }
+            Incoming::ForwardRemote { nodes, publish } => {
+                self.forward_remote(nodes, &publish).await;
+                Ok(())
+            }
+            Incoming::ForwardPublish {
+                origin_node_id,
+                publish,
+            } => {
+                self.handle_forward_publish(origin_node_id, publish);
+                Ok(())
+            }
+            Incoming::NodeUp { node_id, addr } => {
+                self.dispatcher.add_peer(node_id, addr);
+                self.handle_node_status(node_id, NodeStatus::Up).await;
+                Ok(())
+            }
+            Incoming::NodeDown { node_id } => {
+                self.handle_node_status(node_id, NodeStatus::Down).await;
+                self.dispatcher.remove_peer(node_id);
+                Ok(())
+            }
+            Incoming::AddRoute { node_id, filter } => {
+                self.state.storage.write().add_route(&filter, node_id);
+                Ok(())
+            }
+            Incoming::DeleteRoute { node_id, filter } => {
+                self.state.storage.write().remove_route(&filter, node_id);
+                Ok(())
+            }
+            Incoming::UpdateSession {
+                client_id,
+                node_id,
+                removed,
+            } => {
+                let mut storage = self.state.storage.write();
+                if removed {
+                    storage.remove_session_owner(&client_id, node_id);
+                } else {
+                    storage.set_session_owner(&client_id, node_id);
+                }
+                Ok(())
+            }
+            Incoming::TakeoverSession {
+                client_id,
+                node_id,
+                reply_tx,
+            } => {
+                self.handle_takeover_session(client_id, node_id, reply_tx);
+                Ok(())
+            }
+            Incoming::ReleaseSession {
+                client_id,
+                node_id,
+                reply_tx,
+            } => {
+                self.handle_release_session(client_id, node_id, reply_tx)
+                    .await;
+                Ok(())
+            }
+            Incoming::UpdateRetain { retains } => {
+                self.merge_retains(retains);
+                Ok(())
+            }
+            Incoming::SyncRetain {
+                node_id: _,
+                reply_tx,
+            } => {
+                let _ = reply_tx.send(SyncRetainResponse {
+                    retains: self.state.retains.read().messages(),
+                });
+                Ok(())
+            }
+            Incoming::UpdateBan { added, removed } => {
+                let added = added.into_iter().map(ban::from_proto).collect();
+                let removed = removed.into_iter().map(ban::from_proto).collect();
+                self.merge_bans(added, removed).await;
+                Ok(())
+            }
+            Incoming::Admin(request) => self.handle_admin(request).await,
+            // 在 start 中处理
+            Incoming::Shutdown => Err(Error::UnexpectedPacket),
+        }
+    }
+
+    async fn handle_cluster_event(&mut self, event: ClusterEvent) -> Result<(), Error> {
+        match event {
+            ClusterEvent::SessionTakenOver {
+                owner,
+                connect,
+                conn_tx,
+                response,
+            } => {
+                self.handle_session_taken_over(owner, connect, conn_tx, response)
+                    .await
+            }
+            ClusterEvent::SessionReleased {
+                client_id,
+                response,
+            } => self.handle_session_released(client_id, response).await,
+            ClusterEvent::RetainSynced { node_id, response } => {
+                match response {
+                    Some(response) => self.merge_retains(response.retains),
+                    None => warn!("sync retain messages from node {0} failed", node_id),
                 }
                 Ok(())
             }
-            Incoming::Disconnect { client_id } => self.handle_conn_disconnect(&client_id).await,
+        }
+    }
+
+    /// 从所有对等节点拉取保留消息，补上节点不可用期间丢失的变更
+    /// 同时清理过期的删除标记
+    async fn sync_retains(&mut self) {
+        let purged = self.state.retains.write().purge(self.retain_tombstone_ttl);
+        if purged > 0 {
+            debug!("purged {} expired retain tombstones", purged);
+        }
+        for node_id in self.dispatcher.peer_ids() {
+            self.sync_retain(node_id).await;
+        }
+    }
+
+    async fn sync_retain(&mut self, node_id: NodeId) {
+        let response = self.dispatcher.sync_retain(node_id);
+        let cluster_tx = self.cluster_tx.clone();
+        tokio::spawn(async move {
+            let response = response.await.ok().and_then(Result::ok);
+            let event = ClusterEvent::RetainSynced { node_id, response };
+            if let Err(e) = cluster_tx.send(event).await {
+                error!("send cluster event to router error: {:#}", e);
+            }
+        });
+    }
+
+    /// 处理对等节点的状态变更
+    /// * 上线：对方可能重启过，把本节点的路由和会话同步给它，并拉取它的保留消息
+    /// * 宕机：删除它的路由和会话，之后连接到本节点的客户端直接创建新会话
+    async fn handle_node_status(&mut self, node_id: NodeId, status: NodeStatus) {
+        let previous = self.state.storage.write().set_node_status(node_id, status);
+        match status {
+            NodeStatus::Up => {
+                let (filters, sessions) = {
+                    let storage = self.state.storage.read();
+                    (storage.local_filters(), storage.local_sessions())
+                };
+                for filter in filters {
+                    self.dispatcher
+                        .update_route(node_id, &filter, RouteAction::RouteAdd);
+                }
+                for client_id in sessions {
+                    self.dispatcher.update_session(node_id, &client_id, false);
+                }
+                self.sync_retain(node_id).await;
+                let (bans, deleted) = self.bans.snapshot();
+                let bans = bans.iter().map(ban::to_proto).collect();
+                let deleted = deleted.iter().map(ban::to_proto).collect();
+                self.dispatcher.update_ban(node_id, bans, deleted);
+                // 从可疑状态恢复的节点没有被清理过，不算重新加入
+                if !matches!(previous, Some(NodeStatus::Suspect | NodeStatus::Up)) {
+                    info!("peer node {0} up", node_id);
+                    self.hook.node_up(node_id).await;
+                }
+            }
+            NodeStatus::Suspect => warn!("peer node {0} suspect", node_id),
+            NodeStatus::Down => {
+                warn!("peer node {0} down", node_id);
+                self.state.storage.write().remove_node(node_id);
+                self.hook.node_down(node_id).await;
+            }
+        }
+    }
+
+    /// 合并对等节点的保留消息
+    fn merge_retains(&mut self, retains: Vec<RetainMessage>) {
+        let mut store = self.state.retains.write();
+        for message in retains {
+            if let Err(e) = store.merge(message) {
+                error!("merge retain message error: {:#}", e);
+            }
         }
     }
 
     /// 处理客户端连接
-    async fn handle_connect(
+    /// 会话在其它节点上时，先把会话迁移过来
+    /// 进程内客户端连接，已经在 LocalClients 中认证过，这里只检查封禁
+    async fn handle_local_connect(
         &mut self,
         connect: Connect,
-        conn_tx: Sender<Outgoing>,
+        conn_tx: ConnTx,
     ) -> Result<(), Error> {
+        let username = connect.login.username.as_deref();
+        // 进程内客户端没有来源地址，只检查客户端 id 和用户名
+        if self.bans.banned(&connect.client_id, username, None) {
+            let code = ConnectReturnCode::Banned;
+            conn_tx
+                .send(Outgoing::ConnAck(ConnAck::new(code, false)))
+                .await?;
+            return Ok(());
+        }
+        self.handle_connect(connect, conn_tx).await
+    }
+
+    async fn handle_connect(&mut self, connect: Connect, conn_tx: ConnTx) -> Result<(), Error> {
+        if !self.state.sessions.contains(&connect.client_id) {
+            let owner = self.state.storage.read().session_owner(&connect.client_id);
+            if let Some(owner) = owner {
+                if owner != self.dispatcher.node_id() {
+                    return self.takeover_session(owner, connect, conn_tx).await;
+                }
+            }
+        }
+        self.connect_session(connect, conn_tx).await
+    }
+
+    /// 创建或恢复本地会话，回复 connack
+    async fn connect_session(&mut self, connect: Connect, conn_tx: ConnTx) -> Result<(), Error> {
         let client_id = connect.client_id;
         let clean_session = connect.clean_session;
         // 拿出当前存储的 session（没来得及清理）
-        let mut session = match self.sessions.remove(&client_id) {
+        let session = self.state.sessions.shard(&client_id).remove(&client_id);
+        let session = match session {
             Some(session) => {
                 // 客户端断开了，但是服务端还没察觉到，会发生 conn_tx 还存在这种情况
                 if let Some(conn_tx) = &session.conn_tx {
-                    let _ = conn_tx.try_send(Outgoing::Disconnect);
+                    conn_tx.disconnect();
                 }
                 if !clean_session {
                     Some(session)
                 } else {
+                    self.clear_subscriptions(&session).await;
                     None
                 }
             }
@@ -164,24 +457,39 @@ impl<H: Hook> Router<H> {
         self.ineffective_sessions.retain(|(c, _)| c != &client_id);
         let session_present = session.is_some();
 
-        // 清理 session 中还积压的消息
-        if let Some(session) = session.as_mut() {
-            session.resend_packets().await?;
+        {
+            // 持有分片锁直到会话插入，客户端收到 ack 后立即发来的报文一定能找到会话
+            let mut sessions = self.state.sessions.shard(&client_id);
+            // 新连接的队列为空，ack 不会被丢弃，并且排在重发的报文之前
+            let ack = ConnAck {
+                session_present,
+                code: ConnectReturnCode::Success,
+            };
+            conn_tx.try_send(Outgoing::ConnAck(ack));
+
+            let mut new_session = match session {
+                Some(s) => s.into_new(clean_session, conn_tx),
+                None => Session::new(&client_id, clean_session, conn_tx),
+            };
+            // 清理 session 中还积压的消息
+            new_session.resend_packets()?;
+            sessions.insert(client_id.clone(), new_session);
         }
 
-        // 发送 ack 消息
-        let ack = ConnAck {
-            session_present,
-            code: ConnectReturnCode::Success,
-        };
-        conn_tx.send(Outgoing::ConnAck(ack)).await?;
-
-        let new_session = match session {
-            Some(s) => s.into_new(clean_session, conn_tx),
-            None => Session::new(&client_id, clean_session, conn_tx),
-        };
-
-        self.sessions.insert(client_id, new_session);
+        // 更新会话所在节点
+        let node_id = self.dispatcher.node_id();
+        let previous = self
+            .state
+            .storage
+            .write()
+            .set_session_owner(&client_id, node_id);
+        self.dispatcher
+            .broadcast_session(&client_id, false, previous);
+        if !session_present {
+            self.hook.on_session_created(&client_id).await;
+            self.apply_auto_subscribe(&client_id, connect.login.username.as_deref())
+                .await?;
+        }
 
         // 清理一波旧的 session
         let now = time::Instant::now();
@@ -199,217 +507,335 @@ impl<H: Hook> Router<H> {
                 break;
             }
             // 超时的，删除
-            self.sessions.remove(&client_id);
+            let session = self.state.sessions.shard(&client_id).remove(&client_id);
+            if let Some(session) = session {
+                self.clear_subscriptions(&session).await;
+                self.state
+                    .storage
+                    .write()
+                    .remove_session_owner(&client_id, node_id);
+                self.dispatcher.broadcast_session(&client_id, true, None);
+                self.hook.on_session_expired(&client_id).await;
+            }
         }
         Ok(())
     }
 
-    /// 处理订阅请求
-    /// TODO 给订阅的客户端发送所有匹配的保留消息
-    async fn handle_subscribe(
+    /// 新会话自动订阅配置和钩子指定的主题，和客户端订阅一样发送匹配的保留消息
+    async fn apply_auto_subscribe(
         &mut self,
         client_id: &str,
-        subscribe: Subscribe,
+        username: Option<&str>,
     ) -> Result<(), Error> {
-        let Subscribe { packet_id, filters } = subscribe;
+        let mut subscribes = self.auto_subscribe.clone();
+        subscribes.extend(self.hook.auto_subscribe(client_id, username).await);
+        if subscribes.is_empty() {
+            return Ok(());
+        }
 
-        match self.sessions.get_mut(client_id) {
-            Some(session) => {
-                let mut return_codes = Vec::with_capacity(filters.len());
-                for filter in filters {
-                    let path = filter.path;
-                    // 添加到订阅管理
-                    if topic::filter_has_wildcards(&path) {
-                        // 查询一下是否已添加过
-                        if session.wildcard_subscriptions.contains_key(&path) {
-                            continue;
-                        }
-                        // 添加到全局
-                        let token = self.wild_subscriptions.insert(&path, client_id.into());
-                        // 添加到session里
-                        session.wildcard_subscriptions.insert(path, token);
-                    } else {
-                        // 查询一下是否已添加过
-                        if session.concrete_subscriptions.contains(&path) {
-                            continue;
-                        }
-                        // 添加到全局
-                        if let Some(clients) = self.concrete_subscriptions.get_mut(&path) {
-                            clients.insert(client_id.into());
-                        } else {
-                            let new_set = HashSet::from_iter([client_id.into()]);
-                            self.concrete_subscriptions.insert(path.clone(), new_set);
-                        }
-                        // 添加到session里
-                        session.concrete_subscriptions.insert(path);
+        let mut added = Vec::new();
+        {
+            let mut sessions = self.state.sessions.shard(client_id);
+            let Some(session) = sessions.get_mut(client_id) else {
+                return Ok(());
+            };
+            let mut filters = Vec::with_capacity(subscribes.len());
+            {
+                let mut subscriptions = self.state.subscriptions.write();
+                for subscribe in subscribes.iter() {
+                    let Some(filter) = subscribe.filter(client_id, username) else {
+                        continue;
+                    };
+                    if !topic::valid_subscribe_filter(&filter) {
+                        warn!(
+                            "client {0} auto subscribe invalid filter {1}",
+                            client_id, filter
+                        );
+                        continue;
+                    }
+                    if subscriptions.add(session, &filter, subscribe.into()) {
+                        added.push(filter.clone());
                     }
-                    // TODO 添加一些校验，目前 sub 都是 success
-                    return_codes.push(SubscribeReasonCode::Success(filter.qos));
+                    filters.push((filter, subscribe));
                 }
-
-                let ack = SubAck {
-                    packet_id,
-                    return_codes,
-                };
-                Ok(session.send_packet(Packet::SubAck(ack)).await?)
             }
-            None => Err(Error::SessionNotFound),
-        }
-    }
 
-    async fn handle_unsubscribe(
-        &mut self,
-        client_id: &str,
-        unsubscribe: Unsubscribe,
-    ) -> Result<(), Error> {
-        let Unsubscribe { packet_id, filters } = unsubscribe;
-        match self.sessions.get_mut(client_id) {
-            Some(session) => {
-                for filter in filters {
-                    if topic::filter_has_wildcards(&filter) {
-                        if let Some(token) = session.wildcard_subscriptions.remove(&filter) {
-                            self.wild_subscriptions.remove(&filter, token);
-                        }
-                    } else {
-                        session.concrete_subscriptions.remove(&filter);
-                        self.concrete_subscriptions.remove(&filter);
-                    }
+            // 发送保留消息，qos 取消息和订阅中较小的一个
+            let retains = self.state.retains.read();
+            for (filter, subscribe) in filters {
+                if subscribe.retain_handling == config::RetainHandling::DoNotSend {
+                    continue;
+                }
+                for publish in retains.matches(&filter) {
+                    let publish = Publish {
+                        qos: cmp::min(publish.qos, subscribe.qos),
+                        ..publish.clone()
+                    };
+                    session.publish_message(&publish, &*self.state.hook, &self.state.metrics)?;
                 }
-                Ok(session
-                    .send_packet(Packet::UnsubAck(UnsubAck { packet_id }))
-                    .await?)
             }
-            None => Err(Error::SessionNotFound),
         }
+
+        for filter in added {
+            self.handle_local_route(&filter, RouteAction::RouteAdd)
+                .await;
+        }
+        Ok(())
     }
 
-    /// 处理 publish 请求
+    /// 客户端连接到本节点，但会话在 owner 节点上
+    /// 1. 通知 owner 踢掉旧连接，获取会话的订阅信息（owner 上的会话保留，离线期间的消息继续保存）
+    /// 2. 本节点创建会话，更新路由表，回复 connack
+    /// 3. 通知 owner 删除会话，获取会话中未完成的消息
     ///
-    /// QoS0：发送端 和 接受端 均不保存数据
-    /// QoS1：发送端 保存数据，接受端 不保存
-    /// QoS2：发送端 和 接受端 均保存数据
-    async fn handle_publish(&mut self, client_id: &str, publish: Publish) -> Result<(), Error> {
-        let Publish {
-            retain,
-            packet_id,
-            qos,
-            ..
-        } = publish;
-
-        // 保留消息，router 保存一份
-        if retain {
-            self.retains.push(publish.clone());
+    /// 在第 2 步完成之前，发给客户端的消息都保存在 owner 的会话中，迁移过程中不会丢失消息
+    async fn takeover_session(
+        &mut self,
+        owner: NodeId,
+        connect: Connect,
+        conn_tx: ConnTx,
+    ) -> Result<(), Error> {
+        if connect.clean_session {
+            // 不需要旧会话中的数据，通知 owner 删除会话即可
+            drop(self.dispatcher.release_session(owner, &connect.client_id));
+            return self.connect_session(connect, conn_tx).await;
         }
 
-        // 回复 publisher
-        match qos {
-            QoS::AtMostOnce => {
-                // 给订阅端发送消息
-                self.publish_message(&publish).await?
-            }
-            QoS::AtLeastOnce => {
-                if let Some(session) = self.sessions.get_mut(client_id) {
-                    // broker 是接收端，不需要保存消息，直接发送 puback
-                    session
-                        .send_packet(Packet::PubAck(PubAck { packet_id }))
-                        .await?;
-                    // 给订阅端发送消息
-                    self.publish_message(&publish).await?
-                }
+        let response = self.dispatcher.takeover_session(owner, &connect.client_id);
+        let cluster_tx = self.cluster_tx.clone();
+        tokio::spawn(async move {
+            let response = response.await.ok().and_then(Result::ok);
+            let event = ClusterEvent::SessionTakenOver {
+                owner,
+                connect,
+                conn_tx,
+                response,
+            };
+            if let Err(e) = cluster_tx.send(event).await {
+                error!("send cluster event to router error: {:#}", e);
             }
-            QoS::ExactlyOnce => {
-                if let Some(session) = self.sessions.get_mut(client_id) {
-                    // 保存起来，下次接收到 pubrel 消息时删除
-                    session.insert_received(packet_id);
-                    // 发送 pubrec
-                    session
-                        .send_packet(Packet::PubRec(PubRec { packet_id }))
-                        .await?;
+        });
+        Ok(())
+    }
+
+    /// 迁移会话第 2 步：本节点创建会话，更新路由表
+    async fn handle_session_taken_over(
+        &mut self,
+        owner: NodeId,
+        connect: Connect,
+        conn_tx: ConnTx,
+        response: Option<TakeoverSessionResponse>,
+    ) -> Result<(), Error> {
+        let client_id = connect.client_id.clone();
+        let taken = match response {
+            Some(response) if response.found => response.subscriptions,
+            // owner 上没有会话，或 owner 不可用，直接创建新会话
+            _ => return self.connect_session(connect, conn_tx).await,
+        };
+
+        let mut session = Session::offline(&client_id);
+        let mut added = Vec::new();
+        {
+            let mut subscriptions = self.state.subscriptions.write();
+            for subscription in taken {
+                let (filter, options) = match cluster::subscribed(subscription) {
+                    Ok(subscribed) => subscribed,
+                    Err(e) => {
+                        warn!(
+                            "client {0} taken over subscription error: {1:#}",
+                            client_id, e
+                        );
+                        continue;
+                    }
+                };
+                if subscriptions.add(&mut session, &filter, options) {
+                    added.push(filter);
                 }
             }
         }
-
+        self.state
+            .sessions
+            .shard(&client_id)
+            .insert(client_id.clone(), session);
+        for filter in added {
+            self.handle_local_route(&filter, RouteAction::RouteAdd)
+                .await;
+        }
+        self.connect_session(connect, conn_tx).await?;
+
+        let response = self.dispatcher.release_session(owner, &client_id);
+        let cluster_tx = self.cluster_tx.clone();
+        tokio::spawn(async move {
+            let response = response.await.ok().and_then(Result::ok);
+            let event = ClusterEvent::SessionReleased {
+                client_id,
+                response,
+            };
+            if let Err(e) = cluster_tx.send(event).await {
+                error!("send cluster event to router error: {:#}", e);
+            }
+        });
         Ok(())
     }
 
-    /// 给所有符合条件的客户端发送消息
-    async fn publish_message(&mut self, publish: &Publish) -> Result<(), Error> {
-        let Publish { topic, .. } = publish;
-        let mut clients = HashSet::new();
-        // 精确匹配
-        if let Some(client_ids) = self.concrete_subscriptions.get(topic) {
-            for client_id in client_ids {
-                clients.insert(client_id);
-            }
-        }
-        // 模糊匹配
-        clients.extend(self.wild_subscriptions.matches(topic));
+    /// 迁移会话第 3 步：恢复会话中未完成的消息
+    async fn handle_session_released(
+        &mut self,
+        client_id: String,
+        response: Option<ReleaseSessionResponse>,
+    ) -> Result<(), Error> {
+        let state = match response {
+            Some(response) if response.found => match cluster::released_state(response) {
+                Ok(state) => state,
+                Err(e) => {
+                    error!("restore session {0} state error: {1:#}", client_id, e);
+                    return Ok(());
+                }
+            },
+            _ => return Ok(()),
+        };
 
-        // 发送
-        for client_id in clients {
-            if let Some(session) = self.sessions.get_mut(client_id) {
-                session.publish_message(publish).await?;
+        if let Some(session) = self.state.sessions.shard(&client_id).get_mut(&client_id) {
+            session.restore_state(state);
+            if session.conn_tx.is_some() {
+                session.resend_packets()?;
             }
         }
-
         Ok(())
     }
 
-    /// 处理 puback
-    fn handle_publish_ack(&mut self, client_id: &str, puback: PubAck) {
-        if let Some(session) = self.sessions.get_mut(client_id) {
-            session.remove_published(puback.packet_id);
-        }
+    /// 客户端连接到了 node_id 节点，踢掉本节点上的连接
+    /// 会话保留到迁移完成，期间收到的消息保存在会话中
+    fn handle_takeover_session(
+        &mut self,
+        client_id: String,
+        node_id: NodeId,
+        reply_tx: oneshot::Sender<TakeoverSessionResponse>,
+    ) {
+        let response = match self.state.sessions.shard(&client_id).get_mut(&client_id) {
+            Some(session) => {
+                if let Some(conn_tx) = session.conn_tx.take() {
+                    conn_tx.disconnect();
+                }
+                // 迁移没有完成时，会话按过期时间清理
+                self.ineffective_sessions.retain(|(c, _)| c != &client_id);
+                self.ineffective_sessions
+                    .push_back((client_id.clone(), time::Instant::now()));
+                TakeoverSessionResponse {
+                    found: true,
+                    subscriptions: session
+                        .subscriptions()
+                        .into_iter()
+                        .map(|(filter, options)| cluster::subscription(filter, options))
+                        .collect(),
+                }
+            }
+            None => TakeoverSessionResponse::default(),
+        };
+        self.state
+            .storage
+            .write()
+            .set_session_owner(&client_id, node_id);
+        let _ = reply_tx.send(response);
     }
 
-    /// 处理 pubrel
-    async fn handle_publish_release(
+    /// 会话已迁移到 node_id 节点，删除本节点上的会话，交出未完成的消息
+    async fn handle_release_session(
         &mut self,
-        client_id: &str,
-        pubrel: PubRel,
-    ) -> Result<(), Error> {
-        if let Some(session) = self.sessions.get_mut(client_id) {
-            session.publish_release(pubrel).await?;
-        }
+        client_id: String,
+        node_id: NodeId,
+        reply_tx: oneshot::Sender<ReleaseSessionResponse>,
+    ) {
+        self.ineffective_sessions.retain(|(c, _)| c != &client_id);
+        let session = self.state.sessions.shard(&client_id).remove(&client_id);
+        let response = match session {
+            Some(mut session) => {
+                if let Some(conn_tx) = session.conn_tx.take() {
+                    conn_tx.disconnect();
+                }
+                self.clear_subscriptions(&session).await;
+                cluster::release_response(self.dispatcher.node_id(), session.take_state())
+            }
+            None => ReleaseSessionResponse::default(),
+        };
+        self.state
+            .storage
+            .write()
+            .set_session_owner(&client_id, node_id);
+        let _ = reply_tx.send(response);
+    }
 
-        Ok(())
+    /// 关闭 broker
+    /// * 会话只保存在内存中，断开前把暂停发送的消息尽量交给连接，由连接写完缓冲区后断开
+    /// * 删除本节点的会话和路由，通知其它节点不再转发
+    /// * router 退出后，集群管理器随之撤销本节点在 etcd 中的注册
+    async fn shutdown(&mut self) {
+        let sessions = self.state.sessions.take_all();
+        info!("router shutting down, close {} sessions", sessions.len());
+        let node_id = self.dispatcher.node_id();
+        for mut session in sessions {
+            if let Err(e) = session.resume() {
+                warn!("flush session {0} error: {1:#}", session.client_id, e);
+            }
+            if let Some(conn_tx) = session.conn_tx.take() {
+                conn_tx.shutdown();
+            }
+            self.clear_subscriptions(&session).await;
+            self.state
+                .storage
+                .write()
+                .remove_session_owner(&session.client_id, node_id);
+            self.dispatcher
+                .broadcast_session(&session.client_id, true, None);
+        }
+        self.ineffective_sessions.clear();
     }
 
-    /// 处理 pubrec
-    async fn handle_publish_receive(
-        &mut self,
-        client_id: &str,
-        pubrec: PubRec,
-    ) -> Result<(), Error> {
-        if let Some(session) = self.sessions.get_mut(client_id) {
-            session.publish_receive(pubrec).await?;
+    /// 删除 session 时，清理其在全局订阅和路由表中的记录
+    async fn clear_subscriptions(&mut self, session: &Session) {
+        self.state.subscriptions.write().clear(session);
+        for filter in session.filters() {
+            self.handle_local_route(&filter, RouteAction::RouteDelete)
+                .await;
         }
+    }
 
-        Ok(())
+    /// 本节点的订阅变更，只有在第一个订阅加入/最后一个订阅移除时，才需要同步给其它节点
+    async fn handle_local_route(&mut self, filter: &str, action: RouteAction) {
+        let changed = {
+            let mut storage = self.state.storage.write();
+            match action {
+                RouteAction::RouteAdd => storage.add_local_route(filter),
+                RouteAction::RouteDelete => storage.remove_local_route(filter),
+            }
+        };
+        if changed {
+            self.dispatcher.broadcast_route(filter, action);
+        }
     }
 
-    /// 处理 pubcomp
-    fn handle_publish_complete(&mut self, client_id: &str, pubcomp: PubComp) {
-        if let Some(session) = self.sessions.get_mut(client_id) {
-            session.publish_complete(pubcomp);
+    /// 将 publish 消息转发给订阅了它的其它节点
+    async fn forward_remote(&mut self, nodes: HashSet<NodeId>, publish: &Publish) {
+        let request = cluster::forward_request(self.dispatcher.node_id(), publish);
+        for node_id in nodes {
+            self.dispatcher.forward_publish(node_id, request.clone());
         }
     }
 
-    /// 处理客户端断开连接事件
-    /// 不发送 will 消息
-    async fn handle_client_disconnect(&mut self, client_id: &str) -> Result<(), Error> {
-        if let Some(session) = self.sessions.get_mut(client_id) {
-            // 向 conn 返回断开连接确认消息
-            if let Some(conn_tx) = session.conn_tx.take() {
-                conn_tx.send(Outgoing::Disconnect).await?
+    /// 发布本节点的 $SYS 主题，订阅了的对等节点同样转发
+    async fn publish_sys(&mut self) {
+        for publish in self.sys_topics.messages(&self.state) {
+            self.state.publish_local(&publish, None);
+            let nodes = self.state.storage.read().remote_nodes(&publish.topic);
+            if !nodes.is_empty() {
+                self.forward_remote(nodes, &publish).await;
             }
-
-            // 放到会话失效列表
-            self.ineffective_sessions
-                .push_back((session.client_id.clone(), time::Instant::now()));
         }
+    }
 
-        Ok(())
+    /// 处理其它节点转发过来的 publish 消息，只发送给本节点的客户端
+    fn handle_forward_publish(&mut self, _origin_node_id: NodeId, publish: Publish) {
+        self.state.publish_local(&publish, None);
     }
 
     /// 处理客户端的异常退出，发送 will 消息
@@ -417,8 +843,18 @@ impl<H: Hook> Router<H> {
     /// 如：
     /// * 协议格式错误
     /// * 网络错误
-    async fn handle_conn_disconnect(&mut self, client_id: &str) -> Result<(), Error> {
-        if let Some(session) = self.sessions.get_mut(client_id) {
+    async fn handle_conn_disconnect(
+        &mut self,
+        client_id: &str,
+        outbound: &Arc<Outbound>,
+    ) -> Result<(), Error> {
+        if let Some(session) = self.state.sessions.shard(client_id).get_mut(client_id) {
+            // 旧连接断开时，会话可能已经交给了新连接
+            if matches!(&session.conn_tx, Some(conn_tx) if !conn_tx.is_conn(outbound)) {
+                return Ok(());
+            }
+            // 连接已断开，之后的消息保存在会话中
+            session.conn_tx = None;
             self.ineffective_sessions
                 .push_back((session.client_id.clone(), time::Instant::now()));
         }
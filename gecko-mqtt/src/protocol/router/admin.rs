@@ -0,0 +1,442 @@
+//! 管理接口的请求
+//! grpc 服务把请求交给 router，router 处理后通过 oneshot 回复
+//! 只查询和操作本节点上的会话，保留消息、发布的消息和禁止规则同步到整个集群
+
+use std::time::{self, UNIX_EPOCH};
+
+use gecko_mqtt_proto::{
+    admin::{
+        AddBanRequest, AddBanResponse, ClientInfo, DeleteBanRequest, DeleteBanResponse,
+        DeleteRetainedRequest, DeleteRetainedResponse, GetMetricsResponse, GetSessionRequest,
+        GetSessionResponse, JoinNodeRequest, JoinNodeResponse, KickClientRequest,
+        KickClientResponse, LeaveNodeRequest, LeaveNodeResponse, ListBansResponse,
+        ListClientsRequest, ListClientsResponse, ListNodesResponse, ListRetainedRequest,
+        ListRetainedResponse, NodeInfo, PublishResponse, RetainedMessage, SubscribeRequest,
+        SubscribeResponse, UnsubscribeRequest, UnsubscribeResponse,
+    },
+    RouteAction,
+};
+use log::{error, info};
+use tokio::sync::oneshot;
+
+use crate::{
+    ban::{self, Ban},
+    cluster::NodeStatus,
+    network::{
+        packet::{Protocol, QoS},
+        v4::Publish,
+    },
+    protocol::subscripton::SubscribeOptions,
+    Hook,
+};
+
+use super::{session::Session, Error, Router};
+
+/// 管理接口的请求，参数已由 grpc 服务校验
+#[derive(Debug)]
+pub enum AdminRequest {
+    ListClients(ListClientsRequest, oneshot::Sender<ListClientsResponse>),
+    GetSession(GetSessionRequest, oneshot::Sender<GetSessionResponse>),
+    KickClient(KickClientRequest, oneshot::Sender<KickClientResponse>),
+    Subscribe(SubscribeRequest, oneshot::Sender<SubscribeResponse>),
+    Unsubscribe(UnsubscribeRequest, oneshot::Sender<UnsubscribeResponse>),
+    ListRetained(ListRetainedRequest, oneshot::Sender<ListRetainedResponse>),
+    DeleteRetained(
+        DeleteRetainedRequest,
+        oneshot::Sender<DeleteRetainedResponse>,
+    ),
+    Publish(Publish, oneshot::Sender<PublishResponse>),
+    ListNodes(oneshot::Sender<ListNodesResponse>),
+    JoinNode(JoinNodeRequest, oneshot::Sender<JoinNodeResponse>),
+    LeaveNode(LeaveNodeRequest, oneshot::Sender<LeaveNodeResponse>),
+    GetMetrics(oneshot::Sender<GetMetricsResponse>),
+    ListBans(oneshot::Sender<ListBansResponse>),
+    AddBan(AddBanRequest, oneshot::Sender<AddBanResponse>),
+    DeleteBan(DeleteBanRequest, oneshot::Sender<DeleteBanResponse>),
+}
+
+impl<H: Hook> Router<H> {
+    /// 处理管理接口的请求，调用方已断开时丢弃结果
+    pub(super) async fn handle_admin(&mut self, request: AdminRequest) -> Result<(), Error> {
+        match request {
+            AdminRequest::ListClients(request, reply_tx) => {
+                let _ = reply_tx.send(self.list_clients(request));
+            }
+            AdminRequest::GetSession(request, reply_tx) => {
+                let response = match self
+                    .state
+                    .sessions
+                    .shard(&request.client_id)
+                    .get(&request.client_id)
+                {
+                    Some(session) => {
+                        let mut filters = session.filters();
+                        filters.sort();
+                        GetSessionResponse {
+                            found: true,
+                            client: Some(client_info(session)),
+                            filters,
+                        }
+                    }
+                    None => GetSessionResponse::default(),
+                };
+                let _ = reply_tx.send(response);
+            }
+            AdminRequest::KickClient(request, reply_tx) => {
+                let found = self.kick_client(&request.client_id);
+                let _ = reply_tx.send(KickClientResponse { found });
+            }
+            AdminRequest::Subscribe(request, reply_tx) => {
+                let found = self.admin_subscribe(request).await?;
+                let _ = reply_tx.send(SubscribeResponse { found });
+            }
+            AdminRequest::Unsubscribe(request, reply_tx) => {
+                let found = self.admin_unsubscribe(request).await;
+                let _ = reply_tx.send(UnsubscribeResponse { found });
+            }
+            AdminRequest::ListRetained(request, reply_tx) => {
+                let _ = reply_tx.send(self.list_retained(request));
+            }
+            AdminRequest::DeleteRetained(request, reply_tx) => {
+                let found = self.delete_retained(request.topic).await;
+                let _ = reply_tx.send(DeleteRetainedResponse { found });
+            }
+            AdminRequest::Publish(publish, reply_tx) => {
+                self.admin_publish(publish).await;
+                let _ = reply_tx.send(PublishResponse {});
+            }
+            AdminRequest::ListNodes(reply_tx) => {
+                let _ = reply_tx.send(self.list_nodes());
+            }
+            // 心跳成功后，和配置的对等节点一样同步路由和会话
+            AdminRequest::JoinNode(request, reply_tx) => {
+                self.dispatcher.add_peer(request.node_id, request.addr);
+                let _ = reply_tx.send(JoinNodeResponse {});
+            }
+            AdminRequest::LeaveNode(request, reply_tx) => {
+                let found = self.dispatcher.peer_ids().contains(&request.node_id);
+                if found {
+                    self.handle_node_status(request.node_id, NodeStatus::Down)
+                        .await;
+                    self.dispatcher.remove_peer(request.node_id);
+                }
+                let _ = reply_tx.send(LeaveNodeResponse { found });
+            }
+            AdminRequest::GetMetrics(reply_tx) => {
+                let _ = reply_tx.send(self.metrics());
+            }
+            AdminRequest::ListBans(reply_tx) => {
+                let bans = self.bans.list().iter().map(ban::to_proto).collect();
+                let _ = reply_tx.send(ListBansResponse { bans });
+            }
+            AdminRequest::AddBan(request, reply_tx) => {
+                if let Some(added) = request.ban {
+                    let node_id = self.dispatcher.node_id();
+                    match self.bans.insert(ban::from_proto(added), node_id) {
+                        Ok(added) => {
+                            self.bans_changed(true).await;
+                            let added = vec![ban::to_proto(&added)];
+                            self.dispatcher.broadcast_ban(added, Vec::new());
+                        }
+                        Err(e) => error!("add ban error: {:#}", e),
+                    }
+                }
+                let _ = reply_tx.send(AddBanResponse {});
+            }
+            AdminRequest::DeleteBan(request, reply_tx) => {
+                let kind = ban::kind_from_proto(request.kind());
+                let node_id = self.dispatcher.node_id();
+                let removed = self.bans.remove(kind, &request.value, node_id);
+                let found = removed.is_some();
+                if let Some(removed) = removed {
+                    self.bans_changed(false).await;
+                    let removed = vec![ban::to_proto(&removed)];
+                    self.dispatcher.broadcast_ban(Vec::new(), removed);
+                }
+                let _ = reply_tx.send(DeleteBanResponse { found });
+            }
+        }
+        Ok(())
+    }
+
+    /// 按 client id 排序，search 为空时不过滤
+    fn list_clients(&self, request: ListClientsRequest) -> ListClientsResponse {
+        let mut clients = Vec::new();
+        self.state.sessions.for_each(|session| {
+            if request.connected_only && session.conn_tx.is_none() {
+                return;
+            }
+            if session.client_id.contains(&request.search) {
+                clients.push(client_info(session));
+            }
+        });
+        clients.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        if request.limit > 0 {
+            clients.truncate(request.limit as usize);
+        }
+        ListClientsResponse { clients }
+    }
+
+    /// 断开客户端连接，和客户端异常断开一样，会话按过期时间清理
+    fn kick_client(&mut self, client_id: &str) -> bool {
+        let conn_tx = match self.state.sessions.shard(client_id).get_mut(client_id) {
+            Some(session) => session.conn_tx.take(),
+            None => None,
+        };
+        let Some(conn_tx) = conn_tx else {
+            return false;
+        };
+        conn_tx.disconnect();
+        self.ineffective_sessions
+            .push_back((client_id.to_string(), time::Instant::now()));
+        true
+    }
+
+    /// 合并对等节点同步的禁止规则和删除标记，只有比本地新的生效
+    pub(super) async fn merge_bans(&mut self, added: Vec<Ban>, removed: Vec<Ban>) {
+        let mut kick = false;
+        let mut changed = false;
+        for (ban, deleted) in added
+            .into_iter()
+            .map(|ban| (ban, false))
+            .chain(removed.into_iter().map(|ban| (ban, true)))
+        {
+            match self.bans.merge(ban, deleted) {
+                Ok(merged) => {
+                    kick |= merged && !deleted;
+                    changed |= merged;
+                }
+                Err(e) => error!("merge ban error: {:#}", e),
+            }
+        }
+        if changed {
+            self.bans_changed(kick).await;
+        }
+    }
+
+    /// 禁止规则变化后断开已连接的被禁止的客户端，写入持久化文件
+    async fn bans_changed(&mut self, kick: bool) {
+        if kick {
+            let mut banned = Vec::new();
+            self.state.sessions.for_each(|session| {
+                let Some(conn) = session
+                    .conn_tx
+                    .as_ref()
+                    .and_then(|conn_tx| conn_tx.info.as_ref())
+                else {
+                    return;
+                };
+                let username = conn.username.as_deref();
+                if self
+                    .bans
+                    .banned(&session.client_id, username, ban::addr_ip(&conn.addr))
+                {
+                    banned.push(session.client_id.clone());
+                }
+            });
+            for client_id in banned {
+                info!("kick banned client {}", client_id);
+                self.kick_client(&client_id);
+            }
+        }
+        if let Some(path) = &self.ban_file {
+            if let Err(e) = self.bans.save(path).await {
+                error!("save ban file error: {:#}", e);
+            }
+        }
+    }
+
+    /// 和客户端订阅一样，订阅后发送匹配的保留消息，授予 QoS2
+    async fn admin_subscribe(&mut self, request: SubscribeRequest) -> Result<bool, Error> {
+        let client_id = &request.client_id;
+        let mut added = Vec::new();
+        {
+            let mut sessions = self.state.sessions.shard(client_id);
+            let Some(session) = sessions.get_mut(client_id) else {
+                return Ok(false);
+            };
+            {
+                let mut subscriptions = self.state.subscriptions.write();
+                let options = SubscribeOptions::new(QoS::ExactlyOnce);
+                for filter in request.filters.iter() {
+                    if subscriptions.add(session, filter, options) {
+                        added.push(filter.clone());
+                    }
+                }
+            }
+            let retains = self.state.retains.read();
+            for filter in request.filters.iter() {
+                for publish in retains.matches(filter) {
+                    session.publish_message(publish, &*self.state.hook, &self.state.metrics)?;
+                }
+            }
+        }
+        for filter in added {
+            self.handle_local_route(&filter, RouteAction::RouteAdd)
+                .await;
+        }
+        Ok(true)
+    }
+
+    async fn admin_unsubscribe(&mut self, request: UnsubscribeRequest) -> bool {
+        let client_id = &request.client_id;
+        let mut removed = Vec::new();
+        {
+            let mut sessions = self.state.sessions.shard(client_id);
+            let Some(session) = sessions.get_mut(client_id) else {
+                return false;
+            };
+            let mut subscriptions = self.state.subscriptions.write();
+            for filter in request.filters {
+                if subscriptions.remove(session, &filter) {
+                    removed.push(filter);
+                }
+            }
+        }
+        for filter in removed {
+            self.handle_local_route(&filter, RouteAction::RouteDelete)
+                .await;
+        }
+        true
+    }
+
+    /// 按 topic 排序，filter 为空时返回所有的保留消息
+    fn list_retained(&self, request: ListRetainedRequest) -> ListRetainedResponse {
+        let filter = match request.filter.as_str() {
+            "" => "#",
+            filter => filter,
+        };
+        let mut messages = self
+            .state
+            .retains
+            .read()
+            .matches(filter)
+            .into_iter()
+            .map(|publish| RetainedMessage {
+                topic: publish.topic.to_string(),
+                payload: publish.payload.to_vec(),
+                qos: publish.qos as u32,
+            })
+            .collect::<Vec<_>>();
+        messages.sort_by(|a, b| a.topic.cmp(&b.topic));
+        if request.limit > 0 {
+            messages.truncate(request.limit as usize);
+        }
+        ListRetainedResponse { messages }
+    }
+
+    /// 写入 payload 为空的保留消息，同步给对等节点
+    async fn delete_retained(&mut self, topic: String) -> bool {
+        let message = {
+            let mut retains = self.state.retains.write();
+            if retains.matches(&topic).is_empty() {
+                return false;
+            }
+            retains.insert(&Publish {
+                dup: false,
+                qos: QoS::AtMostOnce,
+                retain: true,
+                topic: topic.into(),
+                packet_id: 0,
+                payload: Default::default(),
+                properties: None,
+                encoded: Default::default(),
+            })
+        };
+        self.dispatcher.broadcast_retain(message);
+        true
+    }
+
+    /// 和客户端发布的消息一样，投递给本节点的订阅者，转发给订阅了的对等节点
+    async fn admin_publish(&mut self, publish: Publish) {
+        if publish.retain {
+            let message = self.state.retains.write().insert(&publish);
+            self.dispatcher.broadcast_retain(message);
+        }
+        self.state.publish_local(&publish, None);
+        let nodes = self.state.storage.read().remote_nodes(&publish.topic);
+        if !nodes.is_empty() {
+            self.forward_remote(nodes, &publish).await;
+        }
+    }
+
+    /// 本节点和所有对等节点，按 node id 排序
+    fn list_nodes(&self) -> ListNodesResponse {
+        let node_id = self.dispatcher.node_id();
+        let storage = self.state.storage.read();
+        let mut nodes = vec![NodeInfo {
+            node_id,
+            addr: String::new(),
+            status: "up".into(),
+            local: true,
+        }];
+        for (peer_id, addr) in self.dispatcher.peers() {
+            let status = match storage.node_status(peer_id) {
+                Some(NodeStatus::Up) => "up",
+                Some(NodeStatus::Suspect) => "suspect",
+                Some(NodeStatus::Down) => "down",
+                None => "unknown",
+            };
+            nodes.push(NodeInfo {
+                node_id: peer_id,
+                addr,
+                status: status.into(),
+                local: false,
+            });
+        }
+        nodes.sort_by_key(|node| node.node_id);
+        ListNodesResponse { nodes }
+    }
+
+    /// 本节点的会话和消息统计
+    fn metrics(&self) -> GetMetricsResponse {
+        let sessions = self.state.sessions.stats();
+        let metrics = self.state.metrics.snapshot();
+        GetMetricsResponse {
+            sessions_connected: sessions.connected as u64,
+            sessions_disconnected: sessions.disconnected as u64,
+            subscriptions: sessions.subscriptions as u64,
+            retained: self.state.retains.read().len() as u64,
+            inflight: sessions.inflight as u64,
+            queued: sessions.queued as u64,
+            messages_received: metrics.messages_received,
+            messages_sent: metrics.messages_sent,
+            messages_dropped: metrics.messages_dropped,
+            bytes_received: metrics.bytes_received,
+            bytes_sent: metrics.bytes_sent,
+        }
+    }
+}
+
+/// 会话的信息，离线会话没有连接信息
+fn client_info(session: &Session) -> ClientInfo {
+    let mut info = ClientInfo {
+        client_id: session.client_id.clone(),
+        connected: session.conn_tx.is_some(),
+        clean_session: session.clean_session(),
+        subscriptions: (session.concrete_subscriptions.len() + session.wildcard_subscriptions.len())
+            as u32,
+        inflight: session.inflight() as u32,
+        queued: session.queued() as u32,
+        ..Default::default()
+    };
+    if let Some(conn) = session
+        .conn_tx
+        .as_ref()
+        .and_then(|conn_tx| conn_tx.info.as_ref())
+    {
+        info.addr = conn.addr.clone();
+        info.username = conn.username.clone().unwrap_or_default();
+        info.protocol = match conn.protocol {
+            Protocol::V3 => 3,
+            Protocol::V4 => 4,
+            Protocol::V5 => 5,
+        };
+        info.keep_alive = conn.keep_alive as u32;
+        info.connected_at = conn
+            .connected_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+    }
+    info
+}
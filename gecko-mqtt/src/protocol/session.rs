@@ -1,16 +1,24 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
 
 use packet::v4::{Packet, PubComp, PubRec, PubRel, Publish};
-use tokio::sync::mpsc::{error::SendError, Sender};
 
-use crate::network::packet::{self, QoS};
+use crate::{
+    config::SlowConsumer,
+    metrics::{DropReason, Metrics},
+    network::{
+        packet::{self, QoS},
+        ConnTx,
+    },
+    Hook,
+};
 
-use super::Outgoing;
+use super::{subscripton::SubscribeOptions, Outgoing};
+
+/// 客户端离线或暂停发送期间最多保存的消息数量
+const MAX_QUEUED_MESSAGES: usize = 1000;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("Failed to send outgoing message: {0}")]
-    SendOutgoing(#[from] SendError<Outgoing>),
     #[error("Session conn tx not found")]
     SessionConnTxNotFound,
 }
@@ -26,9 +34,10 @@ pub struct Session {
     clean_session: bool,
 
     /// 订阅的主题（精确匹配，不可以重复订阅）
-    /// key = topic-filter, value = token
-    pub concrete_subscriptions: HashSet<String>,
-    pub wildcard_subscriptions: HashMap<String, u64>,
+    /// key = topic-filter, value = 订阅选项
+    pub concrete_subscriptions: HashMap<String, SubscribeOptions>,
+    /// key = topic-filter, value = (订阅树中的 token, 订阅选项)
+    pub wildcard_subscriptions: HashMap<String, (u64, SubscribeOptions)>,
 
     /// 保存发送给客户端但是还没有删除的消息（QoS1, QoS2）(持久化)
     /// 接收到 puback/pubcomp 后删除
@@ -37,26 +46,48 @@ pub struct Session {
     messages_receive: HashSet<u16>,
     /// 在收到 qos2 pubrec 的消息时保存，在收到 qos2 pubcomp 的消息后删除
     messages_release: HashSet<u16>,
+    /// 客户端离线或消费太慢期间收到的消息，重新连接或连接恢复后发送
+    messages_queued: VecDeque<Publish>,
 
     /// 发送给客户端的消息
-    pub conn_tx: Option<Sender<Outgoing>>,
+    pub conn_tx: Option<ConnTx>,
+    /// 下一个发送给客户端的 publish 消息的 packet id
+    next_packet_id: u16,
 }
 
 impl Session {
-    pub fn new(client_id: &str, clean_session: bool, conn_tx: Sender<Outgoing>) -> Self {
+    pub fn new(client_id: &str, clean_session: bool, conn_tx: ConnTx) -> Self {
         Self {
             client_id: client_id.into(),
             clean_session,
-            concrete_subscriptions: HashSet::new(),
+            concrete_subscriptions: HashMap::new(),
             wildcard_subscriptions: HashMap::new(),
             messages_publish: HashMap::new(),
             messages_receive: HashSet::new(),
             messages_release: HashSet::new(),
+            messages_queued: VecDeque::new(),
             conn_tx: Some(conn_tx),
+            next_packet_id: 1,
+        }
+    }
+
+    /// 从其它节点迁移过来，还没有连接的会话
+    pub fn offline(client_id: &str) -> Self {
+        Self {
+            client_id: client_id.into(),
+            clean_session: false,
+            concrete_subscriptions: HashMap::new(),
+            wildcard_subscriptions: HashMap::new(),
+            messages_publish: HashMap::new(),
+            messages_receive: HashSet::new(),
+            messages_release: HashSet::new(),
+            messages_queued: VecDeque::new(),
+            conn_tx: None,
+            next_packet_id: 1,
         }
     }
 
-    pub fn into_new(self, clean_session: bool, conn_tx: Sender<Outgoing>) -> Self {
+    pub fn into_new(self, clean_session: bool, conn_tx: ConnTx) -> Self {
         Self {
             client_id: self.client_id,
             clean_session,
@@ -65,30 +96,34 @@ impl Session {
             messages_publish: self.messages_publish,
             messages_receive: self.messages_receive,
             messages_release: self.messages_release,
+            messages_queued: self.messages_queued,
             conn_tx: Some(conn_tx),
+            next_packet_id: self.next_packet_id,
         }
     }
 
-    /// 给客户端发送消息
-    pub async fn send_packet(&self, packet: Packet) -> Result<(), Error> {
+    /// 给客户端发送消息，不等待连接处理
+    pub fn send_packet(&self, packet: Packet) -> Result<(), Error> {
         if let Some(ref sender) = self.conn_tx {
-            Ok(sender.send(Outgoing::Packet(packet)).await?)
+            sender.try_send(Outgoing::Packet(packet));
+            Ok(())
         } else {
             Err(Error::SessionConnTxNotFound)
         }
     }
 
     /// 给客户端发送批量消息
-    pub async fn send_packets(&self, packets: Vec<Packet>) -> Result<(), Error> {
+    pub fn send_packets(&self, packets: Vec<Packet>) -> Result<(), Error> {
         if let Some(ref sender) = self.conn_tx {
-            Ok(sender.send(Outgoing::Packets(packets)).await?)
+            sender.try_send(Outgoing::Packets(packets));
+            Ok(())
         } else {
             Err(Error::SessionConnTxNotFound)
         }
     }
 
     /// 给客户端重新发送积压的消息
-    pub async fn resend_packets(&mut self) -> Result<(), Error> {
+    pub fn resend_packets(&mut self) -> Result<(), Error> {
         if !self.messages_publish.is_empty() {
             let messages = self
                 .messages_publish
@@ -100,7 +135,7 @@ impl Session {
                 })
                 .collect();
 
-            self.send_packets(messages).await?;
+            self.send_packets(messages)?;
         }
 
         if !self.messages_release.is_empty() {
@@ -110,23 +145,125 @@ impl Session {
                 .cloned()
                 .map(|packet_id| Packet::PubRel(PubRel { packet_id }))
                 .collect();
-            self.send_packets(messages).await?;
+            self.send_packets(messages)?;
         }
 
+        self.resume()
+    }
+
+    /// 发送暂停期间积压的消息，直到连接的队列再次满
+    pub fn resume(&mut self) -> Result<(), Error> {
+        let Some(conn_tx) = self.conn_tx.clone() else {
+            return Ok(());
+        };
+        while conn_tx.has_capacity() {
+            match self.messages_queued.pop_front() {
+                Some(publish) => self.send_message(publish)?,
+                None => return Ok(()),
+            }
+        }
+        if !self.messages_queued.is_empty() {
+            conn_tx.outbound.pause();
+        }
         Ok(())
     }
 
-    ///
-    pub fn insert_received(&mut self, packet_id: u16) {
-        self.messages_receive.insert(packet_id);
+    pub fn clean_session(&self) -> bool {
+        self.clean_session
+    }
+
+    /// 已发送给客户端，等待 puback/pubcomp 的消息数
+    pub fn inflight(&self) -> usize {
+        self.messages_publish.len()
+    }
+
+    /// 会话中积压的消息数，加上连接队列中等待发送的消息数
+    pub fn queued(&self) -> usize {
+        let conn_queued = self.conn_tx.as_ref().map_or(0, ConnTx::queued);
+        self.messages_queued.len() + conn_queued
+    }
+
+    /// 会话订阅的所有 filter
+    pub fn filters(&self) -> Vec<String> {
+        self.concrete_subscriptions
+            .keys()
+            .chain(self.wildcard_subscriptions.keys())
+            .cloned()
+            .collect()
+    }
+
+    /// 会话订阅的所有 filter 和订阅选项，用于迁移到其它节点
+    pub fn subscriptions(&self) -> Vec<(String, SubscribeOptions)> {
+        let wildcard = self
+            .wildcard_subscriptions
+            .iter()
+            .map(|(filter, (_, options))| (filter, options));
+        self.concrete_subscriptions
+            .iter()
+            .chain(wildcard)
+            .map(|(filter, options)| (filter.clone(), *options))
+            .collect()
+    }
+
+    /// 取出会话中未完成的消息，用于迁移到其它节点
+    pub fn take_state(&mut self) -> SessionState {
+        SessionState {
+            inflight: self.messages_publish.drain().map(|(_, p)| p).collect(),
+            queued: self.messages_queued.drain(..).collect(),
+            received: self.messages_receive.drain().collect(),
+            released: self.messages_release.drain().collect(),
+            next_packet_id: self.next_packet_id,
+        }
+    }
+
+    /// 恢复从其它节点迁移过来的消息
+    pub fn restore_state(&mut self, state: SessionState) {
+        let SessionState {
+            inflight,
+            queued,
+            received,
+            released,
+            next_packet_id,
+        } = state;
+        for publish in inflight {
+            match self.messages_publish.entry(publish.packet_id) {
+                // packet id 已被迁移期间的新消息占用，重新分配后发送
+                Entry::Occupied(_) => self.messages_queued.push_front(publish),
+                Entry::Vacant(entry) => {
+                    entry.insert(publish);
+                }
+            }
+        }
+        self.messages_queued.extend(queued);
+        self.messages_receive.extend(received);
+        self.messages_release.extend(released);
+        self.next_packet_id = self.next_packet_id.max(next_packet_id);
+    }
+
+    /// 保存接收到的 qos2 消息 id
+    /// 返回是否是第一次收到此 packet id 的 QoS2 消息
+    pub fn insert_received(&mut self, packet_id: u16) -> bool {
+        self.messages_receive.insert(packet_id)
     }
 
     pub fn remove_received(&mut self, packet_id: u16) {
         self.messages_receive.insert(packet_id);
     }
 
-    pub fn remove_published(&mut self, packet_id: u16) {
-        self.messages_publish.remove(&packet_id);
+    /// 收到 puback，返回确认的消息
+    pub fn remove_published(&mut self, packet_id: u16) -> Option<Publish> {
+        self.messages_publish.remove(&packet_id)
+    }
+
+    /// 分配一个未被占用的 packet id（1..=65535）
+    fn next_packet_id(&mut self) -> u16 {
+        loop {
+            let packet_id = self.next_packet_id;
+            self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+            if !self.messages_publish.contains_key(&packet_id) {
+                return packet_id;
+            }
+        }
     }
 
     /// 匹配 publish 的 topic
@@ -134,57 +271,253 @@ impl Session {
     /// * qos0: publish
     /// * qos1: store, publish, puback
     /// * qos2: store, pubrec
-    pub async fn publish_message(&mut self, publish: &Publish) -> Result<(), Error> {
+    pub fn publish_message(
+        &mut self,
+        publish: &Publish,
+        hook: &dyn Hook,
+        metrics: &Metrics,
+    ) -> Result<(), Error> {
+        // 客户端离线，qos1/qos2 消息先保存起来，qos0 丢弃
+        let Some(conn_tx) = &self.conn_tx else {
+            let reason = if publish.qos == QoS::AtMostOnce {
+                DropReason::Offline
+            } else if self.messages_queued.len() < MAX_QUEUED_MESSAGES {
+                hook.on_deliver(&self.client_id, publish);
+                self.messages_queued.push_back(publish.clone());
+                return Ok(());
+            } else {
+                DropReason::QueueFull
+            };
+            metrics.dropped(reason);
+            hook.on_message_dropped(&self.client_id, publish, reason);
+            return Ok(());
+        };
+
+        // 连接的队列已满或还有积压的消息，按照策略处理
+        if !conn_tx.has_capacity() || !self.messages_queued.is_empty() {
+            let reason = match conn_tx.policy {
+                SlowConsumer::DropQos0 if publish.qos == QoS::AtMostOnce => {
+                    DropReason::SlowConsumer
+                }
+                SlowConsumer::Disconnect if !conn_tx.has_capacity() => {
+                    conn_tx.outbound.kick();
+                    DropReason::SlowConsumer
+                }
+                // 保持顺序，排在积压的消息后面
+                _ => {
+                    conn_tx.outbound.pause();
+                    if self.messages_queued.len() < MAX_QUEUED_MESSAGES {
+                        hook.on_deliver(&self.client_id, publish);
+                        self.messages_queued.push_back(publish.clone());
+                        return Ok(());
+                    }
+                    DropReason::QueueFull
+                }
+            };
+            conn_tx.outbound.drop_message(reason);
+            hook.on_message_dropped(&self.client_id, publish, reason);
+            return Ok(());
+        }
+
+        hook.on_deliver(&self.client_id, publish);
+        self.send_message(publish.clone())
+    }
+
+    /// 分配 packet id 后发送给客户端
+    fn send_message(&mut self, mut publish: Publish) -> Result<(), Error> {
+        // 发送给订阅端的消息使用本会话分配的 packet id
+        if publish.qos != QoS::AtMostOnce {
+            publish.packet_id = self.next_packet_id();
+        }
         let Publish { qos, packet_id, .. } = publish;
+        if let Some(conn_tx) = &self.conn_tx {
+            conn_tx.outbound.publish_sent(&publish);
+        }
 
         // 根据订阅的qos处理
         match qos {
             QoS::AtMostOnce => {
                 // 发送给订阅的客户端
-                self.send_packet(Packet::Publish(publish.clone())).await?;
+                self.send_packet(Packet::Publish(publish))?;
             }
-            QoS::AtLeastOnce => {
+            QoS::AtLeastOnce | QoS::ExactlyOnce => {
                 // 保存起来，等待接收到 puback/pubcomp 后删除
-                self.messages_publish
-                    .insert(packet_id.to_owned(), publish.clone());
+                self.messages_publish.insert(packet_id, publish.clone());
                 // 发送给订阅的客户端
-                self.send_packet(Packet::Publish(publish.clone())).await?;
-            }
-            QoS::ExactlyOnce => {
-                // 保存数据，收到 pubrel 后再发送
-                self.messages_publish
-                    .insert(packet_id.to_owned(), publish.clone());
+                self.send_packet(Packet::Publish(publish))?;
             }
         }
 
         Ok(())
     }
 
-    pub async fn publish_release(&mut self, pubrel: PubRel) -> Result<(), Error> {
+    pub fn publish_release(&mut self, pubrel: PubRel) -> Result<(), Error> {
         if self.messages_receive.remove(&pubrel.packet_id) {
             self.send_packet(Packet::PubComp(PubComp {
                 packet_id: pubrel.packet_id,
-            }))
-            .await?;
+            }))?;
         }
         Ok(())
     }
 
-    pub async fn publish_receive(&mut self, pubrec: PubRec) -> Result<(), Error> {
+    pub fn publish_receive(&mut self, pubrec: PubRec) -> Result<(), Error> {
         if self.messages_publish.contains_key(&pubrec.packet_id) {
             self.messages_release.insert(pubrec.packet_id);
             self.send_packet(Packet::PubRel(PubRel {
                 packet_id: pubrec.packet_id,
-            }))
-            .await?;
+            }))?;
         }
 
         Ok(())
     }
 
-    pub fn publish_complete(&mut self, pubcomp: PubComp) {
+    /// 收到 pubcomp，返回完成的消息
+    pub fn publish_complete(&mut self, pubcomp: PubComp) -> Option<Publish> {
         if self.messages_release.remove(&pubcomp.packet_id) {
-            self.messages_publish.remove(&pubcomp.packet_id);
+            return self.messages_publish.remove(&pubcomp.packet_id);
+        }
+        None
+    }
+}
+
+/// 会话中未完成的消息
+#[derive(Debug, Default)]
+pub struct SessionState {
+    /// 已发送给客户端，等待确认的消息
+    pub inflight: Vec<Publish>,
+    /// 客户端离线期间积压的消息
+    pub queued: Vec<Publish>,
+    /// 已收到 qos2 publish，等待 pubrel 的 packet id
+    pub received: Vec<u16>,
+    /// 已收到 pubrec，等待 pubcomp 的 packet id
+    pub released: Vec<u16>,
+    pub next_packet_id: u16,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use parking_lot::Mutex;
+
+    use crate::{hook::Hooks, network::outbound, AuthResult, Login};
+
+    use super::*;
+
+    /// 记录投递和丢弃的消息
+    #[derive(Default)]
+    struct Recorder {
+        delivered: Mutex<usize>,
+        dropped: Mutex<Vec<DropReason>>,
+    }
+
+    #[async_trait]
+    impl Hook for Recorder {
+        async fn authenticate(&self, _login: Login) -> AuthResult {
+            AuthResult::Allow
+        }
+        async fn connected(&self, _client_id: &str) {}
+        async fn disconnect(&self, _client_id: &str) {}
+        fn on_deliver(&self, _client_id: &str, _publish: &Publish) {
+            *self.delivered.lock() += 1;
+        }
+        fn on_message_dropped(&self, _client_id: &str, _publish: &Publish, reason: DropReason) {
+            self.dropped.lock().push(reason);
+        }
+    }
+
+    fn publish(qos: QoS) -> Publish {
+        Publish {
+            dup: false,
+            qos,
+            retain: false,
+            topic: "iot/pid/dn".into(),
+            packet_id: 0,
+            payload: Bytes::from("hello"),
+            properties: None,
+            encoded: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_consumer_drops_qos0_and_pauses() {
+        let (conn_tx, mut conn_rx, outbound) =
+            outbound::channel(SlowConsumer::DropQos0, Arc::default());
+        let mut session = Session::new("client", true, conn_tx.clone());
+        let hook = Hooks::default();
+
+        // 占满队列中 publish 可用的位置
+        let mut sent = 0;
+        while conn_tx.has_capacity() {
+            session
+                .publish_message(&publish(QoS::AtMostOnce), &hook, &Metrics::default())
+                .unwrap();
+            sent += 1;
+        }
+        session
+            .publish_message(&publish(QoS::AtMostOnce), &hook, &Metrics::default())
+            .unwrap();
+        session
+            .publish_message(&publish(QoS::AtLeastOnce), &hook, &Metrics::default())
+            .unwrap();
+        let stats = outbound.stats();
+        assert_eq!((stats.dropped, stats.paused), (1, 1));
+        assert_eq!(conn_tx.queued(), sent);
+
+        // 连接清空队列后恢复发送
+        for _ in 0..sent {
+            conn_rx.recv().await.unwrap();
+        }
+        assert!(outbound.take_resume());
+        session.resume().unwrap();
+        match conn_rx.try_recv() {
+            Ok(Outgoing::Packet(Packet::Publish(p))) => assert_eq!(p.qos, QoS::AtLeastOnce),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(!outbound.take_resume());
+    }
+
+    #[tokio::test]
+    async fn offline_and_disconnect_drops_reported() {
+        let metrics = Metrics::default();
+        let hook = Recorder::default();
+
+        // 离线时 qos0 丢弃，qos1 保存到上限后丢弃
+        let mut session = Session::offline("client");
+        session
+            .publish_message(&publish(QoS::AtMostOnce), &hook, &metrics)
+            .unwrap();
+        for _ in 0..=MAX_QUEUED_MESSAGES {
+            session
+                .publish_message(&publish(QoS::AtLeastOnce), &hook, &metrics)
+                .unwrap();
+        }
+        assert_eq!(*hook.delivered.lock(), MAX_QUEUED_MESSAGES);
+        assert_eq!(
+            *hook.dropped.lock(),
+            vec![DropReason::Offline, DropReason::QueueFull]
+        );
+        assert_eq!(metrics.snapshot().messages_dropped, 2);
+
+        // 队列满时断开连接，当前消息丢弃
+        let (conn_tx, _conn_rx, outbound) =
+            outbound::channel(SlowConsumer::Disconnect, Arc::default());
+        let mut session = Session::new("client", true, conn_tx.clone());
+        let hook = Recorder::default();
+        let mut sent = 0;
+        while conn_tx.has_capacity() {
+            session
+                .publish_message(&publish(QoS::AtLeastOnce), &hook, &metrics)
+                .unwrap();
+            sent += 1;
         }
+        session
+            .publish_message(&publish(QoS::AtLeastOnce), &hook, &metrics)
+            .unwrap();
+        assert_eq!(*hook.delivered.lock(), sent);
+        assert_eq!(*hook.dropped.lock(), vec![DropReason::SlowConsumer]);
+        assert_eq!(outbound.stats().dropped, 1);
     }
 }
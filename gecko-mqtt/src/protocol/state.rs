@@ -0,0 +1,316 @@
+//! router 和各个 worker 共享的状态
+//! * 会话按 client id 分片，每个分片一把锁，worker 只在投递消息时访问其它分片
+//! * 订阅索引、保留消息、集群路由表读多写少，使用读写锁
+
+use std::{
+    borrow::Cow,
+    cmp,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use log::error;
+use parking_lot::{Mutex, MutexGuard, RwLock};
+
+use crate::{
+    cluster::{NodeId, RetainStore, Storage},
+    metrics::Metrics,
+    network::v4::Publish,
+    Hook,
+};
+
+use super::{
+    session::Session,
+    subscripton::{SubscribeOptions, Subscriptions},
+};
+
+pub(crate) struct State {
+    /// 本节点的所有会话
+    pub sessions: Sessions,
+    /// 本节点的订阅索引
+    pub subscriptions: RwLock<Subscriptions>,
+    /// 集群全局的保留消息
+    pub retains: RwLock<RetainStore>,
+    /// 集群路由表，只有 router 写入
+    pub storage: RwLock<Storage>,
+    /// 本节点的消息统计
+    pub metrics: Arc<Metrics>,
+    /// 消息相关的钩子，worker 和投递消息时调用
+    pub hook: Arc<dyn Hook>,
+}
+
+impl State {
+    pub fn new(node_id: NodeId, shards: usize, metrics: Arc<Metrics>, hook: Arc<dyn Hook>) -> Self {
+        Self {
+            sessions: Sessions::new(shards),
+            subscriptions: RwLock::new(Subscriptions::default()),
+            retains: RwLock::new(RetainStore::new(node_id)),
+            storage: RwLock::new(Storage::new(node_id)),
+            metrics,
+            hook,
+        }
+    }
+
+    /// 给本节点所有订阅了此 topic 的客户端发送消息
+    /// 先查出所有客户端，再按分片加锁投递，不同时持有两把锁
+    /// publisher 为发布消息的本节点客户端，用于处理订阅的 no local 选项
+    pub fn publish_local(&self, publish: &Publish, publisher: Option<&str>) {
+        let clients = self.subscriptions.read().matches(&publish.topic, publisher);
+        self.metrics.publish_fanout(clients.len());
+
+        let mut by_shard: HashMap<usize, Vec<(String, SubscribeOptions)>> = HashMap::new();
+        for (client_id, options) in clients {
+            by_shard
+                .entry(self.sessions.index(&client_id))
+                .or_default()
+                .push((client_id, options));
+        }
+        for (index, clients) in by_shard {
+            let mut sessions = self.sessions.shards[index].lock();
+            for (client_id, options) in clients {
+                if let Some(session) = sessions.get_mut(&client_id) {
+                    let publish = subscribed_publish(publish, &options);
+                    if let Err(e) = session.publish_message(&publish, &*self.hook, &self.metrics) {
+                        error!("publish to client {0} error: {1:#}", client_id, e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 按订阅选项投递的消息，qos 取消息和订阅中较小的一个
+/// 没有设置 retain as published 时去掉 retain 标记
+fn subscribed_publish<'a>(publish: &'a Publish, options: &SubscribeOptions) -> Cow<'a, Publish> {
+    let qos = cmp::min(publish.qos, options.qos);
+    let retain = publish.retain && options.retain_as_published;
+    if qos == publish.qos && retain == publish.retain {
+        return Cow::Borrowed(publish);
+    }
+    Cow::Owned(Publish {
+        qos,
+        retain,
+        ..publish.clone()
+    })
+}
+
+/// 按 client id 分片保存的会话
+pub(crate) struct Sessions {
+    shards: Box<[Mutex<HashMap<String, Session>>]>,
+}
+
+impl Sessions {
+    fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1))
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+        }
+    }
+
+    /// 客户端所在的分片
+    pub fn index(&self, client_id: &str) -> usize {
+        shard_index(client_id, self.shards.len())
+    }
+
+    /// 锁住客户端所在的分片
+    pub fn shard(&self, client_id: &str) -> MutexGuard<'_, HashMap<String, Session>> {
+        self.shards[self.index(client_id)].lock()
+    }
+
+    pub fn contains(&self, client_id: &str) -> bool {
+        self.shard(client_id).contains_key(client_id)
+    }
+
+    /// 取出所有分片中的会话，用于关闭 broker
+    pub fn take_all(&self) -> Vec<Session> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .lock()
+                    .drain()
+                    .map(|(_, session)| session)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// 依次锁住每个分片，访问其中的会话
+    pub fn for_each(&self, mut f: impl FnMut(&Session)) {
+        for shard in self.shards.iter() {
+            shard.lock().values().for_each(&mut f);
+        }
+    }
+
+    /// 统计所有分片中的会话数和订阅数
+    pub fn stats(&self) -> SessionStats {
+        let mut stats = SessionStats::default();
+        for shard in self.shards.iter() {
+            for session in shard.lock().values() {
+                if session.conn_tx.is_some() {
+                    stats.connected += 1;
+                } else {
+                    stats.disconnected += 1;
+                }
+                stats.subscriptions +=
+                    session.concrete_subscriptions.len() + session.wildcard_subscriptions.len();
+                stats.inflight += session.inflight();
+                stats.queued += session.queued();
+            }
+        }
+        stats
+    }
+}
+
+/// 本节点会话的统计
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SessionStats {
+    /// 在线的客户端数
+    pub connected: usize,
+    /// 离线但会话还没有过期的客户端数
+    pub disconnected: usize,
+    /// 所有会话的订阅数，包括离线会话
+    pub subscriptions: usize,
+    /// 等待客户端确认的消息数
+    pub inflight: usize,
+    /// 会话和连接队列中等待发送的消息数
+    pub queued: usize,
+}
+
+/// client id 对应的分片，会话和 worker 使用同样的分片规则
+pub(crate) fn shard_index(client_id: &str, shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    client_id.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio::sync::mpsc::Receiver;
+
+    use crate::{
+        config::SlowConsumer,
+        hook::Hooks,
+        network::{outbound, packet::QoS, v4::Packet},
+        protocol::Outgoing,
+    };
+
+    use super::*;
+
+    #[test]
+    fn publish_local_reaches_every_shard() {
+        let state = State::new(1, 4, Arc::default(), Arc::new(Hooks::default()));
+        let mut receivers = Vec::new();
+        for i in 0..16 {
+            let client_id = format!("client-{}", i);
+            let (conn_tx, conn_rx, _) = outbound::channel(SlowConsumer::DropQos0, Arc::default());
+            let mut session = Session::new(&client_id, true, conn_tx);
+            let filter = if i % 2 == 0 { "iot/+/dn" } else { "iot/pid/dn" };
+            let options = SubscribeOptions::new(QoS::AtMostOnce);
+            assert!(state
+                .subscriptions
+                .write()
+                .add(&mut session, filter, options));
+            state.sessions.shard(&client_id).insert(client_id, session);
+            receivers.push(conn_rx);
+        }
+
+        state.publish_local(
+            &Publish {
+                dup: false,
+                qos: QoS::AtMostOnce,
+                retain: false,
+                topic: "iot/pid/dn".into(),
+                packet_id: 0,
+                payload: Bytes::from("hello"),
+                properties: None,
+                encoded: Default::default(),
+            },
+            None,
+        );
+        for mut conn_rx in receivers {
+            assert!(matches!(
+                conn_rx.try_recv(),
+                Ok(Outgoing::Packet(Packet::Publish(_)))
+            ));
+        }
+    }
+
+    #[test]
+    fn publish_local_applies_subscribe_options() {
+        let state = State::new(1, 4, Arc::default(), Arc::new(Hooks::default()));
+        let subscribe = |client_id: &str, filters: &[(&str, SubscribeOptions)]| {
+            let (conn_tx, conn_rx, _) = outbound::channel(SlowConsumer::DropQos0, Arc::default());
+            let mut session = Session::new(client_id, true, conn_tx);
+            for (filter, options) in filters {
+                state
+                    .subscriptions
+                    .write()
+                    .add(&mut session, filter, *options);
+            }
+            state
+                .sessions
+                .shard(client_id)
+                .insert(client_id.into(), session);
+            conn_rx
+        };
+        // 重叠的订阅取较大的 QoS
+        let mut a = subscribe(
+            "a",
+            &[
+                ("iot/#", SubscribeOptions::new(QoS::AtMostOnce)),
+                ("iot/pid/dn", SubscribeOptions::new(QoS::AtLeastOnce)),
+            ],
+        );
+        let mut b = subscribe(
+            "b",
+            &[(
+                "iot/pid/dn",
+                SubscribeOptions {
+                    qos: QoS::ExactlyOnce,
+                    no_local: true,
+                    retain_as_published: true,
+                },
+            )],
+        );
+        let recv = |conn_rx: &mut Receiver<Outgoing>| match conn_rx.try_recv() {
+            Ok(Outgoing::Packet(Packet::Publish(publish))) => Some((publish.qos, publish.retain)),
+            _ => None,
+        };
+
+        let publish = Publish {
+            dup: false,
+            qos: QoS::ExactlyOnce,
+            retain: true,
+            topic: "iot/pid/dn".into(),
+            packet_id: 0,
+            payload: Bytes::from("hello"),
+            properties: None,
+            encoded: Default::default(),
+        };
+        state.publish_local(&publish, Some("b"));
+        assert_eq!(recv(&mut a), Some((QoS::AtLeastOnce, false)));
+        assert_eq!(recv(&mut b), None);
+
+        state.publish_local(&publish, Some("a"));
+        assert_eq!(recv(&mut a), Some((QoS::AtLeastOnce, false)));
+        assert_eq!(recv(&mut b), Some((QoS::ExactlyOnce, true)));
+    }
+
+    #[test]
+    fn take_all_empties_every_shard() {
+        let state = State::new(1, 4, Arc::default(), Arc::new(Hooks::default()));
+        for i in 0..16 {
+            let client_id = format!("client-{}", i);
+            let (conn_tx, _, _) = outbound::channel(SlowConsumer::DropQos0, Arc::default());
+            let session = Session::new(&client_id, true, conn_tx);
+            state.sessions.shard(&client_id).insert(client_id, session);
+        }
+
+        assert_eq!(state.sessions.take_all().len(), 16);
+        assert!((0..16).all(|i| !state.sessions.contains(&format!("client-{}", i))));
+    }
+}
@@ -1,4 +1,158 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{cmp, collections::HashMap, fmt::Debug};
+
+use crate::{
+    config::AutoSubscribe,
+    network::{packet::QoS, topic, v4::SubscribeFilter},
+};
+
+use super::session::Session;
+
+/// 订阅选项，客户端订阅、自动订阅和迁移会话时记录在订阅中
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SubscribeOptions {
+    /// 授予的最大 QoS，投递时取消息和订阅中较小的一个
+    pub qos: QoS,
+    /// 不接收自己发布的消息，v5 订阅选项
+    pub no_local: bool,
+    /// 投递时保留消息的 retain 标记，v5 订阅选项，否则只有订阅时发送的保留消息带 retain 标记
+    pub retain_as_published: bool,
+}
+
+impl SubscribeOptions {
+    pub fn new(qos: QoS) -> Self {
+        Self {
+            qos,
+            no_local: false,
+            retain_as_published: false,
+        }
+    }
+
+    /// 同一个客户端的多个订阅匹配同一条消息时，只投递一次，使用最大的 QoS
+    fn merge(&mut self, other: &SubscribeOptions) {
+        self.qos = cmp::max(self.qos, other.qos);
+        self.retain_as_published |= other.retain_as_published;
+    }
+}
+
+impl From<&SubscribeFilter> for SubscribeOptions {
+    fn from(filter: &SubscribeFilter) -> Self {
+        Self {
+            qos: filter.qos,
+            no_local: filter.no_local,
+            retain_as_published: filter.retain_as_published,
+        }
+    }
+}
+
+impl From<&AutoSubscribe> for SubscribeOptions {
+    fn from(subscribe: &AutoSubscribe) -> Self {
+        Self {
+            qos: subscribe.qos,
+            no_local: subscribe.no_local,
+            retain_as_published: subscribe.retain_as_published,
+        }
+    }
+}
+
+/// 本节点所有会话的订阅索引，由 router 和各个 worker 共享
+/// 订阅和取消订阅时同时修改会话中的订阅记录
+#[derive(Debug, Default)]
+pub(crate) struct Subscriptions {
+    /// 精确订阅, key = topic-filter, value = client_id -> 订阅选项
+    concrete: HashMap<String, HashMap<String, SubscribeOptions>>,
+    /// 模糊订阅, T = (client_id, 订阅选项)
+    wild: SubscriptionTree<(String, SubscribeOptions)>,
+}
+
+impl Subscriptions {
+    /// 添加一个订阅，已订阅过时替换订阅选项并返回 false
+    pub fn add(&mut self, session: &mut Session, filter: &str, options: SubscribeOptions) -> bool {
+        let client_id = session.client_id.clone();
+        if topic::filter_has_wildcards(filter) {
+            let token = self.wild.insert(filter, (client_id, options));
+            match session
+                .wildcard_subscriptions
+                .insert(filter.into(), (token, options))
+            {
+                Some((token, _)) => {
+                    self.wild.remove(filter, token);
+                    false
+                }
+                None => true,
+            }
+        } else {
+            self.concrete
+                .entry(filter.into())
+                .or_default()
+                .insert(client_id, options);
+            session
+                .concrete_subscriptions
+                .insert(filter.into(), options)
+                .is_none()
+        }
+    }
+
+    /// 删除一个订阅，返回 false 表示没有订阅过
+    pub fn remove(&mut self, session: &mut Session, filter: &str) -> bool {
+        if topic::filter_has_wildcards(filter) {
+            match session.wildcard_subscriptions.remove(filter) {
+                Some((token, _)) => self.wild.remove(filter, token),
+                None => return false,
+            }
+        } else {
+            if session.concrete_subscriptions.remove(filter).is_none() {
+                return false;
+            }
+            self.remove_concrete(filter, &session.client_id);
+        }
+        true
+    }
+
+    /// 删除会话的所有订阅，会话中的订阅记录保持不变
+    pub fn clear(&mut self, session: &Session) {
+        for filter in session.concrete_subscriptions.keys() {
+            self.remove_concrete(filter, &session.client_id);
+        }
+        for (filter, (token, _)) in session.wildcard_subscriptions.iter() {
+            self.wild.remove(filter, *token);
+        }
+    }
+
+    fn remove_concrete(&mut self, filter: &str, client_id: &str) {
+        if let Some(clients) = self.concrete.get_mut(filter) {
+            clients.remove(client_id);
+            if clients.is_empty() {
+                self.concrete.remove(filter);
+            }
+        }
+    }
+
+    /// 订阅了 topic 的所有客户端和合并后的订阅选项，同一个客户端只出现一次
+    /// publisher 为发布消息的本节点客户端，跳过它设置了 no local 的订阅
+    pub fn matches(
+        &self,
+        topic: &str,
+        publisher: Option<&str>,
+    ) -> HashMap<String, SubscribeOptions> {
+        let mut clients: HashMap<String, SubscribeOptions> = HashMap::new();
+        let concrete = self.concrete.get(topic).into_iter().flatten();
+        let wild = self
+            .wild
+            .matches(topic)
+            .into_iter()
+            .map(|entry| (&entry.0, &entry.1));
+        for (client_id, options) in concrete.chain(wild) {
+            if options.no_local && publisher == Some(client_id.as_str()) {
+                continue;
+            }
+            clients
+                .entry(client_id.clone())
+                .and_modify(|merged| merged.merge(options))
+                .or_insert(*options);
+        }
+        clients
+    }
+}
 
 #[derive(Debug, serde::Serialize)]
 pub struct SubscriptionTree<T: Debug> {
@@ -8,6 +162,12 @@ pub struct SubscriptionTree<T: Debug> {
     token: u64,
 }
 
+impl<T: Debug> Default for SubscriptionTree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Debug> SubscriptionTree<T> {
     pub fn new() -> Self {
         Self {
@@ -26,8 +186,17 @@ impl<T: Debug> SubscriptionTree<T> {
     }
 
     /// 查找发布消息的主题匹配的记录
-    pub fn matches(&mut self, topic: &str) -> Vec<&T> {
-        self.root.matches(topic.split('/'))
+    /// 以 $ 开头的 topic 不匹配第一层是通配符的 filter [MQTT-4.7.2-1]
+    pub fn matches(&self, topic: &str) -> Vec<&T> {
+        let mut levels = topic.split('/');
+        if topic.starts_with('$') {
+            let first = levels.next().unwrap_or_default();
+            return match self.root.children.get(first) {
+                Some(node) => node.matches(levels),
+                None => Vec::new(),
+            };
+        }
+        self.root.matches(levels)
     }
 
     /// 删除订阅记录
@@ -190,4 +359,16 @@ mod tests {
         sub_tree.remove("iot/pid/dn/+", 4);
         assert_eq!(sub_tree.matches("iot/pid/dn/temperature").len(), 3)
     }
+
+    #[test]
+    fn sys_topic_needs_explicit_filter() {
+        let mut sub_tree = SubscriptionTree::new();
+        sub_tree.insert("#", "all");
+        sub_tree.insert("+/brokers/#", "any");
+        sub_tree.insert("$SYS/#", "sys");
+
+        assert_eq!(sub_tree.matches("$SYS/brokers/1/uptime"), vec![&"sys"]);
+        assert!(!topic::matches("$SYS/brokers/1/uptime", "#"));
+        assert!(topic::matches("$SYS/brokers/1/uptime", "$SYS/+/1/uptime"));
+    }
 }
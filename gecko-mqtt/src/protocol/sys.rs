@@ -0,0 +1,144 @@
+//! $SYS 主题
+//! router 定期把本节点的统计发布到 $SYS/brokers/{node_id}/ 下的主题
+//! 以 $ 开头的主题不匹配以通配符开头的 filter，客户端需要明确订阅，如 $SYS/#
+
+use tokio::time::Instant;
+
+use crate::{
+    cluster::NodeId,
+    metrics::MetricsSnapshot,
+    network::{packet::QoS, v4::Publish},
+};
+
+use super::State;
+
+/// 生成 $SYS 主题的消息，记录上一次发布时的统计，用于计算每秒的速率
+pub(crate) struct SysTopics {
+    /// 主题前缀 $SYS/brokers/{node_id}/
+    prefix: String,
+    /// 启动时间
+    started: Instant,
+    last: MetricsSnapshot,
+    last_at: Instant,
+}
+
+impl SysTopics {
+    pub fn new(node_id: NodeId) -> Self {
+        let now = Instant::now();
+        Self {
+            prefix: format!("$SYS/brokers/{}/", node_id),
+            started: now,
+            last: MetricsSnapshot::default(),
+            last_at: now,
+        }
+    }
+
+    /// 本次需要发布的消息
+    /// * version, uptime（秒）
+    /// * clients/connected, clients/disconnected, subscriptions/count, retained/count
+    /// * messages/received, messages/sent, bytes/received, bytes/sent 为上次发布以来每秒的速率
+    /// * messages/dropped 为启动以来的累计值
+    pub fn messages(&mut self, state: &State) -> Vec<Publish> {
+        let now = Instant::now();
+        let metrics = state.metrics.snapshot();
+        let sessions = state.sessions.stats();
+        let retained = state.retains.read().len();
+
+        let elapsed = now.duration_since(self.last_at).as_secs_f64();
+        let rate = |current: u64, last: u64| {
+            if elapsed > 0.0 {
+                ((current - last) as f64 / elapsed).round() as u64
+            } else {
+                0
+            }
+        };
+        let values = [
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+            (
+                "uptime",
+                now.duration_since(self.started).as_secs().to_string(),
+            ),
+            ("clients/connected", sessions.connected.to_string()),
+            ("clients/disconnected", sessions.disconnected.to_string()),
+            ("subscriptions/count", sessions.subscriptions.to_string()),
+            ("retained/count", retained.to_string()),
+            (
+                "messages/received",
+                rate(metrics.messages_received, self.last.messages_received).to_string(),
+            ),
+            (
+                "messages/sent",
+                rate(metrics.messages_sent, self.last.messages_sent).to_string(),
+            ),
+            ("messages/dropped", metrics.messages_dropped.to_string()),
+            (
+                "bytes/received",
+                rate(metrics.bytes_received, self.last.bytes_received).to_string(),
+            ),
+            (
+                "bytes/sent",
+                rate(metrics.bytes_sent, self.last.bytes_sent).to_string(),
+            ),
+        ];
+        self.last = metrics;
+        self.last_at = now;
+
+        values
+            .into_iter()
+            .map(|(name, value)| Publish {
+                dup: false,
+                qos: QoS::AtMostOnce,
+                retain: false,
+                topic: format!("{}{}", self.prefix, name).into(),
+                packet_id: 0,
+                payload: value.into(),
+                properties: None,
+                encoded: Default::default(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        config::SlowConsumer,
+        hook::Hooks,
+        network::{outbound, packet::QoS},
+        protocol::{session::Session, subscripton::SubscribeOptions},
+    };
+
+    use super::*;
+
+    #[test]
+    fn sys_messages_reflect_state() {
+        let state = State::new(1, 2, Arc::default(), Arc::new(Hooks::default()));
+        let (conn_tx, _conn_rx, _) = outbound::channel(SlowConsumer::DropQos0, Arc::default());
+        let mut session = Session::new("client", true, conn_tx);
+        let options = SubscribeOptions::new(QoS::AtMostOnce);
+        assert!(state
+            .subscriptions
+            .write()
+            .add(&mut session, "$SYS/#", options));
+        state
+            .sessions
+            .shard("client")
+            .insert("client".into(), session);
+
+        let mut sys = SysTopics::new(1);
+        let messages = sys.messages(&state);
+        let value = |topic: &str| {
+            messages
+                .iter()
+                .find(|publish| *publish.topic == format!("$SYS/brokers/1/{}", topic))
+                .map(|publish| publish.payload.clone())
+                .unwrap()
+        };
+        assert_eq!(value("clients/connected"), "1");
+        assert_eq!(value("clients/disconnected"), "0");
+        assert_eq!(value("subscriptions/count"), "1");
+        assert_eq!(value("retained/count"), "0");
+    }
+}
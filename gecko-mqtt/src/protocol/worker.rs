@@ -0,0 +1,501 @@
+//! router 工作协程
+//! 客户端连接按 client id 分片，同一个客户端的报文总是由同一个 worker 按顺序处理
+//! publish 的订阅匹配和投递在 worker 中完成，多个 worker 并行，不再经过 router
+//! 需要集群同步的变更（路由、保留消息、转发给其它节点）交给 router 处理
+
+use std::{cmp, sync::Arc};
+
+use gecko_mqtt_proto::RouteAction;
+use log::{error, info, warn};
+use tokio::sync::mpsc::{self, error::SendError, Receiver, Sender, UnboundedSender};
+
+use crate::{
+    network::{
+        packet::{v5::RetainForwardRule, Protocol, QoS},
+        topic,
+        v4::{
+            Packet, PubAck, PubComp, PubRec, PubRel, Publish, SubAck, Subscribe,
+            SubscribeReasonCode, UnsubAck, Unsubscribe,
+        },
+    },
+    PublishDecision,
+};
+
+use super::{
+    rewrite::Rewrite,
+    session,
+    state::{shard_index, State},
+    Incoming, Outgoing,
+};
+
+/// 每个 worker 的消息队列长度
+const WORKER_QUEUE_SIZE: usize = 1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Router closed")]
+    RouterClosed,
+    #[error("Unexpected packet")]
+    UnexpectedPacket,
+    #[error("Session not found")]
+    SessionNotFound,
+    #[error("session error: {0}")]
+    Session(#[from] session::Error),
+}
+
+impl From<SendError<Incoming>> for Error {
+    fn from(_: SendError<Incoming>) -> Self {
+        Error::RouterClosed
+    }
+}
+
+/// 向 worker 发送消息，按 client id 选择 worker
+#[derive(Debug, Clone)]
+pub(crate) struct Workers {
+    txs: Vec<Sender<Incoming>>,
+}
+
+impl Workers {
+    /// 创建 count 个 worker 的消息队列，和 router 的队列一样在 broker 创建时建立
+    /// 启动前就可以获取进程内客户端
+    pub fn new(count: usize) -> (Self, Vec<Receiver<Incoming>>) {
+        let (txs, rxs) = (0..count.max(1))
+            .map(|_| mpsc::channel(WORKER_QUEUE_SIZE))
+            .unzip();
+        (Self { txs }, rxs)
+    }
+
+    /// 启动 worker，与会话的分片一一对应
+    /// worker 发给 router 的消息使用不限长度的队列，router 不会反过来等待 worker，不会互相阻塞
+    pub fn start(
+        receivers: Vec<Receiver<Incoming>>,
+        state: Arc<State>,
+        router_tx: UnboundedSender<Incoming>,
+        rewrite: Arc<Rewrite>,
+    ) {
+        for (id, worker_rx) in receivers.into_iter().enumerate() {
+            let worker = Worker {
+                id,
+                state: state.clone(),
+                worker_rx,
+                router_tx: router_tx.clone(),
+                rewrite: rewrite.clone(),
+            };
+            tokio::spawn(worker.start());
+        }
+    }
+
+    /// worker 的数量
+    pub fn count(&self) -> usize {
+        self.txs.len()
+    }
+
+    /// 处理此客户端的 worker
+    pub fn get(&self, client_id: &str) -> &Sender<Incoming> {
+        &self.txs[shard_index(client_id, self.txs.len())]
+    }
+}
+
+struct Worker {
+    id: usize,
+    state: Arc<State>,
+    worker_rx: Receiver<Incoming>,
+    router_tx: UnboundedSender<Incoming>,
+    /// 主题重写规则
+    rewrite: Arc<Rewrite>,
+}
+
+impl Worker {
+    /// 处理出错只影响当前消息，worker 继续运行
+    async fn start(mut self) {
+        while let Some(incoming) = self.worker_rx.recv().await {
+            if let Err(e) = self.handle_incoming(incoming).await {
+                error!("worker {0} handle incoming error: {1:#}", self.id, e);
+            }
+        }
+    }
+
+    async fn handle_incoming(&mut self, incoming: Incoming) -> Result<(), Error> {
+        match incoming {
+            Incoming::Data { client_id, packets } => {
+                for mut packet in packets.into_iter() {
+                    self.rewrite_packet(&client_id, &mut packet);
+                    match packet {
+                        Packet::Subscribe(subscribe) => {
+                            self.handle_subscribe(&client_id, subscribe).await?
+                        }
+                        Packet::Publish(publish) => {
+                            self.handle_publish(&client_id, publish).await?
+                        }
+                        Packet::PubAck(puback) => self.handle_publish_ack(&client_id, puback).await,
+                        Packet::PubRel(pubrel) => {
+                            self.handle_publish_release(&client_id, pubrel)?
+                        }
+                        Packet::PubRec(pubrec) => {
+                            self.handle_publish_receive(&client_id, pubrec)?
+                        }
+                        Packet::PubComp(pubcomp) => {
+                            self.handle_publish_complete(&client_id, pubcomp).await
+                        }
+                        Packet::Unsubscribe(unsubscribe) => {
+                            self.handle_unsubscribe(&client_id, unsubscribe).await?
+                        }
+                        Packet::Disconnect => self.handle_client_disconnect(&client_id).await?,
+                        _ => return Err(Error::UnexpectedPacket),
+                    }
+                }
+                Ok(())
+            }
+            Incoming::Resume { client_id } => {
+                if let Some(session) = self.state.sessions.shard(&client_id).get_mut(&client_id) {
+                    session.resume()?;
+                }
+                Ok(())
+            }
+            _ => Err(Error::UnexpectedPacket),
+        }
+    }
+
+    /// 按规则重写 publish 的主题和 subscribe/unsubscribe 的 filter
+    fn rewrite_packet(&self, client_id: &str, packet: &mut Packet) {
+        if self.rewrite.is_empty()
+            || !matches!(
+                packet,
+                Packet::Publish(_) | Packet::Subscribe(_) | Packet::Unsubscribe(_)
+            )
+        {
+            return;
+        }
+        let username = self
+            .state
+            .sessions
+            .shard(client_id)
+            .get(client_id)
+            .and_then(|session| session.conn_tx.as_ref()?.info.as_ref()?.username.clone());
+        let username = username.as_deref();
+        match packet {
+            Packet::Publish(publish) => {
+                if let Some(topic) = self.rewrite.publish(&publish.topic, client_id, username) {
+                    publish.topic = topic.into();
+                }
+            }
+            Packet::Subscribe(subscribe) => {
+                for filter in subscribe.filters.iter_mut() {
+                    if let Some(path) = self.rewrite.filter(&filter.path, client_id, username) {
+                        filter.path = path;
+                    }
+                }
+            }
+            Packet::Unsubscribe(unsubscribe) => {
+                for filter in unsubscribe.filters.iter_mut() {
+                    if let Some(path) = self.rewrite.filter(filter, client_id, username) {
+                        *filter = path;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 处理订阅请求
+    /// 回复 suback 后，给订阅的客户端发送所有匹配的保留消息
+    /// 钩子不允许的 filter 回复订阅失败
+    async fn handle_subscribe(
+        &mut self,
+        client_id: &str,
+        subscribe: Subscribe,
+    ) -> Result<(), Error> {
+        let Subscribe { packet_id, filters } = subscribe;
+        let mut allowed = Vec::with_capacity(filters.len());
+        for filter in filters.iter() {
+            allowed.push(
+                self.state
+                    .hook
+                    .on_subscribe(client_id, &filter.path, filter.qos)
+                    .await,
+            );
+        }
+
+        let mut added = Vec::new();
+        // 会话中新增的订阅，Retain Handling 为 1 时只给新订阅发送保留消息
+        let mut new_filters = Vec::with_capacity(filters.len());
+        {
+            let mut sessions = self.state.sessions.shard(client_id);
+            let session = sessions.get_mut(client_id).ok_or(Error::SessionNotFound)?;
+
+            let mut return_codes = Vec::with_capacity(filters.len());
+            {
+                let mut subscriptions = self.state.subscriptions.write();
+                for (filter, allowed) in filters.iter().zip(allowed.iter()) {
+                    if !allowed {
+                        return_codes.push(SubscribeReasonCode::Failure);
+                        new_filters.push(false);
+                        continue;
+                    }
+                    // 添加到订阅管理，已订阅过时替换订阅选项
+                    let new = subscriptions.add(session, &filter.path, filter.into());
+                    if new {
+                        added.push(filter.path.clone());
+                    }
+                    new_filters.push(new);
+                    return_codes.push(SubscribeReasonCode::Success(filter.qos));
+                }
+            }
+            session.send_packet(Packet::SubAck(SubAck {
+                packet_id,
+                return_codes,
+            }))?;
+
+            // 按 Retain Handling 发送保留消息，qos 取消息和订阅中较小的一个
+            let retains = self.state.retains.read();
+            for ((filter, allowed), new) in filters.iter().zip(allowed).zip(new_filters) {
+                let send = match filter.retain_handling {
+                    RetainForwardRule::OnEverySubscribe => allowed,
+                    RetainForwardRule::OnNewSubscribe => new,
+                    RetainForwardRule::Never => false,
+                };
+                if !send {
+                    continue;
+                }
+                for publish in retains.matches(&filter.path) {
+                    let publish = Publish {
+                        qos: cmp::min(publish.qos, filter.qos),
+                        ..publish.clone()
+                    };
+                    session.publish_message(&publish, &*self.state.hook, &self.state.metrics)?;
+                }
+            }
+        }
+
+        // 更新路由表
+        for filter in added {
+            self.router_tx.send(Incoming::LocalRoute {
+                filter,
+                action: RouteAction::RouteAdd,
+            })?;
+        }
+        Ok(())
+    }
+
+    async fn handle_unsubscribe(
+        &mut self,
+        client_id: &str,
+        unsubscribe: Unsubscribe,
+    ) -> Result<(), Error> {
+        let Unsubscribe { packet_id, filters } = unsubscribe;
+
+        let mut removed = Vec::new();
+        {
+            let mut sessions = self.state.sessions.shard(client_id);
+            let session = sessions.get_mut(client_id).ok_or(Error::SessionNotFound)?;
+            let mut subscribed = Vec::with_capacity(filters.len());
+            {
+                let mut subscriptions = self.state.subscriptions.write();
+                for filter in filters {
+                    let exists = subscriptions.remove(session, &filter);
+                    subscribed.push(exists);
+                    if exists {
+                        removed.push(filter);
+                    }
+                }
+            }
+            session.send_packet(Packet::UnsubAck(UnsubAck {
+                packet_id,
+                subscribed,
+            }))?;
+        }
+
+        // 更新路由表
+        for filter in removed {
+            self.state.hook.on_unsubscribe(client_id, &filter).await;
+            self.router_tx.send(Incoming::LocalRoute {
+                filter,
+                action: RouteAction::RouteDelete,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// 处理 publish 请求
+    /// 先交给钩子检查，钩子丢弃的消息照常回复 ack，但不保存也不投递
+    ///
+    /// QoS0：发送端 和 接受端 均不保存数据
+    /// QoS1：发送端 保存数据，接受端 不保存
+    /// QoS2：发送端 和 接受端 均保存数据
+    async fn handle_publish(&mut self, client_id: &str, mut publish: Publish) -> Result<(), Error> {
+        // 钩子可能修改消息，回复 ack 使用客户端发送的 qos 和 packet id
+        let Publish { packet_id, qos, .. } = publish;
+        self.state.metrics.received(&publish);
+
+        let deliver = match self.state.hook.on_publish(client_id, &mut publish).await {
+            PublishDecision::Allow => true,
+            PublishDecision::Modified => {
+                let valid = topic::valid_publish_topic(&publish.topic);
+                if !valid {
+                    warn!(
+                        "drop publish from client {0}, invalid topic {1} after hook",
+                        client_id, publish.topic
+                    );
+                }
+                valid
+            }
+            PublishDecision::Drop => false,
+            PublishDecision::Reject(reason) => {
+                info!("publish from client {0} rejected: {1}", client_id, reason);
+                if self.reject_publish(client_id, qos, packet_id, reason) {
+                    return Ok(());
+                }
+                false
+            }
+        };
+
+        // 保留消息，保存一份并同步给对等节点
+        if deliver && publish.retain {
+            let message = self.state.retains.write().insert(&publish);
+            self.router_tx.send(Incoming::BroadcastRetain { message })?;
+        }
+
+        // 回复 publisher
+        match qos {
+            QoS::AtMostOnce => {
+                // 给订阅端发送消息
+                if deliver {
+                    self.publish_message(client_id, publish).await?
+                }
+            }
+            QoS::AtLeastOnce => {
+                let found = match self.state.sessions.shard(client_id).get(client_id) {
+                    // broker 是接收端，不需要保存消息，直接发送 puback
+                    Some(session) => {
+                        session.send_packet(Packet::PubAck(PubAck { packet_id }))?;
+                        true
+                    }
+                    None => false,
+                };
+                if found && deliver {
+                    // 给订阅端发送消息
+                    self.publish_message(client_id, publish).await?
+                }
+            }
+            QoS::ExactlyOnce => {
+                // 只保存 packet id，第一次收到时立即转发，收到 pubrel 之前重发的消息不再转发
+                let first = match self.state.sessions.shard(client_id).get_mut(client_id) {
+                    Some(session) => {
+                        let first = session.insert_received(packet_id);
+                        session.send_packet(Packet::PubRec(PubRec { packet_id }))?;
+                        first
+                    }
+                    None => false,
+                };
+                if first && deliver {
+                    self.publish_message(client_id, publish).await?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 钩子拒绝了消息，v5 客户端的 QoS1/QoS2 消息回复带原因的 puback/pubrec
+    /// 返回 false 时没有回复，按丢弃处理 [MQTT-3.3.5-2]
+    fn reject_publish(&self, client_id: &str, qos: QoS, packet_id: u16, reason: String) -> bool {
+        if qos == QoS::AtMostOnce {
+            return false;
+        }
+        let sessions = self.state.sessions.shard(client_id);
+        let Some(conn_tx) = sessions
+            .get(client_id)
+            .and_then(|session| session.conn_tx.as_ref())
+        else {
+            return false;
+        };
+        if conn_tx
+            .info
+            .as_ref()
+            .is_none_or(|info| info.protocol != Protocol::V5)
+        {
+            return false;
+        }
+        conn_tx.try_send(Outgoing::PublishRejected {
+            qos,
+            packet_id,
+            reason,
+        });
+        true
+    }
+
+    /// 给所有符合条件的客户端发送消息
+    /// 路由表中订阅了此 topic 的其它节点，交给 router 转发
+    async fn publish_message(&mut self, client_id: &str, publish: Publish) -> Result<(), Error> {
+        self.state.publish_local(&publish, Some(client_id));
+
+        let nodes = self.state.storage.read().remote_nodes(&publish.topic);
+        if !nodes.is_empty() {
+            self.router_tx
+                .send(Incoming::ForwardRemote { nodes, publish })?;
+        }
+        Ok(())
+    }
+
+    /// 处理 puback
+    async fn handle_publish_ack(&mut self, client_id: &str, puback: PubAck) {
+        let acked = self
+            .state
+            .sessions
+            .shard(client_id)
+            .get_mut(client_id)
+            .and_then(|session| session.remove_published(puback.packet_id));
+        if let Some(publish) = acked {
+            self.state.hook.on_acked(client_id, &publish).await;
+        }
+    }
+
+    /// 处理 pubrel
+    fn handle_publish_release(&mut self, client_id: &str, pubrel: PubRel) -> Result<(), Error> {
+        if let Some(session) = self.state.sessions.shard(client_id).get_mut(client_id) {
+            session.publish_release(pubrel)?;
+        }
+        Ok(())
+    }
+
+    /// 处理 pubrec
+    fn handle_publish_receive(&mut self, client_id: &str, pubrec: PubRec) -> Result<(), Error> {
+        if let Some(session) = self.state.sessions.shard(client_id).get_mut(client_id) {
+            session.publish_receive(pubrec)?;
+        }
+        Ok(())
+    }
+
+    /// 处理 pubcomp
+    async fn handle_publish_complete(&mut self, client_id: &str, pubcomp: PubComp) {
+        let completed = self
+            .state
+            .sessions
+            .shard(client_id)
+            .get_mut(client_id)
+            .and_then(|session| session.publish_complete(pubcomp));
+        if let Some(publish) = completed {
+            self.state.hook.on_acked(client_id, &publish).await;
+        }
+    }
+
+    /// 处理客户端断开连接事件
+    /// 通知连接断开后，由 router 把会话放到失效列表
+    async fn handle_client_disconnect(&mut self, client_id: &str) -> Result<(), Error> {
+        let conn_tx = match self.state.sessions.shard(client_id).get_mut(client_id) {
+            Some(session) => session.conn_tx.take(),
+            None => return Ok(()),
+        };
+        // 没有连接说明已经处理过断开
+        let Some(conn_tx) = conn_tx else {
+            return Ok(());
+        };
+        // 向 conn 返回断开连接确认消息，客户端可能已经关闭了连接
+        conn_tx.disconnect();
+        self.router_tx.send(Incoming::Disconnect {
+            client_id: client_id.into(),
+            outbound: conn_tx.outbound,
+        })?;
+        Ok(())
+    }
+}
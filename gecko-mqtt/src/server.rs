@@ -1,25 +1,164 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::StreamExt;
 use gecko_mqtt_proto::{
     gecko_peer_server::{GeckoPeer, GeckoPeerServer},
-    TransferPacketRequest, TransferPacketResponse,
+    ForwardPublishBatch, ForwardPublishRequest, ForwardPublishResponse, PingRequest, PingResponse,
+    ReleaseSessionRequest, ReleaseSessionResponse, SyncRetainRequest, SyncRetainResponse,
+    TakeoverSessionRequest, TakeoverSessionResponse, UpdateBanRequest, UpdateBanResponse,
+    UpdateRetainRequest, UpdateRetainResponse, UpdateRouteRequest, UpdateRouteResponse,
+    UpdateSessionRequest, UpdateSessionResponse,
 };
-use tokio::sync::mpsc::Sender;
+use tokio::sync::{mpsc::Sender, oneshot};
+
+use crate::{cluster::NodeId, network::conn::PeerRequest};
+
+pub(crate) use admin::AdminServer;
+
+mod admin;
 
 pub(crate) struct PeerServer {
-    peer_tx: Sender<()>,
+    /// 当前节点 id
+    node_id: NodeId,
+    /// 当前节点的启动时间戳，对等节点据此判断本节点是否重启过
+    incarnation: u64,
+    peer_tx: Sender<PeerRequest>,
 }
 
 impl PeerServer {
-    pub(crate) fn new_server(peer_tx: Sender<()>) -> GeckoPeerServer<PeerServer> {
-        GeckoPeerServer::new(Self { peer_tx })
+    pub(crate) fn new_server(
+        node_id: NodeId,
+        peer_tx: Sender<PeerRequest>,
+    ) -> GeckoPeerServer<PeerServer> {
+        let incarnation = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        GeckoPeerServer::new(Self {
+            node_id,
+            incarnation,
+            peer_tx,
+        })
+    }
+
+    async fn send(&self, request: PeerRequest) -> Result<(), tonic::Status> {
+        self.peer_tx
+            .send(request)
+            .await
+            .map_err(|_| tonic::Status::unavailable("peer connection closed"))
+    }
+
+    /// 发送请求，并等待 router 处理的结果
+    async fn request<T>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<T>) -> PeerRequest,
+    ) -> Result<T, tonic::Status> {
+        let (tx, rx) = oneshot::channel();
+        self.send(request(tx)).await?;
+        rx.await
+            .map_err(|_| tonic::Status::unavailable("router closed"))
     }
 }
 
 #[tonic::async_trait]
 impl GeckoPeer for PeerServer {
-    async fn transfer_packet(
+    async fn forward_publish(
+        &self,
+        request: tonic::Request<ForwardPublishRequest>,
+    ) -> Result<tonic::Response<ForwardPublishResponse>, tonic::Status> {
+        self.send(PeerRequest::ForwardPublish(request.into_inner()))
+            .await?;
+        Ok(tonic::Response::new(ForwardPublishResponse {}))
+    }
+
+    async fn forward_publish_stream(
+        &self,
+        request: tonic::Request<tonic::Streaming<ForwardPublishBatch>>,
+    ) -> Result<tonic::Response<ForwardPublishResponse>, tonic::Status> {
+        let mut stream = request.into_inner();
+        while let Some(batch) = stream.next().await {
+            for publish in batch?.publishes {
+                self.send(PeerRequest::ForwardPublish(publish)).await?;
+            }
+        }
+        Ok(tonic::Response::new(ForwardPublishResponse {}))
+    }
+
+    async fn ping(
+        &self,
+        _request: tonic::Request<PingRequest>,
+    ) -> Result<tonic::Response<PingResponse>, tonic::Status> {
+        Ok(tonic::Response::new(PingResponse {
+            node_id: self.node_id,
+            incarnation: self.incarnation,
+        }))
+    }
+
+    async fn update_session(
+        &self,
+        request: tonic::Request<UpdateSessionRequest>,
+    ) -> Result<tonic::Response<UpdateSessionResponse>, tonic::Status> {
+        self.send(PeerRequest::UpdateSession(request.into_inner()))
+            .await?;
+        Ok(tonic::Response::new(UpdateSessionResponse {}))
+    }
+
+    async fn takeover_session(
+        &self,
+        request: tonic::Request<TakeoverSessionRequest>,
+    ) -> Result<tonic::Response<TakeoverSessionResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let response = self
+            .request(|tx| PeerRequest::TakeoverSession(request, tx))
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn release_session(
+        &self,
+        request: tonic::Request<ReleaseSessionRequest>,
+    ) -> Result<tonic::Response<ReleaseSessionResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let response = self
+            .request(|tx| PeerRequest::ReleaseSession(request, tx))
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn update_retain(
+        &self,
+        request: tonic::Request<UpdateRetainRequest>,
+    ) -> Result<tonic::Response<UpdateRetainResponse>, tonic::Status> {
+        self.send(PeerRequest::UpdateRetain(request.into_inner()))
+            .await?;
+        Ok(tonic::Response::new(UpdateRetainResponse {}))
+    }
+
+    async fn update_ban(
+        &self,
+        request: tonic::Request<UpdateBanRequest>,
+    ) -> Result<tonic::Response<UpdateBanResponse>, tonic::Status> {
+        self.send(PeerRequest::UpdateBan(request.into_inner()))
+            .await?;
+        Ok(tonic::Response::new(UpdateBanResponse {}))
+    }
+
+    async fn sync_retain(
+        &self,
+        request: tonic::Request<SyncRetainRequest>,
+    ) -> Result<tonic::Response<SyncRetainResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let response = self
+            .request(|tx| PeerRequest::SyncRetain(request, tx))
+            .await?;
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn update_route(
         &self,
-        _request: tonic::Request<TransferPacketRequest>,
-    ) -> Result<tonic::Response<TransferPacketResponse>, tonic::Status> {
-        todo!()
+        request: tonic::Request<UpdateRouteRequest>,
+    ) -> Result<tonic::Response<UpdateRouteResponse>, tonic::Status> {
+        self.send(PeerRequest::UpdateRoute(request.into_inner()))
+            .await?;
+        Ok(tonic::Response::new(UpdateRouteResponse {}))
     }
 }
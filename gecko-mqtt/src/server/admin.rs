@@ -0,0 +1,281 @@
+use bytes::Bytes;
+use gecko_mqtt_proto::admin::{
+    gecko_admin_server::{GeckoAdmin, GeckoAdminServer},
+    AddBanRequest, AddBanResponse, DeleteBanRequest, DeleteBanResponse, DeleteRetainedRequest,
+    DeleteRetainedResponse, GetMetricsRequest, GetMetricsResponse, GetSessionRequest,
+    GetSessionResponse, JoinNodeRequest, JoinNodeResponse, KickClientRequest, KickClientResponse,
+    LeaveNodeRequest, LeaveNodeResponse, ListBansRequest, ListBansResponse, ListClientsRequest,
+    ListClientsResponse, ListNodesRequest, ListNodesResponse, ListRetainedRequest,
+    ListRetainedResponse, PublishRequest, PublishResponse, SubscribeRequest, SubscribeResponse,
+    UnsubscribeRequest, UnsubscribeResponse,
+};
+use tokio::sync::{mpsc::Sender, oneshot};
+
+use crate::{
+    ban,
+    cluster::NodeId,
+    config,
+    network::{packet::QoS, topic, v4::Publish},
+    protocol::{AdminRequest, Incoming},
+};
+
+/// 管理接口，校验参数后交给 router 处理
+pub(crate) struct AdminServer {
+    /// 当前节点 id
+    node_id: NodeId,
+    /// 配置了 etcd 时，对等节点由集群管理器发现，不能手动添加和移除
+    managed: bool,
+    router_tx: Sender<Incoming>,
+}
+
+impl AdminServer {
+    pub(crate) fn new_server(
+        cfg: &config::Cluster,
+        router_tx: Sender<Incoming>,
+    ) -> GeckoAdminServer<AdminServer> {
+        GeckoAdminServer::new(Self {
+            node_id: cfg.node_id,
+            managed: cfg.etcd.is_some(),
+            router_tx,
+        })
+    }
+
+    /// 手动添加和移除的对等节点
+    #[allow(clippy::result_large_err)]
+    fn check_peer(&self, node_id: NodeId) -> Result<(), tonic::Status> {
+        if self.managed {
+            return Err(tonic::Status::failed_precondition(
+                "cluster nodes are managed by etcd",
+            ));
+        }
+        if node_id == self.node_id {
+            return Err(tonic::Status::invalid_argument("cannot join or leave self"));
+        }
+        Ok(())
+    }
+
+    /// 发送请求，并等待 router 处理的结果
+    async fn request<T>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<T>) -> AdminRequest,
+    ) -> Result<tonic::Response<T>, tonic::Status> {
+        let (tx, rx) = oneshot::channel();
+        self.router_tx
+            .send(Incoming::Admin(request(tx)))
+            .await
+            .map_err(|_| tonic::Status::unavailable("router closed"))?;
+        rx.await
+            .map(tonic::Response::new)
+            .map_err(|_| tonic::Status::unavailable("router closed"))
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn check_client_id(client_id: &str) -> Result<(), tonic::Status> {
+    if client_id.is_empty() {
+        return Err(tonic::Status::invalid_argument("empty client id"));
+    }
+    Ok(())
+}
+
+#[allow(clippy::result_large_err)]
+fn check_filters(filters: &[String]) -> Result<(), tonic::Status> {
+    if filters.is_empty() {
+        return Err(tonic::Status::invalid_argument("no topic filters"));
+    }
+    match filters
+        .iter()
+        .find(|filter| !topic::valid_subscribe_filter(filter))
+    {
+        Some(filter) => Err(tonic::Status::invalid_argument(format!(
+            "invalid topic filter: {}",
+            filter
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn check_topic(topic: &str) -> Result<(), tonic::Status> {
+    if topic.is_empty() || !topic::valid_publish_topic(topic) {
+        return Err(tonic::Status::invalid_argument(format!(
+            "invalid topic: {}",
+            topic
+        )));
+    }
+    Ok(())
+}
+
+#[allow(clippy::result_large_err)]
+fn check_ban(ban: &ban::Ban) -> Result<(), tonic::Status> {
+    ban::validate(ban).map_err(|e| tonic::Status::invalid_argument(e.to_string()))
+}
+
+#[tonic::async_trait]
+impl GeckoAdmin for AdminServer {
+    async fn list_clients(
+        &self,
+        request: tonic::Request<ListClientsRequest>,
+    ) -> Result<tonic::Response<ListClientsResponse>, tonic::Status> {
+        let request = request.into_inner();
+        self.request(|tx| AdminRequest::ListClients(request, tx))
+            .await
+    }
+
+    async fn get_session(
+        &self,
+        request: tonic::Request<GetSessionRequest>,
+    ) -> Result<tonic::Response<GetSessionResponse>, tonic::Status> {
+        let request = request.into_inner();
+        check_client_id(&request.client_id)?;
+        self.request(|tx| AdminRequest::GetSession(request, tx))
+            .await
+    }
+
+    async fn kick_client(
+        &self,
+        request: tonic::Request<KickClientRequest>,
+    ) -> Result<tonic::Response<KickClientResponse>, tonic::Status> {
+        let request = request.into_inner();
+        check_client_id(&request.client_id)?;
+        self.request(|tx| AdminRequest::KickClient(request, tx))
+            .await
+    }
+
+    async fn subscribe(
+        &self,
+        request: tonic::Request<SubscribeRequest>,
+    ) -> Result<tonic::Response<SubscribeResponse>, tonic::Status> {
+        let request = request.into_inner();
+        check_client_id(&request.client_id)?;
+        check_filters(&request.filters)?;
+        self.request(|tx| AdminRequest::Subscribe(request, tx))
+            .await
+    }
+
+    async fn unsubscribe(
+        &self,
+        request: tonic::Request<UnsubscribeRequest>,
+    ) -> Result<tonic::Response<UnsubscribeResponse>, tonic::Status> {
+        let request = request.into_inner();
+        check_client_id(&request.client_id)?;
+        check_filters(&request.filters)?;
+        self.request(|tx| AdminRequest::Unsubscribe(request, tx))
+            .await
+    }
+
+    async fn list_retained(
+        &self,
+        request: tonic::Request<ListRetainedRequest>,
+    ) -> Result<tonic::Response<ListRetainedResponse>, tonic::Status> {
+        let request = request.into_inner();
+        if !request.filter.is_empty() {
+            check_filters(std::slice::from_ref(&request.filter))?;
+        }
+        self.request(|tx| AdminRequest::ListRetained(request, tx))
+            .await
+    }
+
+    async fn delete_retained(
+        &self,
+        request: tonic::Request<DeleteRetainedRequest>,
+    ) -> Result<tonic::Response<DeleteRetainedResponse>, tonic::Status> {
+        let request = request.into_inner();
+        check_topic(&request.topic)?;
+        self.request(|tx| AdminRequest::DeleteRetained(request, tx))
+            .await
+    }
+
+    async fn publish(
+        &self,
+        request: tonic::Request<PublishRequest>,
+    ) -> Result<tonic::Response<PublishResponse>, tonic::Status> {
+        let request = request.into_inner();
+        check_topic(&request.topic)?;
+        let qos = u8::try_from(request.qos)
+            .ok()
+            .and_then(|qos| QoS::try_from(qos).ok())
+            .ok_or_else(|| tonic::Status::invalid_argument("invalid qos"))?;
+        let publish = Publish {
+            dup: false,
+            qos,
+            retain: request.retain,
+            topic: request.topic.into(),
+            packet_id: 0,
+            payload: Bytes::from(request.payload),
+            properties: None,
+            encoded: Default::default(),
+        };
+        self.request(|tx| AdminRequest::Publish(publish, tx)).await
+    }
+
+    async fn list_nodes(
+        &self,
+        _request: tonic::Request<ListNodesRequest>,
+    ) -> Result<tonic::Response<ListNodesResponse>, tonic::Status> {
+        self.request(AdminRequest::ListNodes).await
+    }
+
+    async fn join_node(
+        &self,
+        request: tonic::Request<JoinNodeRequest>,
+    ) -> Result<tonic::Response<JoinNodeResponse>, tonic::Status> {
+        let request = request.into_inner();
+        self.check_peer(request.node_id)?;
+        if request.addr.is_empty() {
+            return Err(tonic::Status::invalid_argument("empty node addr"));
+        }
+        self.request(|tx| AdminRequest::JoinNode(request, tx)).await
+    }
+
+    async fn leave_node(
+        &self,
+        request: tonic::Request<LeaveNodeRequest>,
+    ) -> Result<tonic::Response<LeaveNodeResponse>, tonic::Status> {
+        let request = request.into_inner();
+        self.check_peer(request.node_id)?;
+        self.request(|tx| AdminRequest::LeaveNode(request, tx))
+            .await
+    }
+
+    async fn get_metrics(
+        &self,
+        _request: tonic::Request<GetMetricsRequest>,
+    ) -> Result<tonic::Response<GetMetricsResponse>, tonic::Status> {
+        self.request(AdminRequest::GetMetrics).await
+    }
+
+    async fn list_bans(
+        &self,
+        _request: tonic::Request<ListBansRequest>,
+    ) -> Result<tonic::Response<ListBansResponse>, tonic::Status> {
+        self.request(AdminRequest::ListBans).await
+    }
+
+    async fn add_ban(
+        &self,
+        request: tonic::Request<AddBanRequest>,
+    ) -> Result<tonic::Response<AddBanResponse>, tonic::Status> {
+        let mut request = request.into_inner();
+        let Some(added) = request.ban.as_mut() else {
+            return Err(tonic::Status::invalid_argument("no ban"));
+        };
+        check_ban(&ban::from_proto(added.clone()))?;
+        if added.created_at == 0 {
+            added.created_at = ban::now();
+        }
+        self.request(|tx| AdminRequest::AddBan(request, tx)).await
+    }
+
+    async fn delete_ban(
+        &self,
+        request: tonic::Request<DeleteBanRequest>,
+    ) -> Result<tonic::Response<DeleteBanResponse>, tonic::Status> {
+        let request = request.into_inner();
+        if request.value.is_empty() {
+            return Err(tonic::Status::invalid_argument("empty ban value"));
+        }
+        self.request(|tx| AdminRequest::DeleteBan(request, tx))
+            .await
+    }
+}